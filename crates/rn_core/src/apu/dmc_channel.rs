@@ -330,7 +330,7 @@ impl DmcChannel {
             [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
 
         let table = match self.region {
-            crate::region::Region::Ntsc => &NTSC,
+            crate::region::Region::Ntsc | crate::region::Region::Dendy => &NTSC,
             crate::region::Region::Pal => &PAL,
         };
         self.timer = table[(self.control & 0x0F) as usize];
@@ -0,0 +1,440 @@
+//! The Famicom Disk System's sound: one wavetable channel, with a second wavetable bending its
+//! pitch.
+//!
+//! The wave is 64 six-bit samples the program writes to `$4040..=$407F`, played at a 12-bit
+//! frequency from `$4082`/`$4083`. A volume envelope at `$4080` scales it. What makes the chip
+//! sound like itself is the modulator. It steps through a table of 64 three-bit entries, written
+//! through `$4088`, at a frequency of its own, and each entry nudges a signed counter up or down.
+//! The counter times the modulator's own envelope gain is then added to the wave's pitch, every
+//! cycle. That is the source of the vibrato and the metallic FM-like tones in *Zelda no Densetsu*
+//! and *Akumajou Dracula*.
+//!
+//! Both envelopes share one clock, `$408A` times eight times each envelope's own speed, and ramp
+//! their gain one step a tick towards 0 or 32. Writing an envelope with bit 7 set instead sets the
+//! gain directly.
+//!
+//! The output stage is a 6-bit wave times a gain capped at 32, scaled by a two-bit master volume,
+//! then through a low-pass filter on the RAM adapter at about 2 kHz. The filter is why the raw
+//! stepped wave comes out of a Famicom sounding smooth, and it is reproduced here.
+
+use super::{mixer::Mixer, ExpansionChannel};
+
+/// The cutoff of the RAM adapter's output filter, as a one-pole coefficient at the CPU's rate:
+/// `1 - e^(-2π · 2000 / 1789773)`.
+const FILTER_COEFFICIENT: f32 = 0.00700;
+
+/// What each of the modulator's table entries does to its counter; 4 resets it instead.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/// The master volume's four settings, from `$4089` bits 0-1, as fractions of full.
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+/// One of the two envelopes, `$4080` for volume and `$4084` for the modulator.
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope {
+    /// Bits 0-5: the speed, or with `direct` the gain itself.
+    speed: u8,
+    /// Bit 6: ramp up rather than down.
+    increase: bool,
+    /// Bit 7: no envelope, just the gain written.
+    direct: bool,
+    gain: u8,
+    /// CPU cycles to the next step.
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8, master: u8) {
+        self.speed = value & 0x3F;
+        self.increase = value & 0x40 != 0;
+        self.direct = value & 0x80 != 0;
+        if self.direct {
+            self.gain = self.speed;
+        }
+        self.reload(master);
+    }
+
+    fn reload(&mut self, master: u8) {
+        self.timer = 8 * (master as u32 + 1) * (self.speed as u32 + 1);
+    }
+
+    fn tick(&mut self, master: u8) {
+        if self.direct || master == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.reload(master);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.speed,
+            u8::from(self.increase) | u8::from(self.direct) << 1,
+            self.gain,
+        ]);
+        state.extend_from_slice(&self.timer.to_le_bytes());
+    }
+
+    fn load(&mut self, state: &[u8]) {
+        self.speed = state[0] & 0x3F;
+        self.increase = state[1] & 0x01 != 0;
+        self.direct = state[1] & 0x02 != 0;
+        self.gain = state[2] & 0x3F;
+        self.timer = u32::from_le_bytes([state[3], state[4], state[5], state[6]]);
+    }
+
+    const STATE_LEN: usize = 7;
+}
+
+/// The Disk System's wavetable channel and its modulator.
+///
+/// Owned by the RAM adapter, which decodes `$4040..=$4097` and clocks it once per CPU cycle.
+#[derive(Debug)]
+pub(crate) struct FdsAudio {
+    wave: [u8; 64],
+    /// `$4089` bit 7: the wave is open for writing, and holds its output meanwhile.
+    wave_writable: bool,
+    /// `$4089` bits 0-1.
+    master_volume: u8,
+    /// `$408A`, the envelopes' shared clock.
+    envelope_speed: u8,
+    volume: Envelope,
+
+    /// `$4082`/`$4083` bits 0-3.
+    frequency: u16,
+    /// `$4083` bit 7: the wave stops, back at its first sample.
+    wave_halted: bool,
+    /// `$4083` bit 6: both envelopes stop.
+    envelopes_halted: bool,
+    wave_accumulator: u32,
+    wave_position: u8,
+
+    modulator: Envelope,
+    /// 64 three-bit entries, written two at a time.
+    mod_table: [u8; 64],
+    mod_position: u8,
+    /// `$4085`, a seven-bit signed counter.
+    mod_counter: i8,
+    /// `$4086`/`$4087` bits 0-3.
+    mod_frequency: u16,
+    /// `$4087` bit 7: the modulator stops, and its table can be written.
+    mod_halted: bool,
+    mod_accumulator: u32,
+
+    /// The wave times the volume, as the DAC last saw it.
+    level: u16,
+    /// The output filter's state.
+    filtered: f32,
+
+    mixer: Mixer,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_writable: false,
+            master_volume: 0,
+            envelope_speed: 0xE8,
+            volume: Envelope::default(),
+            frequency: 0,
+            wave_halted: true,
+            envelopes_halted: true,
+            wave_accumulator: 0,
+            wave_position: 0,
+            modulator: Envelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_counter: 0,
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_accumulator: 0,
+            level: 0,
+            filtered: 0.0,
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// A read from `$4040..=$4097`: the wave table, and the two envelopes' gains. `None` for the
+    /// addresses that are write-only.
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4040..=0x407F => Some(self.wave[(address & 0x3F) as usize]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.modulator.gain),
+            _ => None,
+        }
+    }
+
+    /// A write to `$4040..=$408A`.
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4040..=0x407F if self.wave_writable => self.wave[(address & 0x3F) as usize] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | value as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_halted = value & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reload(self.envelope_speed);
+                    self.modulator.reload(self.envelope_speed);
+                }
+            },
+            0x4084 => self.modulator.write(value, self.envelope_speed),
+            // Seven bits, sign-extended.
+            0x4085 => self.mod_counter = ((value << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halted = value & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            },
+            // Each write fills two consecutive steps of the table, and only while it is stopped.
+            0x4088 if self.mod_halted => {
+                let position = self.mod_position as usize;
+                self.mod_table[position] = value & 0x07;
+                self.mod_table[(position + 1) & 0x3F] = value & 0x07;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            },
+            0x4089 => {
+                self.wave_writable = value & 0x80 != 0;
+                self.master_volume = value & 0x03;
+            },
+            0x408A => self.envelope_speed = value,
+            _ => {},
+        }
+    }
+
+    /// The wave's pitch this cycle, after the modulator has had its say.
+    ///
+    /// The arithmetic is the chip's, rounding included, as worked out on the NESdev wiki. It is
+    /// odd enough that a tidier formula is audibly out of tune.
+    fn pitch(&self) -> u32 {
+        let mut bend = self.mod_counter as i32 * self.modulator.gain as i32;
+        let remainder = bend & 0x0F;
+        bend >>= 4;
+        if remainder > 0 && bend & 0x80 == 0 {
+            bend += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if bend >= 192 {
+            bend -= 256;
+        } else if bend < -64 {
+            bend += 256;
+        }
+        let mut offset = self.frequency as i32 * bend;
+        let remainder = offset & 0x3F;
+        offset >>= 6;
+        if remainder >= 32 {
+            offset += 1;
+        }
+        (self.frequency as i32 + offset).max(0) as u32
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted {
+            self.volume.tick(self.envelope_speed);
+            self.modulator.tick(self.envelope_speed);
+        }
+
+        if !self.mod_halted && self.mod_frequency > 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        if !self.wave_halted && !self.wave_writable {
+            self.wave_accumulator += self.pitch();
+            self.wave_position = ((self.wave_position as u32 + (self.wave_accumulator >> 16)) & 0x3F) as u8;
+            self.wave_accumulator &= 0xFFFF;
+        }
+
+        // Writing the wave holds the DAC at whatever it last had.
+        if !self.wave_writable {
+            self.level = self.wave[self.wave_position as usize] as u16 * self.volume.gain.min(32) as u16;
+        }
+        let level = self.level as f32 * MASTER_VOLUME[self.master_volume as usize];
+        self.filtered += (level - self.filtered) * FILTER_COEFFICIENT;
+    }
+
+    fn step_modulator(&mut self) {
+        let entry = self.mod_table[self.mod_position as usize];
+        self.mod_counter = if entry == 4 {
+            0
+        } else {
+            // Wraps within seven bits, as the counter does.
+            (self.mod_counter.wrapping_add(MOD_STEPS[entry as usize]) << 1) >> 1
+        };
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        self.mixer.fds(self.filtered)
+    }
+
+    /// The one channel, for display beside the console's own.
+    pub fn channels(&self) -> Vec<ExpansionChannel> {
+        vec![ExpansionChannel {
+            label: "FDS Wave",
+            enabled: !self.wave_halted,
+        }]
+    }
+
+    /// Start or stop the wave through `$4083` bit 7, as a program does.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) {
+        if index == 0 {
+            self.wave_halted = !enabled;
+        }
+    }
+
+    /// The chip's state, appended to `state`.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.wave);
+        state.extend_from_slice(&self.mod_table);
+        self.volume.save(state);
+        self.modulator.save(state);
+        state.extend_from_slice(&[
+            u8::from(self.wave_writable)
+                | u8::from(self.wave_halted) << 1
+                | u8::from(self.envelopes_halted) << 2
+                | u8::from(self.mod_halted) << 3,
+            self.master_volume,
+            self.envelope_speed,
+            self.wave_position,
+            self.mod_position,
+            self.mod_counter as u8,
+        ]);
+        state.extend_from_slice(&self.frequency.to_le_bytes());
+        state.extend_from_slice(&self.mod_frequency.to_le_bytes());
+        state.extend_from_slice(&self.wave_accumulator.to_le_bytes());
+        state.extend_from_slice(&self.mod_accumulator.to_le_bytes());
+        state.extend_from_slice(&self.level.to_le_bytes());
+        state.extend_from_slice(&self.filtered.to_le_bytes());
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        if state.len() < Self::STATE_LEN {
+            return None;
+        }
+        self.wave.copy_from_slice(&state[..64]);
+        self.mod_table.copy_from_slice(&state[64..128]);
+        for entry in self.mod_table.iter_mut() {
+            *entry &= 0x07;
+        }
+        self.volume.load(&state[128..]);
+        self.modulator.load(&state[128 + Envelope::STATE_LEN..]);
+        let rest = &state[128 + 2 * Envelope::STATE_LEN..];
+        self.wave_writable = rest[0] & 0x01 != 0;
+        self.wave_halted = rest[0] & 0x02 != 0;
+        self.envelopes_halted = rest[0] & 0x04 != 0;
+        self.mod_halted = rest[0] & 0x08 != 0;
+        self.master_volume = rest[1] & 0x03;
+        self.envelope_speed = rest[2];
+        self.wave_position = rest[3] & 0x3F;
+        self.mod_position = rest[4] & 0x3F;
+        self.mod_counter = rest[5] as i8;
+        let word = |at: usize| u16::from_le_bytes([rest[at], rest[at + 1]]);
+        let long = |at: usize| u32::from_le_bytes([rest[at], rest[at + 1], rest[at + 2], rest[at + 3]]);
+        self.frequency = word(6) & 0x0FFF;
+        self.mod_frequency = word(8) & 0x0FFF;
+        self.wave_accumulator = long(10) & 0xFFFF;
+        self.mod_accumulator = long(14) & 0xFFFF;
+        self.level = word(18);
+        self.filtered = f32::from_bits(long(20));
+        Some(Self::STATE_LEN)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 128 + 2 * Envelope::STATE_LEN + 6 + 4 + 8 + 2 + 4;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A square wave, written with the table open and then played at `frequency` at full volume.
+    fn square(frequency: u16) -> FdsAudio {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4089, 0x80);
+        for index in 0..64u16 {
+            audio.write(0x4040 + index, if index < 32 { 0x3F } else { 0 });
+        }
+        audio.write(0x4089, 0x00);
+        audio.write(0x4080, 0x80 | 32);
+        audio.write(0x4082, frequency as u8);
+        audio.write(0x4083, (frequency >> 8) as u8);
+        audio
+    }
+
+    #[test]
+    fn the_wave_steps_by_its_frequency_and_sounds_through_the_filter() {
+        let mut audio = square(0x400);
+        // 0x400 per cycle is one sample every 64 cycles, so the 32 high samples take 2048.
+        for _ in 0..2000 {
+            audio.tick();
+        }
+        assert_eq!(audio.wave_position, 31);
+        assert!(audio.output() > 0.0);
+        let high = audio.output();
+        for _ in 0..2048 {
+            audio.tick();
+        }
+        assert!(audio.output() < high, "the low half pulls the filter back down");
+    }
+
+    #[test]
+    fn the_wave_table_only_takes_writes_while_open() {
+        let mut audio = square(0);
+        audio.write(0x4040, 0x12);
+        assert_eq!(audio.read(0x4040), Some(0x3F));
+        assert_eq!(audio.read(0x4082), None, "write-only");
+        assert_eq!(audio.read(0x4090), Some(32));
+    }
+
+    /// An entry of 1 everywhere raises the counter once a step, and a positive counter raises the
+    /// pitch.
+    #[test]
+    fn the_modulator_bends_the_pitch() {
+        let mut audio = square(0x100);
+        audio.write(0x4087, 0x80);
+        for _ in 0..32 {
+            audio.write(0x4088, 1);
+        }
+        audio.write(0x4084, 0x80 | 0x3F);
+        audio.write(0x4086, 0xFF);
+        audio.write(0x4087, 0x0F);
+        assert_eq!(audio.pitch(), 0x100);
+        for _ in 0..64 {
+            audio.tick();
+        }
+        assert!(audio.mod_counter > 0);
+        assert!(audio.pitch() > 0x100);
+
+        let mut saved = Vec::new();
+        audio.save(&mut saved);
+        assert_eq!(saved.len(), FdsAudio::STATE_LEN);
+        let mut restored = FdsAudio::new();
+        restored.load(&saved);
+        assert_eq!(restored.pitch(), audio.pitch());
+    }
+}
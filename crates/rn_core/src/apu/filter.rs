@@ -7,6 +7,11 @@
 //! result is a thump rather than a tone.
 //!
 //! Both are one-pole IIR sections, which is all the hardware's single R-C stage amounts to.
+//!
+//! Sound chips on the cartridge — MMC5's pulses, VRC6, VRC7's FM — return to the console ahead of
+//! these filters and so take them as well. That matters most for VRC7: FM carries far more energy
+//! above a few kHz than the APU's channels do, and the 14 kHz low-pass is part of why *Lagrange
+//! Point* sounds as mellow on a Famicom as it does.
 
 use std::f32::consts::PI;
 
@@ -15,6 +15,8 @@
 //! Step boundaries are in CPU cycles, twice the APU-cycle figures usually quoted, because this is
 //! driven once per CPU cycle.
 
+use crate::region::Region;
+
 /// What a step of the sequence should clock.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
 pub struct FrameClock {
@@ -119,7 +121,7 @@ pub struct FrameCounter {
     /// Which console's step table to count against. A PAL sequencer counts further before each
     /// clock, because its CPU is slower and its frame longer.
     #[serde(default)]
-    region: crate::region::Region,
+    region: Region,
     mode: Mode,
     /// CPU cycles since the sequence last restarted.
     cycle: u64,
@@ -161,31 +163,31 @@ impl Default for FrameCounter {
 impl FrameCounter {
     /// Point this counter at a console. Resets nothing: the sequence keeps running, which is what
     /// hardware does — the region is a property of the board, not something a game switches.
-    pub fn set_region(&mut self, region: crate::region::Region) {
+    pub fn set_region(&mut self, region: Region) {
         self.region = region;
     }
 
     /// The step table and wrap point for the current mode and console.
     fn sequence(&self) -> (&'static [(u64, FrameClock)], u64) {
         match (self.mode, self.region) {
-            (Mode::FourStep, crate::region::Region::Ntsc) => (&FOUR_STEP, FOUR_STEP_LENGTH),
-            (Mode::FiveStep, crate::region::Region::Ntsc) => (&FIVE_STEP, FIVE_STEP_LENGTH),
-            (Mode::FourStep, crate::region::Region::Pal) => (&FOUR_STEP_PAL, FOUR_STEP_LENGTH_PAL),
-            (Mode::FiveStep, crate::region::Region::Pal) => (&FIVE_STEP_PAL, FIVE_STEP_LENGTH_PAL),
+            (Mode::FourStep, Region::Ntsc | Region::Dendy) => (&FOUR_STEP, FOUR_STEP_LENGTH),
+            (Mode::FiveStep, Region::Ntsc | Region::Dendy) => (&FIVE_STEP, FIVE_STEP_LENGTH),
+            (Mode::FourStep, Region::Pal) => (&FOUR_STEP_PAL, FOUR_STEP_LENGTH_PAL),
+            (Mode::FiveStep, Region::Pal) => (&FIVE_STEP_PAL, FIVE_STEP_LENGTH_PAL),
         }
     }
 
     /// The three cycles the 4-step sequence holds its IRQ up for, on this console.
     fn four_step_irq_window(&self) -> std::ops::RangeInclusive<u64> {
         match self.region {
-            crate::region::Region::Ntsc => FOUR_STEP_IRQ,
-            crate::region::Region::Pal => FOUR_STEP_IRQ_PAL,
+            Region::Ntsc | Region::Dendy => FOUR_STEP_IRQ,
+            Region::Pal => FOUR_STEP_IRQ_PAL,
         }
     }
 
     pub fn new() -> Self {
         Self {
-            region: crate::region::Region::default(),
+            region: Region::default(),
             mode: Mode::FourStep,
             cycle: 0,
             irq_inhibit: false,
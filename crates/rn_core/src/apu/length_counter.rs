@@ -40,7 +40,7 @@ pub struct LengthCounter {
 /// The length counter load values
 /// Index is the 5-bit value from the register (bits 3-7), value is the actual length
 /// These values are hardcoded in the NES APU hardware
-const LENGTH_TABLE: [u8; 32] = [
+pub(super) const LENGTH_TABLE: [u8; 32] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16,
     28, 32, 30,
 ];
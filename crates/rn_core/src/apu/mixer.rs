@@ -29,6 +29,8 @@
 //! and it is removed downstream by the same high-pass filters the hardware uses, rather than by
 //! fudging the mix here.
 
+use super::ExpansionChip;
+
 /// `PULSE_TABLE[n]` is the mixer output for `pulse1 + pulse2 == n`.
 const PULSE_TABLE_LEN: usize = 31;
 
@@ -38,6 +40,8 @@ const TND_TABLE_LEN: usize = 203;
 pub struct Mixer {
     pulse_table: [f32; PULSE_TABLE_LEN],
     tnd_table: [f32; TND_TABLE_LEN],
+    /// Each cartridge sound chip's level, by [`ExpansionChip`] index.
+    expansion_levels: [f32; ExpansionChip::ALL.len()],
 }
 
 impl std::fmt::Debug for Mixer {
@@ -65,7 +69,31 @@ impl Mixer {
             *entry = 163.67 / (24329.0 / n as f32 + 100.0);
         }
 
-        Self { pulse_table, tnd_table }
+        Self {
+            pulse_table,
+            tnd_table,
+            expansion_levels: [1.0; ExpansionChip::ALL.len()],
+        }
+    }
+
+    /// A cartridge chip's output, already in mix units, at that chip's level — ready to be added
+    /// to [`mix`](Self::mix). The expansion pin is summed after the console's own DAC, so this is
+    /// linear; there is no curve to go through.
+    pub fn expansion(&self, chip: Option<ExpansionChip>, output: f32) -> f32 {
+        match chip {
+            Some(chip) => output * self.expansion_levels[chip.index()],
+            None => output,
+        }
+    }
+
+    pub fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        self.expansion_levels[chip.index()]
+    }
+
+    /// Set a chip's level, clamped to 0.0..=4.0: enough to bring the quietest Namco 163 board up to
+    /// the rest, and short of anything that only clips.
+    pub fn set_expansion_level(&mut self, chip: ExpansionChip, level: f32) {
+        self.expansion_levels[chip.index()] = level.clamp(0.0, 4.0);
     }
 
     /// Mix five raw DAC levels into one sample in roughly 0.0..=1.0.
@@ -78,6 +106,68 @@ impl Mixer {
 
         self.pulse_table[pulse_index] + self.tnd_table[tnd_index]
     }
+
+    /// The pulse term alone, for `n` summed pulse levels.
+    ///
+    /// For the cartridge sound chips that copied the APU's pulse DAC as well as its pulses. MMC5's
+    /// two land on the same curve, so they are mixed on it rather than on a guess at one.
+    pub fn pulse_group(&self, n: usize) -> f32 {
+        self.pulse_table[n.min(PULSE_TABLE_LEN - 1)]
+    }
+
+    /// The triangle/noise/DMC term alone, for the weighted index `n`.
+    pub fn tnd_group(&self, n: usize) -> f32 {
+        self.tnd_table[n.min(TND_TABLE_LEN - 1)]
+    }
+
+    /// VRC6's three channels: `pulse1` and `pulse2` 0..=15, `sawtooth` 0..=31.
+    ///
+    /// Unlike MMC5, VRC6 has a DAC of its own, and a linear one: the chip adds its channels into a
+    /// 6-bit sum before anything reaches the console. The scale is the usual balance on an
+    /// unmodified Famicom, where a VRC6 pulse at full volume is as loud as an APU pulse at full
+    /// volume, so one step is a fifteenth of the pulse term at 15.
+    pub fn vrc6(&self, pulse1: u8, pulse2: u8, sawtooth: u8) -> f32 {
+        let sum = pulse1 as usize + pulse2 as usize + sawtooth as usize;
+        sum as f32 * self.pulse_table[15] / 15.0
+    }
+
+    /// VRC7's six FM channels, as the sum of their signed levels, each within ±8192.
+    ///
+    /// The OPLL's output is bipolar and its channels add linearly, like VRC6's. One channel at
+    /// full level peaks as high as a full APU pulse; in practice FM instruments rarely sit at full
+    /// level, so Lagrange Point's music comes out close to the APU's, as it does on a Famicom.
+    pub fn vrc7(&self, sum: i32) -> f32 {
+        sum as f32 * self.pulse_table[15] / 8192.0
+    }
+
+    /// Namco 163's output: the average of its channels, each a centred 4-bit sample times a 4-bit
+    /// volume, so within -120..=105.
+    ///
+    /// The boards set the chip's level with a resistor, and not all the same one: measured carts
+    /// vary by nearly a factor of two. This takes one channel at full volume alone to swing about
+    /// one and a half times as far as a full APU pulse, the middle of that range.
+    pub fn n163(&self, level: f32) -> f32 {
+        level * self.pulse_table[15] * 1.5 / 225.0
+    }
+
+    /// Sunsoft 5B's three channels, as the sum of their amplitudes, each 0.0 to 1.0.
+    ///
+    /// The sum is linear here. On the board it is not quite: the 5B's output stage saturates when
+    /// all three channels are loud at once, which softens *Gimmick!*'s loudest chords a little.
+    /// One channel at full volume is as loud as a full APU pulse, the same balance as VRC6.
+    pub fn sunsoft5b(&self, sum: f32) -> f32 {
+        sum * self.pulse_table[15]
+    }
+
+    /// The Disk System's wave after its volume, a 6-bit sample times a gain capped at 32, so
+    /// 0.0 to 2016.0.
+    ///
+    /// The RAM adapter's DAC is unipolar and louder than the cartridge chips: a full-scale wave
+    /// at full volume swings about twice as far as a full APU pulse, which is why *Zelda*'s
+    /// disk-only music carries over the console's channels the way it does.
+    pub fn fds(&self, level: f32) -> f32 {
+        level * self.pulse_table[15] * 2.0 / 2016.0
+    }
 }
 
 #[cfg(test)]
@@ -125,6 +215,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn a_full_vrc6_pulse_matches_a_full_apu_pulse_and_sums_linearly() {
+        let mixer = Mixer::new();
+
+        assert!((mixer.vrc6(15, 0, 0) - mixer.mix(15, 0, 0, 0, 0)).abs() < EPS);
+        assert!((mixer.vrc6(15, 15, 31) - 61.0 * mixer.vrc6(1, 0, 0)).abs() < EPS);
+        assert_eq!(mixer.vrc6(0, 0, 0), 0.0);
+    }
+
+    #[test]
+    fn a_full_vrc7_channel_peaks_at_a_full_apu_pulse() {
+        let mixer = Mixer::new();
+
+        assert!((mixer.vrc7(8192) - mixer.mix(15, 0, 0, 0, 0)).abs() < EPS);
+        assert_eq!(mixer.vrc7(-8192), -mixer.vrc7(8192));
+        assert_eq!(mixer.vrc7(0), 0.0);
+    }
+
+    #[test]
+    fn a_full_sunsoft5b_channel_matches_a_full_apu_pulse() {
+        let mixer = Mixer::new();
+
+        assert!((mixer.sunsoft5b(1.0) - mixer.mix(15, 0, 0, 0, 0)).abs() < EPS);
+        assert!((mixer.sunsoft5b(3.0) - 3.0 * mixer.sunsoft5b(1.0)).abs() < EPS);
+    }
+
+    #[test]
+    fn a_full_fds_wave_swings_two_pulses() {
+        let mixer = Mixer::new();
+
+        assert_eq!(mixer.fds(0.0), 0.0);
+        assert!((mixer.fds(63.0 * 32.0) - 2.0 * mixer.mix(15, 0, 0, 0, 0)).abs() < EPS);
+    }
+
+    #[test]
+    fn a_lone_n163_channel_swings_one_and_a_half_pulses() {
+        let mixer = Mixer::new();
+
+        let swing = mixer.n163(105.0) - mixer.n163(-120.0);
+        assert!((swing - 1.5 * mixer.mix(15, 0, 0, 0, 0)).abs() < EPS);
+    }
+
     #[test]
     fn mixing_is_non_linear() {
         let mixer = Mixer::new();
@@ -155,4 +287,16 @@ mod tests {
             assert!(mixer.mix(0, 0, level, 0, 0) > mixer.mix(0, 0, level - 1, 0, 0));
         }
     }
+
+    #[test]
+    fn each_expansion_chip_has_its_own_level() {
+        let mut mixer = Mixer::new();
+        assert_eq!(mixer.expansion(Some(ExpansionChip::Namco163), 0.25), 0.25, "unity by default");
+
+        mixer.set_expansion_level(ExpansionChip::Namco163, 2.0);
+        mixer.set_expansion_level(ExpansionChip::Vrc6, 99.0);
+        assert_eq!(mixer.expansion(Some(ExpansionChip::Namco163), 0.25), 0.5);
+        assert_eq!(mixer.expansion(Some(ExpansionChip::Vrc7), 0.25), 0.25, "other chips keep theirs");
+        assert_eq!(mixer.expansion_level(ExpansionChip::Vrc6), 4.0, "clamped");
+    }
 }
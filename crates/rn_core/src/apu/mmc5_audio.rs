@@ -0,0 +1,427 @@
+//! MMC5's sound: two more pulse channels and an 8-bit PCM channel.
+//!
+//! The pulses are the APU's own design with two parts removed. There is no sweep unit — `$5001`
+//! and `$5005` are decoded and do nothing — and so nothing mutes a channel for a short period
+//! either, which on the APU is the sweep's doing. Their envelopes and length counters are the
+//! APU's, but clocked by a divider on the cartridge at a fixed 240 Hz rather than by the APU's
+//! frame sequencer, which the cartridge cannot see: lengths therefore run out twice as fast as the
+//! same value would on the console.
+//!
+//! The PCM channel is a raw 8-bit DAC. It is written directly at `$5011`, or, in read mode, takes
+//! whatever the CPU reads from `$8000..=$BFFF` — a game points its sample-playing loop at the data
+//! and the chip picks the bytes off the bus as they go past. A zero byte is never played: written,
+//! it is ignored, and read, it raises the channel's IRQ, which is how a sample marks its end.
+//!
+//! Levels are mixed into the same units as the APU's output, on the APU's own curves: the pulses
+//! on the pulse DAC's, the PCM on the DMC's with its extra bit dropped.
+
+use std::cell::Cell;
+
+use super::{envelope::Envelope, length_counter::LENGTH_TABLE, mixer::Mixer};
+
+/// CPU cycles between clocks of the pulses' envelopes and length counters: 240 Hz on NTSC.
+const FRAME_PERIOD: u16 = 7457;
+
+/// The APU's four duty sequences, high steps as set bits, read from bit 7 down.
+const DUTY_TABLE: [u8; 4] = [0b0100_0000, 0b0110_0000, 0b0111_1000, 0b1001_1111];
+
+/// One of MMC5's two pulse channels.
+#[derive(Debug, Clone)]
+struct Mmc5Pulse {
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    halt: bool,
+    envelope: Envelope,
+}
+
+impl Mmc5Pulse {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            length: 0,
+            halt: false,
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.halt = (value & 0x20) != 0;
+                self.envelope.update_from_register(value);
+            },
+            // The sweep register. Decoded, and wired to nothing.
+            1 => {},
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.restart();
+            },
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    /// One APU cycle: every other CPU cycle, as for the console's own pulses.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame(&mut self) {
+        self.envelope.tick();
+        if self.length > 0 && !self.halt {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || (DUTY_TABLE[self.duty as usize] << self.step) & 0x80 == 0 {
+            return 0;
+        }
+        self.envelope.get_volume()
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        let envelope = &self.envelope;
+        state.extend_from_slice(&[
+            u8::from(self.enabled),
+            self.duty,
+            self.step,
+            self.period as u8,
+            (self.period >> 8) as u8,
+            self.timer as u8,
+            (self.timer >> 8) as u8,
+            self.length,
+            u8::from(self.halt),
+            u8::from(envelope.start),
+            envelope.divider,
+            envelope.counter,
+            u8::from(envelope.loop_flag),
+            u8::from(envelope.constant_volume),
+            envelope.period,
+            envelope.volume,
+        ]);
+    }
+
+    fn load(&mut self, state: &[u8]) {
+        self.enabled = state[0] != 0;
+        self.duty = state[1] & 0x03;
+        self.step = state[2] & 0x07;
+        self.period = u16::from_le_bytes([state[3], state[4]]);
+        self.timer = u16::from_le_bytes([state[5], state[6]]);
+        self.length = state[7];
+        self.halt = state[8] != 0;
+        self.envelope.start = state[9] != 0;
+        self.envelope.divider = state[10];
+        self.envelope.counter = state[11];
+        self.envelope.loop_flag = state[12] != 0;
+        self.envelope.constant_volume = state[13] != 0;
+        self.envelope.period = state[14];
+        self.envelope.volume = state[15];
+    }
+}
+
+/// Bytes one pulse adds to a save state.
+const PULSE_STATE_LEN: usize = 16;
+
+/// MMC5's sound hardware, at `$5000..=$5015`.
+///
+/// Owned by the mapper, which decodes the addresses and clocks it once per CPU cycle.
+#[derive(Debug)]
+pub(crate) struct Mmc5Audio {
+    pulses: [Mmc5Pulse; 2],
+
+    /// `$5010` bit 0: take PCM samples from CPU reads rather than from `$5011`.
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    /// Cells because a CPU read can set them, and reads go through `&self`.
+    pcm_irq_pending: Cell<bool>,
+    pcm: Cell<u8>,
+
+    frame_divider: u16,
+    apu_cycle: bool,
+
+    mixer: Mixer,
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Self {
+        Self {
+            pulses: [Mmc5Pulse::new(), Mmc5Pulse::new()],
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: Cell::new(false),
+            pcm: Cell::new(0),
+            frame_divider: 0,
+            apu_cycle: false,
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// Write one of `$5000..=$5015`. Addresses the chip does not decode are ignored.
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5003 => self.pulses[0].write(address & 0x03, value),
+            0x5004..=0x5007 => self.pulses[1].write(address & 0x03, value),
+            0x5010 => {
+                self.pcm_read_mode = (value & 0x01) != 0;
+                self.pcm_irq_enabled = (value & 0x80) != 0;
+            },
+            // Ignored in read mode, and a zero is ignored always: zero is the end-of-sample mark,
+            // never a level.
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm.set(value),
+            0x5015 => {
+                self.pulses[0].set_enabled((value & 0x01) != 0);
+                self.pulses[1].set_enabled((value & 0x02) != 0);
+            },
+            _ => {},
+        }
+    }
+
+    /// Read `$5010` or `$5015`, the only two of the chip's registers that answer.
+    ///
+    /// `$5010` reports the PCM IRQ in bit 7, and reading it acknowledges it.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x5010 => {
+                let pending = self.pcm_irq_pending.replace(false);
+                if pending {
+                    0x80
+                } else {
+                    0
+                }
+            },
+            _ => u8::from(self.pulses[0].length > 0) | (u8::from(self.pulses[1].length > 0) << 1),
+        }
+    }
+
+    /// Offer the chip a byte the CPU read from `$8000..=$BFFF`, for read mode to sample.
+    pub fn observe_cpu_read(&self, value: u8) {
+        if !self.pcm_read_mode {
+            return;
+        }
+        if value == 0 {
+            self.pcm_irq_pending.set(true);
+        } else {
+            self.pcm.set(value);
+        }
+    }
+
+    /// Whether the PCM channel is holding the cartridge's IRQ line.
+    pub fn irq_pending(&self) -> bool {
+        self.pcm_irq_enabled && self.pcm_irq_pending.get()
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        self.apu_cycle = !self.apu_cycle;
+        if self.apu_cycle {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+
+        self.frame_divider += 1;
+        if self.frame_divider >= FRAME_PERIOD {
+            self.frame_divider = 0;
+            for pulse in &mut self.pulses {
+                pulse.clock_frame();
+            }
+        }
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        let pulses = self.pulses[0].output() as usize + self.pulses[1].output() as usize;
+        self.mixer.pulse_group(pulses) + self.mixer.tnd_group(self.pcm.get() as usize >> 1)
+    }
+
+    /// The chip's state, appended to `state`.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        for pulse in &self.pulses {
+            pulse.save(state);
+        }
+        state.extend_from_slice(&[
+            u8::from(self.pcm_read_mode),
+            u8::from(self.pcm_irq_enabled),
+            u8::from(self.pcm_irq_pending.get()),
+            self.pcm.get(),
+            self.frame_divider as u8,
+            (self.frame_divider >> 8) as u8,
+            u8::from(self.apu_cycle),
+        ]);
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        let len = Self::STATE_LEN;
+        if state.len() < len {
+            return None;
+        }
+        self.pulses[0].load(&state[..PULSE_STATE_LEN]);
+        self.pulses[1].load(&state[PULSE_STATE_LEN..2 * PULSE_STATE_LEN]);
+        let rest = &state[2 * PULSE_STATE_LEN..len];
+        self.pcm_read_mode = rest[0] != 0;
+        self.pcm_irq_enabled = rest[1] != 0;
+        self.pcm_irq_pending.set(rest[2] != 0);
+        self.pcm.set(rest[3]);
+        self.frame_divider = u16::from_le_bytes([rest[4], rest[5]]);
+        self.apu_cycle = rest[6] != 0;
+        Some(len)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 2 * PULSE_STATE_LEN + 7;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start pulse 1 at constant volume `volume`, 50% duty, with a long length.
+    fn play_pulse(audio: &mut Mmc5Audio, volume: u8) {
+        audio.write(0x5015, 0x01);
+        audio.write(0x5000, 0x80 | 0x30 | volume);
+        audio.write(0x5002, 0x40);
+        audio.write(0x5003, 0x08);
+    }
+
+    #[test]
+    fn a_pulse_is_silent_until_enabled_and_then_toggles() {
+        let mut audio = Mmc5Audio::new();
+        audio.write(0x5000, 0xBF);
+        audio.write(0x5003, 0x08);
+        assert_eq!(audio.read(0x5015), 0, "a length written while disabled must not load");
+
+        play_pulse(&mut audio, 15);
+        assert_eq!(audio.read(0x5015), 0x01);
+
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..2000 {
+            audio.tick();
+            seen.insert(audio.pulses[0].output());
+        }
+        assert_eq!(seen, [0, 15].into_iter().collect());
+    }
+
+    /// No sweep unit means no muting for short periods, which the APU's pulses would do below 8.
+    #[test]
+    fn short_periods_are_not_muted() {
+        let mut audio = Mmc5Audio::new();
+        play_pulse(&mut audio, 9);
+        audio.write(0x5002, 0x02);
+        audio.write(0x5003, 0x08);
+
+        let loud = (0..64).filter(|_| {
+            audio.tick();
+            audio.pulses[0].output() == 9
+        });
+        assert!(loud.count() > 0);
+    }
+
+    /// The cartridge clocks lengths at 240 Hz, not 120 Hz as the APU does.
+    #[test]
+    fn length_counts_down_at_240_hz() {
+        let mut audio = Mmc5Audio::new();
+        audio.write(0x5015, 0x01);
+        audio.write(0x5000, 0x10);
+        // Length index 1 is 254; index 3 is 2.
+        audio.write(0x5003, 0x18);
+        assert_eq!(audio.pulses[0].length, 2);
+
+        for _ in 0..FRAME_PERIOD * 2 {
+            audio.tick();
+        }
+        assert_eq!(audio.read(0x5015), 0, "two 240 Hz clocks should exhaust a length of 2");
+    }
+
+    #[test]
+    fn disabling_a_pulse_clears_its_length() {
+        let mut audio = Mmc5Audio::new();
+        play_pulse(&mut audio, 15);
+        audio.write(0x5015, 0x00);
+        assert_eq!(audio.read(0x5015), 0);
+    }
+
+    #[test]
+    fn pcm_write_mode_ignores_zero() {
+        let mut audio = Mmc5Audio::new();
+        audio.write(0x5011, 0x80);
+        let level = audio.output();
+        assert!(level > 0.0);
+
+        audio.write(0x5011, 0x00);
+        assert_eq!(audio.output(), level);
+    }
+
+    #[test]
+    fn pcm_read_mode_samples_cpu_reads_and_flags_zero() {
+        let mut audio = Mmc5Audio::new();
+        audio.write(0x5010, 0x81);
+
+        audio.observe_cpu_read(0x40);
+        assert_eq!(audio.pcm.get(), 0x40);
+        assert!(!audio.irq_pending());
+
+        audio.observe_cpu_read(0x00);
+        assert_eq!(audio.pcm.get(), 0x40, "a zero ends the sample rather than playing");
+        assert!(audio.irq_pending());
+
+        assert_eq!(audio.read(0x5010), 0x80);
+        assert!(!audio.irq_pending(), "reading $5010 acknowledges");
+    }
+
+    #[test]
+    fn pcm_writes_are_ignored_in_read_mode() {
+        let mut audio = Mmc5Audio::new();
+        audio.write(0x5010, 0x01);
+        audio.write(0x5011, 0x55);
+        assert_eq!(audio.pcm.get(), 0);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut audio = Mmc5Audio::new();
+        play_pulse(&mut audio, 7);
+        audio.write(0x5011, 0x33);
+        for _ in 0..1234 {
+            audio.tick();
+        }
+
+        let mut state = Vec::new();
+        audio.save(&mut state);
+        assert_eq!(state.len(), Mmc5Audio::STATE_LEN);
+
+        let mut restored = Mmc5Audio::new();
+        assert_eq!(restored.load(&state), Some(Mmc5Audio::STATE_LEN));
+        for _ in 0..5000 {
+            audio.tick();
+            restored.tick();
+            assert_eq!(audio.output(), restored.output());
+        }
+    }
+}
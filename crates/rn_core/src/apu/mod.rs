@@ -1,25 +1,37 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::{audio::SampleProducer, errors::NesError, memory::Addressable};
+use crate::{audio::SampleProducer, cartridge::CartridgeHandle, errors::NesError, memory::Addressable};
 use derive_more::Debug;
 
 mod dmc_channel;
 mod envelope;
+mod fds_audio;
 mod filter;
 mod frame_counter;
 mod length_counter;
 mod mixer;
+mod mmc5_audio;
+mod n163_audio;
 mod noise_channel;
 mod pulse_channel;
+mod sunsoft5b_audio;
 mod sweep;
 mod triangle_channel;
+mod vrc6_audio;
+mod vrc7_audio;
 use dmc_channel::DmcChannel;
 use filter::OutputFilter;
 use frame_counter::{FrameClock, FrameCounter};
 use mixer::Mixer;
+pub(crate) use fds_audio::FdsAudio;
+pub(crate) use mmc5_audio::Mmc5Audio;
+pub(crate) use n163_audio::N163Audio;
 use noise_channel::NoiseChannel;
 use pulse_channel::PulseChannel;
+pub(crate) use sunsoft5b_audio::Sunsoft5bAudio;
 use triangle_channel::TriangleChannel;
+pub(crate) use vrc6_audio::Vrc6Audio;
+pub(crate) use vrc7_audio::Vrc7Audio;
 
 // Required APU register constants for simple tone test
 const APU_STATUS: u16 = 0x4015; // APU status/control
@@ -71,6 +83,58 @@ impl Channel {
     }
 }
 
+/// A sound channel on the cartridge, beside the console's five.
+///
+/// Expansion chips have no `$4015`: each channel is switched on and off by a bit in its own
+/// registers, so which are playing is asked of the cartridge. See [`ApuWrapper::expansion_channels`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpansionChannel {
+    pub label: &'static str,
+    pub enabled: bool,
+}
+
+/// A cartridge sound chip, for setting how loud it is against the console's own channels.
+///
+/// Each chip already scales its output to the balance an unmodified Famicom gives it, but that
+/// balance was never one thing: boards put different resistors on the expansion pin, and
+/// Namco 163 cartridges in particular vary by a factor of two or more. So the level is adjustable
+/// per chip rather than once for all of them, and a chip keeps its level across cartridges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExpansionChip {
+    Vrc6,
+    Vrc7,
+    Mmc5,
+    Namco163,
+    Sunsoft5b,
+    Fds,
+}
+
+impl ExpansionChip {
+    pub const ALL: [ExpansionChip; 6] = [
+        ExpansionChip::Vrc6,
+        ExpansionChip::Vrc7,
+        ExpansionChip::Mmc5,
+        ExpansionChip::Namco163,
+        ExpansionChip::Sunsoft5b,
+        ExpansionChip::Fds,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExpansionChip::Vrc6 => "VRC6",
+            ExpansionChip::Vrc7 => "VRC7",
+            ExpansionChip::Mmc5 => "MMC5",
+            ExpansionChip::Namco163 => "Namco 163",
+            ExpansionChip::Sunsoft5b => "Sunsoft 5B",
+            ExpansionChip::Fds => "FDS",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
 /// APU status/control register bits.
 const STATUS_FRAME_IRQ: u8 = 0x40;
 /// Set while the DMC is holding an interrupt, and cleared by reading or writing $4015.
@@ -202,6 +266,66 @@ impl ApuWrapper {
     pub fn set_muted(&self, muted: bool) {
         self.apu.borrow_mut().set_muted(muted);
     }
+
+    /// Leave one channel out of the mix. See [`Apu::set_channel_muted`].
+    pub fn set_channel_muted(&self, channel: Channel, muted: bool) {
+        self.apu.borrow_mut().set_channel_muted(channel, muted);
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        self.apu.borrow().channel_muted(channel)
+    }
+
+    /// The cartridge's sound for this cycle. See [`Apu::set_expansion_audio`].
+    pub fn set_expansion_audio(&self, level: f32) {
+        self.apu.borrow_mut().set_expansion_audio(level);
+    }
+
+    /// Tell the APU which cartridge is in the slot, so it can report that cartridge's channels.
+    ///
+    /// The level itself still arrives cycle by cycle through
+    /// [`set_expansion_audio`](Self::set_expansion_audio); this is only for asking which channels
+    /// there are.
+    pub fn connect_cartridge(&self, cartridge: CartridgeHandle) {
+        let mut apu = self.apu.borrow_mut();
+        apu.expansion_chip = cartridge.borrow().mapper().audio_chip();
+        // Whatever the last cartridge was playing when it came out is not this one's sound.
+        apu.expansion = 0.0;
+        apu.cartridge = Some(cartridge);
+    }
+
+    /// How loud a cartridge sound chip is against the console, where 1.0 is the balance of an
+    /// unmodified Famicom. See [`ExpansionChip`].
+    pub fn expansion_level(&self, chip: ExpansionChip) -> f32 {
+        self.apu.borrow().mixer.expansion_level(chip)
+    }
+
+    /// Set [`expansion_level`](Self::expansion_level), for this cartridge and any later one with
+    /// the same chip.
+    pub fn set_expansion_level(&self, chip: ExpansionChip, level: f32) {
+        self.apu.borrow_mut().mixer.set_expansion_level(chip, level);
+    }
+
+    /// The sound chip on the cartridge in the slot, if it has one.
+    pub fn expansion_chip(&self) -> Option<ExpansionChip> {
+        self.apu.borrow().expansion_chip
+    }
+
+    /// The cartridge's own sound channels, in the order its sound chip numbers them, and whether
+    /// each is enabled. Empty for a board without one.
+    pub fn expansion_channels(&self) -> Vec<ExpansionChannel> {
+        let cartridge = self.apu.borrow().cartridge.clone();
+        cartridge.map(|cartridge| cartridge.borrow().mapper().audio_channels()).unwrap_or_default()
+    }
+
+    /// Switch one of [`expansion_channels`](Self::expansion_channels) on or off, through the
+    /// chip's own enable bit, as the running program would.
+    pub fn set_expansion_channel_enabled(&self, index: usize, enabled: bool) {
+        let cartridge = self.apu.borrow().cartridge.clone();
+        if let Some(cartridge) = cartridge {
+            cartridge.borrow_mut().mapper_mut().set_audio_channel_enabled(index, enabled);
+        }
+    }
 }
 
 impl Addressable for ApuWrapper {
@@ -285,6 +409,9 @@ pub struct Apu {
     sample_counter: f64,
     samples_per_cycle: f64,
     sample_rate: f64,
+    /// CPU cycles per second on the console being emulated, which `samples_per_cycle` divides
+    /// into. A PAL machine's music resampled at the NTSC rate plays seven per cent sharp.
+    clock_rate: f64,
 
     /// Running sum of mixed values since the last emitted sample, and how many were summed.
     ///
@@ -296,6 +423,22 @@ pub struct Apu {
 
     /// Frame sequencer: clocks envelopes, sweeps and length counters, and raises the frame IRQ.
     frame_counter: FrameCounter,
+
+    /// The cartridge's own sound, already in mix units, as it stood at the last CPU cycle.
+    ///
+    /// Not saved: the mapper that produces it saves its own state and supplies it again on the
+    /// next cycle.
+    expansion: f32,
+    /// Which chip `expansion` comes from, so the mixer can apply that chip's level.
+    expansion_chip: Option<ExpansionChip>,
+
+    /// The cartridge, for listing its sound channels. See [`ApuWrapper::connect_cartridge`].
+    #[debug(skip)]
+    cartridge: Option<CartridgeHandle>,
+
+    /// Channels left out of the mix, by [`Channel`] order. A listener's choice rather than
+    /// anything the program did, so not saved and not visible through `$4015`.
+    channel_mutes: [bool; 5],
 }
 
 /// Whether `RN_DMC_TRACE` asks for the DMC's cycle ledger: every `$4015` write, fetch request,
@@ -326,6 +469,8 @@ impl Apu {
         self.frame_counter.set_region(region);
         self.dmc.set_region(region);
         self.noise.set_region(region);
+        self.clock_rate = region.cpu_clock_rate();
+        self.samples_per_cycle = self.sample_rate / self.clock_rate;
     }
 
     /// The DMC's current timer period, in CPU cycles. For tests that check which table is in use.
@@ -431,10 +576,15 @@ impl Apu {
             sample_counter: 0.0,
             samples_per_cycle: DEFAULT_SAMPLE_RATE / CPU_CLOCK_RATE,
             sample_rate: DEFAULT_SAMPLE_RATE,
+            clock_rate: CPU_CLOCK_RATE,
             sample_accumulator: 0.0,
             accumulated_cycles: 0,
 
             frame_counter: FrameCounter::new(),
+            expansion: 0.0,
+            expansion_chip: None,
+            cartridge: None,
+            channel_mutes: [false; 5],
         }
     }
 
@@ -449,7 +599,7 @@ impl Apu {
         }
 
         self.sample_rate = sample_rate;
-        self.samples_per_cycle = sample_rate / CPU_CLOCK_RATE;
+        self.samples_per_cycle = sample_rate / self.clock_rate;
         self.filter = OutputFilter::new(sample_rate as f32);
         self.sample_counter = 0.0;
         self.sample_accumulator = 0.0;
@@ -616,13 +766,36 @@ impl Apu {
     /// Kept separate from the resampling in [`Apu::generate_sample`] so it can be tested against
     /// the reference formula without involving any timing.
     fn mix(&self) -> f32 {
+        let unless_muted = |channel: Channel, output: u8| if self.channel_mutes[channel as usize] { 0 } else { output };
         self.mixer.mix(
-            self.pulse1.output(),
-            self.pulse2.output(),
-            self.triangle.output(),
-            self.noise.output(),
-            self.dmc.output(),
-        )
+            unless_muted(Channel::Pulse1, self.pulse1.output()),
+            unless_muted(Channel::Pulse2, self.pulse2.output()),
+            unless_muted(Channel::Triangle, self.triangle.output()),
+            unless_muted(Channel::Noise, self.noise.output()),
+            unless_muted(Channel::Dmc, self.dmc.output()),
+        ) + self.mixer.expansion(self.expansion_chip, self.expansion)
+    }
+
+    /// Leave a channel out of the mix, or put it back.
+    ///
+    /// Not the same as clearing its bit in `$4015`, which the program sees and can set again — a
+    /// music driver does so on every note. This is after the channel, where the program cannot
+    /// reach, so the channel goes on running and comes back in step.
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.channel_mutes[channel as usize] = muted;
+    }
+
+    pub fn channel_muted(&self, channel: Channel) -> bool {
+        self.channel_mutes[channel as usize]
+    }
+
+    /// Set the cartridge's sound, in the units of [`Apu::mix`], to be added to the console's.
+    ///
+    /// Cartridge audio comes back into the console on a pin of its own and is summed after the
+    /// APU's mixer, so it is added here linearly rather than fed through the APU's curves. A board
+    /// without a sound chip leaves it at zero.
+    pub fn set_expansion_audio(&mut self, level: f32) {
+        self.expansion = level;
     }
 
     /// Accumulate this cycle's mix and emit a filtered sample when one is due.
@@ -1134,6 +1307,31 @@ mod tests {
         Ok(())
     }
 
+    /// Cartridge audio takes the console's filters with it: a steady level from the cartridge is
+    /// blocked like the mixer's own offset, and a VRC7 note comes through as a tone.
+    #[test]
+    fn test_expansion_audio_goes_through_the_output_filters() {
+        let (mut apu, mut captured) = apu_with_capture();
+        let mut vrc7 = Vrc7Audio::new();
+        for (register, value) in [(0x30, 0x40), (0x10, 0x21), (0x20, 0x19)] {
+            vrc7.select(register);
+            vrc7.write(value);
+        }
+
+        for _ in 0..CPU_CLOCK_RATE as usize {
+            vrc7.tick();
+            apu.set_expansion_audio(vrc7.output() + 0.25);
+            apu.tick();
+        }
+
+        let samples = captured.samples();
+        let settled = &samples[samples.len() / 2..];
+        let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+        let peak = settled.iter().fold(0.0f32, |a, &b| a.max(b.abs()));
+        assert!(mean.abs() < 0.01, "the cartridge's steady level came through as {mean}");
+        assert!(peak > 0.05, "the note did not come through, peaking at {peak}");
+    }
+
     #[test]
     fn test_length_counter_in_apu() -> Result<()> {
         let mut apu = Apu::new();
@@ -1285,6 +1483,25 @@ mod tests {
         Ok(())
     }
 
+    /// A muted channel is out of the mix but still running, and `$4015` does not know.
+    #[test]
+    fn a_muted_channel_is_silent_without_being_disabled() -> Result<()> {
+        let mut apu = Apu::new();
+        apu.write_byte(0x4010, 0x00)?;
+        apu.write_byte(0x4011, 0x7F)?;
+        apu.write_byte(0x4015, 0x10)?;
+        let level = apu.mix();
+
+        apu.set_channel_muted(Channel::Dmc, true);
+        assert_eq!(apu.mix(), 0.0);
+        assert_eq!(apu.dmc.output(), 127);
+        assert!(apu.channel_muted(Channel::Dmc));
+
+        apu.set_channel_muted(Channel::Dmc, false);
+        assert_eq!(apu.mix(), level);
+        Ok(())
+    }
+
     #[test]
     fn test_mix_never_clips() -> Result<()> {
         let mut apu = Apu::new();
@@ -0,0 +1,351 @@
+//! Namco 163's sound: up to eight wavetable channels, all living in 128 bytes of RAM.
+//!
+//! The chip has no registers of its own for sound. Everything — the waveforms, and each channel's
+//! frequency, phase, length, waveform address and volume — is in one 128-byte RAM that the CPU
+//! reaches a byte at a time through an address port at `$F800` and a data port at `$4800`. The
+//! channels take the top of it, eight bytes each from `$40` for channel 1 to `$78` for channel 8,
+//! and the waveforms are packed as 4-bit samples into whatever the channels leave free. A game
+//! with few channels has more room for waves.
+//!
+//! There is only one adder. Every 15 CPU cycles it updates one channel — adds its frequency to its
+//! phase and looks up its sample — and the chip outputs that channel alone until the next update.
+//! The channels take turns, from channel 8 downward through however many `$7F` bits 4-6 enable,
+//! so with all eight enabled each is only updated at 15 kHz and is heard an eighth of the time.
+//!
+//! That turn-taking is not reproduced as it is. Played literally, it puts a whine at the rate the
+//! chip switches between channels, which on hardware the console's filters and the speaker mostly
+//! hide but which on a PC's sound card would come straight through. What is heard is the average
+//! of the channels, so that is what is output: each channel's most recent sample, summed and
+//! divided by how many are taking turns. More channels therefore means each is quieter, just as
+//! on a Famicom.
+
+use std::cell::Cell;
+
+use super::{mixer::Mixer, ExpansionChannel};
+
+/// CPU cycles the chip spends on each channel.
+const CYCLES_PER_CHANNEL: u8 = 15;
+
+/// The first byte of channel 1's registers; channel `n` is eight bytes on from channel `n - 1`.
+const CHANNEL_BASE: usize = 0x40;
+
+const CHANNEL_LABELS: [&str; 8] = [
+    "N163 Wave 1",
+    "N163 Wave 2",
+    "N163 Wave 3",
+    "N163 Wave 4",
+    "N163 Wave 5",
+    "N163 Wave 6",
+    "N163 Wave 7",
+    "N163 Wave 8",
+];
+
+/// Namco 163's wavetable synthesizer.
+///
+/// Owned by the mapper, which decodes the two ports and clocks it once per CPU cycle.
+#[derive(Debug)]
+pub(crate) struct N163Audio {
+    ram: [u8; 128],
+    /// `$F800`: the RAM address the data port reaches, in bits 0-6, and whether each access moves
+    /// it on by one, in bit 7. A [`Cell`] because reading the data port moves it too.
+    address: Cell<u8>,
+    /// `$E000` bit 6, which stops the chip altogether.
+    disabled: bool,
+
+    /// The channel the chip is working on, 0 for channel 1.
+    current: u8,
+    /// CPU cycles until it moves on to the next one.
+    divider: u8,
+    /// Each channel's sample at its last update: the 4-bit sample, centred, times the volume.
+    outputs: [i8; 8],
+
+    mixer: Mixer,
+}
+
+impl N163Audio {
+    pub fn new() -> Self {
+        Self {
+            ram: [0; 128],
+            address: Cell::new(0),
+            disabled: false,
+            current: 7,
+            divider: CYCLES_PER_CHANNEL,
+            outputs: [0; 8],
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// `$F800`: set the address, and whether it increments.
+    pub fn set_address(&mut self, value: u8) {
+        self.address.set(value);
+    }
+
+    /// Move the address on after an access to the data port, if the game asked for that.
+    fn advance(&self) -> usize {
+        let address = self.address.get();
+        if address & 0x80 != 0 {
+            self.address.set(0x80 | (address.wrapping_add(1) & 0x7F));
+        }
+        (address & 0x7F) as usize
+    }
+
+    /// `$4800` read.
+    pub fn read(&self) -> u8 {
+        self.ram[self.advance()]
+    }
+
+    /// `$4800` write.
+    pub fn write(&mut self, value: u8) {
+        let address = self.advance();
+        self.ram[address] = value;
+    }
+
+    /// `$E000` bit 6: silence the chip and stop it updating, or let it run.
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// How many channels are taking turns, 1 to 8, from `$7F` bits 4-6.
+    fn active_channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0x07) as usize + 1
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.divider -= 1;
+        if self.divider > 0 {
+            return;
+        }
+        self.divider = CYCLES_PER_CHANNEL;
+
+        self.update(self.current as usize);
+        let first = 8 - self.active_channels() as u8;
+        self.current = if self.current <= first { 7 } else { self.current - 1 };
+    }
+
+    /// Step channel `index` on by its frequency and take its next sample.
+    ///
+    /// The phase lives in the RAM, not in the chip, and is written back there: a game can read it,
+    /// and some restart a note by writing it.
+    fn update(&mut self, index: usize) {
+        let registers = CHANNEL_BASE + index * 8;
+        let byte = |offset: usize| self.ram[registers + offset] as u32;
+
+        let frequency = byte(0) | (byte(2) << 8) | ((byte(4) & 0x03) << 16);
+        let phase = byte(1) | (byte(3) << 8) | (byte(5) << 16);
+        // The length is in samples, a multiple of four, written as 256 minus itself.
+        let length = 256 - (byte(4) & 0xFC);
+        let phase = (phase + frequency) % (length << 16);
+
+        let sample_address = ((phase >> 16) + byte(6)) as usize & 0xFF;
+        let sample = (self.ram[sample_address >> 1] >> ((sample_address & 1) * 4)) & 0x0F;
+        let volume = (byte(7) & 0x0F) as i8;
+        self.outputs[index] = (sample as i8 - 8) * volume;
+
+        self.ram[registers + 1] = phase as u8;
+        self.ram[registers + 3] = (phase >> 8) as u8;
+        self.ram[registers + 5] = (phase >> 16) as u8;
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+        let active = self.active_channels();
+        let sum: i32 = self.outputs[8 - active..].iter().map(|&output| output as i32).sum();
+        self.mixer.n163(sum as f32 / active as f32)
+    }
+
+    /// The eight channels, and which of them are taking turns, for display beside the console's
+    /// own.
+    pub fn channels(&self) -> Vec<ExpansionChannel> {
+        let first = 8 - self.active_channels();
+        CHANNEL_LABELS
+            .iter()
+            .enumerate()
+            .map(|(index, &label)| ExpansionChannel {
+                label,
+                enabled: index >= first,
+            })
+            .collect()
+    }
+
+    /// Change how many channels take turns so that channel `index` is, or is not, among them.
+    ///
+    /// The chip has no enable for each channel, only the count in `$7F`, which always runs down
+    /// from channel 8: so enabling a channel enables all those above it, disabling one disables
+    /// all those below it, and channel 8 cannot be switched off at all.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) {
+        if index >= 8 {
+            return;
+        }
+        let active = self.active_channels();
+        let wanted = if enabled {
+            active.max(8 - index)
+        } else {
+            active.min(7 - index)
+        };
+        if wanted == 0 {
+            return;
+        }
+        self.ram[0x7F] = (self.ram[0x7F] & 0x8F) | (((wanted - 1) as u8) << 4);
+    }
+
+    /// The chip's state, appended to `state`. The RAM is all of it but the turn-taking.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&[self.address.get(), u8::from(self.disabled), self.current, self.divider]);
+        state.extend(self.outputs.iter().map(|&output| output as u8));
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        if state.len() < Self::STATE_LEN {
+            return None;
+        }
+        self.ram.copy_from_slice(&state[..128]);
+        let rest = &state[128..];
+        self.address.set(rest[0]);
+        self.disabled = rest[1] != 0;
+        self.current = rest[2] & 0x07;
+        self.divider = rest[3].clamp(1, CYCLES_PER_CHANNEL);
+        for (output, &saved) in self.outputs.iter_mut().zip(&rest[4..12]) {
+            *output = saved as i8;
+        }
+        Some(Self::STATE_LEN)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 128 + 4 + 8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poke(audio: &mut N163Audio, address: u8, values: &[u8]) {
+        audio.set_address(0x80 | address);
+        for &value in values {
+            audio.write(value);
+        }
+    }
+
+    /// A 16-sample wave at `$00` — eight bytes, a ramp from 0 to 15 — and channel 8 playing it at
+    /// `frequency` and `volume`, with `count` channels enabled.
+    fn ramp(audio: &mut N163Audio, frequency: u32, volume: u8, count: u8) {
+        poke(audio, 0x00, &[0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE]);
+        poke(
+            audio,
+            0x78,
+            &[
+                frequency as u8,
+                0,
+                (frequency >> 8) as u8,
+                0,
+                (256u32 - 16) as u8 | (frequency >> 16) as u8,
+                0,
+                0x00,
+                ((count - 1) << 4) | volume,
+            ],
+        );
+    }
+
+    fn run(audio: &mut N163Audio, cycles: usize) -> Vec<f32> {
+        (0..cycles)
+            .map(|_| {
+                audio.tick();
+                audio.output()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_data_port_reads_and_writes_ram_and_can_increment() {
+        let mut audio = N163Audio::new();
+        poke(&mut audio, 0x10, &[1, 2, 3]);
+        audio.set_address(0x11);
+        assert_eq!([audio.read(), audio.read()], [2, 2], "no increment without bit 7");
+
+        audio.set_address(0xFF);
+        audio.write(0x44);
+        assert_eq!(audio.ram[0x7F], 0x44);
+        assert_eq!(audio.address.get(), 0x80, "the address wraps within the RAM");
+    }
+
+    /// With one channel, it is updated every 15 cycles; a 16-sample wave stepped a sample at a time
+    /// therefore repeats every 240.
+    #[test]
+    fn a_channel_steps_through_its_wave() {
+        let mut audio = N163Audio::new();
+        ramp(&mut audio, 1 << 16, 15, 1);
+        run(&mut audio, 15 * 16);
+
+        let samples = run(&mut audio, 15 * 16);
+        let levels: Vec<f32> = samples.iter().skip(14).step_by(15).copied().collect();
+        assert!(levels.windows(2).take(14).all(|pair| pair[1] > pair[0]), "{levels:?}");
+        assert_eq!(run(&mut audio, 15 * 16), samples, "and starts again");
+        assert_eq!(audio.ram[0x7D], 0, "the phase is written back to RAM");
+    }
+
+    /// Enabling more channels makes each one's turn come round less often and its share of the
+    /// output smaller.
+    #[test]
+    fn more_channels_take_longer_turns_and_share_the_output() {
+        let mut alone = N163Audio::new();
+        ramp(&mut alone, 1 << 16, 15, 1);
+        let mut shared = N163Audio::new();
+        ramp(&mut shared, 1 << 16, 15, 4);
+
+        let peak = |samples: Vec<f32>| samples.into_iter().fold(0.0f32, f32::max);
+        let (alone, shared) = (peak(run(&mut alone, 15 * 64)), peak(run(&mut shared, 15 * 64)));
+        assert!(
+            (shared * 4.0 - alone).abs() < 1e-6,
+            "{alone} alone, {shared} among four"
+        );
+    }
+
+    #[test]
+    fn channels_report_and_set_the_count() {
+        let mut audio = N163Audio::new();
+        assert_eq!(audio.channels().iter().filter(|c| c.enabled).count(), 1);
+        assert!(audio.channels()[7].enabled);
+
+        audio.set_channel_enabled(5, true);
+        assert_eq!(audio.active_channels(), 3);
+        audio.set_channel_enabled(6, false);
+        assert_eq!(audio.active_channels(), 1);
+        audio.set_channel_enabled(7, false);
+        assert_eq!(audio.active_channels(), 1, "channel 8 always plays");
+    }
+
+    #[test]
+    fn disabling_silences_the_chip() {
+        let mut audio = N163Audio::new();
+        ramp(&mut audio, 1 << 16, 15, 1);
+        run(&mut audio, 100);
+        audio.set_disabled(true);
+        assert_eq!(audio.output(), 0.0);
+        let phase = audio.ram[0x7D];
+        run(&mut audio, 1000);
+        assert_eq!(audio.ram[0x7D], phase, "nor does it update");
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut audio = N163Audio::new();
+        ramp(&mut audio, 0x1_2345, 9, 3);
+        run(&mut audio, 1234);
+
+        let mut state = Vec::new();
+        audio.save(&mut state);
+        assert_eq!(state.len(), N163Audio::STATE_LEN);
+        let mut restored = N163Audio::new();
+        assert_eq!(restored.load(&state), Some(N163Audio::STATE_LEN));
+        assert_eq!(run(&mut restored, 500), run(&mut audio, 500));
+    }
+}
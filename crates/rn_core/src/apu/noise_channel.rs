@@ -151,7 +151,7 @@ impl NoiseChannel {
         // Timer period is looked up from a table based on the low 4 bits of timer_lo
         let period_index = self.timer_lo & 0x0F;
         self.timer = match self.region {
-            crate::region::Region::Ntsc => TIMER_PERIOD[period_index as usize],
+            crate::region::Region::Ntsc | crate::region::Region::Dendy => TIMER_PERIOD[period_index as usize],
             crate::region::Region::Pal => TIMER_PERIOD_PAL[period_index as usize],
         };
     }
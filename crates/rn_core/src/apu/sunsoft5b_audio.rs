@@ -0,0 +1,484 @@
+//! Sunsoft 5B's sound: a Yamaha YM2149F, the licensed twin of General Instrument's AY-3-8910.
+//!
+//! Three square-wave channels, one noise generator that any of them can mix in, and one envelope
+//! generator that any of them can take its volume from. Only *Gimmick!* uses it; every other
+//! mapper 69 game has an FME-7, which is the same chip without the sound, and never writes the
+//! sound ports.
+//!
+//! The 5B divides the CPU's clock by two before the YM2149 sees it, and the YM2149 divides by
+//! eight more, so everything here steps once every sixteen CPU cycles. A square wave toggles
+//! each time its counter reaches its period, for a frequency of `CPU / (32 * period)`; the noise
+//! shifts its register at half that rate; and the envelope walks through 32 levels, one each
+//! `period` steps.
+//!
+//! Volume is logarithmic, unlike anything else on the console: each of a channel's 16 fixed
+//! volumes is 3 dB from the next, and the envelope's 32 levels are 1.5 dB apart. Level zero is
+//! silence.
+
+use super::{mixer::Mixer, ExpansionChannel};
+
+/// CPU cycles per step of the chip's generators.
+const CYCLES_PER_STEP: u8 = 16;
+
+const CHANNEL_LABELS: [&str; 3] = ["5B Square A", "5B Square B", "5B Square C"];
+
+/// One of the three square channels.
+#[derive(Debug, Clone, Copy, Default)]
+struct Square {
+    /// Registers 0-5, a pair each: the 12-bit period.
+    period: u16,
+    counter: u16,
+    /// The square's current half: high or low.
+    high: bool,
+    /// Registers 8-A: a fixed volume in bits 0-3, or the envelope's level when bit 4 is set.
+    volume: u8,
+}
+
+impl Square {
+    fn step(&mut self) {
+        self.counter += 1;
+        if self.counter >= self.period.max(1) {
+            self.counter = 0;
+            self.high = !self.high;
+        }
+    }
+}
+
+/// The envelope generator, shared by every channel that asks for it.
+#[derive(Debug, Clone, Copy, Default)]
+struct Envelope {
+    /// Registers B-C: steps per level.
+    period: u16,
+    counter: u16,
+    /// Register D: continue, attack, alternate and hold, in bits 3 to 0.
+    shape: u8,
+    /// Where in its 32 levels the envelope is: 0 at the start of a cycle, counting up.
+    position: u8,
+    /// Whether the current cycle runs upward. Starts as the attack bit; alternate flips it.
+    rising: bool,
+    holding: bool,
+}
+
+impl Envelope {
+    /// Writing the shape restarts the envelope from the beginning.
+    fn restart(&mut self, shape: u8) {
+        self.shape = shape & 0x0F;
+        self.counter = 0;
+        self.position = 0;
+        self.rising = self.shape & 0x04 != 0;
+        self.holding = false;
+    }
+
+    fn step(&mut self) {
+        if self.holding {
+            return;
+        }
+        self.counter += 1;
+        if self.counter < self.period.max(1) {
+            return;
+        }
+        self.counter = 0;
+
+        if self.position < 31 {
+            self.position += 1;
+            return;
+        }
+
+        // The end of a 32-level cycle: stop, hold, flip, or go round again.
+        let (continues, alternate, hold) = (self.shape & 0x08 != 0, self.shape & 0x02 != 0, self.shape & 0x01 != 0);
+        if !continues {
+            // Shapes 0-7 fall to silence and stay there, whichever way they went.
+            self.rising = false;
+            self.holding = true;
+        } else if hold {
+            // Hold at the end of this cycle — or, with alternate, at the start of it.
+            if alternate {
+                self.rising = !self.rising;
+            }
+            self.holding = true;
+        } else {
+            if alternate {
+                self.rising = !self.rising;
+            }
+            self.position = 0;
+        }
+    }
+
+    /// The envelope's current level, 0 to 31.
+    fn level(&self) -> u8 {
+        match (self.holding, self.rising) {
+            // Holding at the end: the last level of the last direction.
+            (true, true) => 31,
+            (true, false) => 0,
+            (false, true) => self.position,
+            (false, false) => 31 - self.position,
+        }
+    }
+}
+
+/// Sunsoft 5B's three squares, noise and envelope, at `$C000` (register select) and `$E000`
+/// (data).
+///
+/// Owned by the mapper, which decodes the two ports and clocks it once per CPU cycle.
+#[derive(Debug)]
+pub(crate) struct Sunsoft5bAudio {
+    selected: u8,
+    squares: [Square; 3],
+    /// Register 6: the noise's 5-bit period.
+    noise_period: u8,
+    noise_counter: u8,
+    /// The 17-bit shift register behind the noise. Its low bit is the noise's output.
+    noise_shift: u32,
+    /// Noise shifts every other step, so this alternates.
+    noise_half: bool,
+    /// Register 7: bits 0-2 turn each channel's square off, bits 3-5 its noise.
+    mixer_control: u8,
+    envelope: Envelope,
+
+    /// CPU cycles until the generators next step.
+    divider: u8,
+    /// Each level's amplitude, 0 to 1: `LEVELS[0]` silent and each of the others 1.5 dB louder.
+    levels: [f32; 32],
+    mixer: Mixer,
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Self {
+        let mut levels = [0.0; 32];
+        for (level, amplitude) in levels.iter_mut().enumerate().skip(1) {
+            *amplitude = 10f32.powf(-1.5 * (31 - level) as f32 / 20.0);
+        }
+
+        Self {
+            selected: 0,
+            squares: [Square::default(); 3],
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift: 1,
+            noise_half: false,
+            // Everything off until the game turns it on.
+            mixer_control: 0x3F,
+            envelope: Envelope::default(),
+            divider: CYCLES_PER_STEP,
+            levels,
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// `$C000`: choose the register the next data write goes to.
+    ///
+    /// Only the low four bits name a register; with any of the upper four set, the chip is not
+    /// selected at all and the following data write goes nowhere.
+    pub fn select(&mut self, value: u8) {
+        self.selected = value;
+    }
+
+    /// `$E000`: write the selected register.
+    pub fn write(&mut self, value: u8) {
+        match self.selected {
+            register @ 0x00..=0x05 => {
+                let square = &mut self.squares[register as usize / 2];
+                square.period = if register & 1 == 0 {
+                    (square.period & 0x0F00) | value as u16
+                } else {
+                    (square.period & 0x00FF) | ((value as u16 & 0x0F) << 8)
+                };
+            },
+            0x06 => self.noise_period = value & 0x1F,
+            0x07 => self.mixer_control = value,
+            register @ 0x08..=0x0A => self.squares[register as usize - 8].volume = value & 0x1F,
+            0x0B => self.envelope.period = (self.envelope.period & 0xFF00) | value as u16,
+            0x0C => self.envelope.period = (self.envelope.period & 0x00FF) | ((value as u16) << 8),
+            0x0D => self.envelope.restart(value),
+            // Registers E and F are the YM2149's I/O ports, which the 5B leaves unconnected.
+            _ => {},
+        }
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        self.divider -= 1;
+        if self.divider > 0 {
+            return;
+        }
+        self.divider = CYCLES_PER_STEP;
+
+        for square in &mut self.squares {
+            square.step();
+        }
+        self.envelope.step();
+
+        self.noise_half = !self.noise_half;
+        if self.noise_half {
+            self.noise_counter += 1;
+            if self.noise_counter >= self.noise_period.max(1) {
+                self.noise_counter = 0;
+                let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+                self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+            }
+        }
+    }
+
+    /// Channel `index`'s amplitude this cycle, 0 to 1.
+    fn channel_output(&self, index: usize) -> f32 {
+        let square = &self.squares[index];
+        let tone_off = self.mixer_control & (0x01 << index) != 0;
+        let noise_off = self.mixer_control & (0x08 << index) != 0;
+        let gate = (tone_off || square.high) && (noise_off || self.noise_shift & 1 != 0);
+        if !gate {
+            return 0.0;
+        }
+
+        // A fixed volume v sits on the envelope's scale at 2v + 1, so fixed volume 15 is level 31
+        // and fixed volume 0 is silent.
+        let level = if square.volume & 0x10 != 0 {
+            self.envelope.level()
+        } else {
+            match square.volume & 0x0F {
+                0 => 0,
+                volume => volume * 2 + 1,
+            }
+        };
+        self.levels[level as usize]
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        self.mixer
+            .sunsoft5b((0..3).map(|index| self.channel_output(index)).sum())
+    }
+
+    /// The three channels, each enabled while its square or its noise is mixed in, for display
+    /// beside the console's own.
+    pub fn channels(&self) -> Vec<ExpansionChannel> {
+        CHANNEL_LABELS
+            .iter()
+            .enumerate()
+            .map(|(index, &label)| {
+                let enabled = self.mixer_control & (0x09 << index) != 0x09 << index;
+                ExpansionChannel { label, enabled }
+            })
+            .collect()
+    }
+
+    /// Switch a channel's square on or off through register 7. Off turns its noise off as well,
+    /// so the channel is silent; on leaves its noise as the game last set it.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) {
+        if index >= 3 {
+            return;
+        }
+        if enabled {
+            self.mixer_control &= !(0x01 << index);
+        } else {
+            self.mixer_control |= 0x09 << index;
+        }
+    }
+
+    /// The chip's state, appended to `state`.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        for square in &self.squares {
+            state.extend_from_slice(&square.period.to_le_bytes());
+            state.extend_from_slice(&square.counter.to_le_bytes());
+            state.extend_from_slice(&[u8::from(square.high), square.volume]);
+        }
+        let envelope = &self.envelope;
+        state.extend_from_slice(&envelope.period.to_le_bytes());
+        state.extend_from_slice(&envelope.counter.to_le_bytes());
+        state.extend_from_slice(&[
+            envelope.shape,
+            envelope.position,
+            u8::from(envelope.rising),
+            u8::from(envelope.holding),
+        ]);
+        state.extend_from_slice(&self.noise_shift.to_le_bytes());
+        state.extend_from_slice(&[
+            self.noise_period,
+            self.noise_counter,
+            u8::from(self.noise_half),
+            self.mixer_control,
+            self.selected,
+            self.divider,
+        ]);
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        if state.len() < Self::STATE_LEN {
+            return None;
+        }
+        let word = |at: usize| u16::from_le_bytes([state[at], state[at + 1]]);
+
+        for (index, square) in self.squares.iter_mut().enumerate() {
+            let at = index * 6;
+            square.period = word(at) & 0x0FFF;
+            square.counter = word(at + 2);
+            square.high = state[at + 4] != 0;
+            square.volume = state[at + 5] & 0x1F;
+        }
+        let rest = &state[18..];
+        self.envelope = Envelope {
+            period: word(18),
+            counter: word(20),
+            shape: rest[4] & 0x0F,
+            position: rest[5] & 0x1F,
+            rising: rest[6] != 0,
+            holding: rest[7] != 0,
+        };
+        self.noise_shift = u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]) & 0x1_FFFF;
+        self.noise_period = rest[12] & 0x1F;
+        self.noise_counter = rest[13];
+        self.noise_half = rest[14] != 0;
+        self.mixer_control = rest[15];
+        self.selected = rest[16];
+        self.divider = rest[17].clamp(1, CYCLES_PER_STEP);
+        Some(Self::STATE_LEN)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 3 * 6 + 8 + 4 + 6;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Sunsoft5bAudio, register: u8, value: u8) {
+        audio.select(register);
+        audio.write(value);
+    }
+
+    fn run(audio: &mut Sunsoft5bAudio, cycles: usize) -> Vec<f32> {
+        (0..cycles)
+            .map(|_| {
+                audio.tick();
+                audio.output()
+            })
+            .collect()
+    }
+
+    fn rising_edges(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|pair| pair[1] > pair[0]).count()
+    }
+
+    /// Channel A alone: square on, noise off, fixed volume 15, period `period`.
+    fn square_a(audio: &mut Sunsoft5bAudio, period: u16) {
+        write(audio, 0x00, period as u8);
+        write(audio, 0x01, (period >> 8) as u8);
+        write(audio, 0x07, 0x3E);
+        write(audio, 0x08, 0x0F);
+    }
+
+    #[test]
+    fn a_square_plays_at_cpu_over_32_times_its_period() {
+        let mut audio = Sunsoft5bAudio::new();
+        square_a(&mut audio, 100);
+        // A cycle is 3200 CPU cycles, so 32,000 hold ten.
+        assert_eq!(rising_edges(&run(&mut audio, 32_000)), 10);
+    }
+
+    /// Fixed volumes are 3 dB apart: two steps down halves the amplitude.
+    #[test]
+    fn volume_is_logarithmic() {
+        let mut audio = Sunsoft5bAudio::new();
+        square_a(&mut audio, 100);
+        let loud = run(&mut audio, 4000).into_iter().fold(0.0f32, f32::max);
+        write(&mut audio, 0x08, 0x0D);
+        let quieter = run(&mut audio, 4000).into_iter().fold(0.0f32, f32::max);
+        assert!((quieter / loud - 0.5).abs() < 0.01, "{loud} then {quieter}");
+
+        write(&mut audio, 0x08, 0x00);
+        assert!(run(&mut audio, 4000).iter().all(|&s| s == 0.0), "volume 0 is silent");
+    }
+
+    #[test]
+    fn disabled_channels_are_silent_and_the_mixer_reports_them() {
+        let mut audio = Sunsoft5bAudio::new();
+        assert!(audio.channels().iter().all(|channel| !channel.enabled));
+        square_a(&mut audio, 100);
+        assert_eq!(
+            audio.channels().iter().map(|c| c.enabled).collect::<Vec<_>>(),
+            [true, false, false]
+        );
+
+        audio.set_channel_enabled(0, false);
+        assert_eq!(audio.mixer_control & 0x09, 0x09);
+        // Tone and noise both off leaves the gate open: the channel is a steady level, which the
+        // output filters remove, and nothing audible.
+        let samples = run(&mut audio, 4000);
+        assert!(samples.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    /// Noise alone on channel B flickers irregularly rather than at a fixed period.
+    #[test]
+    fn noise_is_irregular() {
+        let mut audio = Sunsoft5bAudio::new();
+        write(&mut audio, 0x06, 0x01);
+        write(&mut audio, 0x07, 0x2F);
+        write(&mut audio, 0x09, 0x0F);
+        let samples = run(&mut audio, 32_000);
+
+        let mut runs = Vec::new();
+        let mut length = 0;
+        for pair in samples.windows(2) {
+            length += 1;
+            if pair[0] != pair[1] {
+                runs.push(length);
+                length = 0;
+            }
+        }
+        runs.sort_unstable();
+        runs.dedup();
+        assert!(runs.len() > 3, "the noise only ever changed after {runs:?} cycles");
+    }
+
+    /// Shape `$0E`, continue and alternate, rises and falls as a triangle; shape `$00` falls once
+    /// and stays silent.
+    #[test]
+    fn envelope_shapes() {
+        let mut audio = Sunsoft5bAudio::new();
+        audio.envelope.period = 1;
+        audio.envelope.restart(0x0E);
+        let levels: Vec<u8> = (0..64)
+            .map(|_| {
+                let level = audio.envelope.level();
+                audio.envelope.step();
+                level
+            })
+            .collect();
+        assert_eq!(levels[..32], (0..32).collect::<Vec<u8>>()[..]);
+        assert_eq!(levels[32..], (0..32).rev().collect::<Vec<u8>>()[..]);
+
+        audio.envelope.restart(0x00);
+        assert_eq!(audio.envelope.level(), 31);
+        for _ in 0..100 {
+            audio.envelope.step();
+        }
+        assert_eq!(audio.envelope.level(), 0);
+
+        audio.envelope.restart(0x0D);
+        for _ in 0..100 {
+            audio.envelope.step();
+        }
+        assert_eq!(audio.envelope.level(), 31, "attack and hold stays at the top");
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut audio = Sunsoft5bAudio::new();
+        square_a(&mut audio, 0x123);
+        write(&mut audio, 0x06, 0x07);
+        write(&mut audio, 0x07, 0x30);
+        write(&mut audio, 0x09, 0x10);
+        write(&mut audio, 0x0B, 0x20);
+        write(&mut audio, 0x0D, 0x0A);
+        run(&mut audio, 5555);
+
+        let mut state = Vec::new();
+        audio.save(&mut state);
+        assert_eq!(state.len(), Sunsoft5bAudio::STATE_LEN);
+        let mut restored = Sunsoft5bAudio::new();
+        assert_eq!(restored.load(&state), Some(Sunsoft5bAudio::STATE_LEN));
+        assert_eq!(run(&mut restored, 3000), run(&mut audio, 3000));
+    }
+}
@@ -0,0 +1,440 @@
+//! VRC6's sound: two pulse channels and a sawtooth.
+//!
+//! Nothing here is the APU's design. There are no envelopes, sweeps or length counters: each
+//! channel is a 12-bit period, a volume and an enable bit, and plays until told otherwise. The
+//! pulses have eight duty settings in sixteenths rather than the APU's four, plus a mode that
+//! ignores the duty and holds the output at its volume — a 4-bit DAC that games used for samples.
+//! The sawtooth is an accumulator that adds a rate every other time its timer runs out and clears
+//! itself after the sixth addition, so one period of the ramp is fourteen timer periods long.
+//!
+//! The timers run at the CPU's full rate, not halved as the APU's pulses are. `$9003` can stop all
+//! three, or speed them up by sixteen or 256 times, a test mode no game is known to use.
+//!
+//! The chip sums the three on a linear DAC of its own; the mix into the console's units is
+//! [`Mixer::vrc6`].
+
+use super::{mixer::Mixer, ExpansionChannel};
+
+/// One of VRC6's two pulse channels.
+#[derive(Debug, Clone, Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    /// High for `duty + 1` sixteenths of each period.
+    duty: u8,
+    /// Bit 7 of the control register: ignore the duty and output the volume constantly.
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.constant = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0x07;
+                self.volume = value & 0x0F;
+            },
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.set_enabled(value & 0x80 != 0);
+            },
+        }
+    }
+
+    /// Disabling silences the channel and puts the duty sequence back to its start.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.step = 0;
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.volume, self.duty, u8::from(self.constant), u8::from(self.enabled)]);
+        state.extend_from_slice(&self.period.to_le_bytes());
+        state.extend_from_slice(&self.timer.to_le_bytes());
+        state.push(self.step);
+    }
+
+    fn load(&mut self, state: &[u8]) {
+        self.volume = state[0] & 0x0F;
+        self.duty = state[1] & 0x07;
+        self.constant = state[2] != 0;
+        self.enabled = state[3] != 0;
+        self.period = u16::from_le_bytes([state[4], state[5]]) & 0x0FFF;
+        self.timer = u16::from_le_bytes([state[6], state[7]]);
+        self.step = state[8] & 0x0F;
+    }
+}
+
+/// Bytes one pulse adds to a save state.
+const PULSE_STATE_LEN: usize = 9;
+
+/// VRC6's sawtooth channel.
+#[derive(Debug, Clone, Default)]
+struct Vrc6Sawtooth {
+    /// Added to the accumulator on every second timer expiry.
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    /// Timer expiries since the ramp last restarted, 0 to 13.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.set_enabled(value & 0x80 != 0);
+            },
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.step = 0;
+            self.accumulator = 0;
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// The top five bits of the accumulator, 0..=31.
+    fn output(&self) -> u8 {
+        if self.enabled {
+            self.accumulator >> 3
+        } else {
+            0
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.rate, u8::from(self.enabled)]);
+        state.extend_from_slice(&self.period.to_le_bytes());
+        state.extend_from_slice(&self.timer.to_le_bytes());
+        state.extend_from_slice(&[self.step, self.accumulator]);
+    }
+
+    fn load(&mut self, state: &[u8]) {
+        self.rate = state[0] & 0x3F;
+        self.enabled = state[1] != 0;
+        self.period = u16::from_le_bytes([state[2], state[3]]) & 0x0FFF;
+        self.timer = u16::from_le_bytes([state[4], state[5]]);
+        self.step = state[6] % 14;
+        self.accumulator = state[7];
+    }
+}
+
+/// Bytes the sawtooth adds to a save state.
+const SAWTOOTH_STATE_LEN: usize = 8;
+
+/// The names the channels are shown under, in [`Vrc6Audio::channels`] order.
+const CHANNEL_LABELS: [&str; 3] = ["VRC6 Pulse 1", "VRC6 Pulse 2", "VRC6 Sawtooth"];
+
+/// VRC6's sound hardware, at `$9000..=$B002`.
+///
+/// Owned by the mapper, which decodes the board's address wiring and passes the chip its register
+/// as `$9000`, `$9001`, `$9002` or `$9003` and so on, then clocks it once per CPU cycle.
+#[derive(Debug)]
+pub(crate) struct Vrc6Audio {
+    pulses: [Vrc6Pulse; 2],
+    sawtooth: Vrc6Sawtooth,
+
+    /// `$9003` bit 0: stop every timer.
+    halted: bool,
+    /// `$9003` bits 1 and 2, as how far to shift each period right: 0, 4 or 8.
+    shift: u8,
+
+    mixer: Mixer,
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Self {
+        Self {
+            pulses: [Vrc6Pulse::default(), Vrc6Pulse::default()],
+            sawtooth: Vrc6Sawtooth::default(),
+            halted: false,
+            shift: 0,
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// Write one of the chip's registers, already decoded to its canonical address. Anything
+    /// outside them is ignored.
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0x9000..=0x9002 => self.pulses[0].write(register & 0x03, value),
+            0x9003 => {
+                self.halted = value & 0x01 != 0;
+                self.shift = if value & 0x04 != 0 {
+                    8
+                } else if value & 0x02 != 0 {
+                    4
+                } else {
+                    0
+                };
+            },
+            0xA000..=0xA002 => self.pulses[1].write(register & 0x03, value),
+            0xB000..=0xB002 => self.sawtooth.write(register & 0x03, value),
+            _ => {},
+        }
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        if self.halted {
+            return;
+        }
+        for pulse in &mut self.pulses {
+            pulse.clock(self.shift);
+        }
+        self.sawtooth.clock(self.shift);
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        self.mixer
+            .vrc6(self.pulses[0].output(), self.pulses[1].output(), self.sawtooth.output())
+    }
+
+    /// The three channels and whether each is enabled, for display beside the console's own.
+    pub fn channels(&self) -> Vec<ExpansionChannel> {
+        let enabled = [self.pulses[0].enabled, self.pulses[1].enabled, self.sawtooth.enabled];
+        CHANNEL_LABELS
+            .iter()
+            .zip(enabled)
+            .map(|(&label, enabled)| ExpansionChannel { label, enabled })
+            .collect()
+    }
+
+    /// Set a channel's enable bit, exactly as writing it would, leaving its period alone.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) {
+        match index {
+            0 | 1 => self.pulses[index].set_enabled(enabled),
+            2 => self.sawtooth.set_enabled(enabled),
+            _ => {},
+        }
+    }
+
+    /// The chip's state, appended to `state`.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        for pulse in &self.pulses {
+            pulse.save(state);
+        }
+        self.sawtooth.save(state);
+        state.extend_from_slice(&[u8::from(self.halted), self.shift]);
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        let len = Self::STATE_LEN;
+        if state.len() < len {
+            return None;
+        }
+        self.pulses[0].load(&state[..PULSE_STATE_LEN]);
+        self.pulses[1].load(&state[PULSE_STATE_LEN..2 * PULSE_STATE_LEN]);
+        self.sawtooth
+            .load(&state[2 * PULSE_STATE_LEN..2 * PULSE_STATE_LEN + SAWTOOTH_STATE_LEN]);
+        let rest = &state[2 * PULSE_STATE_LEN + SAWTOOTH_STATE_LEN..len];
+        self.halted = rest[0] != 0;
+        self.shift = match rest[1] {
+            4 | 8 => rest[1],
+            _ => 0,
+        };
+        Some(len)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 2 * PULSE_STATE_LEN + SAWTOOTH_STATE_LEN + 2;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Count the CPU cycles between rising edges of `level`, over a few periods.
+    fn period_of(audio: &mut Vrc6Audio, level: impl Fn(&Vrc6Audio) -> u8) -> usize {
+        let mut rises = Vec::new();
+        let mut previous = level(audio);
+        for cycle in 0..20_000 {
+            audio.tick();
+            let current = level(audio);
+            if current > previous && previous == 0 {
+                rises.push(cycle);
+            }
+            previous = current;
+            if rises.len() == 3 {
+                break;
+            }
+        }
+        assert_eq!(rises.len(), 3, "the channel never repeated");
+        rises[2] - rises[1]
+    }
+
+    #[test]
+    fn a_pulse_runs_sixteen_steps_of_its_period_at_the_cpu_rate() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x7F); // half duty, volume 15
+        audio.write(0x9001, 99);
+        audio.write(0x9002, 0x80);
+
+        assert_eq!(period_of(&mut audio, |a| a.pulses[0].output()), 16 * 100);
+    }
+
+    /// Duty `d` is high for `d + 1` of sixteen steps.
+    #[test]
+    fn duty_is_in_sixteenths() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xA000, 0x2F); // duty 2
+        audio.write(0xA001, 0);
+        audio.write(0xA002, 0x80);
+
+        let high = (0..16).filter(|_| {
+            audio.tick();
+            audio.pulses[1].output() > 0
+        });
+        assert_eq!(high.count(), 3);
+    }
+
+    #[test]
+    fn the_constant_mode_ignores_the_duty() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x89);
+        audio.write(0x9002, 0x80);
+        for _ in 0..64 {
+            audio.tick();
+            assert_eq!(audio.pulses[0].output(), 9);
+        }
+    }
+
+    /// Seven levels, six additions and a reset, one every other expiry of the timer.
+    #[test]
+    fn the_sawtooth_ramps_over_fourteen_timer_periods() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 42);
+        audio.write(0xB001, 9);
+        audio.write(0xB002, 0x80);
+
+        let mut levels = Vec::new();
+        for _ in 0..14 * 10 {
+            audio.tick();
+            let level = audio.sawtooth.output();
+            if levels.last() != Some(&level) {
+                levels.push(level);
+            }
+        }
+        assert_eq!(&levels[..8], &[0, 5, 10, 15, 21, 26, 31, 0]);
+
+        assert_eq!(period_of(&mut audio, |a| a.sawtooth.output()), 14 * 10);
+    }
+
+    #[test]
+    fn disabling_silences_and_restarts_a_channel() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 0x3F);
+        audio.write(0xB002, 0x80);
+        for _ in 0..10 {
+            audio.tick();
+        }
+        assert!(audio.sawtooth.accumulator > 0);
+
+        audio.set_channel_enabled(2, false);
+        assert_eq!(audio.sawtooth.accumulator, 0);
+        assert_eq!(audio.output(), 0.0);
+        assert!(!audio.channels()[2].enabled);
+    }
+
+    #[test]
+    fn the_frequency_control_halts_and_speeds_up_the_timers() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x7F);
+        audio.write(0x9001, 0xFF);
+        audio.write(0x9002, 0x80);
+
+        audio.write(0x9003, 0x01);
+        let before = audio.pulses[0].clone();
+        for _ in 0..1000 {
+            audio.tick();
+        }
+        assert_eq!(
+            (audio.pulses[0].timer, audio.pulses[0].step),
+            (before.timer, before.step)
+        );
+
+        audio.write(0x9003, 0x02);
+        assert_eq!(period_of(&mut audio, |a| a.pulses[0].output()), 16 * 16);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut audio = Vrc6Audio::new();
+        audio.write(0x9000, 0x35);
+        audio.write(0x9001, 0x12);
+        audio.write(0x9002, 0x83);
+        audio.write(0xB000, 0x11);
+        audio.write(0xB002, 0x81);
+        audio.write(0x9003, 0x02);
+        for _ in 0..777 {
+            audio.tick();
+        }
+
+        let mut state = Vec::new();
+        audio.save(&mut state);
+        assert_eq!(state.len(), Vrc6Audio::STATE_LEN);
+
+        let mut restored = Vrc6Audio::new();
+        assert_eq!(restored.load(&state), Some(Vrc6Audio::STATE_LEN));
+        let mut again = Vec::new();
+        restored.save(&mut again);
+        assert_eq!(again, state);
+        assert_eq!(restored.output(), audio.output());
+    }
+}
@@ -0,0 +1,817 @@
+//! VRC7's sound: a cut-down Yamaha YM2413 (OPLL), six channels of two-operator FM.
+//!
+//! Each channel is a modulator and a carrier, both sine generators whose loudness follows an
+//! envelope. The modulator's output is added to the carrier's phase, which is what turns two sine
+//! waves into brass or a bell, and the modulator can also feed back into its own phase. Timbre
+//! comes from an instrument: eight bytes of multipliers, levels, envelope rates and feedback.
+//! Fifteen are in the chip, and one more, the custom instrument, is written by the game at
+//! `$00..=$07`. Konami's chip has its own fifteen, not the YM2413's, and drops the YM2413's
+//! rhythm mode and its last three channels.
+//!
+//! The arithmetic is done the way the chip does it, in logarithms: a quarter sine stored as
+//! attenuation, envelopes and levels added to it as attenuation, and a single exponential table at
+//! the end to get back to a level. Doing it in floating point would sound nearly the same but
+//! drift from the chip's own rounding in every quiet passage.
+//!
+//! Two low-frequency oscillators are shared by every channel: a 3.7 Hz tremolo of 4.8 dB for
+//! operators with the AM bit, and a 6.4 Hz vibrato for those with VIB.
+//!
+//! The chip produces a sample every 72 cycles of its 3.58 MHz clock — every 36 CPU cycles — and
+//! holds it in between. What comes out joins the console's mix ahead of the output filters in
+//! `apu::filter`, as the board's audio does on hardware, on its way back in through the cartridge
+//! connector.
+
+use super::{mixer::Mixer, ExpansionChannel};
+
+const CHANNEL_LABELS: [&str; 6] = [
+    "VRC7 FM 1",
+    "VRC7 FM 2",
+    "VRC7 FM 3",
+    "VRC7 FM 4",
+    "VRC7 FM 5",
+    "VRC7 FM 6",
+];
+
+/// CPU cycles per sample the chip produces.
+const CYCLES_PER_SAMPLE: u8 = 36;
+
+/// The fifteen built-in instruments, in the register layout of the custom one.
+///
+/// Read from a decapped chip; Konami's set, which shares no instrument with the YM2413's.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06], // sweep
+];
+
+/// Frequency multipliers, doubled so the first, one half, is a whole number.
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scaling: how much quieter each octave's top notes are, in dB at block 7, indexed by the top
+/// four bits of the frequency number.
+const KEY_SCALE_DB: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25, 20.625, 21.0,
+];
+
+/// Vibrato offsets to the frequency number, by its top three bits and the step of the vibrato.
+const VIBRATO: [[i8; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [0, 0, 1, 0, 0, 0, -1, 0],
+    [0, 1, 2, 1, 0, -1, -2, -1],
+    [0, 1, 3, 1, 0, -1, -3, -1],
+    [0, 2, 4, 2, 0, -2, -4, -2],
+    [0, 2, 5, 2, 0, -2, -5, -2],
+    [0, 3, 6, 3, 0, -3, -6, -3],
+    [0, 3, 7, 3, 0, -3, -7, -3],
+];
+
+/// Samples per vibrato step; eight steps make a cycle of about 6.1 Hz.
+const VIBRATO_STEP: u16 = 1024;
+
+/// Samples in one cycle of the tremolo, about 3.7 Hz, and its depth in envelope steps.
+const TREMOLO_PERIOD: u16 = 13_440;
+const TREMOLO_DEPTH: u32 = 13;
+
+/// The loudest an envelope gets quiet: 127 steps of 0.375 dB, 48 dB down.
+const ENVELOPE_MAX: u32 = 127;
+
+/// Envelope positions carry sixteen bits of fraction, so slow rates can advance by less than a
+/// step a sample.
+const ENVELOPE_FRACTION: u32 = 16;
+
+/// The attenuation tables everything is computed through.
+struct Tables {
+    /// `-log2(sin)` over a quarter wave, in 1/256ths.
+    log_sin: [u16; 256],
+    /// `2^(-x/256)`, scaled to 2048 at zero.
+    power: [u16; 256],
+}
+
+impl Tables {
+    fn new() -> Self {
+        let mut log_sin = [0u16; 256];
+        for (i, entry) in log_sin.iter_mut().enumerate() {
+            let angle = (2 * i + 1) as f64 * std::f64::consts::PI / 1024.0;
+            *entry = (-angle.sin().log2() * 256.0).round() as u16;
+        }
+        let mut power = [0u16; 256];
+        for (i, entry) in power.iter_mut().enumerate() {
+            *entry = (2048.0 * 2f64.powf(-(i as f64) / 256.0)).round() as u16;
+        }
+        Self { log_sin, power }
+    }
+
+    /// One operator's output for a 10-bit `phase` at `attenuation` envelope steps, as a signed
+    /// 13-bit level. `half_sine` drops the wave's negative half, the instrument's DM and DC bits.
+    fn operator(&self, phase: u32, attenuation: u32, half_sine: bool) -> i32 {
+        let phase = phase & 0x3FF;
+        let negative = phase & 0x200 != 0;
+        if negative && half_sine {
+            return 0;
+        }
+
+        let quarter = (phase & 0xFF) as usize;
+        let quarter = if phase & 0x100 != 0 { 0xFF - quarter } else { quarter };
+        // An envelope step of 0.375 dB is sixteen of the sine table's 1/256ths of a doubling.
+        let total = self.log_sin[quarter] as u32 + (attenuation << 4);
+        if total >= 13 << 8 {
+            return 0;
+        }
+
+        let level = ((self.power[(total & 0xFF) as usize] as u32) << 2 >> (total >> 8)) as i32;
+        if negative {
+            -level
+        } else {
+            level
+        }
+    }
+}
+
+/// Where an operator's envelope is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    #[default]
+    Release,
+}
+
+impl Stage {
+    fn from_index(index: u8) -> Self {
+        match index {
+            0 => Self::Attack,
+            1 => Self::Decay,
+            2 => Self::Sustain,
+            _ => Self::Release,
+        }
+    }
+}
+
+/// One of an instrument's two operators, as the instrument's bytes describe it.
+#[derive(Debug, Clone, Copy)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Hold at the sustain level while the key is down, rather than decaying away.
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u32,
+    key_scale_level: u8,
+    half_sine: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    /// Operator `which` of `patch`: 0 the modulator, 1 the carrier.
+    fn from_patch(patch: &[u8; 8], which: usize) -> Self {
+        let flags = patch[which];
+        Self {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: MULTIPLIERS[(flags & 0x0F) as usize],
+            key_scale_level: patch[2 + which] >> 6,
+            half_sine: patch[3] & (0x08 << which) != 0,
+            attack: patch[4 + which] >> 4,
+            decay: patch[4 + which] & 0x0F,
+            sustain_level: patch[6 + which] >> 4,
+            release: patch[6 + which] & 0x0F,
+        }
+    }
+}
+
+/// One operator's running state.
+#[derive(Debug, Clone, Copy)]
+struct Operator {
+    /// Position in the wave, with nineteen bits to a cycle.
+    phase: u32,
+    stage: Stage,
+    /// Envelope attenuation in steps, with [`ENVELOPE_FRACTION`] bits of fraction.
+    envelope: u32,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            phase: 0,
+            stage: Stage::Release,
+            envelope: ENVELOPE_MAX << ENVELOPE_FRACTION,
+        }
+    }
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.stage = Stage::Release;
+    }
+
+    /// Advance the envelope one sample.
+    ///
+    /// `key_scale` is the note's rate-scaling offset and `release` the rate to release at, which
+    /// depends on the channel as well as the instrument.
+    fn clock_envelope(&mut self, patch: &OperatorPatch, key_scale: u8, release: u8) {
+        let full = ENVELOPE_MAX << ENVELOPE_FRACTION;
+        let rate = |r: u8| {
+            if r == 0 {
+                return 0;
+            }
+            let scaled = if patch.key_scale_rate {
+                key_scale
+            } else {
+                key_scale >> 2
+            };
+            let rate = (4 * r + scaled).min(63);
+            (4 + (rate & 3) as u32) << ((rate >> 2) + 1)
+        };
+
+        match self.stage {
+            Stage::Attack => {
+                if patch.attack == 15 {
+                    self.envelope = 0;
+                } else {
+                    // The attack is exponential: each step takes off an eighth of what is left.
+                    let speed = rate(patch.attack) as u64;
+                    let step = ((self.envelope as u64 >> 3) + (1 << ENVELOPE_FRACTION)) * speed;
+                    self.envelope = self.envelope.saturating_sub((step >> ENVELOPE_FRACTION) as u32);
+                }
+                if self.envelope == 0 {
+                    self.stage = Stage::Decay;
+                }
+            },
+            Stage::Decay => {
+                self.envelope = (self.envelope + rate(patch.decay)).min(full);
+                if self.envelope >= (patch.sustain_level as u32 * 8) << ENVELOPE_FRACTION {
+                    self.stage = Stage::Sustain;
+                }
+            },
+            // A sustained instrument holds here; a percussive one fades out at its release rate
+            // even with the key still down.
+            Stage::Sustain if patch.sustained => {},
+            Stage::Sustain => self.envelope = (self.envelope + rate(patch.release)).min(full),
+            Stage::Release => self.envelope = (self.envelope + rate(release)).min(full),
+        }
+    }
+
+    /// The envelope's attenuation in steps. At the bottom of the envelope the operator is cut off
+    /// altogether, not left murmuring at -48 dB.
+    fn attenuation(&self) -> u32 {
+        match self.envelope >> ENVELOPE_FRACTION {
+            ENVELOPE_MAX => ENVELOPE_MAX * 2,
+            steps => steps,
+        }
+    }
+}
+
+/// One of the six channels.
+#[derive(Debug, Clone, Copy, Default)]
+struct Channel {
+    /// The 9-bit frequency number.
+    frequency: u16,
+    block: u8,
+    key: bool,
+    /// `$2x` bit 5: release slowly on key-off, whatever the instrument says.
+    sustain: bool,
+    instrument: u8,
+    /// 3 dB steps of attenuation on the carrier.
+    volume: u8,
+
+    operators: [Operator; 2],
+    /// The modulator's last two outputs, for its feedback.
+    feedback: [i32; 2],
+}
+
+impl Channel {
+    /// The offset rate scaling adds: the block and the frequency number's top bit.
+    fn key_scale(&self) -> u8 {
+        (self.block << 1) | (self.frequency >> 8) as u8
+    }
+
+    /// How much quieter key scaling makes this note for an operator with level `ksl`.
+    fn key_scale_level(&self, ksl: u8) -> u32 {
+        if ksl == 0 {
+            return 0;
+        }
+        let db = KEY_SCALE_DB[(self.frequency >> 5) as usize] - 6.0 * (7 - self.block) as f32;
+        if db <= 0.0 {
+            return 0;
+        }
+        ((db / 0.375) as u32) >> (3 - ksl)
+    }
+}
+
+/// VRC7's FM synthesizer, at `$9010` (register select) and `$9030` (data).
+///
+/// Owned by the mapper, which decodes the two addresses and clocks it once per CPU cycle.
+pub(crate) struct Vrc7Audio {
+    /// The register file as last written: `$00..=$07` the custom instrument, then the channels'
+    /// `$10..=$15`, `$20..=$25` and `$30..=$35`. Kept whole because a save has to restore it.
+    registers: [u8; 0x40],
+    selected: u8,
+    channels: [Channel; 6],
+
+    /// CPU cycles until the next sample.
+    divider: u8,
+    tremolo_counter: u16,
+    vibrato_counter: u16,
+    vibrato_step: u8,
+    /// The last sample, as the sum of the channels' signed levels.
+    output: i32,
+    /// `$E000` bit 7 on the board: the chip is held in reset and silent.
+    held_in_reset: bool,
+
+    tables: Tables,
+    mixer: Mixer,
+}
+
+impl std::fmt::Debug for Vrc7Audio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The tables are constant, and five hundred numbers long.
+        f.debug_struct("Vrc7Audio")
+            .field("registers", &self.registers)
+            .field("channels", &self.channels)
+            .field("output", &self.output)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 0x40],
+            selected: 0,
+            channels: [Channel::default(); 6],
+            divider: CYCLES_PER_SAMPLE,
+            tremolo_counter: 0,
+            vibrato_counter: 0,
+            vibrato_step: 0,
+            output: 0,
+            held_in_reset: false,
+            tables: Tables::new(),
+            mixer: Mixer::new(),
+        }
+    }
+
+    /// `$9010`: choose the register the next data write goes to.
+    pub fn select(&mut self, register: u8) {
+        self.selected = register;
+    }
+
+    /// `$9030`: write the selected register.
+    pub fn write(&mut self, value: u8) {
+        if self.held_in_reset {
+            return;
+        }
+        let register = self.selected;
+        match register {
+            0x00..=0x07 => self.registers[register as usize] = value,
+            0x10..=0x15 | 0x20..=0x25 | 0x30..=0x35 => {
+                self.registers[register as usize] = value;
+                self.write_channel(register, value);
+            },
+            // The YM2413's rhythm and test registers, and the three channels it has and VRC7
+            // does not. Nothing answers.
+            _ => {},
+        }
+    }
+
+    fn write_channel(&mut self, register: u8, value: u8) {
+        let channel = &mut self.channels[(register & 0x0F) as usize];
+        match register >> 4 {
+            1 => channel.frequency = (channel.frequency & 0x100) | value as u16,
+            2 => {
+                channel.frequency = (channel.frequency & 0xFF) | ((value as u16 & 0x01) << 8);
+                channel.block = (value >> 1) & 0x07;
+                channel.sustain = value & 0x20 != 0;
+                let key = value & 0x10 != 0;
+                if key && !channel.key {
+                    channel.operators.iter_mut().for_each(Operator::key_on);
+                } else if !key && channel.key {
+                    channel.operators.iter_mut().for_each(Operator::key_off);
+                }
+                channel.key = key;
+            },
+            _ => {
+                channel.instrument = value >> 4;
+                channel.volume = value & 0x0F;
+            },
+        }
+    }
+
+    /// Hold the chip in reset, or let it go. Reset clears every register and silences it.
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset && !self.held_in_reset {
+            self.registers = [0; 0x40];
+            self.channels = [Channel::default(); 6];
+            self.output = 0;
+        }
+        self.held_in_reset = reset;
+    }
+
+    /// The instrument channel `index` is playing, as its eight bytes.
+    fn patch(&self, index: usize) -> [u8; 8] {
+        match self.channels[index].instrument {
+            0 => self.registers[..8].try_into().expect("eight bytes"),
+            n => PATCHES[n as usize - 1],
+        }
+    }
+
+    /// Advance one CPU cycle.
+    pub fn tick(&mut self) {
+        if self.held_in_reset {
+            return;
+        }
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = CYCLES_PER_SAMPLE;
+            self.output = self.sample();
+        }
+    }
+
+    /// Run the chip for one of its own samples.
+    fn sample(&mut self) -> i32 {
+        self.tremolo_counter = (self.tremolo_counter + 1) % TREMOLO_PERIOD;
+        self.vibrato_counter += 1;
+        if self.vibrato_counter == VIBRATO_STEP {
+            self.vibrato_counter = 0;
+            self.vibrato_step = (self.vibrato_step + 1) & 0x07;
+        }
+
+        // A triangle from nothing up to the full depth and back.
+        let half = TREMOLO_PERIOD as u32 / 2;
+        let position = self.tremolo_counter as u32;
+        let distance = if position < half {
+            position
+        } else {
+            TREMOLO_PERIOD as u32 - position
+        };
+        let tremolo = distance * TREMOLO_DEPTH / half;
+
+        (0..self.channels.len())
+            .map(|index| self.channel_sample(index, tremolo))
+            .sum()
+    }
+
+    fn channel_sample(&mut self, index: usize, tremolo: u32) -> i32 {
+        let patch = self.patch(index);
+        let operators = [
+            OperatorPatch::from_patch(&patch, 0),
+            OperatorPatch::from_patch(&patch, 1),
+        ];
+        let feedback_shift = patch[3] & 0x07;
+        let modulator_level = (patch[2] & 0x3F) as u32 * 2;
+        let vibrato_step = self.vibrato_step as usize;
+        let tables = &self.tables;
+        let channel = &mut self.channels[index];
+
+        let key_scale = channel.key_scale();
+        let key_scale_levels = operators.map(|patch| channel.key_scale_level(patch.key_scale_level));
+        let mut levels = [0u32; 2];
+        for (which, (operator, patch)) in channel.operators.iter_mut().zip(&operators).enumerate() {
+            let vibrato = if patch.vibrato {
+                VIBRATO[(channel.frequency >> 6) as usize & 0x07][vibrato_step] as i32
+            } else {
+                0
+            };
+            let frequency = (2 * channel.frequency as i32 + vibrato).max(0) as u32;
+            operator.phase = operator
+                .phase
+                .wrapping_add(((frequency * patch.multiplier) << channel.block) >> 2);
+
+            let release = if channel.sustain { 5 } else { patch.release };
+            operator.clock_envelope(patch, key_scale, release);
+
+            let fixed = if which == 0 {
+                modulator_level
+            } else {
+                channel.volume as u32 * 8
+            };
+            let tremolo = if patch.tremolo { tremolo } else { 0 };
+            levels[which] = operator.attenuation() + fixed + key_scale_levels[which] + tremolo;
+        }
+
+        let feedback = if feedback_shift == 0 {
+            0
+        } else {
+            (channel.feedback[0] + channel.feedback[1]) >> (10 - feedback_shift)
+        };
+        let modulator = tables.operator(
+            ((channel.operators[0].phase >> 9) as i32 + feedback) as u32,
+            levels[0],
+            operators[0].half_sine,
+        );
+        channel.feedback = [channel.feedback[1], modulator];
+
+        tables.operator(
+            ((channel.operators[1].phase >> 9) as i32 + (modulator >> 1)) as u32,
+            levels[1],
+            operators[1].half_sine,
+        )
+    }
+
+    /// The chip's output, in the units of the APU's mix.
+    pub fn output(&self) -> f32 {
+        self.mixer.vrc7(self.output)
+    }
+
+    /// The six channels, each enabled while its key is down, for display beside the console's own.
+    ///
+    /// FM channels have no enable bit; the key is what starts and stops a note, so it stands in.
+    pub fn channels(&self) -> Vec<ExpansionChannel> {
+        CHANNEL_LABELS
+            .iter()
+            .zip(&self.channels)
+            .map(|(&label, channel)| ExpansionChannel {
+                label,
+                enabled: channel.key,
+            })
+            .collect()
+    }
+
+    /// Press or release a channel's key, exactly as writing its `$2x` register would.
+    pub fn set_channel_enabled(&mut self, index: usize, enabled: bool) {
+        if index >= self.channels.len() || self.held_in_reset {
+            return;
+        }
+        let register = 0x20 + index as u8;
+        let value = (self.registers[register as usize] & !0x10) | if enabled { 0x10 } else { 0 };
+        self.registers[register as usize] = value;
+        self.write_channel(register, value);
+    }
+
+    /// The chip's state, appended to `state`.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&[
+            self.selected,
+            self.divider,
+            self.vibrato_step,
+            u8::from(self.held_in_reset),
+        ]);
+        state.extend_from_slice(&self.tremolo_counter.to_le_bytes());
+        state.extend_from_slice(&self.vibrato_counter.to_le_bytes());
+        state.extend_from_slice(&self.output.to_le_bytes());
+        for channel in &self.channels {
+            for operator in &channel.operators {
+                state.extend_from_slice(&operator.phase.to_le_bytes());
+                state.extend_from_slice(&operator.envelope.to_le_bytes());
+                state.push(operator.stage as u8);
+            }
+            for feedback in channel.feedback {
+                state.extend_from_slice(&feedback.to_le_bytes());
+            }
+        }
+    }
+
+    /// Restore what [`save`](Self::save) wrote, returning the bytes it used, or `None` if `state`
+    /// is too short to hold it.
+    ///
+    /// The channels' notes and instruments are not saved separately: they are replayed from the
+    /// register file, without the key-on that would restart the envelopes.
+    pub fn load(&mut self, state: &[u8]) -> Option<usize> {
+        let len = Self::STATE_LEN;
+        if state.len() < len {
+            return None;
+        }
+
+        self.registers.copy_from_slice(&state[..0x40]);
+        let rest = &state[0x40..];
+        self.selected = rest[0];
+        self.divider = rest[1].clamp(1, CYCLES_PER_SAMPLE);
+        self.vibrato_step = rest[2] & 0x07;
+        self.held_in_reset = rest[3] != 0;
+        self.tremolo_counter = u16::from_le_bytes([rest[4], rest[5]]) % TREMOLO_PERIOD;
+        self.vibrato_counter = u16::from_le_bytes([rest[6], rest[7]]) % VIBRATO_STEP;
+        self.output = i32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]);
+
+        let mut offset = 12;
+        let word = |at: usize| u32::from_le_bytes([rest[at], rest[at + 1], rest[at + 2], rest[at + 3]]);
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            let registers = &self.registers;
+            channel.frequency = registers[0x10 + index] as u16 | ((registers[0x20 + index] as u16 & 0x01) << 8);
+            channel.block = (registers[0x20 + index] >> 1) & 0x07;
+            channel.sustain = registers[0x20 + index] & 0x20 != 0;
+            channel.key = registers[0x20 + index] & 0x10 != 0;
+            channel.instrument = registers[0x30 + index] >> 4;
+            channel.volume = registers[0x30 + index] & 0x0F;
+
+            for operator in &mut channel.operators {
+                operator.phase = word(offset);
+                operator.envelope = word(offset + 4).min(ENVELOPE_MAX << ENVELOPE_FRACTION);
+                operator.stage = Stage::from_index(rest[offset + 8]);
+                offset += 9;
+            }
+            for feedback in &mut channel.feedback {
+                *feedback = word(offset) as i32;
+                offset += 4;
+            }
+        }
+        Some(len)
+    }
+
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 0x40 + 12 + 6 * (2 * 9 + 2 * 4);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(audio: &mut Vrc7Audio, register: u8, value: u8) {
+        audio.select(register);
+        audio.write(value);
+    }
+
+    /// Start channel 0 on `instrument` at volume 0, the loudest.
+    fn play(audio: &mut Vrc7Audio, instrument: u8, frequency: u16, block: u8) {
+        write(audio, 0x30, instrument << 4);
+        write(audio, 0x10, frequency as u8);
+        write(audio, 0x20, 0x10 | (block << 1) | (frequency >> 8) as u8);
+    }
+
+    /// Run for `samples` of the chip's samples, returning them.
+    fn run(audio: &mut Vrc7Audio, samples: usize) -> Vec<i32> {
+        (0..samples)
+            .map(|_| {
+                for _ in 0..CYCLES_PER_SAMPLE {
+                    audio.tick();
+                }
+                audio.output
+            })
+            .collect()
+    }
+
+    /// A custom instrument that is a plain sine: no modulation, instant attack, held.
+    fn sine_patch(audio: &mut Vrc7Audio) {
+        for (register, value) in [0x01, 0x21, 0x3F, 0x00, 0xFF, 0xF0, 0x0F, 0x0F].into_iter().enumerate() {
+            write(audio, register as u8, value);
+        }
+    }
+
+    fn upward_crossings(samples: &[i32]) -> usize {
+        samples.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count()
+    }
+
+    #[test]
+    fn the_tables_give_a_full_scale_sine() {
+        let tables = Tables::new();
+        assert_eq!(tables.operator(0x100, 0, false), 8192, "the peak, at a quarter turn");
+        assert_eq!(tables.operator(0x300, 0, false), -8192);
+        assert_eq!(tables.operator(0x300, 0, true), 0, "half-sine drops the negative half");
+        assert_eq!(tables.operator(0x100, 16, false), 8192 / 2, "sixteen steps is 6 dB");
+        assert_eq!(tables.operator(0x100, ENVELOPE_MAX * 2, false), 0);
+    }
+
+    /// A4 is frequency number 289 in block 4 at the chip's 49.7 kHz.
+    #[test]
+    fn a_note_plays_at_its_frequency() {
+        let mut audio = Vrc7Audio::new();
+        sine_patch(&mut audio);
+        play(&mut audio, 0, 289, 4);
+
+        let samples = run(&mut audio, 49_716);
+        let hz = upward_crossings(&samples) as f64;
+        assert!((hz - 438.3).abs() < 2.0, "played {hz} Hz");
+    }
+
+    #[test]
+    fn the_volume_register_attenuates_in_3_db_steps() {
+        let peak = |volume: u8| {
+            let mut audio = Vrc7Audio::new();
+            sine_patch(&mut audio);
+            play(&mut audio, 0, 289, 4);
+            write(&mut audio, 0x30, volume);
+            run(&mut audio, 2000).into_iter().max().unwrap()
+        };
+        let (loud, quieter) = (peak(0), peak(2));
+        assert!(
+            (quieter as f32 / loud as f32 - 0.5).abs() < 0.02,
+            "{loud} then {quieter}"
+        );
+    }
+
+    #[test]
+    fn key_off_releases_and_key_on_restarts() {
+        let mut audio = Vrc7Audio::new();
+        play(&mut audio, 4, 289, 4);
+        assert!(run(&mut audio, 2000).iter().any(|&s| s != 0));
+
+        write(&mut audio, 0x20, 0x08);
+        run(&mut audio, 49_716);
+        assert!(run(&mut audio, 100).iter().all(|&s| s == 0), "released to silence");
+        assert_eq!(audio.channels[0].operators[1].stage, Stage::Release);
+
+        write(&mut audio, 0x20, 0x18);
+        assert_eq!(audio.channels[0].operators[1].stage, Stage::Attack);
+        assert!(run(&mut audio, 2000).iter().any(|&s| s != 0));
+    }
+
+    /// A percussive instrument dies away with the key still held; a sustained one does not.
+    #[test]
+    fn percussive_instruments_fade_while_held() {
+        let mut audio = Vrc7Audio::new();
+        sine_patch(&mut audio);
+        write(&mut audio, 0x01, 0x01); // the carrier, now percussive
+        write(&mut audio, 0x07, 0x0A);
+        play(&mut audio, 0, 289, 4);
+        run(&mut audio, 49_716 * 2);
+        assert!(run(&mut audio, 100).iter().all(|&s| s == 0));
+
+        let mut held = Vrc7Audio::new();
+        sine_patch(&mut held);
+        write(&mut held, 0x07, 0x0A);
+        play(&mut held, 0, 289, 4);
+        run(&mut held, 49_716 * 2);
+        assert!(run(&mut held, 100).iter().any(|&s| s != 0));
+    }
+
+    /// Turning the modulator up puts harmonics into what was a sine.
+    #[test]
+    fn the_modulator_changes_the_timbre() {
+        let spectrum_of = |modulator_level: u8| {
+            let mut audio = Vrc7Audio::new();
+            sine_patch(&mut audio);
+            write(&mut audio, 0x02, modulator_level);
+            play(&mut audio, 0, 289, 4);
+            let samples = run(&mut audio, 4096);
+            // Steep steps are harmonics: a pure sine at this pitch never moves far in one sample.
+            samples.windows(2).map(|pair| (pair[1] - pair[0]).abs()).max().unwrap()
+        };
+        assert!(spectrum_of(0x00) > 2 * spectrum_of(0x3F));
+    }
+
+    #[test]
+    fn tremolo_makes_the_level_swell() {
+        let mut audio = Vrc7Audio::new();
+        sine_patch(&mut audio);
+        write(&mut audio, 0x01, 0xA1);
+        play(&mut audio, 0, 289, 4);
+
+        let peaks: Vec<i32> = run(&mut audio, 13_440)
+            .chunks(500)
+            .map(|chunk| chunk.iter().copied().max().unwrap())
+            .collect();
+        let (low, high) = (*peaks.iter().min().unwrap(), *peaks.iter().max().unwrap());
+        assert!(low < high * 3 / 4, "peaks only ran from {low} to {high}");
+    }
+
+    #[test]
+    fn reset_silences_and_clears_the_chip() {
+        let mut audio = Vrc7Audio::new();
+        play(&mut audio, 1, 289, 4);
+        run(&mut audio, 100);
+        audio.set_reset(true);
+        assert_eq!(audio.output(), 0.0);
+        write(&mut audio, 0x30, 0x10);
+        assert_eq!(audio.registers[0x30], 0, "writes are ignored while held");
+        audio.set_reset(false);
+        assert!(run(&mut audio, 100).iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let mut audio = Vrc7Audio::new();
+        sine_patch(&mut audio);
+        play(&mut audio, 7, 300, 3);
+        write(&mut audio, 0x31, 0x02);
+        write(&mut audio, 0x11, 0x80);
+        write(&mut audio, 0x21, 0x17);
+        run(&mut audio, 777);
+
+        let mut state = Vec::new();
+        audio.save(&mut state);
+        assert_eq!(state.len(), Vrc7Audio::STATE_LEN);
+
+        let mut restored = Vrc7Audio::new();
+        assert_eq!(restored.load(&state), Some(Vrc7Audio::STATE_LEN));
+        let mut again = Vec::new();
+        restored.save(&mut again);
+        assert_eq!(again, state);
+        assert_eq!(
+            run(&mut restored, 200),
+            run(&mut audio, 200),
+            "the two play on identically"
+        );
+    }
+}
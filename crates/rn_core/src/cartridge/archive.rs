@@ -0,0 +1,331 @@
+//! ROMs inside `.zip` and `.gz` archives, which is how collections are nearly always kept.
+//!
+//! An archive is recognised by its first bytes, not its name, and opened in memory: the ROM it
+//! holds is handed on as though it had been the file, so headers, patches and the cartridge
+//! database all see the same bytes they would have unpacked. Battery saves and patches still live
+//! beside the archive, under its name.
+//!
+//! A gzip file holds one stream and so one ROM. A zip can hold anything, and only the entries
+//! whose names say they are something this emulator loads are considered: with one of those it is
+//! taken, with several the caller has to say which, and [`RomLoadError::ArchiveHasSeveral`] lists
+//! them for asking. Zip entries are read from the central directory at the end of the file, and
+//! may be stored or deflated — the two methods any tool writes for files this small.
+
+use std::{io::Read, path::Path};
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+
+use super::{database::crc32, RomLoadError};
+
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+const END_OF_DIRECTORY: u32 = 0x0605_4B50;
+const DIRECTORY_ENTRY: u32 = 0x0201_4B50;
+const LOCAL_HEADER: u32 = 0x0403_4B50;
+const END_OF_DIRECTORY_SIZE: usize = 22;
+
+/// What an entry's name has to end in to be taken for something loadable.
+const ROM_EXTENSIONS: [&str; 6] = ["nes", "fds", "nsf", "nsfe", "unf", "unif"];
+
+/// The most an archive may unpack to. Far beyond any real cartridge, and there so that a damaged
+/// or hostile archive claiming gigabytes is refused rather than allocated.
+const MAX_UNPACKED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Whether `bytes` are a zip or gzip archive rather than a ROM.
+pub fn is_archive(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZIP_MAGIC) || bytes.starts_with(&GZIP_MAGIC)
+}
+
+/// Read the file at `path`, unpacking it first if it is an archive.
+///
+/// `entry` names the zip entry to take, for an archive holding several; `None` takes the only one.
+pub fn read_rom_file(path: &Path, entry: Option<&str>) -> Result<Vec<u8>, RomLoadError> {
+    let bytes = std::fs::read(path)?;
+    if is_archive(&bytes) {
+        unpack(&bytes, entry)
+    } else {
+        Ok(bytes)
+    }
+}
+
+/// The ROM inside an archive. See [`read_rom_file`] for `entry`.
+pub fn unpack(bytes: &[u8], entry: Option<&str>) -> Result<Vec<u8>, RomLoadError> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut unpacked = Vec::new();
+        MultiGzDecoder::new(bytes)
+            .take(MAX_UNPACKED_SIZE as u64 + 1)
+            .read_to_end(&mut unpacked)
+            .map_err(|error| RomLoadError::Archive(format!("the gzip file is damaged: {error}")))?;
+        return within_limit(unpacked);
+    }
+
+    let entries = zip_entries(bytes)?;
+    let mut roms = entries.iter().filter(|found| is_rom_name(&found.name));
+    let chosen = match entry {
+        Some(name) => entries
+            .iter()
+            .find(|found| found.name == name)
+            .ok_or_else(|| RomLoadError::Archive(format!("the archive has no entry named {name}")))?,
+        None => match (roms.next(), roms.next()) {
+            (Some(only), None) => only,
+            (None, _) => {
+                return Err(RomLoadError::Archive(
+                    "the archive holds no .nes, .fds or .nsf file".to_string(),
+                ))
+            },
+            (Some(_), Some(_)) => {
+                let names = entries.iter().filter(|found| is_rom_name(&found.name));
+                return Err(RomLoadError::ArchiveHasSeveral(
+                    names.map(|found| found.name.clone()).collect(),
+                ));
+            },
+        },
+    };
+    chosen.read(bytes)
+}
+
+/// The names of the loadable entries in a zip archive, for offering a choice. Empty for gzip,
+/// which only ever holds one.
+pub fn rom_entries(bytes: &[u8]) -> Result<Vec<String>, RomLoadError> {
+    if !bytes.starts_with(&ZIP_MAGIC) {
+        return Ok(Vec::new());
+    }
+    Ok(zip_entries(bytes)?
+        .into_iter()
+        .map(|found| found.name)
+        .filter(|name| is_rom_name(name))
+        .collect())
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ROM_EXTENSIONS.iter().any(|rom| extension.eq_ignore_ascii_case(rom)))
+}
+
+fn within_limit(unpacked: Vec<u8>) -> Result<Vec<u8>, RomLoadError> {
+    if unpacked.len() > MAX_UNPACKED_SIZE {
+        return Err(too_large());
+    }
+    Ok(unpacked)
+}
+
+fn too_large() -> RomLoadError {
+    RomLoadError::Archive("the archive unpacks to more than any ROM could be".to_string())
+}
+
+fn damaged(what: &str) -> RomLoadError {
+    RomLoadError::Archive(format!("the zip file is damaged ({what})"))
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<usize> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as usize)
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// One file in a zip's central directory.
+struct ZipEntry {
+    name: String,
+    method: usize,
+    encrypted: bool,
+    crc: u32,
+    packed_size: usize,
+    size: usize,
+    local_header: usize,
+}
+
+impl ZipEntry {
+    fn read(&self, archive: &[u8]) -> Result<Vec<u8>, RomLoadError> {
+        if self.encrypted {
+            return Err(RomLoadError::Archive(format!("{} is encrypted", self.name)));
+        }
+        if self.size > MAX_UNPACKED_SIZE {
+            return Err(too_large());
+        }
+
+        // The local header repeats the name and may carry a different extra field, so the data's
+        // start has to be worked out from it rather than from the directory.
+        let header = self.local_header;
+        if u32_at(archive, header) != Some(LOCAL_HEADER) {
+            return Err(damaged("an entry's local header is missing"));
+        }
+        let name_length = u16_at(archive, header + 26).ok_or_else(|| damaged("truncated"))?;
+        let extra_length = u16_at(archive, header + 28).ok_or_else(|| damaged("truncated"))?;
+        let start = header + 30 + name_length + extra_length;
+        let packed = archive
+            .get(start..start + self.packed_size)
+            .ok_or_else(|| damaged("an entry runs past the end of the file"))?;
+
+        let data = match self.method {
+            0 => packed.to_vec(),
+            8 => {
+                let mut data = Vec::with_capacity(self.size);
+                DeflateDecoder::new(packed)
+                    .take(self.size as u64)
+                    .read_to_end(&mut data)
+                    .map_err(|error| RomLoadError::Archive(format!("{} does not inflate: {error}", self.name)))?;
+                data
+            },
+            method => {
+                return Err(RomLoadError::Archive(format!(
+                    "{} is compressed with method {method}, which this loader cannot read; \
+                     only stored and deflated entries are supported",
+                    self.name
+                )))
+            },
+        };
+        if data.len() != self.size || crc32(&[&data]) != self.crc {
+            return Err(RomLoadError::Archive(format!(
+                "{} is damaged (its checksum does not match)",
+                self.name
+            )));
+        }
+        Ok(data)
+    }
+}
+
+/// Every file in the central directory, folders left out.
+fn zip_entries(bytes: &[u8]) -> Result<Vec<ZipEntry>, RomLoadError> {
+    // The end record sits at the very end, unless the archive has a comment after it, which can
+    // be up to 64 KB long. Searched for backwards so that a comment containing the signature does
+    // not matter.
+    let last_possible = bytes
+        .len()
+        .checked_sub(END_OF_DIRECTORY_SIZE)
+        .ok_or_else(|| damaged("too short to have a directory"))?;
+    let earliest = last_possible.saturating_sub(u16::MAX as usize);
+    let end = (earliest..=last_possible)
+        .rev()
+        .find(|&at| u32_at(bytes, at) == Some(END_OF_DIRECTORY))
+        .ok_or_else(|| damaged("no directory at the end"))?;
+
+    let count = u16_at(bytes, end + 10).ok_or_else(|| damaged("truncated"))?;
+    let mut at = u32_at(bytes, end + 16).ok_or_else(|| damaged("truncated"))? as usize;
+    if at == u32::MAX as usize {
+        return Err(RomLoadError::Archive("zip64 archives are not supported".to_string()));
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        if u32_at(bytes, at) != Some(DIRECTORY_ENTRY) {
+            return Err(damaged("the directory is corrupt"));
+        }
+        let field = |offset: usize| u16_at(bytes, at + offset).ok_or_else(|| damaged("truncated"));
+        let word = |offset: usize| u32_at(bytes, at + offset).ok_or_else(|| damaged("truncated"));
+        let (name_length, extra_length, comment_length) = (field(28)?, field(30)?, field(32)?);
+        let name = bytes
+            .get(at + 46..at + 46 + name_length)
+            .ok_or_else(|| damaged("truncated"))?;
+
+        let entry = ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: field(10)?,
+            encrypted: field(8)? & 1 != 0,
+            crc: word(16)?,
+            packed_size: word(20)? as usize,
+            size: word(24)? as usize,
+            local_header: word(42)? as usize,
+        };
+        at += 46 + name_length + extra_length + comment_length;
+        if !entry.name.ends_with('/') {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::Write;
+
+    use flate2::{
+        write::{DeflateEncoder, GzEncoder},
+        Compression,
+    };
+
+    use super::*;
+
+    /// A zip archive of `files`, deflated.
+    pub(in super::super) fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let (mut archive, mut directory) = (Vec::new(), Vec::new());
+        for (name, data) in files {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            let packed = encoder.finish().unwrap();
+
+            // Fields common to both headers, from "version needed" on.
+            let mut common = vec![20, 0, 0, 0, 8, 0, 0, 0, 0, 0];
+            common.extend_from_slice(&crc32(&[data]).to_le_bytes());
+            common.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&[0, 0]);
+
+            directory.extend_from_slice(&DIRECTORY_ENTRY.to_le_bytes());
+            directory.extend_from_slice(&[20, 0]);
+            directory.extend_from_slice(&common);
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&(archive.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            archive.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            archive.extend_from_slice(&common);
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&packed);
+        }
+        let directory_start = archive.len() as u32;
+        archive.extend_from_slice(&directory);
+        archive.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&directory_start.to_le_bytes());
+        archive.extend_from_slice(&[0, 0]);
+        archive
+    }
+
+    #[test]
+    fn the_only_rom_in_a_zip_is_taken_and_other_files_are_ignored() {
+        let archive = zip(&[("readme.txt", b"hello"), ("Game (E).nes", b"NES\x1Adata")]);
+        assert!(is_archive(&archive));
+        assert_eq!(unpack(&archive, None).unwrap(), b"NES\x1Adata");
+    }
+
+    #[test]
+    fn a_zip_with_several_roms_lists_them_and_takes_the_one_named() {
+        let archive = zip(&[("a.nes", b"first"), ("b.FDS", b"second")]);
+        match unpack(&archive, None) {
+            Err(RomLoadError::ArchiveHasSeveral(names)) => assert_eq!(names, ["a.nes", "b.FDS"]),
+            other => panic!("expected a choice, got {other:?}"),
+        }
+        assert_eq!(rom_entries(&archive).unwrap(), ["a.nes", "b.FDS"]);
+        assert_eq!(unpack(&archive, Some("b.FDS")).unwrap(), b"second");
+    }
+
+    #[test]
+    fn a_zip_without_a_rom_or_with_a_damaged_one_says_so() {
+        let error = unpack(&zip(&[("readme.txt", b"hello")]), None).unwrap_err();
+        assert!(error.to_string().contains("no .nes"), "{error}");
+
+        let mut archive = zip(&[("game.nes", b"NES\x1Adata")]);
+        let directory = u32_at(&archive, archive.len() - 6).unwrap() as usize;
+        archive[directory + 16] ^= 0xFF; // the entry's CRC32
+        let error = unpack(&archive, None).unwrap_err();
+        assert!(error.to_string().contains("checksum"), "{error}");
+    }
+
+    #[test]
+    fn a_gzip_file_is_unpacked() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"NES\x1Agzipped").unwrap();
+        let archive = encoder.finish().unwrap();
+        assert!(is_archive(&archive));
+        assert_eq!(unpack(&archive, None).unwrap(), b"NES\x1Agzipped");
+    }
+}
@@ -0,0 +1,562 @@
+//! Bandai's FCG family (mappers 16, 153, 157 and 159): the boards behind the *Dragon Ball Z* and
+//! *SD Gundam* games.
+//!
+//! Sixteen registers, decoded from the low four address bits:
+//!
+//! | Register | Meaning |
+//! |---|---|
+//! | 0-7 | 1 KB CHR banks |
+//! | 8 | 16 KB PRG bank at `$8000`; `$C000` is fixed to the last |
+//! | 9 | Mirroring: vertical, horizontal, one-screen lower, one-screen upper |
+//! | A | IRQ enable, and acknowledge |
+//! | B, C | IRQ counter, low and high byte |
+//! | D | The EEPROM's clock and data lines, or work RAM enable |
+//!
+//! The IRQ counts CPU cycles down from sixteen bits and fires at zero. The chips differ in where
+//! the registers are and what a counter write means. The first ones, FCG-1 and FCG-2, sit at
+//! `$6000-$7FFF` and are written straight into the counter. The LZ93D50 that replaced them moved
+//! the registers to `$8000-$FFFF` and added a latch: `B` and `C` set it and `A` copies it into the
+//! counter, so a game can rearm the same interval without writing it again.
+//!
+//! The LZ93D50 boards save to a serial EEPROM, driven bit by bit through register `D` and read back
+//! at bit 4 of `$6000-$7FFF`. Mapper 159 has the 128-byte X24C01, mapper 16 the 256-byte 24C02,
+//! and mapper 157 — the Datach Joint ROM System, a barcode reader with game cartridges plugged
+//! into it — a 24C02 as well. The barcode reader itself is not emulated, so Datach games run
+//! until they ask for a card. Mapper 153 is the odd one: 8 KB of battery-backed work RAM instead
+//! of an EEPROM, enabled by register `D`, and CHR RAM, with the CHR bank registers' low bits
+//! choosing which 256 KB half of its 512 KB of PRG the rest of the banking looks at.
+//!
+//! Either way the board hands the system its save as its PRG RAM, so an EEPROM's contents go to
+//! the `.sav` beside the ROM and come back from it on load, as any battery-backed RAM does.
+//!
+//! Mapper 16's submapper says which chip: 4 for FCG-1/2, 5 for the LZ93D50. Without one, the
+//! registers are listened for in both places, which works because no game writes the other, and
+//! the EEPROM is assumed to be there.
+
+use super::{
+    eeprom::{Eeprom, EepromKind, EEPROM_STATE_LEN},
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+    },
+    Mirroring,
+};
+
+/// The 16 KB PRG bank.
+const PRG_WINDOW: usize = 16 * 1024;
+
+/// Mapper 153's work RAM.
+const PRG_RAM: usize = 8 * 1024;
+
+/// Bytes of state before the EEPROM's and the RAM's.
+const STATE_LEN: usize = 17;
+
+/// Which board, by how its chip is wired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FcgBoard {
+    /// FCG-1 or FCG-2: registers at `$6000`, no latch, no EEPROM.
+    Fcg,
+    /// LZ93D50, with the EEPROM it is given, if any.
+    Lz93d50 { eeprom: Option<EepromKind> },
+    /// Mapper 16 without a submapper: either chip, with a 24C02.
+    Either,
+    /// Mapper 153, an LZ93D50 with work RAM and 512 KB of PRG.
+    WorkRam,
+    /// Mapper 157, the Datach: an LZ93D50 with a 24C02 and CHR RAM.
+    Datach,
+}
+
+impl FcgBoard {
+    /// The board a header describes.
+    ///
+    /// An LZ93D50 board under mapper 16 has a 24C02 unless an NES 2.0 header says it keeps nothing.
+    pub fn from_header(mapper: u16, submapper: u8, nes2: bool, nvram: usize) -> Self {
+        match (mapper, submapper) {
+            (153, _) => Self::WorkRam,
+            (157, _) => Self::Datach,
+            (159, _) => Self::Lz93d50 {
+                eeprom: Some(EepromKind::X24c01),
+            },
+            (_, 4) => Self::Fcg,
+            (_, 5) => Self::Lz93d50 {
+                eeprom: (!nes2 || nvram > 0).then_some(EepromKind::C24c02),
+            },
+            _ => Self::Either,
+        }
+    }
+
+    fn eeprom(self) -> Option<EepromKind> {
+        match self {
+            Self::Lz93d50 { eeprom } => eeprom,
+            Self::Either | Self::Datach => Some(EepromKind::C24c02),
+            Self::Fcg | Self::WorkRam => None,
+        }
+    }
+
+    /// Whether the IRQ counter is written through a latch, as the LZ93D50's is.
+    fn has_latch(self) -> bool {
+        !matches!(self, Self::Fcg)
+    }
+
+    fn registers_at_6000(self) -> bool {
+        matches!(self, Self::Fcg | Self::Either)
+    }
+
+    fn registers_at_8000(self) -> bool {
+        !matches!(self, Self::Fcg)
+    }
+}
+
+/// Bandai FCG-1/2 and LZ93D50 (mappers 16, 153, 157, 159). See the module documentation.
+#[derive(Debug)]
+pub struct BandaiFcg {
+    board: FcgBoard,
+
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    eeprom: Option<Eeprom>,
+    /// Mapper 153's work RAM. Empty on every other board.
+    prg_ram: Vec<u8>,
+    prg_ram_enabled: bool,
+
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_pending: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+}
+
+impl BandaiFcg {
+    pub fn new(board: FcgBoard, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+        Self {
+            board,
+            chr_is_ram,
+            prg,
+            chr,
+            eeprom: board.eeprom().map(Eeprom::new),
+            prg_ram: vec![0; if board == FcgBoard::WorkRam { PRG_RAM } else { 0 }],
+            prg_ram_enabled: false,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_counter: 0,
+            irq_latch: 0,
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Mapper 153's 256 KB half of PRG, from bit 0 of any CHR bank register. It has no CHR banking,
+    /// and the registers were free to be reused.
+    fn outer_bank(&self) -> usize {
+        if self.board == FcgBoard::WorkRam {
+            (self.chr_banks.iter().fold(0, |bits, bank| bits | bank) & 0x01) as usize * 16
+        } else {
+            0
+        }
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let inner = if address < 0xC000 {
+            (self.prg_bank & 0x0F) as usize
+        } else {
+            0x0F
+        };
+        self.outer_bank() + inner
+    }
+
+    /// Whether the board's CHR is banked at all. Neither the Datach nor mapper 153 bank theirs.
+    fn banks_chr(&self) -> bool {
+        !matches!(self.board, FcgBoard::WorkRam | FcgBoard::Datach)
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        if self.banks_chr() {
+            let banks = (self.chr.len() / CHR_BANK).max(1);
+            (self.chr_banks[address as usize >> 10 & 0x07] as usize % banks) * CHR_BANK + (address as usize & 0x03FF)
+        } else {
+            address as usize & 0x1FFF
+        }
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0..=7 => self.chr_banks[register as usize] = value,
+            8 => self.prg_bank = value,
+            9 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            },
+            0xA => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_pending = false;
+                if self.board.has_latch() {
+                    self.irq_counter = self.irq_latch;
+                }
+            },
+            0xB | 0xC => {
+                let target = if self.board.has_latch() {
+                    &mut self.irq_latch
+                } else {
+                    &mut self.irq_counter
+                };
+                *target = if register == 0xB {
+                    *target & 0xFF00 | value as u16
+                } else {
+                    *target & 0x00FF | (value as u16) << 8
+                };
+            },
+            0xD => {
+                if let Some(eeprom) = self.eeprom.as_mut() {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+                self.prg_ram_enabled = value & 0x20 != 0;
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Mapper for BandaiFcg {
+    fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+        address >= 0x6000
+    }
+
+    /// At `$6000-$7FFF` only the EEPROM's data line is driven, at bit 4, or mapper 153's RAM.
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        match address {
+            0x8000.. => 0,
+            _ if self.board == FcgBoard::WorkRam && self.prg_ram_enabled => 0,
+            _ if self.eeprom.is_some() => 0xEF,
+            _ => 0xFF,
+        }
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        if address >= 0x8000 {
+            return banked(
+                &self.prg,
+                self.prg_bank_for(address),
+                PRG_WINDOW,
+                address as usize & 0x3FFF,
+            );
+        }
+        if self.board == FcgBoard::WorkRam {
+            return self.prg_ram[address as usize & (PRG_RAM - 1)];
+        }
+        self.eeprom.as_ref().map_or(0, |eeprom| u8::from(eeprom.output()) << 4)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000.. if self.board.registers_at_8000() => self.write_register(address & 0x0F, value),
+            0x6000..=0x7FFF if self.board == FcgBoard::WorkRam && self.prg_ram_enabled => {
+                self.prg_ram[address as usize & (PRG_RAM - 1)] = value;
+            },
+            0x6000..=0x7FFF if self.board.registers_at_6000() => self.write_register(address & 0x0F, value),
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address) % self.chr.len()]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let offset = self.chr_offset(address) % self.chr.len();
+            self.chr[offset] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    /// The counter fires when it is already zero as the cycle begins, and then carries on below
+    /// it. Testing after the decrement instead puts the split a cycle early, which *Famicom Jump
+    /// II* shows as a flickering line.
+    fn cpu_cycle(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let mut windows = Vec::new();
+        if self.board == FcgBoard::WorkRam {
+            windows.push(BankWindow::new(0x6000, PRG_RAM, BankMemory::Ram, 0));
+        }
+        windows.extend([0x8000, 0xC000].into_iter().map(|start| {
+            BankWindow::banked(start, PRG_WINDOW, BankMemory::Rom, self.prg_bank_for(start), self.prg.len())
+        }));
+        windows
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        if !self.banks_chr() {
+            return vec![BankWindow::new(0x0000, 8 * 1024, memory, 0)];
+        }
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| BankWindow::new(start, CHR_BANK, memory, self.chr_offset(start) % self.chr.len()))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers: Vec<_> = self
+            .chr_banks
+            .iter()
+            .enumerate()
+            .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank))
+            .collect();
+        registers.push(MapperRegister::new("PRG bank", self.prg_bank));
+        if self.board == FcgBoard::WorkRam {
+            registers.push(MapperRegister::new("RAM enable", self.prg_ram_enabled));
+        }
+        registers
+    }
+
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.irq_counter.into(),
+            latch: if self.board.has_latch() {
+                self.irq_latch
+            } else {
+                self.irq_counter
+            }
+            .into(),
+            enabled: self.irq_enabled,
+            pending: self.irq_pending,
+        })
+    }
+
+    /// The EEPROM's contents, or mapper 153's RAM: whichever the board keeps its save in.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        match &self.eeprom {
+            Some(eeprom) => Some(eeprom.data()),
+            None => (!self.prg_ram.is_empty()).then_some(&self.prg_ram[..]),
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        match &mut self.eeprom {
+            Some(eeprom) => Some(eeprom.data_mut()),
+            None => (!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..]),
+        }
+    }
+
+    /// Registers and counter, then the EEPROM's transaction and contents, or the work RAM.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.chr_banks.to_vec();
+        state.extend_from_slice(&[
+            self.prg_bank,
+            self.mirroring as u8,
+            u8::from(self.irq_enabled),
+            u8::from(self.irq_pending),
+            u8::from(self.prg_ram_enabled),
+        ]);
+        state.extend_from_slice(&self.irq_counter.to_le_bytes());
+        state.extend_from_slice(&self.irq_latch.to_le_bytes());
+        if let Some(eeprom) = &self.eeprom {
+            eeprom.save(&mut state);
+            state.extend_from_slice(eeprom.data());
+        }
+        state.extend_from_slice(&self.prg_ram);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let eeprom_len = self
+            .eeprom
+            .as_ref()
+            .map_or(0, |eeprom| EEPROM_STATE_LEN + eeprom.data().len());
+        if state.len() < STATE_LEN + eeprom_len + self.prg_ram.len() {
+            return;
+        }
+
+        self.chr_banks.copy_from_slice(&state[..8]);
+        self.prg_bank = state[8];
+        self.mirroring = Mirroring::from_index(state[9]);
+        self.irq_enabled = state[10] != 0;
+        self.irq_pending = state[11] != 0;
+        self.prg_ram_enabled = state[12] != 0;
+        self.irq_counter = u16::from_le_bytes([state[13], state[14]]);
+        self.irq_latch = u16::from_le_bytes([state[15], state[16]]);
+
+        let mut rest = &state[STATE_LEN..];
+        if let Some(eeprom) = self.eeprom.as_mut() {
+            eeprom.load(&rest[..EEPROM_STATE_LEN]);
+            let len = eeprom.data().len();
+            eeprom
+                .data_mut()
+                .copy_from_slice(&rest[EEPROM_STATE_LEN..EEPROM_STATE_LEN + len]);
+            rest = &rest[EEPROM_STATE_LEN + len..];
+        }
+        let len = self.prg_ram.len();
+        self.prg_ram.copy_from_slice(&rest[..len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(board: FcgBoard) -> BandaiFcg {
+        BandaiFcg::new(board, numbered_banks(16, PRG_WINDOW), numbered_banks(256, CHR_BANK))
+    }
+
+    #[test]
+    fn the_registers_bank_prg_and_chr_and_set_mirroring() {
+        let mut mapper = board(FcgBoard::Lz93d50 { eeprom: None });
+        mapper.write_prg(0x8008, 0x03);
+        mapper.write_prg(0x8005, 0x44);
+        mapper.write_prg(0x8009, 0x02);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xC000)), (3, 15));
+        assert_eq!(mapper.read_chr(0x1400), 0x44);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        mapper.write_prg(0x6008, 0x05);
+        assert_eq!(mapper.read_prg(0x8000), 3, "the LZ93D50 does not listen at $6000");
+        let mut fcg = board(FcgBoard::Fcg);
+        fcg.write_prg(0x6008, 0x05);
+        fcg.write_prg(0x8008, 0x06);
+        assert_eq!(fcg.read_prg(0x8000), 5, "and FCG-1/2 only listens there");
+    }
+
+    /// The LZ93D50's latch is copied in by the enable; FCG-1/2's counter is written directly.
+    #[test]
+    fn the_irq_counts_cpu_cycles_to_zero() {
+        for (chip, registers) in [(FcgBoard::Lz93d50 { eeprom: None }, 0x8000), (FcgBoard::Fcg, 0x6000)] {
+            let mut mapper = board(chip);
+            mapper.write_prg(registers | 0xB, 0x02);
+            mapper.write_prg(registers | 0xC, 0x00);
+            mapper.write_prg(registers | 0xA, 0x01);
+            mapper.cpu_cycle();
+            mapper.cpu_cycle();
+            assert!(!mapper.irq_pending(), "{chip:?}: counted 2 and 1");
+            mapper.cpu_cycle();
+            assert!(mapper.irq_pending(), "{chip:?}: fires on the cycle that finds zero");
+            mapper.write_prg(registers | 0xA, 0x00);
+            assert!(!mapper.irq_pending(), "{chip:?}: acknowledged by a write to A");
+        }
+    }
+
+    /// A byte written and read through register D and bit 4 of `$6000`, as *Dragon Ball Z* saves.
+    #[test]
+    fn the_eeprom_is_driven_through_register_d() {
+        let mut mapper = board(FcgBoard::Lz93d50 {
+            eeprom: Some(EepromKind::C24c02),
+        });
+        fn lines(mapper: &mut BandaiFcg, scl: bool, sda: bool) {
+            mapper.write_prg(0x800D, u8::from(scl) << 5 | u8::from(sda) << 6);
+        }
+        fn start(mapper: &mut BandaiFcg) {
+            for (scl, sda) in [(false, true), (true, true), (true, false), (false, false)] {
+                lines(mapper, scl, sda);
+            }
+        }
+        /// Eight bits and a released ninth for the EEPROM's acknowledge.
+        fn send(mapper: &mut BandaiFcg, byte: u8) {
+            for bit in (0..8).rev().map(|bit| byte >> bit & 1 != 0).chain([true]) {
+                lines(mapper, false, bit);
+                lines(mapper, true, bit);
+                lines(mapper, false, bit);
+            }
+        }
+
+        start(&mut mapper);
+        send(&mut mapper, 0xA0);
+        send(&mut mapper, 0x10);
+        send(&mut mapper, 0x5A);
+        assert_eq!(mapper.prg_ram().unwrap()[0x10], 0x5A, "the save is in the EEPROM");
+
+        start(&mut mapper);
+        send(&mut mapper, 0xA0);
+        send(&mut mapper, 0x10);
+        start(&mut mapper);
+        send(&mut mapper, 0xA1);
+        let mut byte = 0;
+        for _ in 0..8 {
+            mapper.write_prg(0x800D, 0x60);
+            byte = byte << 1 | (mapper.read_prg(0x6000) >> 4 & 1);
+            mapper.write_prg(0x800D, 0x40);
+        }
+        assert_eq!(byte, 0x5A);
+        assert_eq!(mapper.open_bus_mask(0x6000), 0xEF, "only bit 4 is driven");
+
+        let saved = mapper.save_state();
+        let mut other = board(FcgBoard::Lz93d50 {
+            eeprom: Some(EepromKind::C24c02),
+        });
+        other.load_state(&saved);
+        assert_eq!(other.save_state(), saved);
+    }
+
+    #[test]
+    fn mapper_153_has_work_ram_and_a_prg_half_in_the_chr_registers() {
+        let prg = (0..32u8).flat_map(|bank| vec![bank; PRG_WINDOW]).collect();
+        let mut mapper = BandaiFcg::new(FcgBoard::WorkRam, prg, Vec::new());
+        mapper.write_prg(0x8008, 0x02);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xC000)), (2, 15));
+        mapper.write_prg(0x8003, 0x01);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0xC000)), (18, 31));
+
+        mapper.write_prg(0x6123, 0x77);
+        assert_eq!(mapper.open_bus_mask(0x6123), 0xFF, "disabled");
+        mapper.write_prg(0x800D, 0x20);
+        mapper.write_prg(0x6123, 0x77);
+        assert_eq!(mapper.read_prg(0x6123), 0x77);
+        assert_eq!(mapper.prg_ram().map(<[u8]>::len), Some(PRG_RAM));
+    }
+
+    #[test]
+    fn the_header_picks_the_board() {
+        assert_eq!(FcgBoard::from_header(16, 4, true, 0), FcgBoard::Fcg);
+        assert_eq!(
+            FcgBoard::from_header(16, 5, true, 256).eeprom(),
+            Some(EepromKind::C24c02)
+        );
+        assert_eq!(FcgBoard::from_header(16, 5, true, 0).eeprom(), None);
+        assert_eq!(FcgBoard::from_header(16, 0, false, 0), FcgBoard::Either);
+        assert_eq!(
+            FcgBoard::from_header(159, 0, false, 0).eeprom(),
+            Some(EepromKind::X24c01)
+        );
+        assert_eq!(FcgBoard::from_header(153, 0, false, 0), FcgBoard::WorkRam);
+    }
+}
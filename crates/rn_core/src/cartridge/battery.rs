@@ -0,0 +1,165 @@
+//! Battery-backed save RAM, kept in a `.sav` file beside the ROM.
+//!
+//! A cartridge with a battery keeps its work RAM powered while the console is off, and that RAM
+//! is where games like Zelda keep their saved progress. The emulated equivalent is a file: read
+//! into the RAM when the cartridge is loaded, and written back whenever it might otherwise be
+//! lost. Named after the ROM with `.sav` in place of `.nes`, which is what every other emulator
+//! uses — so a save made in one can be carried to another.
+//!
+//! Boards that save by rewriting their own flash rather than RAM keep a `.flash` file instead,
+//! handled the same way; what goes in it is up to the board. A Disk System image keeps what the
+//! game has written to the disk in a `.fdsdiff` beside the image.
+//!
+//! Only written when the contents have changed since they were last read or written. Most of the
+//! time a game is not saving, and rewriting the same eight kilobytes every few seconds would be
+//! wear on someone's disk for nothing.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The `.sav` file for one cartridge.
+#[derive(Debug)]
+pub struct BatteryFile {
+    path: PathBuf,
+
+    /// Never write, only read. For test runs, where a save left behind by one run must not change
+    /// what the next one sees — the file is still loaded, so a run can *start* from a save, but
+    /// every run starts from the same one.
+    read_only: bool,
+
+    /// What the file holds now, as far as this process knows. Compared against before writing.
+    on_disk: Vec<u8>,
+}
+
+impl BatteryFile {
+    /// The save file for the ROM at `rom_path`.
+    pub fn beside(rom_path: &Path, read_only: bool) -> Self {
+        Self {
+            path: rom_path.with_extension("sav"),
+            read_only,
+            on_disk: Vec::new(),
+        }
+    }
+
+    /// The `.flash` file for a ROM whose board saves into its own flash, rather than a `.sav`.
+    ///
+    /// Kept apart from `.sav` because what is in it is not RAM: another emulator finding it under
+    /// that name would load a list of flash sectors as though it were the contents of `$6000`.
+    pub fn flash_beside(rom_path: &Path, read_only: bool) -> Self {
+        Self {
+            path: rom_path.with_extension("flash"),
+            ..Self::beside(rom_path, read_only)
+        }
+    }
+
+    /// The `.fdsdiff` file for a Famicom Disk System image: the bytes the game has rewritten on
+    /// the disk, so the image itself stays as it was dumped.
+    pub fn disk_beside(image_path: &Path, read_only: bool) -> Self {
+        Self {
+            path: image_path.with_extension("fdsdiff"),
+            ..Self::beside(image_path, read_only)
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Read the save, if there is one yet.
+    ///
+    /// A missing file is not an error: it is what every cartridge looks like the first time it is
+    /// played.
+    pub fn read(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(data) => {
+                self.on_disk = data.clone();
+                Ok(Some(data))
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Write `data` out, unless it is what the file already holds or the file is read-only.
+    ///
+    /// Returns whether anything was written. Goes through a temporary file and a rename, so a
+    /// crash or a full disk partway through leaves the previous save intact rather than half of a
+    /// new one — losing a save is bad, and replacing it with garbage is worse.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<bool> {
+        if self.read_only || data == self.on_disk.as_slice() {
+            return Ok(false);
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)?;
+
+        self.on_disk = data.to_vec();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_rom(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}.nes", std::process::id(), name));
+        let _ = fs::remove_file(path.with_extension("sav"));
+        path
+    }
+
+    #[test]
+    fn the_save_sits_beside_the_rom() {
+        let file = BatteryFile::beside(Path::new("/games/zelda.nes"), false);
+        assert_eq!(file.path(), Path::new("/games/zelda.sav"));
+        let file = BatteryFile::flash_beside(Path::new("/games/homebrew.nes"), false);
+        assert_eq!(file.path(), Path::new("/games/homebrew.flash"));
+        let file = BatteryFile::disk_beside(Path::new("/games/zelda.fds"), false);
+        assert_eq!(file.path(), Path::new("/games/zelda.fdsdiff"));
+    }
+
+    /// A first play has no save, and that is not a failure.
+    #[test]
+    fn a_missing_save_reads_as_none() {
+        let mut file = BatteryFile::beside(&scratch_rom("battery_missing"), false);
+        assert_eq!(file.read().expect("reading"), None);
+    }
+
+    #[test]
+    fn a_write_is_read_back_and_an_unchanged_one_is_skipped() {
+        let rom = scratch_rom("battery_round_trip");
+        let mut file = BatteryFile::beside(&rom, false);
+
+        assert!(file.write(&[1, 2, 3]).expect("writing"), "new contents are written");
+        assert!(!file.write(&[1, 2, 3]).expect("writing"), "the same contents are not");
+
+        let mut reopened = BatteryFile::beside(&rom, false);
+        assert_eq!(reopened.read().expect("reading"), Some(vec![1, 2, 3]));
+        assert!(!reopened.write(&[1, 2, 3]).expect("writing"), "what was just read is on disk");
+
+        let _ = fs::remove_file(file.path());
+    }
+
+    /// Read-only loads the save and never replaces it, so every test run starts from the same one.
+    #[test]
+    fn read_only_never_writes() {
+        let rom = scratch_rom("battery_read_only");
+        let mut file = BatteryFile::beside(&rom, true);
+
+        assert!(!file.write(&[9; 16]).expect("writing"));
+        assert!(!file.path().exists(), "a read-only save must not create the file");
+    }
+}
@@ -0,0 +1,417 @@
+//! A database of cartridges whose headers are wrong, and what they should have said.
+//!
+//! A header is written by whoever dumped the cartridge, and iNES gave them little room to be
+//! right: one bit for the TV system that plenty of European dumps leave clear, no submapper, and
+//! RAM sizes that are guesses. Reference emulators fix this by recognising the ROM itself, and
+//! this does the same. An entry is keyed by the CRC32 of the PRG and CHR data together — the
+//! header and any trainer left out, since those are what varies between copies of one dump — and
+//! may carry the SHA-1 as well, which must then match too.
+//!
+//! The entries live in `database.txt` beside this file, one cartridge to a line:
+//!
+//! ```text
+//! 0123ABCD region=pal mapper=4 submapper=0 mirroring=vertical battery=yes prg_nvram=8K "Name"
+//! ```
+//!
+//! Every field but the CRC32 and the name is optional, and a field that is left out keeps what
+//! the header said. `rom_test header` prints a ROM's hashes and a line to start from.
+
+use lazy_static::lazy_static;
+use log::warn;
+
+use super::{INesHeader, Mirroring, Timing};
+use crate::region::Region;
+
+const BUILT_IN: &str = include_str!("database.txt");
+
+lazy_static! {
+    static ref DATABASE: Vec<DatabaseEntry> = parse_database(BUILT_IN).unwrap_or_else(|error| {
+        warn!("the built-in cartridge database does not parse, so it is not used: {error}");
+        Vec::new()
+    });
+}
+
+/// What the database says about a cartridge. `None` leaves the header's value alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Correction {
+    pub region: Option<Region>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    /// Horizontal, vertical or four-screen: what a header can say about the board's wiring.
+    pub mirroring: Option<Mirroring>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub chr_nvram_size: Option<usize>,
+    pub battery: Option<bool>,
+}
+
+/// One cartridge in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub name: String,
+    pub correction: Correction,
+}
+
+/// A correction the loader made, for saying so: the cartridge it recognised and the fields whose
+/// values it changed. Fields the header already had right are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedCorrection {
+    pub name: String,
+    pub crc32: u32,
+    pub changed: Correction,
+}
+
+impl std::fmt::Display for AppliedCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let changed = &self.changed;
+        let mut fields = Vec::new();
+        if let Some(region) = changed.region {
+            fields.push(format!("region {region:?}"));
+        }
+        if let Some(mapper) = changed.mapper {
+            fields.push(format!("mapper {mapper}"));
+        }
+        if let Some(submapper) = changed.submapper {
+            fields.push(format!("submapper {submapper}"));
+        }
+        if let Some(mirroring) = changed.mirroring {
+            fields.push(format!("{mirroring:?} mirroring"));
+        }
+        for (label, size) in [
+            ("PRG RAM", changed.prg_ram_size),
+            ("PRG NVRAM", changed.prg_nvram_size),
+            ("CHR RAM", changed.chr_ram_size),
+            ("CHR NVRAM", changed.chr_nvram_size),
+        ] {
+            if let Some(size) = size {
+                fields.push(format!("{label} {} KB", size / 1024));
+            }
+        }
+        if let Some(battery) = changed.battery {
+            fields.push(if battery { "battery" } else { "no battery" }.to_string());
+        }
+        write!(f, "{} (CRC32 {:08X}): {}", self.name, self.crc32, fields.join(", "))
+    }
+}
+
+/// Correct `header` from the built-in database, if it knows this PRG and CHR data and disagrees
+/// with the header about anything.
+pub fn correct(header: &mut INesHeader, prg: &[u8], chr: &[u8]) -> Option<AppliedCorrection> {
+    correct_from(&DATABASE, header, prg, chr)
+}
+
+/// The built-in database's entry for this PRG and CHR data, if it has one.
+pub fn lookup(prg: &[u8], chr: &[u8]) -> Option<&'static DatabaseEntry> {
+    lookup_in(&DATABASE, prg, chr)
+}
+
+fn lookup_in<'a>(database: &'a [DatabaseEntry], prg: &[u8], chr: &[u8]) -> Option<&'a DatabaseEntry> {
+    let crc = crc32(&[prg, chr]);
+    // The SHA-1 is only worked out when an entry with the CRC32 asks for it.
+    let mut digest = None;
+    database.iter().find(|entry| {
+        entry.crc32 == crc
+            && entry
+                .sha1
+                .is_none_or(|sha1| sha1 == *digest.get_or_insert_with(|| sha1_digest(&[prg, chr])))
+    })
+}
+
+fn correct_from(
+    database: &[DatabaseEntry],
+    header: &mut INesHeader,
+    prg: &[u8],
+    chr: &[u8],
+) -> Option<AppliedCorrection> {
+    let entry = lookup_in(database, prg, chr)?;
+    let wanted = entry.correction;
+    let mut changed = Correction::default();
+
+    /// Set `field` to the database's value where that differs, and note that it did.
+    fn update<T: Copy + PartialEq>(field: &mut T, wanted: Option<T>, changed: &mut Option<T>) {
+        if let Some(value) = wanted.filter(|value| value != field) {
+            *field = value;
+            *changed = Some(value);
+        }
+    }
+
+    if let Some(region) = wanted.region.filter(|&region| region != header.region) {
+        header.region = region;
+        header.timing = match region {
+            Region::Ntsc => Timing::Ntsc,
+            Region::Pal => Timing::Pal,
+            Region::Dendy => Timing::Dendy,
+        };
+        changed.region = Some(region);
+    }
+    update(&mut header.mapper, wanted.mapper, &mut changed.mapper);
+    update(&mut header.submapper, wanted.submapper, &mut changed.submapper);
+    if let Some(mirroring) = wanted
+        .mirroring
+        .filter(|&mirroring| mirroring != header.nametable_mirroring())
+    {
+        header.four_screen = mirroring == Mirroring::FourScreen;
+        header.mirroring = mirroring == Mirroring::Vertical;
+        changed.mirroring = Some(mirroring);
+    }
+    update(&mut header.prg_ram_size, wanted.prg_ram_size, &mut changed.prg_ram_size);
+    update(
+        &mut header.prg_nvram_size,
+        wanted.prg_nvram_size,
+        &mut changed.prg_nvram_size,
+    );
+    update(&mut header.chr_ram_size, wanted.chr_ram_size, &mut changed.chr_ram_size);
+    update(
+        &mut header.chr_nvram_size,
+        wanted.chr_nvram_size,
+        &mut changed.chr_nvram_size,
+    );
+    update(&mut header.battery, wanted.battery, &mut changed.battery);
+
+    (changed != Correction::default()).then(|| AppliedCorrection {
+        name: entry.name.clone(),
+        crc32: entry.crc32,
+        changed,
+    })
+}
+
+/// Read database lines. An error names the line, since the file is edited by hand.
+pub fn parse_database(text: &str) -> Result<Vec<DatabaseEntry>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| parse_entry(line).map_err(|error| format!("line {number}: {error}")))
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Result<DatabaseEntry, String> {
+    let (fields, name) = match (line.find('"'), line.rfind('"')) {
+        (Some(open), Some(close)) if close > open => (&line[..open], &line[open + 1..close]),
+        _ => return Err("no quoted name".into()),
+    };
+    let mut fields = fields.split_whitespace();
+    let crc32 = fields
+        .next()
+        .and_then(|crc| u32::from_str_radix(crc, 16).ok())
+        .ok_or("no CRC32 at the start")?;
+
+    let mut entry = DatabaseEntry {
+        crc32,
+        sha1: None,
+        name: name.to_string(),
+        correction: Correction::default(),
+    };
+    let correction = &mut entry.correction;
+    for field in fields {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("{field:?} is not key=value"))?;
+        let bad = || format!("{value:?} is not a {key}");
+        match key {
+            "sha1" => entry.sha1 = Some(parse_sha1(value).ok_or_else(bad)?),
+            "region" => {
+                correction.region = Some(match value {
+                    "ntsc" => Region::Ntsc,
+                    "pal" => Region::Pal,
+                    "dendy" => Region::Dendy,
+                    _ => return Err(bad()),
+                })
+            },
+            "mapper" => correction.mapper = Some(value.parse().map_err(|_| bad())?),
+            "submapper" => correction.submapper = Some(value.parse().map_err(|_| bad())?),
+            "mirroring" => {
+                correction.mirroring = Some(match value {
+                    "horizontal" => Mirroring::Horizontal,
+                    "vertical" => Mirroring::Vertical,
+                    "four" => Mirroring::FourScreen,
+                    _ => return Err(bad()),
+                })
+            },
+            "prg_ram" => correction.prg_ram_size = Some(parse_size(value).ok_or_else(bad)?),
+            "prg_nvram" => correction.prg_nvram_size = Some(parse_size(value).ok_or_else(bad)?),
+            "chr_ram" => correction.chr_ram_size = Some(parse_size(value).ok_or_else(bad)?),
+            "chr_nvram" => correction.chr_nvram_size = Some(parse_size(value).ok_or_else(bad)?),
+            "battery" => {
+                correction.battery = Some(match value {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err(bad()),
+                })
+            },
+            _ => return Err(format!("unknown field {key:?}")),
+        }
+    }
+    Ok(entry)
+}
+
+/// A size in bytes, or in kilobytes with a `K`.
+fn parse_size(value: &str) -> Option<usize> {
+    match value.strip_suffix(['K', 'k']) {
+        Some(kilobytes) => kilobytes.parse::<usize>().ok()?.checked_mul(1024),
+        None => value.parse().ok(),
+    }
+}
+
+fn parse_sha1(value: &str) -> Option<[u8; 20]> {
+    if value.len() != 40 || !value.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (byte, pair) in digest.iter_mut().zip(value.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// CRC-32 as zip and the ROM databases compute it, over `parts` as if they were one.
+pub fn crc32(parts: &[&[u8]]) -> u32 {
+    !parts.iter().flat_map(|part| part.iter()).fold(!0u32, |mut crc, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+        crc
+    })
+}
+
+/// SHA-1 over `parts` as if they were one.
+pub fn sha1_digest(parts: &[&[u8]]) -> [u8; 20] {
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    let mut message: Vec<u8> = parts.concat();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((length as u64) * 8).to_be_bytes());
+
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_built_in_database_parses() {
+        parse_database(BUILT_IN).unwrap();
+    }
+
+    #[test]
+    fn hashes_match_the_published_check_values() {
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+        let hex: String = sha1_digest(&[b"ab", b"c"])
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    /// `super-mario-3-eu.nes`'s trouble: a PAL cartridge whose header says NTSC.
+    #[test]
+    fn a_recognised_cartridge_has_its_header_corrected_and_says_what_changed() {
+        let (prg, chr) = (vec![1u8; 16 * 1024], vec![2u8; 8 * 1024]);
+        let line = format!(
+            "{:08X} region=pal mapper=4 battery=yes prg_nvram=8K \"Test Cart (Europe)\"",
+            crc32(&[&prg, &chr])
+        );
+        let database = parse_database(&line).unwrap();
+
+        let mut header = INesHeader {
+            mapper: 4,
+            prg_ram_size: 8 * 1024,
+            ..INesHeader::default()
+        };
+        let applied = correct_from(&database, &mut header, &prg, &chr).expect("a correction");
+
+        assert_eq!(header.region, Region::Pal);
+        assert_eq!(header.timing, Timing::Pal);
+        assert!(header.battery);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(applied.changed.mapper, None, "the header already had the mapper right");
+        assert_eq!(
+            applied.to_string(),
+            format!(
+                "Test Cart (Europe) (CRC32 {:08X}): region Pal, PRG NVRAM 8 KB, battery",
+                applied.crc32
+            )
+        );
+
+        // Correct already, so there is nothing to report the second time.
+        assert_eq!(correct_from(&database, &mut header, &prg, &chr), None);
+    }
+
+    /// The No-Intro CRC32 of the cartridge's PRG and CHR, which is what every copy of the good
+    /// dump shares whatever its header says.
+    #[test]
+    fn the_built_in_database_knows_super_mario_bros() {
+        let entry = DATABASE
+            .iter()
+            .find(|entry| entry.crc32 == 0x3337_EC46)
+            .expect("an entry");
+        assert_eq!(entry.name, "Super Mario Bros. (World)");
+        assert_eq!(entry.correction.mapper, Some(0));
+        assert_eq!(entry.correction.mirroring, Some(Mirroring::Vertical));
+        assert_eq!(entry.correction.region, Some(Region::Ntsc));
+    }
+
+    #[test]
+    fn a_sha1_in_the_entry_must_match_as_well() {
+        let prg = vec![3u8; 16 * 1024];
+        let line = format!(
+            "{:08X} sha1={} region=pal \"Collision\"",
+            crc32(&[&prg]),
+            "00".repeat(20)
+        );
+        let database = parse_database(&line).unwrap();
+        assert_eq!(lookup_in(&database, &prg, &[]), None);
+    }
+
+    #[test]
+    fn a_bad_line_is_reported_by_number() {
+        let error = parse_database("# comment\n\n0000ABCD mirroring=diagonal \"X\"").unwrap_err();
+        assert!(error.starts_with("line 3:"), "{error}");
+    }
+}
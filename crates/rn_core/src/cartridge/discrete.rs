@@ -0,0 +1,538 @@
+//! Discrete-logic boards: mappers 11, 13, 34, 66, 71, 79 and 232.
+//!
+//! None of these has a mapper chip. Each is a latch or two from the 74-series logic family, wired
+//! so that a CPU write stores its data bits and those bits drive the upper address lines of the
+//! ROMs. What tells the boards apart is only which bits go where, and where the latch answers.
+//! So one model serves them all, and [`DiscreteBoard`] says which wiring it has.
+//!
+//! | Mapper | Board | Latch | PRG | CHR |
+//! |---|---|---|---|---|
+//! | 11 | Color Dreams | `$8000-$FFFF` | 32 KB, bits 0-1 | 8 KB, bits 4-7 |
+//! | 13 | CPROM | `$8000-$FFFF` | fixed | 4 KB at `$1000`, bits 0-1; RAM |
+//! | 34 | BNROM | `$8000-$FFFF` | 32 KB, all bits | fixed |
+//! | 34 | NINA-001 | `$7FFD-$7FFF` | 32 KB, `$7FFD` bit 0 | 4 KB each, `$7FFE` and `$7FFF` |
+//! | 66 | GxROM | `$8000-$FFFF` | 32 KB, bits 4-5 | 8 KB, bits 0-1 |
+//! | 71 | Camerica | `$C000-$FFFF` | 16 KB, bits 0-3, last fixed | fixed |
+//! | 79 | NINA-03/06 | `$4100-$5FFF` | 32 KB, bit 3 | 8 KB, bits 0-2 |
+//! | 232 | Quattro | `$8000` and `$C000` | 16 KB in a 64 KB block, last of the block fixed | fixed |
+//!
+//! Mirroring is soldered, so it is the header's — except on Camerica's *Fire Hawk* board, which
+//! picks one of the two screens from bit 4 of a write to `$9000`.
+//!
+//! The latches in ROM space have bus conflicts on every board here that Nintendo or Color Dreams
+//! built; see [`Mapper::set_bus_conflicts`]. Camerica's chips and the NINA boards' latches below
+//! `$8000` do not.
+
+use super::{
+    mapper::{banked, chr_memory, latched, resize_chr_ram, BankMemory, BankWindow, Mapper, MapperRegister},
+    Mirroring,
+};
+
+/// Bytes of state in a save.
+const STATE_LEN: usize = 6;
+
+/// The unit CHR is banked in here. Boards that switch 8 KB at a time set both halves.
+const CHR_HALF: usize = 4 * 1024;
+
+/// Which wiring a [`Discrete`] board has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscreteBoard {
+    /// Mapper 11.
+    ColorDreams,
+    /// Mapper 13, 16 KB of CHR RAM with the upper half switchable. *Videomation* alone.
+    CpRom,
+    /// Mapper 34 with CHR RAM: *Deadly Towers*, and most homebrew on 34.
+    Bnrom,
+    /// Mapper 34 with CHR ROM and 8 KB of work RAM whose last three bytes are the registers:
+    /// *Impossible Mission II*.
+    Nina001,
+    /// Mapper 66.
+    GxRom,
+    /// Mapper 71. `fire_hawk` is the board with one-screen mirroring control, submapper 1.
+    Camerica { fire_hawk: bool },
+    /// Mapper 79, American Video Entertainment's boards.
+    Nina03,
+    /// Mapper 232, Camerica's multicarts. `aladdin` is the Aladdin Deck Enhancer, submapper 1,
+    /// whose two block-select bits are wired the other way round.
+    Quattro { aladdin: bool },
+}
+
+/// A discrete-logic board (mappers 11, 13, 34, 66, 71, 79 and 232). See the module documentation.
+#[derive(Debug)]
+pub struct Discrete {
+    board: DiscreteBoard,
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    /// NINA-001's work RAM, owned here because its registers share the window.
+    prg_ram: Vec<u8>,
+
+    /// The switchable PRG bank, in whichever size the board switches.
+    prg_bank: u8,
+    /// Quattro's 64 KB block.
+    outer_bank: u8,
+    /// The 4 KB CHR banks at `$0000` and `$1000`.
+    chr_banks: [u8; 2],
+    mirroring: Mirroring,
+    /// Whether a Camerica board has been seen to control its mirroring. See [`Discrete::new`].
+    mirroring_control: bool,
+    bus_conflicts: bool,
+}
+
+impl Discrete {
+    /// Build a board with `board`'s wiring and the header's `mirroring`.
+    ///
+    /// Only *Fire Hawk* controls its mirroring, but most copies of it predate the submapper that
+    /// says so. The other Camerica games never write to `$9000-$9FFF`, though — some do write
+    /// elsewhere below `$C000` — so the first write there switches the control on.
+    pub fn new(board: DiscreteBoard, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr_ram = if board == DiscreteBoard::CpRom {
+            16 * 1024
+        } else {
+            8 * 1024
+        };
+        let chr = if chr_is_ram { vec![0; chr_ram] } else { chr };
+        let prg_ram = if board == DiscreteBoard::Nina001 {
+            vec![0; 8 * 1024]
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            board,
+            chr_is_ram,
+            prg,
+            chr,
+            prg_ram,
+            prg_bank: 0,
+            outer_bank: 0,
+            chr_banks: [0, 1],
+            mirroring,
+            mirroring_control: board == DiscreteBoard::Camerica { fire_hawk: true },
+            bus_conflicts: false,
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than its usual amount. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Build the board with bus conflicts. See [`Mapper::set_bus_conflicts`].
+    pub fn with_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.bus_conflicts = enabled;
+        self
+    }
+
+    /// Switch 8 KB of CHR, as both 4 KB halves.
+    fn set_chr_8k(&mut self, bank: u8) {
+        self.chr_banks = [bank * 2, bank * 2 + 1];
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let halves = (self.chr.len() / CHR_HALF).max(1);
+        let bank = self.chr_banks[(address as usize >> 12) & 1] as usize % halves;
+        (bank * CHR_HALF + (address as usize & 0x0FFF)) % self.chr.len()
+    }
+}
+
+impl Mapper for Discrete {
+    fn maps_cpu_address(&self, address: u16, write: bool) -> bool {
+        match self.board {
+            DiscreteBoard::Nina001 => (0x6000..0x8000).contains(&address),
+            // Decoded from A8 and the top three lines, so it repeats through `$4100-$5FFF`. Reads
+            // there find nothing.
+            DiscreteBoard::Nina03 => write && address < 0x6000 && address & 0xE100 == 0x4100,
+            _ => false,
+        }
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x8000 {
+            if self.prg_ram.is_empty() {
+                return 0;
+            }
+            return self.prg_ram[address as usize & 0x1FFF];
+        }
+
+        const BANK_16K: usize = 16 * 1024;
+        let last_16k = (self.prg.len() / BANK_16K).max(1) - 1;
+        match self.board {
+            DiscreteBoard::CpRom => banked(&self.prg, 0, 32 * 1024, address as usize & 0x7FFF),
+            DiscreteBoard::Camerica { .. } => {
+                let bank = if address < 0xC000 {
+                    self.prg_bank as usize
+                } else {
+                    last_16k
+                };
+                banked(&self.prg, bank, BANK_16K, address as usize & 0x3FFF)
+            },
+            DiscreteBoard::Quattro { .. } => {
+                let inner = if address < 0xC000 { self.prg_bank as usize } else { 3 };
+                banked(
+                    &self.prg,
+                    self.outer_bank as usize * 4 + inner,
+                    BANK_16K,
+                    address as usize & 0x3FFF,
+                )
+            },
+            _ => banked(&self.prg, self.prg_bank as usize, 32 * 1024, address as usize & 0x7FFF),
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            match self.board {
+                // The registers are the last three bytes of the RAM, which stores them too.
+                DiscreteBoard::Nina001 => {
+                    self.prg_ram[address as usize & 0x1FFF] = value;
+                    match address {
+                        0x7FFD => self.prg_bank = value & 0x01,
+                        0x7FFE => self.chr_banks[0] = value & 0x0F,
+                        0x7FFF => self.chr_banks[1] = value & 0x0F,
+                        _ => {},
+                    }
+                },
+                DiscreteBoard::Nina03 => {
+                    self.prg_bank = (value >> 3) & 0x01;
+                    self.set_chr_8k(value & 0x07);
+                },
+                _ => {},
+            }
+            return;
+        }
+
+        let value = latched(value, self.read_prg(address), self.bus_conflicts);
+        match self.board {
+            DiscreteBoard::ColorDreams => {
+                self.prg_bank = value & 0x03;
+                self.set_chr_8k(value >> 4);
+            },
+            DiscreteBoard::CpRom => self.chr_banks[1] = value & 0x03,
+            DiscreteBoard::Bnrom => self.prg_bank = value,
+            DiscreteBoard::GxRom => {
+                self.prg_bank = (value >> 4) & 0x03;
+                self.set_chr_8k(value & 0x03);
+            },
+            DiscreteBoard::Camerica { .. } => match address {
+                0x8000..=0xBFFF => {
+                    if (0x9000..0xA000).contains(&address) {
+                        self.mirroring_control = true;
+                    }
+                    if self.mirroring_control {
+                        self.mirroring = if value & 0x10 != 0 {
+                            Mirroring::SingleScreenUpper
+                        } else {
+                            Mirroring::SingleScreenLower
+                        };
+                    }
+                },
+                _ => self.prg_bank = value & 0x0F,
+            },
+            DiscreteBoard::Quattro { aladdin } => match address {
+                0x8000..=0xBFFF => {
+                    self.outer_bank = if aladdin {
+                        ((value >> 4) & 0x01) | ((value >> 2) & 0x02)
+                    } else {
+                        (value >> 3) & 0x03
+                    };
+                },
+                _ => self.prg_bank = value & 0x03,
+            },
+            DiscreteBoard::Nina001 | DiscreteBoard::Nina03 => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        const BANK_16K: usize = 16 * 1024;
+        let len = self.prg.len();
+        let rom_16k = |start, bank| BankWindow::banked(start, BANK_16K, BankMemory::Rom, bank, len);
+        let mut windows = Vec::new();
+        if !self.prg_ram.is_empty() {
+            windows.push(BankWindow::new(0x6000, self.prg_ram.len(), BankMemory::Ram, 0));
+        }
+        match self.board {
+            DiscreteBoard::CpRom => windows.push(BankWindow::banked(0x8000, 32 * 1024, BankMemory::Rom, 0, len)),
+            DiscreteBoard::Camerica { .. } => {
+                windows.push(rom_16k(0x8000, self.prg_bank as usize));
+                windows.push(rom_16k(0xC000, (len / BANK_16K).max(1) - 1));
+            },
+            DiscreteBoard::Quattro { .. } => {
+                let outer = self.outer_bank as usize * 4;
+                windows.push(rom_16k(0x8000, outer + self.prg_bank as usize));
+                windows.push(rom_16k(0xC000, outer + 3));
+            },
+            _ => windows.push(BankWindow::banked(0x8000, 32 * 1024, BankMemory::Rom, self.prg_bank as usize, len)),
+        }
+        windows
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        [0x0000, 0x1000]
+            .into_iter()
+            .map(|start| BankWindow::new(start, CHR_HALF, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("PRG bank", self.prg_bank)];
+        if matches!(self.board, DiscreteBoard::Quattro { .. }) {
+            registers.push(MapperRegister::new("Block", self.outer_bank));
+        }
+        registers.push(MapperRegister::new("CHR bank $0000", self.chr_banks[0]));
+        registers.push(MapperRegister::new("CHR bank $1000", self.chr_banks[1]));
+        registers
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        (!self.prg_ram.is_empty()).then_some(self.prg_ram.as_slice())
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        (!self.prg_ram.is_empty()).then_some(self.prg_ram.as_mut_slice())
+    }
+
+    /// The latches only. NINA-001's work RAM is always what `$6000` shows, so the save state's
+    /// copy of that window already holds it.
+    /// CHR RAM goes along with the registers: CPROM keeps 16 KB of tiles in it and BNROM 8 KB,
+    /// none of which the ROM can put back.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.prg_bank,
+            self.outer_bank,
+            self.chr_banks[0],
+            self.chr_banks[1],
+            self.mirroring as u8,
+            u8::from(self.mirroring_control),
+        ];
+        if self.chr_is_ram {
+            state.extend_from_slice(&self.chr);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let chr_ram = if self.chr_is_ram { self.chr.len() } else { 0 };
+        if state.len() < STATE_LEN + chr_ram {
+            return;
+        }
+        self.prg_bank = state[0];
+        self.outer_bank = state[1];
+        self.chr_banks = [state[2], state[3]];
+        self.mirroring = Mirroring::from_index(state[4] & 0x03);
+        self.mirroring_control = state[5] != 0;
+        if self.chr_is_ram {
+            self.chr.copy_from_slice(&state[STATE_LEN..STATE_LEN + chr_ram]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(board: DiscreteBoard, prg_16k: usize, chr_4k: usize) -> Discrete {
+        let prg = numbered_banks(prg_16k, 16 * 1024);
+        Discrete::new(board, prg, numbered_banks(chr_4k, CHR_HALF), Mirroring::Vertical)
+    }
+
+    /// The 16 KB banks at `$8000` and `$C000`, and the 4 KB CHR banks at `$0000` and `$1000`.
+    fn banks(mapper: &Discrete) -> [u8; 4] {
+        [
+            mapper.read_prg(0x8000),
+            mapper.read_prg(0xC000),
+            mapper.read_chr(0x0000),
+            mapper.read_chr(0x1000),
+        ]
+    }
+
+    /// Save, scramble and restore, and check the banks come back.
+    fn assert_round_trips(mut mapper: Discrete, scramble: impl Fn(&mut Discrete)) {
+        let saved = mapper.save_state();
+        let before = (banks(&mapper), mapper.mirroring());
+        scramble(&mut mapper);
+        mapper.load_state(&saved);
+        assert_eq!((banks(&mapper), mapper.mirroring()), before);
+        assert_eq!(mapper.save_state(), saved);
+    }
+
+    #[test]
+    fn color_dreams_switches_both_from_one_write() {
+        let mut mapper = board(DiscreteBoard::ColorDreams, 8, 32);
+        mapper.write_prg(0xC123, 0x52);
+        assert_eq!(banks(&mapper), [4, 5, 10, 11]);
+        assert_round_trips(mapper, |m| m.write_prg(0x8000, 0));
+    }
+
+    #[test]
+    fn cprom_switches_only_the_upper_half_of_its_chr_ram() {
+        let mut mapper = board(DiscreteBoard::CpRom, 2, 0);
+        mapper.write_chr(0x0000, 0xAA);
+        // Bank 0 at $1000 is the same RAM as $0000, so only the other three are filled.
+        for bank in 1..4 {
+            mapper.write_prg(0x8000, bank);
+            mapper.write_chr(0x1000, 0x10 + bank);
+        }
+        for bank in 1..4 {
+            mapper.write_prg(0xFFFF, bank | 0xFC);
+            assert_eq!(mapper.read_chr(0x1000), 0x10 + bank);
+            assert_eq!(mapper.read_chr(0x0000), 0xAA, "the lower half never moves");
+        }
+        assert_eq!(mapper.read_prg(0x8000), 0, "and neither does the program");
+        assert_round_trips(mapper, |m| m.write_prg(0x8000, 0));
+    }
+
+    /// A state brings back the tiles a game drew into CHR RAM, not only which bank shows them —
+    /// all 16 KB of CPROM's, including the banks not switched in.
+    #[test]
+    fn chr_ram_round_trips_through_a_state() {
+        for (board_kind, chr_ram) in [(DiscreteBoard::CpRom, 16 * 1024), (DiscreteBoard::Bnrom, 8 * 1024)] {
+            let mut mapper = board(board_kind, 8, 0);
+            assert_eq!(mapper.chr.len(), chr_ram, "{board_kind:?}");
+            let tiles: Vec<u8> = (0..chr_ram).map(|offset| (offset / 7) as u8).collect();
+            mapper.chr.copy_from_slice(&tiles);
+
+            let saved = mapper.save_state();
+            mapper.chr.fill(0xEE);
+            mapper.load_state(&saved);
+
+            assert!(mapper.chr == tiles, "{board_kind:?}: the CHR RAM did not come back");
+        }
+    }
+
+    #[test]
+    fn bnrom_switches_32k() {
+        let mut mapper = board(DiscreteBoard::Bnrom, 8, 0);
+        mapper.write_prg(0x8000, 2);
+        assert_eq!([mapper.read_prg(0x8000), mapper.read_prg(0xFFFF)], [4, 5]);
+        assert!(!mapper.maps_cpu_address(0x6000, true), "no work RAM of its own");
+        assert_round_trips(mapper, |m| m.write_prg(0x8000, 0));
+    }
+
+    /// NINA-001's registers are the last three bytes of its work RAM, and writing them stores the
+    /// byte as well as switching.
+    #[test]
+    fn nina001_registers_are_also_ram() {
+        let mut mapper = board(DiscreteBoard::Nina001, 4, 16);
+        assert!(mapper.maps_cpu_address(0x6000, false) && mapper.maps_cpu_address(0x7FFF, true));
+
+        mapper.write_prg(0x6000, 0x42);
+        mapper.write_prg(0x7FFD, 1);
+        mapper.write_prg(0x7FFE, 5);
+        mapper.write_prg(0x7FFF, 9);
+        assert_eq!(banks(&mapper), [2, 3, 5, 9]);
+        assert_eq!([mapper.read_prg(0x6000), mapper.read_prg(0x7FFE)], [0x42, 5]);
+        assert_eq!(mapper.prg_ram().unwrap()[0x1FFF], 9);
+
+        mapper.write_prg(0x8000, 0);
+        assert_eq!(banks(&mapper), [2, 3, 5, 9], "nothing answers above $8000");
+        assert_round_trips(mapper, |m| m.write_prg(0x7FFD, 0));
+    }
+
+    #[test]
+    fn gxrom_switches_both_from_one_write() {
+        let mut mapper = board(DiscreteBoard::GxRom, 8, 8);
+        mapper.write_prg(0x8000, 0x21);
+        assert_eq!(banks(&mapper), [4, 5, 2, 3]);
+        assert_round_trips(mapper, |m| m.write_prg(0x8000, 0));
+    }
+
+    /// The latch gets the AND of the write and the ROM byte under it, so a zero in the ROM loses
+    /// the write entirely and a partial match keeps only the bits both sides drive high.
+    #[test]
+    fn gxrom_bus_conflicts_and_the_bank_number() {
+        let mut mapper = board(DiscreteBoard::GxRom, 8, 8).with_bus_conflicts(true);
+        mapper.write_prg(0x8000, 0x23);
+        assert_eq!(banks(&mapper), [0, 1, 0, 1], "$23 & $00");
+
+        mapper.prg[0] = 0x31;
+        mapper.write_prg(0x8000, 0x23);
+        assert_eq!(banks(&mapper), [4, 5, 2, 3], "$23 & $31 is $21");
+    }
+
+    #[test]
+    fn camerica_switches_16k_below_a_fixed_last_bank() {
+        let mut mapper = board(DiscreteBoard::Camerica { fire_hawk: false }, 8, 0);
+        mapper.write_prg(0xC000, 3);
+        assert_eq!([mapper.read_prg(0x8000), mapper.read_prg(0xC000)], [3, 7]);
+
+        mapper.write_prg(0x8000, 0x10);
+        assert_eq!(
+            mapper.mirroring(),
+            Mirroring::Vertical,
+            "only $9000 turns mirroring control on"
+        );
+        assert_round_trips(mapper, |m| m.write_prg(0xC000, 0));
+    }
+
+    #[test]
+    fn fire_hawk_picks_one_screen() {
+        let mut mapper = board(DiscreteBoard::Camerica { fire_hawk: true }, 8, 0);
+        mapper.write_prg(0x8000, 0x10);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        let mut mapper = board(DiscreteBoard::Camerica { fire_hawk: false }, 8, 0);
+        mapper.write_prg(0x9000, 0x10);
+        assert_eq!(
+            mapper.mirroring(),
+            Mirroring::SingleScreenUpper,
+            "found by its first write"
+        );
+        mapper.write_prg(0x8000, 0x00);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        assert_round_trips(mapper, |m| m.write_prg(0x9000, 0x10));
+    }
+
+    #[test]
+    fn nina03_answers_only_where_a8_is_set_below_6000() {
+        let mut mapper = board(DiscreteBoard::Nina03, 4, 16);
+        assert!(mapper.maps_cpu_address(0x4100, true) && mapper.maps_cpu_address(0x5FFF, true));
+        assert!(!mapper.maps_cpu_address(0x4100, false), "write only");
+        assert!(!mapper.maps_cpu_address(0x4200, true) && !mapper.maps_cpu_address(0x6100, true));
+
+        mapper.write_prg(0x4100, 0x0B);
+        assert_eq!(banks(&mapper), [2, 3, 6, 7]);
+        mapper.write_prg(0x8000, 0x00);
+        assert_eq!(banks(&mapper), [2, 3, 6, 7], "nothing answers above $8000");
+        assert_round_trips(mapper, |m| m.write_prg(0x4100, 0));
+    }
+
+    #[test]
+    fn quattro_switches_within_a_64k_block() {
+        let mut mapper = board(DiscreteBoard::Quattro { aladdin: false }, 16, 0);
+        mapper.write_prg(0x8000, 0x10);
+        mapper.write_prg(0xC000, 0x01);
+        assert_eq!([mapper.read_prg(0x8000), mapper.read_prg(0xC000)], [9, 11]);
+        assert_round_trips(mapper, |m| m.write_prg(0x8000, 0));
+
+        let mut aladdin = board(DiscreteBoard::Quattro { aladdin: true }, 16, 0);
+        aladdin.write_prg(0x8000, 0x10);
+        assert_eq!(aladdin.read_prg(0xC000), 7, "the block bits are swapped");
+    }
+}
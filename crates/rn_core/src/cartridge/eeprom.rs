@@ -0,0 +1,382 @@
+//! The serial EEPROMs Bandai put on its FCG boards for saves: a 24C02 of 256 bytes, and the older
+//! Xicor X24C01 of 128.
+//!
+//! Both talk I2C over two lines the board drives bit by bit from a register: a clock, SCL, and a
+//! data line, SDA, that either side may pull low. A transaction starts with SDA falling while SCL
+//! is high and stops with it rising while SCL is high; between the two, data changes only while
+//! SCL is low and is sampled as it rises. Every byte is eight bits, most significant first, and a
+//! ninth clock in which the receiver pulls SDA low to acknowledge it.
+//!
+//! The two chips differ in what the first byte means. The 24C02 is standard I2C: a device-select
+//! byte (`1010` and three chip-select bits) with the read/write bit, then for a write the word
+//! address, then data. A read takes its address from the last write, which is why reading a
+//! particular byte is a write of just its address, a second start, and a read. The X24C01 predates
+//! that: its first byte is the seven-bit word address and the read/write bit, and there is no
+//! device select at all.
+//!
+//! Writes land at once rather than after the chip's few milliseconds of programming, and a write
+//! of more than a page wraps within the page as the chips do: eight bytes on the 24C02, four on
+//! the X24C01.
+
+/// Which of the two chips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EepromKind {
+    /// Xicor X24C01, 128 bytes, addressed without a device select.
+    X24c01,
+    /// 24C02, 256 bytes, standard I2C.
+    C24c02,
+}
+
+impl EepromKind {
+    fn size(self) -> usize {
+        match self {
+            Self::X24c01 => 128,
+            Self::C24c02 => 256,
+        }
+    }
+
+    fn page(self) -> u8 {
+        match self {
+            Self::X24c01 => 4,
+            Self::C24c02 => 8,
+        }
+    }
+}
+
+/// What the chip is doing with the byte now being clocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for a start condition.
+    Idle,
+    /// Receiving the 24C02's device-select byte.
+    Device,
+    /// Receiving a word address: the 24C02's second byte, or the X24C01's first.
+    Address,
+    /// Receiving bytes to store.
+    Write,
+    /// Sending bytes.
+    Read,
+}
+
+impl Phase {
+    fn from_index(index: u8) -> Self {
+        match index {
+            1 => Self::Device,
+            2 => Self::Address,
+            3 => Self::Write,
+            4 => Self::Read,
+            _ => Self::Idle,
+        }
+    }
+}
+
+/// Bytes [`Eeprom::save`] writes before the contents.
+pub(super) const EEPROM_STATE_LEN: usize = 8;
+
+/// A 24C01 or 24C02 and the two lines to it. See the module documentation.
+#[derive(Debug, Clone)]
+pub struct Eeprom {
+    kind: EepromKind,
+    data: Vec<u8>,
+
+    /// The lines as the board last drove them.
+    scl: bool,
+    sda: bool,
+    /// SDA as the chip drives it: low to acknowledge or to send a zero, high — released — otherwise.
+    output: bool,
+
+    phase: Phase,
+    /// The byte just received asked for a read, which begins after its acknowledge.
+    read_next: bool,
+    /// Rising clock edges seen in the current byte, up to nine with its acknowledge.
+    clocks: u8,
+    shift: u8,
+    address: u8,
+}
+
+impl Eeprom {
+    /// A chip as it comes from the factory, every byte erased to `$FF`.
+    pub fn new(kind: EepromKind) -> Self {
+        Self {
+            kind,
+            data: vec![0xFF; kind.size()],
+            scl: false,
+            sda: false,
+            output: true,
+            phase: Phase::Idle,
+            read_next: false,
+            clocks: 0,
+            shift: 0,
+            address: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// SDA as the chip is driving it, for the board to read back.
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    /// Set both lines, as a write to the board's control register does.
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        match (self.scl, scl) {
+            // Data changing while the clock is high is a start or a stop, never data.
+            (true, true) if self.sda && !sda => self.start(),
+            (true, true) if !self.sda && sda => {
+                self.phase = Phase::Idle;
+                self.output = true;
+            },
+            (false, true) => self.rise(sda),
+            (true, false) => self.fall(),
+            _ => {},
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn start(&mut self) {
+        self.phase = match self.kind {
+            EepromKind::X24c01 => Phase::Address,
+            EepromKind::C24c02 => Phase::Device,
+        };
+        self.read_next = false;
+        self.clocks = 0;
+        self.shift = 0;
+        self.output = true;
+    }
+
+    /// The clock rising, when whichever side is receiving samples SDA.
+    fn rise(&mut self, sda: bool) {
+        if self.phase == Phase::Idle {
+            return;
+        }
+        if self.phase != Phase::Read && self.clocks < 8 {
+            self.shift = self.shift << 1 | u8::from(sda);
+        }
+        // On the ninth clock of a read the board acknowledges, or by leaving SDA high says it
+        // wants no more.
+        if self.phase == Phase::Read && self.clocks == 8 && sda {
+            self.phase = Phase::Idle;
+        }
+        self.clocks += 1;
+    }
+
+    /// The clock falling, when whichever side is sending sets SDA for the next bit.
+    fn fall(&mut self) {
+        match (self.phase, self.clocks) {
+            (Phase::Idle, _) => self.output = true,
+            (Phase::Read, 0..=7) => self.output = self.data[self.address as usize] << self.clocks & 0x80 != 0,
+            // Let go of SDA for the board's acknowledge.
+            (Phase::Read, 8) => self.output = true,
+            (Phase::Read, _) => {
+                self.address = ((self.address as usize + 1) % self.kind.size()) as u8;
+                self.begin_byte();
+            },
+            (_, 8) => self.receive(),
+            (_, _) if self.clocks > 8 => self.begin_byte(),
+            _ => {},
+        }
+    }
+
+    /// A whole byte has arrived: act on it, and acknowledge it if the chip took it.
+    fn receive(&mut self) {
+        let byte = self.shift;
+        let mask = (self.kind.size() - 1) as u8;
+        let next = match (self.phase, self.kind) {
+            (Phase::Device, _) if byte & 0xF0 != 0xA0 => Phase::Idle,
+            (Phase::Device, _) if byte & 0x01 != 0 => Phase::Read,
+            (Phase::Device, _) => Phase::Address,
+            (Phase::Address, EepromKind::X24c01) => {
+                self.address = byte >> 1;
+                if byte & 0x01 != 0 {
+                    Phase::Read
+                } else {
+                    Phase::Write
+                }
+            },
+            (Phase::Address, EepromKind::C24c02) => {
+                self.address = byte & mask;
+                Phase::Write
+            },
+            (Phase::Write, _) => {
+                self.data[self.address as usize] = byte;
+                let page = self.kind.page();
+                self.address = self.address & !(page - 1) | self.address.wrapping_add(1) & (page - 1);
+                Phase::Write
+            },
+            (phase, _) => phase,
+        };
+        // Sending starts once the acknowledge is over: until then the ninth clock is still the
+        // chip's, and not the board's acknowledge of a byte sent to it.
+        if next == Phase::Read {
+            self.read_next = true;
+        } else {
+            self.phase = next;
+        }
+        self.output = next == Phase::Idle;
+    }
+
+    /// Start the next byte once the acknowledge clock is over: listen for it, or put its first bit
+    /// on SDA if it is the chip's to send.
+    fn begin_byte(&mut self) {
+        if self.read_next {
+            self.phase = Phase::Read;
+            self.read_next = false;
+        }
+        self.clocks = 0;
+        self.shift = 0;
+        self.output = match self.phase {
+            Phase::Read => self.data[self.address as usize] & 0x80 != 0,
+            _ => true,
+        };
+    }
+
+    /// The transaction in progress, for a save state. The contents are not included: the board
+    /// saves them with its RAM.
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            u8::from(self.scl),
+            u8::from(self.sda),
+            u8::from(self.output),
+            self.phase as u8,
+            u8::from(self.read_next),
+            self.clocks,
+            self.shift,
+            self.address,
+        ]);
+    }
+
+    /// Put back what [`save`](Self::save) wrote, `EEPROM_STATE_LEN` bytes of it.
+    pub fn load(&mut self, state: &[u8]) {
+        self.scl = state[0] != 0;
+        self.sda = state[1] != 0;
+        self.output = state[2] != 0;
+        self.phase = Phase::from_index(state[3]);
+        self.read_next = state[4] != 0;
+        self.clocks = state[5];
+        self.shift = state[6];
+        self.address = (state[7] as usize % self.kind.size()) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A board driving the two lines as a game's save routine does.
+    struct Master<'a>(&'a mut Eeprom);
+
+    impl Master<'_> {
+        fn start(&mut self) {
+            self.0.write(false, true);
+            self.0.write(true, true);
+            self.0.write(true, false);
+            self.0.write(false, false);
+        }
+
+        fn stop(&mut self) {
+            self.0.write(false, false);
+            self.0.write(true, false);
+            self.0.write(true, true);
+        }
+
+        /// Clock one bit out, and return what SDA read while the clock was high.
+        fn bit(&mut self, sda: bool) -> bool {
+            self.0.write(false, sda);
+            self.0.write(true, sda);
+            let read = self.0.output() && sda;
+            self.0.write(false, sda);
+            read
+        }
+
+        /// Send a byte and return whether it was acknowledged.
+        fn send(&mut self, byte: u8) -> bool {
+            for bit in (0..8).rev() {
+                self.bit(byte >> bit & 1 != 0);
+            }
+            !self.bit(true)
+        }
+
+        /// Read a byte, then acknowledge it or not.
+        fn receive(&mut self, ack: bool) -> u8 {
+            let byte = (0..8).fold(0, |byte, _| byte << 1 | u8::from(self.bit(true)));
+            self.bit(!ack);
+            byte
+        }
+    }
+
+    #[test]
+    fn a_24c02_writes_a_page_and_reads_it_back_at_random() {
+        let mut chip = Eeprom::new(EepromKind::C24c02);
+        let mut master = Master(&mut chip);
+
+        master.start();
+        assert!(master.send(0xA0), "device select, write");
+        assert!(master.send(0x43), "word address");
+        for byte in [0x11, 0x22, 0x33] {
+            assert!(master.send(byte));
+        }
+        master.stop();
+
+        master.start();
+        assert!(!master.send(0x50), "another device is not answered");
+        master.stop();
+
+        // Random read: set the address with a write that carries no data, then read.
+        master.start();
+        master.send(0xA0);
+        master.send(0x44);
+        master.start();
+        assert!(master.send(0xA1));
+        assert_eq!(master.receive(true), 0x22);
+        assert_eq!(master.receive(false), 0x33);
+        master.stop();
+
+        assert_eq!(chip.data()[0x42..0x46], [0xFF, 0x11, 0x22, 0x33]);
+    }
+
+    #[test]
+    fn a_24c02_page_write_wraps_within_its_page() {
+        let mut chip = Eeprom::new(EepromKind::C24c02);
+        let mut master = Master(&mut chip);
+        master.start();
+        master.send(0xA0);
+        master.send(0x0E);
+        for byte in 1..=3 {
+            master.send(byte);
+        }
+        master.stop();
+        assert_eq!((chip.data()[0x0E], chip.data()[0x0F], chip.data()[0x08]), (1, 2, 3));
+    }
+
+    #[test]
+    fn an_x24c01_takes_the_address_in_its_first_byte() {
+        let mut chip = Eeprom::new(EepromKind::X24c01);
+        let mut master = Master(&mut chip);
+        master.start();
+        assert!(master.send(0x05 << 1));
+        assert!(master.send(0x99));
+        master.stop();
+
+        master.start();
+        assert!(master.send(0x05 << 1 | 1));
+        assert_eq!(master.receive(true), 0x99);
+        assert_eq!(
+            master.receive(false),
+            0xFF,
+            "sequential reads carry on to the next byte"
+        );
+        master.stop();
+
+        let mut saved = Vec::new();
+        chip.save(&mut saved);
+        assert_eq!(saved.len(), EEPROM_STATE_LEN);
+    }
+}
@@ -0,0 +1,675 @@
+//! The Famicom Disk System: the RAM adapter and the drive behind it.
+//!
+//! The adapter plugs into the cartridge slot like any cartridge, but what it carries is memory
+//! rather than a game. There is 32 KB of RAM at `$6000..=$DFFF` for the program, 8 KB of CHR RAM,
+//! and the BIOS ROM at `$E000`. The BIOS is Nintendo's and is not distributed with the emulator;
+//! the user supplies it. The game is loaded from disk into the RAM by that BIOS, through the
+//! adapter's registers at `$4020..=$4033`, one byte at a time as the disk turns past the head.
+//!
+//! The drive is modelled at that level rather than by handing files to the BIOS directly. Every
+//! licensed game uses the BIOS's loader, but plenty also read the disk themselves, and
+//! copy-protected ones time it. The disk spins at one byte every 150 CPU cycles or so. The motor
+//! starts when `$4025` bit 0 is set and the head parks at the start. The head then reads whatever
+//! passes, sets the transfer flag and raises an IRQ for each byte, and stops the motor once it
+//! reaches the end. Gaps and CRCs are on the disk as [`FdsDisk`] rebuilds it, and the BIOS finds
+//! its blocks by waiting for the end of each gap, as it would on the real drive. CRCs are computed
+//! but the CRC error bit in `$4030` is never set: a corrupt dump would only ever fail where a real
+//! disk did.
+//!
+//! Beside the drive are a 16-bit timer IRQ, which the BIOS leaves to games for raster effects,
+//! and the wavetable sound channel (see [`FdsAudio`]).
+//!
+//! Changing sides is ejecting the disk and inserting another, with about a second between the
+//! two. Games wait for `$4032` to report the drive empty before they ask for the next side, and
+//! swapping sides in a single cycle would leave them waiting for ever.
+
+use std::cell::Cell;
+
+use super::{
+    fds_disk::crc_step,
+    fds_disk::FdsDisk,
+    fds_disk::GAP_END,
+    mapper::{BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister},
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip, FdsAudio};
+
+/// Bytes of the BIOS, mapped at `$E000..=$FFFF`.
+pub const BIOS_SIZE: usize = 8 * 1024;
+const RAM_SIZE: usize = 32 * 1024;
+const CHR_SIZE: usize = 8 * 1024;
+
+/// CPU cycles for one byte to pass the head.
+const BYTE_CYCLES: u32 = 150;
+/// CPU cycles from the motor starting to the first byte, while the head returns to the start.
+const REWIND_CYCLES: u32 = 50_000;
+/// CPU cycles between a disk going out and the next one going in: about a second.
+const SWAP_CYCLES: u32 = 1_800_000;
+
+/// Bytes of drive and register state in a save, ahead of the sound, the RAM and the disk.
+const REGISTER_STATE_LEN: usize = 26;
+
+/// "No disk", in a saved side number.
+const NO_SIDE: u8 = 0xFF;
+
+/// The Famicom Disk System's RAM adapter and drive. See the module documentation.
+#[derive(Debug)]
+pub struct Fds {
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+    chr: Vec<u8>,
+    disk: FdsDisk,
+    audio: FdsAudio,
+
+    /// `$4020`/`$4021`, loaded into the counter when the timer starts and each time it fires.
+    timer_reload: u16,
+    timer_counter: u16,
+    /// `$4022` bit 0: keep firing rather than stopping after once.
+    timer_repeat: bool,
+    /// `$4022` bit 1.
+    timer_enabled: bool,
+    /// Cells because reading `$4030` acknowledges them, and reads go through `&self`.
+    timer_irq: Cell<bool>,
+    disk_irq: Cell<bool>,
+
+    /// `$4023` bit 0: the drive's registers respond.
+    disk_registers: bool,
+    /// `$4023` bit 1: the sound registers respond.
+    sound_registers: bool,
+
+    /// `$4024`: the next byte to write.
+    write_data: u8,
+    /// The last byte the head read, for `$4031`.
+    read_data: u8,
+    /// `$4025`: see the `CONTROL_` bits.
+    control: u8,
+    /// `$4026`: the expansion port's outputs, which read back through `$4033`.
+    external: u8,
+    /// Set as each byte passes in either direction, and cleared by reading `$4030` or `$4031`, or
+    /// writing `$4024`. Only a cell for the same reason as the IRQs.
+    transfer_complete: Cell<bool>,
+
+    /// The side in the drive.
+    side: Option<usize>,
+    /// The side going in next, once `swap_delay` runs out.
+    next_side: Option<usize>,
+    swap_delay: u32,
+    /// Where the head is on the side, in bytes of the gapped form.
+    position: usize,
+    /// CPU cycles to the next byte.
+    delay: u32,
+    /// The head is at rest at the start, until the motor turns the disk.
+    end_of_head: bool,
+    /// The disk has been turning long enough to reach its first byte.
+    scanning: bool,
+    /// A gap's end has gone past since the BIOS said to look for one.
+    gap_ended: bool,
+    /// `$4025`'s CRC bit as it was on the last byte, to know when a CRC is about to be written.
+    previous_crc_control: bool,
+    crc: u16,
+}
+
+const CONTROL_MOTOR: u8 = 0x01;
+const CONTROL_RESET_TRANSFER: u8 = 0x02;
+const CONTROL_READ: u8 = 0x04;
+const CONTROL_HORIZONTAL: u8 = 0x08;
+const CONTROL_CRC: u8 = 0x10;
+const CONTROL_DISK_READY: u8 = 0x40;
+const CONTROL_DISK_IRQ: u8 = 0x80;
+
+impl Fds {
+    /// The adapter with `bios` at `$E000` and the first side of `disk` already in the drive, as a
+    /// console is switched on with a disk waiting.
+    pub fn new(bios: Vec<u8>, disk: FdsDisk) -> Self {
+        Self {
+            bios,
+            ram: vec![0; RAM_SIZE],
+            chr: vec![0; CHR_SIZE],
+            disk,
+            audio: FdsAudio::new(),
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: Cell::new(false),
+            disk_irq: Cell::new(false),
+            disk_registers: false,
+            sound_registers: false,
+            write_data: 0,
+            read_data: 0,
+            control: 0,
+            external: 0,
+            transfer_complete: Cell::new(false),
+            side: Some(0),
+            next_side: None,
+            swap_delay: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            previous_crc_control: false,
+            crc: 0,
+        }
+    }
+
+    fn control(&self, bit: u8) -> bool {
+        self.control & bit != 0
+    }
+
+    fn tick_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_irq.set(true);
+            self.timer_counter = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn tick_drive(&mut self) {
+        if self.next_side.is_some() {
+            self.swap_delay = self.swap_delay.saturating_sub(1);
+            if self.swap_delay == 0 {
+                self.side = self.next_side.take();
+            }
+        }
+
+        let Some(side) = self.side.filter(|_| self.control(CONTROL_MOTOR)) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if self.control(CONTROL_RESET_TRANSFER) && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.end_of_head = false;
+            self.delay = REWIND_CYCLES;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        self.transfer_byte(side);
+        self.previous_crc_control = self.control(CONTROL_CRC);
+        self.position += 1;
+        if self.position >= self.disk.side(side).len() {
+            // The end of the side stops the motor, and the head goes back to the start.
+            self.control &= !CONTROL_MOTOR;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    /// The byte under the head goes past, into `$4031` or out of `$4024`.
+    fn transfer_byte(&mut self, side: usize) {
+        let irq = self.control(CONTROL_DISK_IRQ);
+        if self.control(CONTROL_READ) {
+            let byte = self.disk.side(side)[self.position];
+            if !self.previous_crc_control {
+                self.crc = crc_step(self.crc, byte);
+            }
+            if !self.control(CONTROL_DISK_READY) {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if byte == GAP_END && !self.gap_ended {
+                // The gap's end is not a byte of the block, and raises nothing: the next byte is
+                // the first the BIOS reads.
+                self.gap_ended = true;
+                return;
+            }
+            if self.gap_ended {
+                self.read_data = byte;
+                self.transfer_complete.set(true);
+                self.disk_irq.set(self.disk_irq.get() || irq);
+            }
+            return;
+        }
+
+        let mut byte = self.write_data;
+        if !self.control(CONTROL_CRC) {
+            self.transfer_complete.set(true);
+            self.disk_irq.set(self.disk_irq.get() || irq);
+        }
+        if !self.control(CONTROL_DISK_READY) {
+            // A gap, with nothing to check in it yet.
+            byte = 0;
+            self.crc = 0;
+        }
+        if self.control(CONTROL_CRC) {
+            if !self.previous_crc_control {
+                self.crc = crc_step(crc_step(self.crc, 0), 0);
+            }
+            byte = self.crc as u8;
+            self.crc >>= 8;
+        } else {
+            self.crc = crc_step(self.crc, byte);
+        }
+        self.disk.side_mut(side)[self.position] = byte;
+        self.gap_ended = false;
+    }
+}
+
+impl Mapper for Fds {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            0x4030 if self.disk_registers => {
+                let status = u8::from(self.timer_irq.get())
+                    | u8::from(self.transfer_complete.get()) << 1
+                    | u8::from(self.end_of_head) << 6;
+                self.timer_irq.set(false);
+                self.disk_irq.set(false);
+                self.transfer_complete.set(false);
+                status
+            },
+            0x4031 if self.disk_registers => {
+                self.disk_irq.set(false);
+                self.transfer_complete.set(false);
+                self.read_data
+            },
+            0x4032 if self.disk_registers => {
+                let inserted = self.side.is_some();
+                // No disk reads as write-protected too; a disk in the drive can always be written.
+                u8::from(!inserted) | u8::from(!inserted || !self.scanning) << 1 | u8::from(!inserted) << 2
+            },
+            // Bit 7 is the battery in the drive, which is always good.
+            0x4033 if self.disk_registers => 0x80 | (self.external & 0x7F),
+            0x4040..=0x4097 => self.audio.read(address).unwrap_or(0),
+            0x6000..=0xDFFF => self.ram[address as usize - 0x6000],
+            0xE000..=0xFFFF => self.bios[(address as usize - 0xE000) % self.bios.len().max(1)],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => {
+                self.timer_repeat = value & 0x01 != 0;
+                self.timer_enabled = value & 0x02 != 0 && self.disk_registers;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq.set(false);
+                }
+            },
+            0x4023 => {
+                self.disk_registers = value & 0x01 != 0;
+                self.sound_registers = value & 0x02 != 0;
+                if !self.disk_registers {
+                    self.timer_enabled = false;
+                    self.timer_irq.set(false);
+                    self.disk_irq.set(false);
+                }
+            },
+            0x4024 if self.disk_registers => {
+                self.write_data = value;
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            },
+            0x4025 if self.disk_registers => {
+                self.control = value;
+                self.disk_irq.set(false);
+            },
+            0x4026 if self.disk_registers => self.external = value,
+            0x4040..=0x408A if self.sound_registers => self.audio.write(address, value),
+            0x6000..=0xDFFF => self.ram[address as usize - 0x6000] = value,
+            _ => {},
+        }
+    }
+
+    fn maps_cpu_address(&self, address: u16, write: bool) -> bool {
+        match address {
+            0x6000..=0x7FFF => true,
+            0x4040..=0x407F => true,
+            _ if write => matches!(address, 0x4020..=0x4026 | 0x4080..=0x408A),
+            _ => matches!(address, 0x4030..=0x4033 | 0x4090 | 0x4092),
+        }
+    }
+
+    /// The status registers drive only the bits they have, as do the sound's six-bit values.
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        match address {
+            0x4030 => 0x2C,
+            0x4032 => 0xF8,
+            0x4040..=0x407F | 0x4090 | 0x4092 => 0xC0,
+            _ => 0,
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[address as usize & 0x1FFF]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr[address as usize & 0x1FFF] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control(CONTROL_HORIZONTAL) {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.timer_irq.set(false);
+        self.disk_irq.set(false);
+    }
+
+    /// The RAM adapter's 32 KB, and the BIOS as the ROM.
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        vec![
+            BankWindow::new(0x6000, self.ram.len(), BankMemory::Ram, 0),
+            BankWindow::new(0xE000, 8 * 1024, BankMemory::Rom, 0),
+        ]
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x0000, 8 * 1024, BankMemory::Ram, 0)]
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("Timer repeat", self.timer_repeat),
+            MapperRegister::new("I/O enable", u8::from(self.disk_registers) | u8::from(self.sound_registers) << 1),
+            MapperRegister::new("Write data", self.write_data),
+            MapperRegister::new("Read data", self.read_data),
+            MapperRegister::new("Control", self.control),
+            MapperRegister::new("External", self.external),
+        ]
+    }
+
+    /// The timer. The disk's transfer IRQ has no counter, and shows only as pending.
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.timer_counter.into(),
+            latch: self.timer_reload.into(),
+            enabled: self.timer_enabled,
+            pending: self.timer_irq.get() || self.disk_irq.get(),
+        })
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.tick_timer();
+        self.tick_drive();
+        self.audio.tick();
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Fds)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.audio.channels()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        self.audio.set_channel_enabled(index, enabled);
+    }
+
+    /// What the game has written to the disk, as [`FdsDisk::diff`] gives it. Empty, but still
+    /// `Some`, before anything has been: the disk is always writable.
+    fn flash_save(&self) -> Option<Vec<u8>> {
+        Some(self.disk.diff())
+    }
+
+    fn restore_flash_save(&mut self, save: &[u8]) {
+        self.disk.apply_diff(save);
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.disk.side_count()
+    }
+
+    fn disk_side(&self) -> Option<usize> {
+        self.next_side.or(self.side)
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.side = None;
+        self.next_side = side.filter(|&side| side < self.disk.side_count());
+        self.swap_delay = SWAP_CYCLES;
+    }
+
+    /// The registers and the drive, the sound, all of the RAM, and the disk as the game has
+    /// written it — a save state from before a save to disk has to undo it.
+    fn save_state(&self) -> Vec<u8> {
+        let side = |side: Option<usize>| side.map_or(NO_SIDE, |side| side as u8);
+        let mut state = Vec::new();
+        state.extend_from_slice(&self.timer_reload.to_le_bytes());
+        state.extend_from_slice(&self.timer_counter.to_le_bytes());
+        state.push(
+            u8::from(self.timer_repeat)
+                | u8::from(self.timer_enabled) << 1
+                | u8::from(self.timer_irq.get()) << 2
+                | u8::from(self.disk_irq.get()) << 3
+                | u8::from(self.disk_registers) << 4
+                | u8::from(self.sound_registers) << 5
+                | u8::from(self.transfer_complete.get()) << 6,
+        );
+        state.push(
+            u8::from(self.end_of_head)
+                | u8::from(self.scanning) << 1
+                | u8::from(self.gap_ended) << 2
+                | u8::from(self.previous_crc_control) << 3,
+        );
+        state.extend_from_slice(&[
+            self.write_data,
+            self.read_data,
+            self.control,
+            self.external,
+            side(self.side),
+            side(self.next_side),
+        ]);
+        state.extend_from_slice(&self.swap_delay.to_le_bytes());
+        state.extend_from_slice(&(self.position as u32).to_le_bytes());
+        state.extend_from_slice(&self.delay.to_le_bytes());
+        state.extend_from_slice(&self.crc.to_le_bytes());
+        self.audio.save(&mut state);
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.chr);
+        state.extend_from_slice(&self.disk.diff());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let disk_at = REGISTER_STATE_LEN + FdsAudio::STATE_LEN + RAM_SIZE + CHR_SIZE;
+        if state.len() < disk_at {
+            return;
+        }
+        let word = |at: usize| u16::from_le_bytes([state[at], state[at + 1]]);
+        let long = |at: usize| u32::from_le_bytes([state[at], state[at + 1], state[at + 2], state[at + 3]]);
+        let sides = self.disk.side_count();
+        let side = |byte: u8| Some(byte as usize).filter(|&side| side < sides);
+
+        self.timer_reload = word(0);
+        self.timer_counter = word(2);
+        self.timer_repeat = state[4] & 0x01 != 0;
+        self.timer_enabled = state[4] & 0x02 != 0;
+        self.timer_irq.set(state[4] & 0x04 != 0);
+        self.disk_irq.set(state[4] & 0x08 != 0);
+        self.disk_registers = state[4] & 0x10 != 0;
+        self.sound_registers = state[4] & 0x20 != 0;
+        self.transfer_complete.set(state[4] & 0x40 != 0);
+        self.end_of_head = state[5] & 0x01 != 0;
+        self.scanning = state[5] & 0x02 != 0;
+        self.gap_ended = state[5] & 0x04 != 0;
+        self.previous_crc_control = state[5] & 0x08 != 0;
+        self.write_data = state[6];
+        self.read_data = state[7];
+        self.control = state[8];
+        self.external = state[9];
+        self.side = side(state[10]);
+        self.next_side = side(state[11]);
+        self.swap_delay = long(12);
+        self.position = long(16) as usize;
+        self.delay = long(20);
+        self.crc = word(24);
+        self.audio.load(&state[REGISTER_STATE_LEN..]);
+        let ram_at = REGISTER_STATE_LEN + FdsAudio::STATE_LEN;
+        self.ram.copy_from_slice(&state[ram_at..ram_at + RAM_SIZE]);
+        self.chr.copy_from_slice(&state[ram_at + RAM_SIZE..disk_at]);
+        self.disk.apply_diff(&state[disk_at..]);
+        if let Some(side) = self.side {
+            self.position = self.position.min(self.disk.side(side).len() - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::fds_disk::side_with_file, *};
+
+    /// The adapter with its registers on, a BIOS of `$EA`, and a disk of two sides.
+    fn adapter() -> Fds {
+        let mut image = side_with_file(&[0x11, 0x22, 0x33]);
+        image.extend(side_with_file(&[0x44]));
+        let mut fds = Fds::new(vec![0xEA; BIOS_SIZE], FdsDisk::new(&image));
+        fds.write_prg(0x4023, 0x03);
+        fds
+    }
+
+    /// Run until the head has something for `$4031`, as the BIOS waits for its IRQ.
+    fn next_byte(fds: &mut Fds) -> u8 {
+        for _ in 0..1_000_000 {
+            fds.cpu_cycle();
+            if fds.irq_pending() {
+                return fds.read_prg(0x4031);
+            }
+        }
+        panic!("no byte from the drive");
+    }
+
+    /// Let one byte go by under the head, whatever it does.
+    fn pass_byte(fds: &mut Fds) {
+        for _ in 0..=BYTE_CYCLES {
+            fds.cpu_cycle();
+        }
+    }
+
+    #[test]
+    fn memory_is_ram_below_the_bios() {
+        let mut fds = adapter();
+        fds.write_prg(0x6000, 1);
+        fds.write_prg(0xDFFF, 2);
+        fds.write_prg(0xE000, 3);
+        assert_eq!([0x6000, 0xDFFF, 0xE000].map(|a| fds.read_prg(a)), [1, 2, 0xEA]);
+        fds.write_chr(0x1FFF, 4);
+        assert_eq!(fds.read_chr(0x1FFF), 4);
+        assert!(fds.maps_cpu_address(0x6000, false));
+        assert!(!fds.maps_cpu_address(0x4024, false), "write-only");
+    }
+
+    #[test]
+    fn the_timer_fires_after_its_reload_and_repeats_if_asked() {
+        let mut fds = adapter();
+        fds.write_prg(0x4020, 10);
+        fds.write_prg(0x4021, 0);
+        fds.write_prg(0x4022, 0x03);
+        for _ in 0..10 {
+            fds.cpu_cycle();
+        }
+        assert!(!fds.irq_pending());
+        fds.cpu_cycle();
+        assert!(fds.irq_pending());
+        assert_eq!(fds.read_prg(0x4030) & 0x01, 0x01);
+        assert!(!fds.irq_pending(), "reading $4030 acknowledges");
+        for _ in 0..11 {
+            fds.cpu_cycle();
+        }
+        assert!(fds.irq_pending(), "repeating");
+    }
+
+    /// The BIOS's read loop: motor on, wait for the gap's end, then a byte per IRQ.
+    #[test]
+    fn the_drive_reads_blocks_after_their_gaps() {
+        let mut fds = adapter();
+        assert_eq!(fds.read_prg(0x4032) & 0x03, 0x02, "a disk, but not turning");
+        fds.write_prg(
+            0x4025,
+            CONTROL_MOTOR | CONTROL_READ | CONTROL_DISK_READY | CONTROL_DISK_IRQ | 0x20,
+        );
+        let header: Vec<u8> = (0..15).map(|_| next_byte(&mut fds)).collect();
+        assert_eq!(header, super::super::fds_disk::DISK_MAGIC);
+        assert_eq!(fds.read_prg(0x4032) & 0x03, 0x00, "ready");
+    }
+
+    /// Writing the second side's file data, as the BIOS does a save: find the block, switch to
+    /// writing, and the change comes back in the disk's save.
+    #[test]
+    fn writes_reach_the_disk_save_and_sides_swap_with_a_delay() {
+        let mut fds = adapter();
+        fds.insert_disk(Some(1));
+        assert_eq!(fds.read_prg(0x4032) & 0x01, 0x01, "out of the drive for now");
+        assert_eq!(fds.disk_side(), Some(1));
+        for _ in 0..SWAP_CYCLES {
+            fds.cpu_cycle();
+        }
+        assert_eq!(fds.read_prg(0x4032) & 0x01, 0x00);
+
+        let read = CONTROL_MOTOR | CONTROL_READ | CONTROL_DISK_READY | CONTROL_DISK_IRQ;
+        fds.write_prg(0x4025, read);
+        // Disk header, file count, file header: 56 + 2 + 16 bytes, each after a gap.
+        for len in [56, 2, 16] {
+            for _ in 0..len {
+                next_byte(&mut fds);
+            }
+            // The CRC, then the BIOS drops "disk ready" to look for the next gap.
+            next_byte(&mut fds);
+            next_byte(&mut fds);
+            fds.write_prg(0x4025, read & !CONTROL_DISK_READY);
+            pass_byte(&mut fds);
+            fds.write_prg(0x4025, read);
+        }
+        // Rewrite the file data over the gap: some of the gap, its end, the block type, the new
+        // byte, and the CRC the adapter works out itself.
+        let write = CONTROL_MOTOR | CONTROL_DISK_READY | CONTROL_DISK_IRQ;
+        fds.write_prg(0x4025, write & !CONTROL_DISK_READY);
+        pass_byte(&mut fds);
+        fds.write_prg(0x4025, write);
+        for byte in [GAP_END, 4, 0x99] {
+            fds.write_prg(0x4024, byte);
+            next_byte(&mut fds);
+        }
+        fds.write_prg(0x4025, write | CONTROL_CRC);
+        pass_byte(&mut fds);
+        pass_byte(&mut fds);
+        let save = fds.flash_save().unwrap();
+        assert!(!save.is_empty());
+
+        let mut restored = adapter();
+        restored.restore_flash_save(&save);
+        assert_eq!(
+            restored.disk.image()[super::super::fds_disk::SIDE_SIZE + 56 + 2 + 16 + 1],
+            0x99
+        );
+        let mut state_copy = adapter();
+        state_copy.load_state(&fds.save_state());
+        assert_eq!(state_copy.disk.diff(), save);
+        assert_eq!(state_copy.disk_side(), Some(1));
+    }
+}
@@ -0,0 +1,312 @@
+//! Famicom Disk System disk images, and the difference a game's writes make to them.
+//!
+//! A `.fds` file holds each side as 65500 bytes of its blocks back to back: the disk header, the
+//! file count, and a header and a data block for each file. That is what is *on* the disk but
+//! not what the drive reads, because between blocks a real disk has gaps. Each block is preceded
+//! by a run of zero bits and a single 1 the BIOS waits for, and followed by a CRC the dump left
+//! out. The drive model here reads the disk as it would be on the medium. Every side is
+//! rebuilt with its gaps and CRCs at load, and written games are read back out of that form.
+//!
+//! The gaps are the lengths Nintendo's own formatting used: about 28300 bits of lead-in and 976
+//! between blocks. The BIOS times out on anything much shorter, and copy-protected games check
+//! them.
+//!
+//! Saves are kept as the difference from the image as loaded, so the image itself is never
+//! rewritten and an untouched side costs nothing. The difference is a list of records, each a
+//! 32-bit little-endian offset into the sides as the file lays them out, a 16-bit length, and that
+//! many bytes.
+
+/// Bytes of one side in a `.fds` file.
+pub const SIDE_SIZE: usize = 65500;
+
+/// The fwNES header some `.fds` files begin with: `FDS\x1A`, a side count, and eleven zeros.
+pub const FWNES_MAGIC: [u8; 4] = *b"FDS\x1A";
+pub const FWNES_HEADER_SIZE: usize = 16;
+
+/// How every side begins: the disk header block's type and the string the BIOS checks for.
+pub const DISK_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
+/// Zero bytes before the first block, and between one block's CRC and the next block's mark.
+const LEAD_IN: usize = 28300 / 8;
+const GAP: usize = 976 / 8;
+
+/// The byte whose single set bit ends a gap. The BIOS syncs to its 1 and reads from the next.
+pub(super) const GAP_END: u8 = 0x80;
+
+/// The least a gapped side is padded to, so a game has room to append files and the drive takes
+/// about as long to reach the end as a real one does.
+const GAPPED_SIDE_SIZE: usize = 80 * 1024;
+
+/// One step of the drive's CRC: CRC-16 with the polynomial reversed, `0x8408`, fed one byte.
+///
+/// The BIOS seeds it with the gap-end byte and finishes it with two zeros, so a block's stored CRC
+/// is what this gives over `0x80`, the block and `00 00`.
+pub(super) fn crc_step(mut crc: u16, byte: u8) -> u16 {
+    for bit in 0..8 {
+        let carry = crc & 1 != 0;
+        crc = (crc >> 1) | (((byte >> bit) as u16 & 1) << 15);
+        if carry {
+            crc ^= 0x8408;
+        }
+    }
+    crc
+}
+
+fn block_crc(block: &[u8]) -> u16 {
+    std::iter::once(GAP_END)
+        .chain(block.iter().copied())
+        .chain([0, 0])
+        .fold(0, crc_step)
+}
+
+/// How long the block starting at `data[at]` is, from its type and, for a file's data, the size
+/// its header gave. `None` past the last block.
+fn block_len(data: &[u8], at: usize, file_size: usize) -> Option<usize> {
+    match data.get(at)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+/// The size a file header block at `data[at]` gives its data.
+fn file_size(data: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([data[at + 13], data[at + 14]]) as usize
+}
+
+/// The blocks of one raw side, each with its offset into the side.
+fn blocks(raw: &[u8]) -> Vec<(usize, usize)> {
+    let mut blocks = Vec::new();
+    let mut at = 0;
+    let mut size = 0;
+    while let Some(len) = block_len(raw, at, size) {
+        if at + len > raw.len() {
+            break;
+        }
+        if raw[at] == 3 {
+            size = file_size(raw, at);
+        }
+        blocks.push((at, len));
+        at += len;
+    }
+    blocks
+}
+
+/// A raw side as the drive sees it: gaps, a mark before each block and its CRC after.
+fn gapped(raw: &[u8]) -> Vec<u8> {
+    let mut side = vec![0; LEAD_IN];
+    for (at, len) in blocks(raw) {
+        let block = &raw[at..at + len];
+        side.push(GAP_END);
+        side.extend_from_slice(block);
+        side.extend_from_slice(&block_crc(block).to_le_bytes());
+        side.extend(std::iter::repeat_n(0, GAP));
+    }
+    let len = side.len().max(GAPPED_SIDE_SIZE);
+    side.resize(len, 0);
+    side
+}
+
+/// The blocks of a gapped side, back to back as a `.fds` file holds them, whatever gaps the drive
+/// wrote between them.
+fn ungapped(side: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(SIDE_SIZE);
+    let mut at = 0;
+    let mut size = 0;
+    loop {
+        // Skip the gap to its mark. A gap with no mark after it ends the side.
+        while side.get(at) == Some(&0) {
+            at += 1;
+        }
+        if side.get(at) != Some(&GAP_END) {
+            break;
+        }
+        at += 1;
+        let Some(len) = block_len(side, at, size).filter(|len| at + len <= side.len()) else {
+            break;
+        };
+        if side[at] == 3 {
+            size = file_size(side, at);
+        }
+        raw.extend_from_slice(&side[at..at + len]);
+        at += len + 2;
+    }
+    raw.resize(raw.len().max(SIDE_SIZE), 0);
+    raw
+}
+
+/// The sides of a disk image, as the drive reads them.
+#[derive(Debug, Clone)]
+pub struct FdsDisk {
+    /// The image as loaded, side after side, for working out what has changed.
+    original: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+    /// Which sides have been handed out for writing. Only those are read back out of their gaps,
+    /// so whatever a dump keeps past its last block survives on the sides nobody wrote.
+    written: Vec<bool>,
+}
+
+impl FdsDisk {
+    /// The disk in `image`, its sides back to back without the fwNES header. A short last side is
+    /// padded out with zeros, as an unwritten disk reads.
+    pub fn new(image: &[u8]) -> Self {
+        let mut original = image.to_vec();
+        let count = image.len().div_ceil(SIDE_SIZE).max(1);
+        original.resize(count * SIDE_SIZE, 0);
+        let sides = original.chunks(SIDE_SIZE).map(gapped).collect();
+        Self {
+            original,
+            sides,
+            written: vec![false; count],
+        }
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// One side, gaps and all.
+    pub fn side(&self, side: usize) -> &[u8] {
+        &self.sides[side]
+    }
+
+    pub fn side_mut(&mut self, side: usize) -> &mut [u8] {
+        self.written[side] = true;
+        &mut self.sides[side]
+    }
+
+    /// The sides as a `.fds` file lays them out, with whatever the game has written.
+    pub fn image(&self) -> Vec<u8> {
+        let unchanged = self.original.chunks(SIDE_SIZE);
+        self.sides
+            .iter()
+            .zip(&self.written)
+            .zip(unchanged)
+            .flat_map(|((side, &written), original)| {
+                if !written {
+                    return original.to_vec();
+                }
+                let mut raw = ungapped(side);
+                raw.truncate(SIDE_SIZE);
+                raw
+            })
+            .collect()
+    }
+
+    /// What the game has changed since the image was loaded, in the form the module describes.
+    /// Empty for a disk nobody has written.
+    pub fn diff(&self) -> Vec<u8> {
+        let image = self.image();
+        let mut diff = Vec::new();
+        let mut at = 0;
+        while at < image.len() {
+            if image[at] == self.original[at] {
+                at += 1;
+                continue;
+            }
+            let start = at;
+            while at < image.len() && image[at] != self.original[at] && at - start < u16::MAX as usize {
+                at += 1;
+            }
+            diff.extend_from_slice(&(start as u32).to_le_bytes());
+            diff.extend_from_slice(&((at - start) as u16).to_le_bytes());
+            diff.extend_from_slice(&image[start..at]);
+        }
+        diff
+    }
+
+    /// Put back the changes [`diff`](Self::diff) described, on top of the image as loaded. A
+    /// record that runs off the end, from a save of some other disk, stops the restore there.
+    pub fn apply_diff(&mut self, diff: &[u8]) {
+        let mut image = self.original.clone();
+        let mut at = 0;
+        while at + 6 <= diff.len() {
+            let offset = u32::from_le_bytes([diff[at], diff[at + 1], diff[at + 2], diff[at + 3]]) as usize;
+            let len = u16::from_le_bytes([diff[at + 4], diff[at + 5]]) as usize;
+            at += 6;
+            let (Some(bytes), Some(target)) = (diff.get(at..at + len), image.get_mut(offset..offset + len)) else {
+                break;
+            };
+            target.copy_from_slice(bytes);
+            for side in offset / SIDE_SIZE..(offset + len).div_ceil(SIDE_SIZE) {
+                self.written[side] = true;
+            }
+            at += len;
+        }
+        self.sides = image.chunks(SIDE_SIZE).map(gapped).collect();
+    }
+}
+
+/// A side holding a disk header, a file count of one, and one file of `data`, for tests here
+/// and of the drive.
+#[cfg(test)]
+pub(super) fn side_with_file(data: &[u8]) -> Vec<u8> {
+    let mut side = DISK_MAGIC.to_vec();
+    side.resize(56, 0);
+    side.extend_from_slice(&[2, 1]);
+    let mut header = vec![3, 0, 0];
+    header.extend_from_slice(b"FILENAME");
+    header.extend_from_slice(&[0x00, 0x60]);
+    header.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    header.push(0);
+    side.extend_from_slice(&header);
+    side.push(4);
+    side.extend_from_slice(data);
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sides_gain_gaps_and_crcs_and_lose_them_again() {
+        let raw = side_with_file(&[1, 2, 3]);
+        let disk = FdsDisk::new(&raw);
+        let side = disk.side(0);
+        assert_eq!(disk.side_count(), 1);
+        assert!(side[..LEAD_IN].iter().all(|&byte| byte == 0));
+        assert_eq!(side[LEAD_IN], GAP_END);
+        assert_eq!(&side[LEAD_IN + 1..LEAD_IN + 16], DISK_MAGIC);
+        // The file count block follows the header's CRC and a gap.
+        let count = LEAD_IN + 1 + 56 + 2 + GAP;
+        assert_eq!(&side[count..count + 3], &[GAP_END, 2, 1]);
+        assert_eq!(disk.image(), raw);
+        assert!(disk.diff().is_empty());
+    }
+
+    /// The CRC the drive would read back after a block checks out when fed through the same
+    /// steps: a CRC over its own message and itself is zero.
+    #[test]
+    fn a_block_and_its_crc_sum_to_zero() {
+        let block = [2u8, 1];
+        let crc = block_crc(&block);
+        let check = [GAP_END, 2, 1].into_iter().chain(crc.to_le_bytes()).fold(0, crc_step);
+        assert_eq!(check, 0);
+    }
+
+    #[test]
+    fn writes_come_back_as_a_diff_and_restore_from_it() {
+        let mut raw = side_with_file(&[1, 2, 3]);
+        raw.extend(side_with_file(&[9]));
+        let mut disk = FdsDisk::new(&raw);
+        assert_eq!(disk.side_count(), 2);
+
+        // The first byte of the second side's file data, after its block type.
+        let data = LEAD_IN + 3 * (1 + 2 + GAP) + 56 + 2 + 16 + 2;
+        assert_eq!(disk.side(1)[data], 9);
+        disk.side_mut(1)[data] = 7;
+        let diff = disk.diff();
+        assert_eq!(diff.len(), 7, "one record of one byte");
+
+        let mut restored = FdsDisk::new(&raw);
+        restored.apply_diff(&diff);
+        assert_eq!(restored.image(), disk.image());
+        assert_eq!(restored.image()[SIDE_SIZE + 56 + 2 + 16 + 1], 7);
+        restored.apply_diff(&[0xFF; 9]);
+        assert_eq!(restored.image(), raw, "garbage restores nothing");
+    }
+}
@@ -0,0 +1,469 @@
+//! Sunsoft FME-7 and 5B (mapper 69).
+//!
+//! Four switchable 8 KB PRG banks — one of them at `$6000`, where it can be ROM or work RAM — ahead
+//! of a fixed last bank, eight 1 KB CHR banks, and a 16-bit IRQ counter that counts down once per
+//! CPU cycle. The 5B is the same chip with a YM2149 sound core beside it, in
+//! [`crate::apu::Sunsoft5bAudio`]; its two ports sit at `$C000` and `$E000`, where the FME-7 has
+//! nothing and no FME-7 game writes, so one model serves both.
+//!
+//! Everything goes through two ports: a command number written to `$8000` picks one of sixteen
+//! internal registers, and the next write to `$A000` sets it.
+//!
+//! | Command | Register |
+//! |---|---|
+//! | 0-7 | The CHR banks, `$0000` to `$1C00` |
+//! | 8 | `$6000`: bank in bits 0-5, RAM rather than ROM in bit 6, RAM enabled in bit 7 |
+//! | 9-B | The PRG banks at `$8000`, `$A000` and `$C000` |
+//! | C | Mirroring: vertical, horizontal, lower, upper |
+//! | D | IRQ: bit 0 raises it when the counter wraps, bit 7 lets the counter run |
+//! | E-F | The counter's low and high bytes |
+//!
+//! Unlike MMC3's counter, this one knows nothing of the picture. Games that split the screen —
+//! *Gimmick!*'s status bar, *Batman: Return of the Joker*'s parallax — load it with the number of
+//! CPU cycles to the line they want, which is why it has sixteen bits where MMC3 has eight.
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip, Sunsoft5bAudio};
+
+/// Bytes of register state in a save, before the work RAM and the sound chip.
+const REGISTER_STATE_LEN: usize = 19;
+
+/// Sunsoft FME-7 and 5B (mapper 69). See the module documentation.
+#[derive(Debug)]
+pub struct Fme7 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    /// The work RAM command 8 can put at `$6000`. Owned here rather than left to the bus because
+    /// the same window shows ROM as often as RAM.
+    prg_ram: Vec<u8>,
+
+    /// `$8000`: which register the next `$A000` write sets.
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Command 8.
+    ram_bank: u8,
+    /// Commands 9-B.
+    prg_banks: [u8; 3],
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5bAudio,
+}
+
+impl Fme7 {
+    /// Build the board with `prg_ram` bytes of work RAM.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, prg_ram: usize) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            prg_ram: vec![0; prg_ram],
+            command: 0,
+            chr_banks: [0; 8],
+            ram_bank: 0,
+            prg_banks: [0; 3],
+            mirroring: Mirroring::Vertical,
+            irq_enabled: false,
+            counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5bAudio::new(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Where in the work RAM `$6000` reaches, if it reaches RAM at all and the RAM is enabled.
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram_bank & 0xC0 != 0xC0 || self.prg_ram.is_empty() {
+            return None;
+        }
+        let offset = (self.ram_bank & 0x3F) as usize * PRG_BANK + (address as usize & 0x1FFF);
+        Some(offset % self.prg_ram.len())
+    }
+
+    /// Whether `$6000` shows RAM, enabled or not, rather than ROM.
+    fn ram_selected(&self) -> bool {
+        self.ram_bank & 0x40 != 0
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        match address {
+            0x6000..=0x7FFF => (self.ram_bank & 0x3F) as usize,
+            0x8000..=0xDFFF => (self.prg_banks[(address as usize - 0x8000) / PRG_BANK] & 0x3F) as usize,
+            _ => (self.prg.len() / PRG_BANK).max(1) - 1,
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let bank = self.chr_banks[(address as usize >> 10) & 0x07] as usize % banks;
+        bank * CHR_BANK + (address as usize & 0x03FF)
+    }
+
+    fn write_register(&mut self, value: u8) {
+        match self.command {
+            command @ 0x0..=0x7 => self.chr_banks[command as usize] = value,
+            0x8 => self.ram_bank = value,
+            command @ 0x9..=0xB => self.prg_banks[command as usize - 0x9] = value,
+            0xC => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            },
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16) << 8),
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+        address >= 0x6000
+    }
+
+    /// With RAM selected but not enabled, `$6000` is not driven at all.
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        if (0x6000..0x8000).contains(&address) && self.ram_selected() && self.ram_offset(address).is_none() {
+            0xFF
+        } else {
+            0
+        }
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x8000 && self.ram_selected() {
+            return self.ram_offset(address).map_or(0, |offset| self.prg_ram[offset]);
+        }
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let Some(offset) = self.ram_offset(address) {
+                    self.prg_ram[offset] = value;
+                }
+            },
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_register(value),
+            0xC000..=0xDFFF => self.audio.select(value),
+            0xE000..=0xFFFF => self.audio.write(value),
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    /// `$6000` too, as RAM or ROM as command 8 says.
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let ram = if self.ram_selected() && !self.prg_ram.is_empty() {
+            BankWindow::banked(0x6000, PRG_BANK, BankMemory::Ram, self.prg_bank_for(0x6000), self.prg_ram.len())
+        } else {
+            BankWindow::banked(0x6000, PRG_BANK, BankMemory::Rom, self.prg_bank_for(0x6000), self.prg.len())
+        };
+        let rom = (0x8000..=0xE000).step_by(PRG_BANK).map(|start| {
+            BankWindow::banked(start, PRG_BANK, BankMemory::Rom, self.prg_bank_for(start), self.prg.len())
+        });
+        std::iter::once(ram).chain(rom).collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| BankWindow::new(start, CHR_BANK, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("Command", self.command)];
+        registers.extend(
+            self.chr_banks
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank)),
+        );
+        registers.push(MapperRegister::new("PRG bank $6000", self.ram_bank));
+        registers.extend(
+            self.prg_banks
+                .iter()
+                .zip(["PRG bank $8000", "PRG bank $A000", "PRG bank $C000"])
+                .map(|(&bank, name)| MapperRegister::new(name, bank)),
+        );
+        registers
+    }
+
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.irq_counter.into(),
+            latch: self.irq_counter.into(),
+            enabled: self.irq_enabled && self.counter_enabled,
+            pending: self.irq_pending,
+        })
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    /// The counter runs whenever its enable is set, whether or not the IRQ is; it is the IRQ
+    /// enable alone that decides whether wrapping from `$0000` to `$FFFF` raises one.
+    fn cpu_cycle(&mut self) {
+        if self.counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Sunsoft5b)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.audio.channels()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        self.audio.set_channel_enabled(index, enabled);
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    /// Registers, then all of the work RAM, then the sound chip. The RAM is here because the
+    /// `$6000` window a save state otherwise reads may be showing ROM.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.command, self.ram_bank, self.mirroring as u8];
+        state.extend_from_slice(&self.chr_banks);
+        state.extend_from_slice(&self.prg_banks);
+        state.extend_from_slice(&self.irq_counter.to_le_bytes());
+        state.extend_from_slice(&[
+            u8::from(self.irq_enabled),
+            u8::from(self.counter_enabled),
+            u8::from(self.irq_pending),
+        ]);
+        state.extend_from_slice(&self.prg_ram);
+        self.audio.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < REGISTER_STATE_LEN + self.prg_ram.len() + Sunsoft5bAudio::STATE_LEN {
+            return;
+        }
+
+        let (registers, rest) = state.split_at(REGISTER_STATE_LEN);
+        self.command = registers[0] & 0x0F;
+        self.ram_bank = registers[1];
+        self.mirroring = Mirroring::from_index(registers[2] & 0x03);
+        self.chr_banks.copy_from_slice(&registers[3..11]);
+        self.prg_banks.copy_from_slice(&registers[11..14]);
+        self.irq_counter = u16::from_le_bytes([registers[14], registers[15]]);
+        self.irq_enabled = registers[16] != 0;
+        self.counter_enabled = registers[17] != 0;
+        self.irq_pending = registers[18] != 0;
+
+        let (prg_ram, rest) = rest.split_at(self.prg_ram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        self.audio.load(rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board() -> Fme7 {
+        Fme7::new(numbered_banks(32, PRG_BANK), numbered_banks(256, CHR_BANK), 8 * 1024)
+    }
+
+    fn command(mapper: &mut Fme7, command: u8, value: u8) {
+        mapper.write_prg(0x8000, command);
+        mapper.write_prg(0xA000, value);
+    }
+
+    #[test]
+    fn prg_and_chr_banks_through_the_command_port() {
+        let mut mapper = board();
+        command(&mut mapper, 0x9, 4);
+        command(&mut mapper, 0xA, 5);
+        command(&mut mapper, 0xB, 0x46);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [4, 5, 6, 31]
+        );
+
+        for bank in 0..8 {
+            command(&mut mapper, bank, 0x80 + bank);
+        }
+        assert_eq!(
+            (0..8u16).map(|w| mapper.read_chr(w * 0x400)).collect::<Vec<_>>(),
+            [0x80, 0x81, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87]
+        );
+
+        command(&mut mapper, 0xC, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        command(&mut mapper, 0xC, 3);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    /// `$6000` is a ROM bank, or RAM — which is open bus until enabled.
+    #[test]
+    fn the_6000_window_is_rom_or_ram() {
+        let mut mapper = board();
+        assert!(mapper.maps_cpu_address(0x6000, false) && mapper.maps_cpu_address(0x7FFF, true));
+
+        command(&mut mapper, 0x8, 0x07);
+        assert_eq!(mapper.read_prg(0x6000), 7, "ROM bank 7");
+        mapper.write_prg(0x6000, 0x55);
+        assert_eq!(mapper.read_prg(0x6000), 7, "ROM ignores the write");
+
+        command(&mut mapper, 0x8, 0x40);
+        mapper.write_prg(0x6000, 0x55);
+        assert_eq!(
+            mapper.open_bus_mask(0x6000),
+            0xFF,
+            "RAM selected but disabled is open bus"
+        );
+
+        command(&mut mapper, 0x8, 0xC0);
+        assert_eq!(mapper.open_bus_mask(0x6000), 0);
+        assert_eq!(mapper.read_prg(0x6000), 0, "the disabled write did not land");
+        mapper.write_prg(0x6123, 0x55);
+        assert_eq!(mapper.read_prg(0x6123), 0x55);
+        assert_eq!(mapper.prg_ram().unwrap()[0x123], 0x55);
+    }
+
+    #[test]
+    fn the_irq_fires_when_the_counter_wraps() {
+        let mut mapper = board();
+        command(&mut mapper, 0xE, 0x02);
+        command(&mut mapper, 0xF, 0x00);
+        command(&mut mapper, 0xD, 0x81);
+
+        mapper.cpu_cycle();
+        mapper.cpu_cycle();
+        assert!(!mapper.irq_pending(), "at zero, not yet wrapped");
+        mapper.cpu_cycle();
+        assert!(mapper.irq_pending());
+
+        command(&mut mapper, 0xD, 0x80);
+        assert!(!mapper.irq_pending(), "writing the control acknowledges");
+        for _ in 0..0x10000 {
+            mapper.cpu_cycle();
+        }
+        assert!(
+            !mapper.irq_pending(),
+            "the counter ran round again with the IRQ disabled"
+        );
+        assert_eq!(mapper.irq_counter, 0xFFFF);
+    }
+
+    #[test]
+    fn the_5b_is_reached_at_c000_and_e000() {
+        let mut mapper = board();
+        for (register, value) in [(0x00, 0x10), (0x07, 0x3E), (0x08, 0x0F)] {
+            mapper.write_prg(0xC000, register);
+            mapper.write_prg(0xE000, value);
+        }
+        let heard = (0..2000).any(|_| {
+            mapper.cpu_cycle();
+            mapper.audio_output() > 0.0
+        });
+        assert!(heard);
+        assert_eq!(mapper.audio_channels().iter().filter(|c| c.enabled).count(), 1);
+    }
+
+    #[test]
+    fn save_state_round_trips_with_the_work_ram() {
+        let mut mapper = board();
+        command(&mut mapper, 0x8, 0xC0);
+        mapper.write_prg(0x7ABC, 0x99);
+        command(&mut mapper, 0x8, 0x03);
+        command(&mut mapper, 0xA, 9);
+        command(&mut mapper, 0xC, 2);
+        command(&mut mapper, 0xE, 0x34);
+        command(&mut mapper, 0xD, 0x81);
+        for _ in 0..100 {
+            mapper.cpu_cycle();
+        }
+
+        let state = mapper.save_state();
+        let mut restored = board();
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0xA000), 9);
+        assert_eq!(restored.read_prg(0x6000), 3);
+        assert_eq!(restored.prg_ram().unwrap()[0x1ABC], 0x99);
+        assert_eq!(restored.mirroring(), Mirroring::SingleScreenLower);
+    }
+}
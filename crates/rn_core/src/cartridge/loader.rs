@@ -1,7 +1,19 @@
 use std::{
     fs::File,
     io::{self, Read},
-    path::Path,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+
+use super::{
+    archive,
+    database::{self, AppliedCorrection},
+    fds::BIOS_SIZE,
+    fds_disk::{DISK_MAGIC, FWNES_HEADER_SIZE, FWNES_MAGIC},
+    patch,
+    unif::{self, UNIF_MAGIC},
+    Mirroring,
 };
 
 /// Constants for iNES ROM format
@@ -13,6 +25,14 @@ const INES_MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // "NES" followed by MS-DO
 pub enum RomLoadError {
     IoError(io::Error),
     InvalidFormat(&'static str),
+    /// A UNIF file names a board that has no mapper here. Kept apart from `InvalidFormat` because
+    /// the file is fine and the name is the useful part of the message.
+    UnsupportedBoard(String),
+    /// A zip or gzip archive that could not be opened, or that holds nothing loadable.
+    Archive(String),
+    /// A zip archive holding several ROMs, with their names, so that the caller can ask which one
+    /// and load it with [`load_rom_from_archive`].
+    ArchiveHasSeveral(Vec<String>),
 }
 
 impl std::fmt::Display for RomLoadError {
@@ -20,6 +40,11 @@ impl std::fmt::Display for RomLoadError {
         match self {
             Self::IoError(error) => write!(f, "{error}"),
             Self::InvalidFormat(reason) => write!(f, "{reason}"),
+            Self::UnsupportedBoard(board) => write!(f, "the UNIF board \"{board}\" is not supported"),
+            Self::Archive(reason) => write!(f, "{reason}"),
+            Self::ArchiveHasSeveral(names) => {
+                write!(f, "the archive holds several ROMs, so one has to be chosen: {}", names.join(", "))
+            },
         }
     }
 }
@@ -28,7 +53,10 @@ impl std::error::Error for RomLoadError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::IoError(error) => Some(error),
-            Self::InvalidFormat(_) => None,
+            Self::InvalidFormat(_)
+            | Self::UnsupportedBoard(_)
+            | Self::Archive(_)
+            | Self::ArchiveHasSeveral(_) => None,
         }
     }
 }
@@ -39,19 +67,209 @@ impl From<io::Error> for RomLoadError {
     }
 }
 
-/// Simple iNES ROM header representation
-#[derive(Debug)]
+/// Which revision of the header a file uses.
+///
+/// The original iNES layout filled seven of its sixteen bytes and left the rest to whatever the
+/// dumping tool felt like writing there. NES 2.0 reuses those bytes for the things iNES could not
+/// say — a twelve-bit mapper, a submapper, exact RAM sizes, the console — and marks itself with
+/// the bit pattern `10` in byte 7, which no iNES header written by a sane tool has.
+///
+/// `Fds` is a Famicom Disk System image, which has no such header at all. The loader makes one
+/// up for it: mapper 20, the number iNES set aside for the Disk System, and the disk in place of
+/// PRG ROM.
+///
+/// `Unif` is a UNIF image, whose board name has been turned into a mapper number and whose other
+/// fields are filled in as an iNES header would have them. See the `unif` module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderFormat {
+    #[default]
+    INes,
+    Nes2,
+    Fds,
+    Unif,
+}
+
+/// The CPU/PPU timing an NES 2.0 header declares.
+///
+/// `MultiRegion` is a cartridge that detects the console and adapts, and runs on NTSC timing
+/// unless told otherwise, which is what every such game was tested on first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    #[default]
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+impl Timing {
+    fn from_nes2_header(byte12: u8) -> Self {
+        match byte12 & 0x03 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        }
+    }
+
+    /// The console to emulate for this timing.
+    pub const fn region(self) -> crate::region::Region {
+        match self {
+            Timing::Ntsc | Timing::MultiRegion => crate::region::Region::Ntsc,
+            Timing::Pal => crate::region::Region::Pal,
+            Timing::Dendy => crate::region::Region::Dendy,
+        }
+    }
+}
+
+/// The machine a cartridge was made for.
+///
+/// Vs. System and PlayChoice-10 boards are arcade hardware built around the NES chips, with their
+/// own palettes, coin slots and DIP switches, and the header says so in byte 7. That is read but
+/// not acted on: such an image is run as an ordinary cartridge. The extended types
+/// are NES 2.0's catalogue of clones and variants, kept as the raw number — nothing here runs any
+/// of them yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    /// The PPU variant and the protection hardware, from NES 2.0 byte 13. Both zero on iNES.
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice10,
+    Extended(u8),
+}
+
+impl ConsoleType {
+    fn from_header(byte7: u8, byte13: u8, format: HeaderFormat) -> Self {
+        let nes2 = format == HeaderFormat::Nes2;
+        match byte7 & 0x03 {
+            0 => ConsoleType::Nes,
+            1 if nes2 => ConsoleType::VsSystem {
+                ppu: byte13 & 0x0F,
+                hardware: byte13 >> 4,
+            },
+            1 => ConsoleType::VsSystem { ppu: 0, hardware: 0 },
+            2 => ConsoleType::Playchoice10,
+            _ if nes2 => ConsoleType::Extended(byte13 & 0x0F),
+            // iNES has no byte 13 to say which, so all that is known is that it is not an NES.
+            _ => ConsoleType::Extended(0),
+        }
+    }
+}
+
+/// What the header says should be plugged into the controller ports, from NES 2.0 byte 15.
+///
+/// Only the devices worth naming are named; the rest of the list — there are over seventy — is
+/// kept as its number so nothing is lost by parsing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpansionDevice {
+    #[default]
+    Unspecified,
+    StandardControllers,
+    FourScore,
+    FamicomFourPlayers,
+    Zapper,
+    TwoZappers,
+    PowerPad,
+    ArkanoidController,
+    FamilyBasicKeyboard,
+    Other(u8),
+}
+
+impl ExpansionDevice {
+    fn from_nes2_header(byte15: u8) -> Self {
+        match byte15 & 0x3F {
+            0x00 => ExpansionDevice::Unspecified,
+            0x01 => ExpansionDevice::StandardControllers,
+            0x02 => ExpansionDevice::FourScore,
+            0x03 => ExpansionDevice::FamicomFourPlayers,
+            0x08 => ExpansionDevice::Zapper,
+            0x09 => ExpansionDevice::TwoZappers,
+            0x0B | 0x0C => ExpansionDevice::PowerPad,
+            0x0F | 0x10 => ExpansionDevice::ArkanoidController,
+            0x23 => ExpansionDevice::FamilyBasicKeyboard,
+            other => ExpansionDevice::Other(other),
+        }
+    }
+}
+
+/// A parsed iNES or NES 2.0 header.
+///
+/// Sizes are in bytes whatever the format, because NES 2.0's exponent-multiplier notation can
+/// describe images that are not a whole number of 16 KB banks and RAM that is not a whole number
+/// of anything but 64 bytes. Anything an iNES header cannot say is filled in with what the
+/// common boards had, and [`format`](Self::format) records which kind of answer each field is.
+#[derive(Debug, Clone, Default)]
 pub struct INesHeader {
-    pub prg_rom_size: usize, // Size of PRG ROM in 16 KB units
-    pub chr_rom_size: usize, // Size of CHR ROM in 8 KB units
-    pub mapper: u8,          // Mapper number
+    pub format: HeaderFormat,
+    pub prg_rom_size: usize, // Size of PRG ROM in bytes
+    pub chr_rom_size: usize, // Size of CHR ROM in bytes; zero means the board has CHR RAM
+    pub mapper: u16,         // Mapper number, twelve bits on NES 2.0
+    pub submapper: u8,       // Board variant within a mapper; always zero on iNES
     pub mirroring: bool,     // true = vertical, false = horizontal
     pub battery: bool,       // Has battery-backed RAM
     pub trainer: bool,       // Has trainer
     pub four_screen: bool,   // Four-screen VRAM layout
+
+    /// Work RAM at `$6000`, volatile and battery-backed, in bytes.
+    ///
+    /// iNES can only say "8 KB, unless byte 8 says more" and whether a battery is fitted, so a
+    /// battery there puts the whole of it in `prg_nvram_size`.
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+
+    /// Character RAM, volatile and battery-backed, in bytes. iNES implies 8 KB whenever there is
+    /// no CHR ROM, which is right for almost everything and wrong for the boards that carry 16 or
+    /// 32 KB and bank it — the reason NES 2.0 spells it out.
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    /// The timing the header declares. iNES's one bit can only say NTSC or PAL.
+    pub timing: Timing,
+
     /// What the header claims about the TV system, which is worth reading and worth not trusting:
     /// plenty of European releases ship with this clear. See [`Region`](crate::region::Region).
     pub region: crate::region::Region,
+
+    pub console_type: ConsoleType,
+    pub expansion_device: ExpansionDevice,
+}
+
+impl INesHeader {
+    /// A header for `mapper` with nothing else said, as an iNES header with zeros elsewhere reads.
+    ///
+    /// For building a board directly, in tests and tools, without synthesising a file to parse.
+    pub fn for_mapper(mapper: u16) -> Self {
+        Self {
+            mapper,
+            ..Self::default()
+        }
+    }
+
+    /// All of the work RAM the cartridge has at `$6000`, battery-backed or not.
+    pub fn prg_ram_total(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    /// All of the character RAM the cartridge has, or zero when its graphics are in ROM.
+    pub fn chr_ram_total(&self) -> usize {
+        self.chr_ram_size + self.chr_nvram_size
+    }
+
+    /// How the board wires the nametables, as far as the header can say: four-screen when the bit
+    /// is set, and otherwise the mirroring bit. Boards whose mapper switches mirroring only start
+    /// from this.
+    pub fn nametable_mirroring(&self) -> Mirroring {
+        match (self.four_screen, self.mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        }
+    }
+
+    pub fn is_nes2(&self) -> bool {
+        self.format == HeaderFormat::Nes2
+    }
 }
 
 /// A loaded iNES ROM: its header plus both memory images.
@@ -59,11 +277,22 @@ pub struct INesHeader {
 /// PRG-ROM is the program the CPU executes and CHR-ROM the pattern tables the PPU draws from.
 /// Previously only CHR was returned and the PRG data was read purely to seek past it, which meant
 /// no `.nes` file could actually be run.
+///
+/// A disk image loads as one of these too, with its sides in `prg_rom` and no CHR at all; see
+/// [`HeaderFormat::Fds`]. Nothing in it runs without the Disk System's BIOS.
 #[derive(Debug)]
 pub struct Rom {
     pub header: INesHeader,
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
+
+    /// Where the image was read from, which is where its battery save goes. `None` for an image
+    /// that never came from a file, which then has nowhere to keep one.
+    pub path: Option<PathBuf>,
+
+    /// What the cartridge database changed in `header`, when it recognised the ROM and the header
+    /// had something wrong. `header` is already corrected; this is for saying so.
+    pub correction: Option<AppliedCorrection>,
 }
 
 impl Rom {
@@ -86,58 +315,159 @@ impl Rom {
     }
 }
 
-/// Load a complete iNES ROM: header, PRG-ROM and CHR-ROM.
+/// Load a complete iNES ROM: header, PRG-ROM and CHR-ROM. A Famicom Disk System image, with or
+/// without its fwNES header, loads as well, and so does a UNIF image, and any of them zipped or
+/// gzipped.
+///
+/// A patch beside the ROM — the same name with `.ips`, `.ups` or `.bps` — is applied on the way in,
+/// which is how translations and hacks are distributed. [`load_rom_with_patch`] names the patch
+/// instead, or asks for none.
 pub fn load_rom(path: &Path) -> Result<Rom, RomLoadError> {
-    let mut file = File::open(path)?;
+    let patch = patch::beside(path);
+    if let Some(patch) = &patch {
+        info!("Applying {} to {}", patch.display(), path.display());
+    }
+    load_rom_with_patch(path, patch.as_deref())
+}
 
-    // Read the header only if the file is long enough to have one. Letting `read_exact` fail
-    // instead reports "failed to fill whole buffer", which says nothing about the file not being
-    // a ROM.
-    let mut header = [0u8; INES_HEADER_SIZE];
-    if file.read(&mut header)? < INES_HEADER_SIZE {
+/// Load a ROM with `patch` applied to it, or exactly as it is when `patch` is `None`.
+///
+/// The patch is applied to the whole file, header included, since that is what IPS offsets count
+/// from and what UPS and BPS checksums cover. The image it produces is then read as any other.
+pub fn load_rom_with_patch(path: &Path, patch: Option<&Path>) -> Result<Rom, RomLoadError> {
+    load(path, None, patch)
+}
+
+/// Load the ROM named `entry` from the zip archive at `path`, for an archive holding several, with
+/// `patch` applied to it as [`load_rom_with_patch`] would.
+pub fn load_rom_from_archive(path: &Path, entry: &str, patch: Option<&Path>) -> Result<Rom, RomLoadError> {
+    load(path, Some(entry), patch)
+}
+
+fn load(path: &Path, entry: Option<&str>, patch: Option<&Path>) -> Result<Rom, RomLoadError> {
+    let mut bytes = archive::read_rom_file(path, entry)?;
+    if let Some(patch) = patch {
+        bytes = patch::apply(&bytes, &std::fs::read(patch)?)?;
+    }
+    parse_rom(&bytes, Some(path))
+}
+
+/// Read a ROM image already in memory. `path` is where its battery save goes, if anywhere.
+pub fn parse_rom(bytes: &[u8], path: Option<&Path>) -> Result<Rom, RomLoadError> {
+    let path = path.map(Path::to_path_buf);
+
+    // Checked before anything is sliced, so that a short file says it is not a ROM rather than
+    // panicking or failing somewhere that says nothing about why.
+    let Some(header) = bytes.first_chunk::<INES_HEADER_SIZE>() else {
         return Err(RomLoadError::InvalidFormat(
             "file is too small to be an iNES ROM (under 16 bytes)",
         ));
+    };
+
+    if header[0..4] == FWNES_MAGIC || header.starts_with(DISK_MAGIC) {
+        return load_fds(bytes, path);
+    }
+
+    if header[0..4] == UNIF_MAGIC {
+        let (mut header, prg_rom, chr_rom) = unif::parse(bytes)?;
+        let correction = database::correct(&mut header, &prg_rom, &chr_rom);
+        return Ok(Rom {
+            header,
+            prg_rom,
+            chr_rom,
+            path,
+            correction,
+        });
     }
 
     if header[0..4] != INES_MAGIC {
         return Err(RomLoadError::InvalidFormat(
-            "not an iNES ROM (missing the \"NES\\x1A\" signature)",
+            "not an iNES, UNIF or FDS image (missing the \"NES\\x1A\", \"UNIF\" or \"FDS\\x1A\" signature)",
         ));
     }
 
-    let parsed = parse_ines_header(&header)?;
+    let mut parsed = parse_ines_header(header)?;
 
-    // A trainer, when present, sits between the header and the PRG data.
-    if parsed.trainer {
-        let mut trainer = [0u8; 512];
-        file.read_exact(&mut trainer)?;
+    // A trainer, when present, sits between the header and the PRG data, and is skipped.
+    //
+    // NES 2.0's exponent notation can claim sizes far beyond any real cartridge, so the claim is
+    // checked against the file before anything that size is allocated.
+    let data = bytes.get(file_position(&parsed)..).unwrap_or_default();
+    if parsed.prg_rom_size.saturating_add(parsed.chr_rom_size) > data.len() {
+        return Err(RomLoadError::InvalidFormat(
+            "the header declares more PRG and CHR data than the file contains",
+        ));
     }
 
-    let mut prg_rom = vec![0u8; parsed.prg_rom_size * 16 * 1024];
-    file.read_exact(&mut prg_rom)?;
+    let (prg_rom, rest) = data.split_at(parsed.prg_rom_size);
+    let prg_rom = prg_rom.to_vec();
 
     // A CHR size of zero means the cartridge uses CHR RAM rather than ROM, and that is left as an
     // *empty* vector rather than eight kilobytes of zeros. The two are indistinguishable once
     // filled in, and the mappers need to tell them apart: RAM accepts writes and ROM ignores them.
     // Filling it here meant every board looked like RAM, so a cartridge with CHR ROM could have
     // its own tiles overwritten — see `Nrom::write_chr`. Each mapper allocates the 8 KB itself.
-    let chr_rom_size = parsed.chr_rom_size * 8 * 1024;
-    let chr_rom = if chr_rom_size == 0 {
-        Vec::new()
-    } else {
-        let mut chr_rom = vec![0u8; chr_rom_size];
-        file.read_exact(&mut chr_rom)?;
-        chr_rom
-    };
+    let chr_rom = rest[..parsed.chr_rom_size].to_vec();
+
+    // Only now that the data is in hand can the ROM be recognised, and a header that the database
+    // knows better than corrected before anything is built from it.
+    let correction = database::correct(&mut parsed, &prg_rom, &chr_rom);
 
     Ok(Rom {
         header: parsed,
         prg_rom,
         chr_rom,
+        path,
+        correction,
+    })
+}
+
+/// A Famicom Disk System image.
+///
+/// fwNES's header is dropped; it says only how many sides there are, which the length says too.
+/// Without it the image starts straight in with its first side's disk header.
+fn load_fds(bytes: &[u8], path: Option<PathBuf>) -> Result<Rom, RomLoadError> {
+    let disk = if bytes[0..4] == FWNES_MAGIC {
+        bytes[FWNES_HEADER_SIZE..].to_vec()
+    } else {
+        bytes.to_vec()
+    };
+    if !disk.starts_with(DISK_MAGIC) {
+        return Err(RomLoadError::InvalidFormat(
+            "not an FDS image (its first side does not start with a disk header)",
+        ));
+    }
+
+    Ok(Rom {
+        header: INesHeader {
+            format: HeaderFormat::Fds,
+            prg_rom_size: disk.len(),
+            mapper: 20,
+            // What the game writes back to the disk is kept, like a battery save.
+            battery: true,
+            prg_ram_size: 32 * 1024,
+            chr_ram_size: 8 * 1024,
+            ..INesHeader::default()
+        },
+        prg_rom: disk,
+        chr_rom: Vec::new(),
+        path,
+        correction: None,
     })
 }
 
+/// Load the Disk System's BIOS, the 8 KB ROM in the RAM adapter.
+///
+/// Nintendo's, and not something this emulator can ship, so it is whatever file the user points
+/// at — checked only for its size, since the revisions and the fan-made replacements all differ.
+pub fn load_fds_bios(path: &Path) -> Result<Vec<u8>, RomLoadError> {
+    let bios = std::fs::read(path)?;
+    if bios.len() != BIOS_SIZE {
+        return Err(RomLoadError::InvalidFormat("an FDS BIOS is 8 KB, and this file is not"));
+    }
+    Ok(bios)
+}
+
 /// Load CHR ROM data from an iNES format file
 pub fn load_chr_rom(path: &Path) -> Result<Vec<u8>, RomLoadError> {
     // Open the file
@@ -155,6 +485,15 @@ pub fn load_chr_rom(path: &Path) -> Result<Vec<u8>, RomLoadError> {
     // Parse the header
     let parsed_header = parse_ines_header(&header)?;
 
+    // The same check `parse_rom` makes, for the same reason: the buffers below are sized from the
+    // header, so its claim is held against the file's length before anything is allocated.
+    let data_len = (file.metadata()?.len() as usize).saturating_sub(file_position(&parsed_header));
+    if parsed_header.prg_rom_size.saturating_add(parsed_header.chr_rom_size) > data_len {
+        return Err(RomLoadError::InvalidFormat(
+            "the header declares more PRG and CHR data than the file contains",
+        ));
+    }
+
     // Skip the trainer if present (512 bytes)
     if parsed_header.trainer {
         let mut trainer = [0u8; 512];
@@ -162,12 +501,11 @@ pub fn load_chr_rom(path: &Path) -> Result<Vec<u8>, RomLoadError> {
     }
 
     // Skip the PRG ROM data
-    let prg_rom_size = parsed_header.prg_rom_size * 16 * 1024; // Convert to bytes
-    let mut prg_rom = vec![0u8; prg_rom_size];
+    let mut prg_rom = vec![0u8; parsed_header.prg_rom_size];
     file.read_exact(&mut prg_rom)?;
 
     // Read the CHR ROM data
-    let chr_rom_size = parsed_header.chr_rom_size * 8 * 1024; // Convert to bytes
+    let chr_rom_size = parsed_header.chr_rom_size;
 
     // If CHR ROM size is 0, it means the game uses CHR RAM
     if chr_rom_size == 0 {
@@ -180,16 +518,49 @@ pub fn load_chr_rom(path: &Path) -> Result<Vec<u8>, RomLoadError> {
     Ok(chr_rom)
 }
 
-/// Parse an iNES header into a structured format
+/// Where the PRG data starts: after the header, and after the trainer if there is one.
+fn file_position(header: &INesHeader) -> usize {
+    INES_HEADER_SIZE + if header.trainer { 512 } else { 0 }
+}
+
+/// A ROM size from NES 2.0's pair of bytes: the iNES count in the low byte and four more bits in
+/// the high nibble, in units of `unit` bytes.
+///
+/// A high nibble of `$F` switches to exponent-multiplier notation instead — `2^E * (2M + 1)` bytes
+/// with `E` the top six bits of the low byte and `M` the bottom two — which is how a header says
+/// "24 KB" or "1.5 MB" without padding the image out to a power of two. `None` for a size that
+/// does not fit in memory at all, which only a corrupt header produces.
+fn nes2_rom_size(low: u8, high_nibble: u8, unit: usize) -> Option<usize> {
+    if high_nibble == 0x0F {
+        let exponent = (low >> 2) as u32;
+        let multiplier = (low & 0x03) as usize * 2 + 1;
+        1usize.checked_shl(exponent)?.checked_mul(multiplier)
+    } else {
+        Some(((high_nibble as usize) << 8 | low as usize) * unit)
+    }
+}
+
+/// A RAM size from one of NES 2.0's shift counts: zero means none, anything else `64 << count`.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+/// Parse an iNES or NES 2.0 header into a structured format
 fn parse_ines_header(header: &[u8; INES_HEADER_SIZE]) -> Result<INesHeader, RomLoadError> {
     // Validate header size
     if header.len() < INES_HEADER_SIZE {
         return Err(RomLoadError::InvalidFormat("Header too small"));
     }
 
-    // Extract basic information
-    let prg_rom_size = header[4] as usize;
-    let chr_rom_size = header[5] as usize;
+    let format = if header[7] & 0x0C == 0x08 {
+        HeaderFormat::Nes2
+    } else {
+        HeaderFormat::INes
+    };
 
     // Extract flags
     let mirroring = (header[6] & 0x01) != 0;
@@ -200,17 +571,62 @@ fn parse_ines_header(header: &[u8; INES_HEADER_SIZE]) -> Result<INesHeader, RomL
     // Extract mapper number (low and high nibbles)
     let mapper_low = (header[6] & 0xF0) >> 4;
     let mapper_high = header[7] & 0xF0;
-    let mapper = mapper_high | mapper_low;
+    let mapper = (mapper_high | mapper_low) as u16;
+
+    let console_type = ConsoleType::from_header(header[7], header[13], format);
+
+    if format == HeaderFormat::INes {
+        // Byte 8 is PRG RAM in 8 KB units, and zero — which is what nearly every file has — means
+        // the 8 KB most boards fitted rather than none.
+        let prg_ram = (header[8].max(1) as usize) * 8 * 1024;
+        let chr_rom_size = header[5] as usize * 8 * 1024;
+        let timing = if header[9] & 0x01 != 0 { Timing::Pal } else { Timing::Ntsc };
+
+        return Ok(INesHeader {
+            format,
+            region: crate::region::Region::from_ines_header(header[9]),
+            prg_rom_size: header[4] as usize * 16 * 1024,
+            chr_rom_size,
+            mapper,
+            submapper: 0,
+            mirroring,
+            battery,
+            trainer,
+            four_screen,
+            prg_ram_size: if battery { 0 } else { prg_ram },
+            prg_nvram_size: if battery { prg_ram } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { 8 * 1024 } else { 0 },
+            chr_nvram_size: 0,
+            timing,
+            console_type,
+            expansion_device: ExpansionDevice::Unspecified,
+        });
+    }
+
+    let prg_rom_size = nes2_rom_size(header[4], header[9] & 0x0F, 16 * 1024)
+        .ok_or(RomLoadError::InvalidFormat("the header's PRG ROM size is too large to load"))?;
+    let chr_rom_size = nes2_rom_size(header[5], header[9] >> 4, 8 * 1024)
+        .ok_or(RomLoadError::InvalidFormat("the header's CHR ROM size is too large to load"))?;
+    let timing = Timing::from_nes2_header(header[12]);
 
     Ok(INesHeader {
-        region: crate::region::Region::from_ines_header(header[9]),
+        format,
+        region: timing.region(),
         prg_rom_size,
         chr_rom_size,
-        mapper,
+        mapper: mapper | ((header[8] & 0x0F) as u16) << 8,
+        submapper: header[8] >> 4,
         mirroring,
         battery,
         trainer,
         four_screen,
+        prg_ram_size: nes2_ram_size(header[10] & 0x0F),
+        prg_nvram_size: nes2_ram_size(header[10] >> 4),
+        chr_ram_size: nes2_ram_size(header[11] & 0x0F),
+        chr_nvram_size: nes2_ram_size(header[11] >> 4),
+        timing,
+        console_type,
+        expansion_device: ExpansionDevice::from_nes2_header(header[15]),
     })
 }
 
@@ -240,12 +656,60 @@ mod tests {
         let parsed = parse_ines_header(&header).unwrap();
 
         // Verify the parsed data
-        assert_eq!(parsed.prg_rom_size, 2);
-        assert_eq!(parsed.chr_rom_size, 1);
+        assert_eq!(parsed.prg_rom_size, 32 * 1024);
+        assert_eq!(parsed.chr_rom_size, 8 * 1024);
         assert_eq!(parsed.mapper, 1);
         assert!(parsed.mirroring);
         assert!(!parsed.battery);
         assert!(!parsed.trainer);
         assert!(!parsed.four_screen);
+        assert_eq!(parsed.format, HeaderFormat::INes);
+        assert_eq!(parsed.prg_ram_total(), 8 * 1024, "iNES implies 8 KB of work RAM");
+        assert_eq!(parsed.chr_ram_total(), 0, "a board with CHR ROM has no CHR RAM");
+    }
+
+    #[test]
+    fn disk_images_load_with_or_without_the_fwnes_header() {
+        let side = super::super::fds_disk::side_with_file(&[1, 2, 3]);
+        let directory = std::env::temp_dir();
+        for (name, file) in [
+            ("raw", side.clone()),
+            ("fwnes", [&FWNES_MAGIC[..], &[1], &[0; 11], &side].concat()),
+        ] {
+            let path = directory.join(format!("{}_{}.fds", std::process::id(), name));
+            std::fs::write(&path, &file).unwrap();
+            let rom = load_rom(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(rom.header.format, HeaderFormat::Fds, "{name}");
+            assert_eq!(rom.prg_rom, side, "{name}");
+            assert!(rom.chr_rom.is_empty());
+        }
+    }
+
+    #[test]
+    fn unif_images_load_through_load_rom() {
+        let file = super::super::unif::unif_file(
+            "NES-CNROM",
+            &[(b"PRG0", &[0xEA; 32 * 1024]), (b"CHR0", &[7; 8 * 1024])],
+        );
+        let path = std::env::temp_dir().join(format!("{}_cnrom.unf", std::process::id()));
+        std::fs::write(&path, &file).unwrap();
+        let rom = load_rom(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rom.header.format, HeaderFormat::Unif);
+        assert_eq!(rom.header.mapper, 3);
+        assert_eq!(rom.prg_rom, vec![0xEA; 32 * 1024]);
+        assert_eq!(rom.chr_rom, vec![7; 8 * 1024]);
+    }
+
+    /// Exponent-multiplier sizes: `2^E * (2M + 1)`, for images that are not a power of two.
+    #[test]
+    fn nes2_exponent_multiplier_sizes() {
+        assert_eq!(nes2_rom_size(0x02, 0x0, 16 * 1024), Some(32 * 1024), "plain count");
+        assert_eq!(nes2_rom_size(0x00, 0x1, 16 * 1024), Some(256 * 16 * 1024), "high nibble");
+        // E = 13, M = 1: 8 KB * 3 = 24 KB.
+        assert_eq!(nes2_rom_size(13 << 2 | 1, 0xF, 16 * 1024), Some(24 * 1024));
+        // E = 63 does not fit, and must be refused rather than wrap.
+        assert_eq!(nes2_rom_size(63 << 2 | 3, 0xF, 16 * 1024), None);
     }
 }
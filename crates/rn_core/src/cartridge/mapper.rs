@@ -9,7 +9,23 @@
 //! modelled as plain ROM: `STA $8000` is not a discarded write to read-only memory, it is a bank
 //! switch, and treating cartridge space as RAM silently corrupts the program instead.
 
-use super::Mirroring;
+use super::{
+    bandai_fcg::{BandaiFcg, FcgBoard},
+    discrete::{Discrete, DiscreteBoard},
+    fme7::Fme7,
+    mmc2::Mmc2,
+    mmc3::Mmc3,
+    mmc5::Mmc5,
+    namco163::Namco163,
+    sunsoft4::Sunsoft4,
+    unrom512::{Unrom512, Unrom512Nametables},
+    vrc24::Vrc24,
+    vrc6::Vrc6,
+    vrc7::Vrc7,
+    INesHeader,
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip};
 
 /// A cartridge's bank-switching hardware.
 ///
@@ -73,14 +89,304 @@ pub trait Mapper: std::fmt::Debug {
     /// with an address of $1000 or more raises A12 and clocks the counter, with no scanline
     /// involved at all.
     fn on_ppu_address(&mut self, _address: u16) {}
+
+    /// Whether the cartridge answers at `address`, which is below `$8000`.
+    ///
+    /// Cartridge space begins at `$4020`, not at `$8000`, but almost every board leaves the lower
+    /// part to the work RAM at `$6000` or to nothing at all — so the bus only asks. A mapper with
+    /// registers or RAM of its own down there claims the addresses here, separately for reads and
+    /// writes, so a write-only register still reads back as open bus.
+    fn maps_cpu_address(&self, _address: u16, _write: bool) -> bool {
+        false
+    }
+
+    /// Which bits of a read from `address` the board leaves undriven. See
+    /// `Addressable::open_bus_mask`; zero, all eight driven, for everything but the odd register
+    /// narrower than a byte, such as VRC2's one-bit latch at `$6000`.
+    fn open_bus_mask(&self, _address: u16) -> u8 {
+        0
+    }
+
+    /// Read a nametable byte for the PPU, `$2000..=$2FFF`.
+    ///
+    /// `ciram` is the console's own 2 KB of nametable RAM, followed by the 2 KB a four-screen board
+    /// adds (see [`NAMETABLE_RAM`](crate::ppu::NAMETABLE_RAM)). The default places the four logical
+    /// tables in it by [`mirroring`](Self::mirroring), which is all most boards do: they only
+    /// choose how the console's RAM is wired, and a four-screen board only has its mirroring say
+    /// so. A board that supplies nametable data itself overrides this — MMC5 with its ExRAM and
+    /// fill mode, Sunsoft-4 and Namco 163 with banks of CHR ROM — and can ignore `ciram` entirely.
+    ///
+    /// Asked at every read rather than once at load, because the mirroring is the mapper's to
+    /// change whenever the game likes. Setting it on the PPU at load time meant MMC1 and MMC3
+    /// games kept the header's arrangement for ever, whatever they wrote.
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        ciram[self.mirroring().ciram_offset(address)]
+    }
+
+    /// Write a nametable byte from the PPU. The counterpart of [`read_nametable`](Self::read_nametable).
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        ciram[self.mirroring().ciram_offset(address)] = value;
+    }
+
+    /// Whether [`on_ppu_fetch`](Self::on_ppu_fetch) wants calling.
+    ///
+    /// Asked once, when the mapper is connected. The PPU makes some forty thousand rendering reads
+    /// a frame, and a call for each into a mapper that ignores them is cost for nothing.
+    fn watches_ppu_fetches(&self) -> bool {
+        false
+    }
+
+    /// Notify the mapper of a rendering read, just before the PPU makes it.
+    ///
+    /// Only called for a mapper whose [`watches_ppu_fetches`](Self::watches_ppu_fetches) says so.
+    fn on_ppu_fetch(&mut self, _address: u16, _fetch: PpuFetch) {}
+
+    /// Notify the mapper of a CPU write to a PPU register, `$2000..=$2007`.
+    ///
+    /// The cartridge connector carries the whole CPU bus, so a board can watch writes that are not
+    /// addressed to it. MMC5 does exactly that to learn whether sprites are 8x16.
+    fn on_ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    /// Whether [`cpu_cycle`](Self::cpu_cycle) wants calling.
+    ///
+    /// Asked once, when the mapper is connected, for the same reason as
+    /// [`watches_ppu_fetches`](Self::watches_ppu_fetches): a call every CPU cycle is nearly two
+    /// million a second, and most boards have nothing on them that a cycle moves. Those that do —
+    /// the VRCs, FME-7, Namco 163, Bandai's FCG and MMC5 — say so here.
+    fn counts_cpu_cycles(&self) -> bool {
+        false
+    }
+
+    /// Advance the cartridge by one CPU cycle.
+    ///
+    /// For the hardware on a board that runs from the CPU's clock rather than from anything the
+    /// PPU does: expansion sound, and IRQ counters that count cycles. Only called for a mapper
+    /// whose [`counts_cpu_cycles`](Self::counts_cpu_cycles) says so.
+    fn cpu_cycle(&mut self) {}
+
+    /// The sound chip on the board, if there is one.
+    ///
+    /// Asked once, when the mapper is connected. The APU only collects
+    /// [`audio_output`](Self::audio_output) from a board that has a chip, and mixes it at that
+    /// chip's level.
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        None
+    }
+
+    /// The cartridge's own sound at this moment, in the units of the APU's mix, where the console's
+    /// five channels together reach about 1.0. Silence is 0.0, which is what boards without a
+    /// sound chip return. Collected after each [`cpu_cycle`](Self::cpu_cycle), and only from a
+    /// board whose [`audio_chip`](Self::audio_chip) names one.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// The cartridge's sound channels, for showing beside the console's own. None for a board
+    /// without a sound chip.
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        Vec::new()
+    }
+
+    /// Switch one of [`audio_channels`](Self::audio_channels) on or off, through whatever enable
+    /// bit the chip has for it.
+    fn set_audio_channel_enabled(&mut self, _index: usize, _enabled: bool) {}
+
+    /// All of the board's program RAM, when the board keeps it itself rather than leaving a plain
+    /// 8 KB at `$6000` to the bus.
+    ///
+    /// A battery save has to be all of it, and a board that banks its RAM only ever shows one
+    /// window of it at `$6000` — so reading the save through the CPU's bus, as works for
+    /// everything else, would keep whichever bank happened to be switched in.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// The same RAM, to restore a save into.
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// The parts of the board's PRG flash the game has rewritten, in the form the file beside the
+    /// ROM keeps them, or `None` for a board whose program the game cannot rewrite.
+    ///
+    /// For the boards that save by reprogramming their own ROM instead of keeping RAM powered. The
+    /// system writes this out as it does battery RAM, and hands it back through
+    /// [`restore_flash_save`](Self::restore_flash_save) on the next load; what is in it is the
+    /// board's business.
+    fn flash_save(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Put back what [`flash_save`](Self::flash_save) gave, from an earlier run.
+    fn restore_flash_save(&mut self, _save: &[u8]) {}
+
+    /// How many disk sides there are to put in the drive: none, for anything but the Disk System.
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    /// The side in the drive, or the one on its way in while the last is swapped out. `None` with
+    /// the drive empty.
+    fn disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    /// Take out whatever disk is in the drive and, after the time a hand takes to do it, put in
+    /// `side`, or nothing.
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    /// Emulate bus conflicts or not, whatever the board would otherwise do.
+    ///
+    /// A board whose bank latch sits on the data bus with nothing to stop the ROM answering at the
+    /// same address sees both drivers at once, and gets the AND of the two — a zero from either
+    /// side wins. Games written for such a board write a value equal to the ROM byte at the address
+    /// they write to, and so never notice. Some homebrew and some hacks were only ever run on
+    /// emulators that leave the conflict out, and need it left out here too; this is for them.
+    /// Only the boards with a latch in ROM space do anything with it.
+    fn set_bus_conflicts(&mut self, _enabled: bool) {}
+
+    /// Where each part of CPU space currently points: which bank of the program ROM, or of the
+    /// board's RAM, the CPU sees there. In address order, and only the ranges the board maps.
+    ///
+    /// For the debugger and the trace logger rather than for emulation, which goes through
+    /// [`read_prg`](Self::read_prg) and never needs to ask. Empty for a board that does not say.
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        Vec::new()
+    }
+
+    /// Where each part of pattern-table space currently points, as
+    /// [`prg_windows`](Self::prg_windows) does for CPU space.
+    ///
+    /// A board that switches its banks partway through a line — MMC2's latches, MMC5's separate
+    /// sprite banks — shows the ones the next read would use.
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        Vec::new()
+    }
+
+    /// The board's registers as the game last wrote them, by the names its documentation uses.
+    fn registers(&self) -> Vec<MapperRegister> {
+        Vec::new()
+    }
+
+    /// The IRQ counter, for a board that has one.
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        None
+    }
+}
+
+/// Which memory a [`BankWindow`] shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankMemory {
+    /// The image's PRG or CHR ROM.
+    Rom,
+    /// RAM on the board: work RAM in CPU space, character RAM in PPU space.
+    Ram,
+}
+
+/// A range of CPU or PPU addresses and where in the cartridge's memory it currently points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BankWindow {
+    /// The first address in the window.
+    pub start: u16,
+    /// The window's length in bytes, which is also the size of the banks switched into it.
+    pub size: usize,
+    pub memory: BankMemory,
+    /// Where in `memory` the window begins.
+    pub offset: usize,
+}
+
+impl BankWindow {
+    pub fn new(start: u16, size: usize, memory: BankMemory, offset: usize) -> Self {
+        Self {
+            start,
+            size,
+            memory,
+            offset,
+        }
+    }
+
+    /// Bank `bank` of `size`-byte banks in `len` bytes of memory, wrapped as [`banked`] wraps it.
+    pub(super) fn banked(start: u16, size: usize, memory: BankMemory, bank: usize, len: usize) -> Self {
+        let banks = (len / size).max(1);
+        Self::new(start, size, memory, (bank % banks) * size)
+    }
+
+    /// The last address in the window.
+    pub fn end(&self) -> u16 {
+        self.start.saturating_add((self.size - 1) as u16)
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end()).contains(&address)
+    }
+
+    /// The bank number, counted in banks of the window's size.
+    pub fn bank(&self) -> usize {
+        self.offset / self.size
+    }
+
+    /// Where in `memory` `address` reads from, if it is in the window.
+    pub fn offset_of(&self, address: u16) -> Option<usize> {
+        self.contains(address)
+            .then(|| self.offset + usize::from(address - self.start))
+    }
 }
 
-const PRG_BANK: usize = 8 * 1024;
-const CHR_BANK: usize = 1024;
+/// One of a board's registers, named and with its current value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapperRegister {
+    pub name: String,
+    pub value: u16,
+}
+
+impl MapperRegister {
+    pub fn new(name: impl Into<String>, value: impl Into<u16>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// A board's IRQ counter: where it has got to, what it reloads from, and whether it will fire or
+/// has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IrqCounter {
+    pub counter: u32,
+    /// What the counter reloads from. For a board whose counter is written directly, with
+    /// nothing to reload it from, the same as `counter`.
+    pub latch: u32,
+    pub enabled: bool,
+    pub pending: bool,
+}
+
+/// Which of the PPU's rendering reads an access is.
+///
+/// A cartridge sees only the address bus, and a mapper that cares which read is which — MMC5, to
+/// give sprites and background their own character banks — works it out on hardware from the
+/// pattern of addresses going past. The PPU knows it outright, so it says so, and what the mapper
+/// does with the answer stays the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuFetch {
+    /// A background tile's nametable byte.
+    Nametable,
+    /// A background tile's attribute byte.
+    Attribute,
+    /// One of a background tile's two pattern bytes.
+    Background,
+    /// A sprite's pattern bytes.
+    Sprite,
+    /// A nametable read whose result is thrown away: two for each sprite slot, and two more at the
+    /// very end of a line. Hardware makes them, so a mapper counting reads sees them.
+    Dummy,
+}
+
+pub(super) const PRG_BANK: usize = 8 * 1024;
+pub(super) const CHR_BANK: usize = 1024;
 
 /// Read a byte from `data` as if it were `bank`-sized windows, wrapping if the bank is out of
 /// range — which is what a real cartridge's address lines do.
-fn banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
+pub(super) fn banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
     if data.is_empty() {
         return 0;
     }
@@ -93,6 +399,57 @@ fn banked(data: &[u8], bank: usize, bank_size: usize, offset: usize) -> u8 {
     data[((bank % banks) * bank_size + offset) % data.len()]
 }
 
+/// `count` banks of `size` bytes, each filled with its own number, so that a read in a test says
+/// which bank answered. Past bank 255 the numbers wrap.
+#[cfg(test)]
+pub(super) fn numbered_banks(count: usize, size: usize) -> Vec<u8> {
+    (0..count).flat_map(|bank| vec![bank as u8; size]).collect()
+}
+
+/// What a bank latch in ROM space receives from a CPU write of `value`: the ROM drives `rom` onto
+/// the same lines, so with bus conflicts the two are ANDed. See [`Mapper::set_bus_conflicts`].
+pub(super) fn latched(value: u8, rom: u8, bus_conflicts: bool) -> u8 {
+    if bus_conflicts {
+        value & rom
+    } else {
+        value
+    }
+}
+
+/// Replace a board's character RAM with `bytes` of it, where the header says it has more than the
+/// 8 KB every constructor assumes.
+///
+/// Only the boards that bank CHR can reach the rest, and only a board with RAM in the first place
+/// is resized: an NES 2.0 header declaring CHR RAM alongside CHR ROM describes hardware none of
+/// these mappers has.
+pub(super) fn resize_chr_ram(chr_is_ram: bool, chr: &mut Vec<u8>, bytes: usize) {
+    if chr_is_ram && bytes > chr.len() {
+        *chr = vec![0; bytes];
+    }
+}
+
+/// Which memory a board's pattern tables are: RAM when the header said no CHR ROM.
+pub(super) fn chr_memory(chr_is_ram: bool) -> BankMemory {
+    if chr_is_ram {
+        BankMemory::Ram
+    } else {
+        BankMemory::Rom
+    }
+}
+
+/// The windows of a board that puts its whole program at `$8000` without banking it, as NROM
+/// does: one 32 KB window, or a 16 KB image shown twice.
+pub(super) fn unbanked_prg_windows(len: usize) -> Vec<BankWindow> {
+    if len > 16 * 1024 {
+        vec![BankWindow::new(0x8000, 32 * 1024, BankMemory::Rom, 0)]
+    } else {
+        vec![
+            BankWindow::new(0x8000, 16 * 1024, BankMemory::Rom, 0),
+            BankWindow::new(0xC000, 16 * 1024, BankMemory::Rom, 0),
+        ]
+    }
+}
+
 /// NROM (mapper 0): no banking at all.
 ///
 /// The whole ROM is visible at once. A 16 KB image is mirrored into both halves of `$8000..=$FFFF`,
@@ -159,6 +516,14 @@ impl Mapper for Nrom {
     fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        unbanked_prg_windows(self.prg.len())
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x0000, 8 * 1024, chr_memory(self.chr_is_ram), 0)]
+    }
 }
 
 /// UxROM (mapper 2): one switchable 16 KB PRG bank, plus a fixed last bank.
@@ -175,6 +540,7 @@ pub struct UxRom {
     chr: Vec<u8>,
     bank: usize,
     mirroring: Mirroring,
+    bus_conflicts: bool,
 }
 
 impl UxRom {
@@ -188,9 +554,17 @@ impl UxRom {
             chr,
             bank: 0,
             mirroring,
+            bus_conflicts: false,
         }
     }
 
+    /// Build the board with bus conflicts, as UNROM and UOROM have. See
+    /// [`Mapper::set_bus_conflicts`].
+    pub fn with_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.bus_conflicts = enabled;
+        self
+    }
+
     fn last_bank(&self) -> usize {
         (self.prg.len() / (16 * 1024)).saturating_sub(1)
     }
@@ -202,9 +576,9 @@ impl Mapper for UxRom {
         banked(&self.prg, bank, 16 * 1024, address as usize & 0x3FFF)
     }
 
-    fn write_prg(&mut self, _address: u16, value: u8) {
+    fn write_prg(&mut self, address: u16, value: u8) {
         // Any write into cartridge space selects the low bank.
-        self.bank = value as usize & 0x0F;
+        self.bank = latched(value, self.read_prg(address), self.bus_conflicts) as usize & 0x0F;
     }
 
     fn read_chr(&self, address: u16) -> u8 {
@@ -230,6 +604,26 @@ impl Mapper for UxRom {
     fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let len = self.prg.len();
+        vec![
+            BankWindow::banked(0x8000, 16 * 1024, BankMemory::Rom, self.bank, len),
+            BankWindow::banked(0xC000, 16 * 1024, BankMemory::Rom, self.last_bank(), len),
+        ]
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x0000, 8 * 1024, chr_memory(self.chr_is_ram), 0)]
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![MapperRegister::new("PRG bank", self.bank as u16)]
+    }
 }
 
 /// CNROM (mapper 3): fixed program, switchable character banks.
@@ -252,6 +646,7 @@ pub struct CnRom {
     chr: Vec<u8>,
     bank: usize,
     mirroring: Mirroring,
+    bus_conflicts: bool,
 }
 
 impl CnRom {
@@ -265,9 +660,23 @@ impl CnRom {
             chr,
             bank: 0,
             mirroring,
+            bus_conflicts: false,
         }
     }
 
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Build the board with bus conflicts, as every Nintendo CNROM board has. See
+    /// [`Mapper::set_bus_conflicts`].
+    pub fn with_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.bus_conflicts = enabled;
+        self
+    }
+
     fn banks(&self) -> usize {
         (self.chr.len() / (8 * 1024)).max(1)
     }
@@ -283,8 +692,9 @@ impl Mapper for CnRom {
         self.prg[offset % self.prg.len()]
     }
 
-    fn write_prg(&mut self, _address: u16, value: u8) {
+    fn write_prg(&mut self, address: u16, value: u8) {
         // No address decoding: any write into cartridge space lands here.
+        let value = latched(value, self.read_prg(address), self.bus_conflicts);
         self.bank = (value as usize) % self.banks();
     }
 
@@ -310,6 +720,23 @@ impl Mapper for CnRom {
     fn mirroring(&self) -> Mirroring {
         self.mirroring
     }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        unbanked_prg_windows(self.prg.len())
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        vec![BankWindow::banked(0x0000, 8 * 1024, memory, self.bank, self.chr.len())]
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![MapperRegister::new("CHR bank", self.bank as u16)]
+    }
 }
 
 /// MMC1 (mapper 1): the most common mapper, configured through a serial shift register.
@@ -355,6 +782,12 @@ impl Mmc1 {
         }
     }
 
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
     fn prg_banks_16k(&self) -> usize {
         (self.prg.len() / (16 * 1024)).max(1)
     }
@@ -492,6 +925,35 @@ impl Mapper for Mmc1 {
             _ => Mirroring::Horizontal,
         }
     }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        [0x8000, 0xC000]
+            .into_iter()
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, 16 * 1024, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        [0x0000, 0x1000]
+            .into_iter()
+            .map(|start| BankWindow::banked(start, 4 * 1024, memory, self.chr_bank_for(start), self.chr.len()))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("Control", self.control),
+            MapperRegister::new("CHR bank 0", self.chr_bank_0),
+            MapperRegister::new("CHR bank 1", self.chr_bank_1),
+            MapperRegister::new("PRG bank", self.prg_bank),
+            MapperRegister::new("Shift register", self.shift),
+            MapperRegister::new("Shift count", self.shift_count),
+        ]
+    }
 }
 
 /// AxROM (mapper 7): 32 KB PRG banking with single-screen mirroring.
@@ -507,6 +969,7 @@ pub struct AxRom {
     chr: Vec<u8>,
     bank: usize,
     upper_screen: bool,
+    bus_conflicts: bool,
 }
 
 impl AxRom {
@@ -519,8 +982,16 @@ impl AxRom {
             chr,
             bank: 0,
             upper_screen: false,
+            bus_conflicts: false,
         }
     }
+
+    /// Build the board with bus conflicts, as AMROM and AOROM have and ANROM does not. See
+    /// [`Mapper::set_bus_conflicts`].
+    pub fn with_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.bus_conflicts = enabled;
+        self
+    }
 }
 
 impl Mapper for AxRom {
@@ -528,7 +999,8 @@ impl Mapper for AxRom {
         banked(&self.prg, self.bank, 32 * 1024, address as usize & 0x7FFF)
     }
 
-    fn write_prg(&mut self, _address: u16, value: u8) {
+    fn write_prg(&mut self, address: u16, value: u8) {
+        let value = latched(value, self.read_prg(address), self.bus_conflicts);
         self.bank = (value & 0x07) as usize;
         // Bit 4 picks which single screen the whole background uses.
         self.upper_screen = (value & 0x10) != 0;
@@ -561,265 +1033,69 @@ impl Mapper for AxRom {
             Mirroring::SingleScreenLower
         }
     }
-}
 
-/// MMC3 (mapper 4): eight banks plus a scanline counter.
-///
-/// Used by Super Mario Bros 3 among many others. Two PRG banks are switchable and two fixed; CHR
-/// is split into six windows. Its scanline IRQ is what games use to split the screen — SMB3's
-/// status bar is exactly that.
-#[derive(Debug)]
-pub struct Mmc3 {
-    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
-    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
-    chr_is_ram: bool,
-    prg: Vec<u8>,
-    chr: Vec<u8>,
-
-    /// Which of the eight bank registers the next `$8001` write targets, plus the mode bits.
-    bank_select: u8,
-    banks: [u8; 8],
-
-    mirroring: Mirroring,
-
-    irq_latch: u8,
-    irq_counter: u8,
-    irq_enabled: bool,
-    irq_pending: bool,
-    irq_reload: bool,
-    /// The last state of PPU address bit 12, so a rise can be told from a steady level.
-    a12_high: bool,
-}
-
-impl Mmc3 {
-    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
-        let chr_is_ram = chr.is_empty();
-        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
-        Self {
-            chr_is_ram,
-            prg,
-            chr,
-            bank_select: 0,
-            banks: [0; 8],
-            mirroring,
-            irq_latch: 0,
-            irq_counter: 0,
-            irq_enabled: false,
-            irq_pending: false,
-            irq_reload: false,
-            a12_high: false,
-        }
-    }
-
-    fn prg_banks(&self) -> usize {
-        (self.prg.len() / PRG_BANK).max(1)
-    }
-
-    /// Which 8 KB PRG bank appears in each of the four windows.
-    ///
-    /// Bit 6 of the bank-select register swaps which of the two switchable windows is which; the
-    /// last bank is always fixed at `$E000`, so the vectors never move.
-    fn prg_bank_for(&self, address: u16) -> usize {
-        let last = self.prg_banks() - 1;
-        let second_last = last.saturating_sub(1);
-        let swapped = (self.bank_select & 0x40) != 0;
-
-        match address {
-            0x8000..=0x9FFF => {
-                if swapped {
-                    second_last
-                } else {
-                    self.banks[6] as usize
-                }
-            },
-            0xA000..=0xBFFF => self.banks[7] as usize,
-            0xC000..=0xDFFF => {
-                if swapped {
-                    self.banks[6] as usize
-                } else {
-                    second_last
-                }
-            },
-            _ => last,
-        }
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
     }
 
-    /// Which 1 KB CHR bank appears at `address`.
-    ///
-    /// Bit 7 of the bank-select register swaps the 2 KB and 1 KB halves of pattern space.
-    fn chr_bank_for(&self, address: u16) -> usize {
-        let address = address & 0x1FFF;
-        let inverted = (self.bank_select & 0x80) != 0;
-        let slot = if inverted { (address ^ 0x1000) >> 10 } else { address >> 10 };
-
-        match slot {
-            0 => (self.banks[0] & 0xFE) as usize,
-            1 => (self.banks[0] | 0x01) as usize,
-            2 => (self.banks[1] & 0xFE) as usize,
-            3 => (self.banks[1] | 0x01) as usize,
-            4 => self.banks[2] as usize,
-            5 => self.banks[3] as usize,
-            6 => self.banks[4] as usize,
-            _ => self.banks[5] as usize,
-        }
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::banked(0x8000, 32 * 1024, BankMemory::Rom, self.bank, self.prg.len())]
     }
-}
 
-impl Mapper for Mmc3 {
-    fn read_prg(&self, address: u16) -> u8 {
-        banked(&self.prg, self.prg_bank_for(address), PRG_BANK, address as usize & 0x1FFF)
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x0000, 8 * 1024, chr_memory(self.chr_is_ram), 0)]
     }
 
-    fn write_prg(&mut self, address: u16, value: u8) {
-        // Registers are selected by the address's high bits and whether it is even or odd.
-        match (address & 0xE001, address) {
-            (0x8000, _) => self.bank_select = value,
-            (0x8001, _) => {
-                let index = (self.bank_select & 0x07) as usize;
-                self.banks[index] = value;
-            },
-            (0xA000, _) => {
-                self.mirroring = if value & 0x01 == 0 {
-                    Mirroring::Vertical
-                } else {
-                    Mirroring::Horizontal
-                };
-            },
-            (0xA001, _) => { /* PRG-RAM protect; not modelled */ },
-            (0xC000, _) => self.irq_latch = value,
-            (0xC001, _) => self.irq_reload = true,
-            (0xE000, _) => {
-                self.irq_enabled = false;
-                self.irq_pending = false;
-            },
-            (0xE001, _) => self.irq_enabled = true,
-            _ => {},
-        }
-    }
-
-    fn read_chr(&self, address: u16) -> u8 {
-        banked(&self.chr, self.chr_bank_for(address), CHR_BANK, address as usize & 0x03FF)
-    }
-
-    fn write_chr(&mut self, address: u16, value: u8) {
-        // Only a board with CHR *RAM* accepts this; one with CHR ROM ignores it. The comment here
-        // said so for a long time while the code wrote regardless, and that is not harmless: a
-        // cartridge whose tiles are in ROM, running a program that clears all of VRAM at startup,
-        // had its own character data overwritten with zeros and drew a blank screen for ever
-        // after. `ny2011` is such a program — its nametables and attributes were right, its
-        // pattern fetches all zero — and it is why this is now checked rather than described.
-        if !self.chr_is_ram {
-            return;
-        }
-        let bank = self.chr_bank_for(address);
-        let banks = (self.chr.len() / CHR_BANK).max(1);
-        let index = (bank % banks) * CHR_BANK + (address as usize & 0x03FF);
-        if index < self.chr.len() {
-            self.chr[index] = value;
-        }
-    }
-
-    fn mirroring(&self) -> Mirroring {
-        self.mirroring
-    }
-
-    fn irq_pending(&self) -> bool {
-        self.irq_pending
-    }
-
-    fn acknowledge_irq(&mut self) {
-        self.irq_pending = false;
-    }
-
-    /// Count down one scanline, raising the IRQ when the counter reaches zero.
-    ///
-    /// This is what a game uses to know it has reached a particular line — the mechanism behind
-    /// a status bar that stays put while the playfield scrolls.
-    fn save_state(&self) -> Vec<u8> {
-        let mut state = vec![self.bank_select];
-        state.extend_from_slice(&self.banks);
-        state.extend_from_slice(&[
-            self.irq_latch,
-            self.irq_counter,
-            u8::from(self.irq_enabled),
-            u8::from(self.irq_pending),
-            u8::from(self.irq_reload),
-            u8::from(self.a12_high),
-            self.mirroring as u8,
-        ]);
-        state
-    }
-
-    fn load_state(&mut self, state: &[u8]) {
-        if state.len() < 16 {
-            return;
-        }
-
-        self.bank_select = state[0];
-        self.banks.copy_from_slice(&state[1..9]);
-        self.irq_latch = state[9];
-        self.irq_counter = state[10];
-        self.irq_enabled = state[11] != 0;
-        self.irq_pending = state[12] != 0;
-        self.irq_reload = state[13] != 0;
-        self.a12_high = state[14] != 0;
-        self.mirroring = Mirroring::from_index(state[15]);
-    }
-
-    fn on_ppu_address(&mut self, address: u16) {
-        let high = (address & 0x1000) != 0;
-        // Only the transition counts. A run of fetches from the upper half of pattern memory is
-        // one rise, not one per fetch.
-        if high && !self.a12_high {
-            self.clock_irq_counter();
-        }
-        self.a12_high = high;
-    }
-
-    fn on_scanline(&mut self) {
-        self.clock_irq_counter();
-    }
-}
-
-impl Mmc3 {
-    /// One step of the scanline counter, however it was clocked.
-    ///
-    /// Reload is deferred rather than immediate: writing $C001 does not load the latch there and
-    /// then, it arranges for the *next* clock to load it. That is why a game rewrites the latch
-    /// and then waits, rather than expecting the new value to take effect at once.
-    fn clock_irq_counter(&mut self) {
-        if self.irq_counter == 0 || self.irq_reload {
-            self.irq_counter = self.irq_latch;
-            self.irq_reload = false;
-        } else {
-            self.irq_counter -= 1;
-        }
-
-        if self.irq_counter == 0 && self.irq_enabled {
-            self.irq_pending = true;
-        }
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("PRG bank", self.bank as u16),
+            MapperRegister::new("Upper screen", self.upper_screen),
+        ]
     }
 }
 
-/// Build the mapper a ROM's header asks for.
-///
-/// Returns `None` for schemes that are not implemented, so the caller can say so plainly rather
-/// than running the game with silently wrong banking.
 /// The mappers this emulator implements, as `(number, name)`.
 ///
 /// Kept beside `create` so the two cannot disagree — a list that claims support the factory does
 /// not provide is worse than no list.
-pub const SUPPORTED: [(u8, &str); 6] = [
+pub const SUPPORTED: [(u16, &str); 33] = [
     (0, "NROM"),
     (1, "MMC1"),
     (2, "UxROM"),
     (3, "CNROM"),
     (4, "MMC3"),
+    (5, "MMC5"),
     (7, "AxROM"),
+    (9, "MMC2"),
+    (10, "MMC4"),
+    (11, "Color Dreams"),
+    (13, "CPROM"),
+    (16, "Bandai FCG"),
+    (19, "Namco 163"),
+    (21, "VRC4"),
+    (22, "VRC2"),
+    (23, "VRC2/VRC4"),
+    (24, "VRC6a"),
+    (25, "VRC2/VRC4"),
+    (26, "VRC6b"),
+    (30, "UNROM 512"),
+    (34, "BNROM/NINA-001"),
+    (66, "GxROM"),
+    (68, "Sunsoft-4"),
+    (69, "FME-7/5B"),
+    (71, "Camerica"),
+    (79, "NINA-03/06"),
+    (85, "VRC7"),
+    (118, "TxSROM"),
+    (119, "TQROM"),
+    (153, "Bandai LZ93D50 with RAM"),
+    (157, "Datach"),
+    (159, "Bandai LZ93D50 with 24C01"),
+    (232, "Quattro"),
 ];
 
 /// The name of a mapper, if it is implemented.
-pub fn name(number: u8) -> Option<&'static str> {
+pub fn name(number: u16) -> Option<&'static str> {
     SUPPORTED
         .iter()
         .find(|(supported, _)| *supported == number)
@@ -835,16 +1111,100 @@ pub fn supported_list() -> String {
         .join(", ")
 }
 
-pub fn create(number: u8, prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Option<Box<dyn Mapper>> {
-    match number {
+/// Build the mapper a ROM's header asks for.
+///
+/// Returns `None` for schemes that are not implemented, so the caller can say so plainly rather
+/// than running the game with silently wrong banking.
+///
+/// The whole header rather than just the mapper number, because the number alone does not
+/// describe a board: the submapper picks between variants that share one, and an NES 2.0 header
+/// says how much CHR RAM there is instead of leaving each mapper to assume 8 KB.
+pub fn create(header: &INesHeader, prg: Vec<u8>, chr: Vec<u8>) -> Option<Box<dyn Mapper>> {
+    let mirroring = header.nametable_mirroring();
+    let chr_ram = header.chr_ram_total();
+    // Which boards have bus conflicts is a property of the board, and so of the submapper where
+    // one is given. Where it is not, the common boards are assumed: UNROM and CNROM had them, and
+    // so did every Color Dreams, CPROM, BNROM and GxROM board. AxROM is the exception, because
+    // ANROM did not, and games written for it break if they are emulated.
+    let bus_conflicts = match header.mapper {
+        2 | 3 => header.submapper != 1,
+        7 => header.submapper == 2,
+        11 | 13 | 66 => true,
+        34 => header.submapper != 1,
+        // UNROM 512 boards built to save move the register out of the way of the flash; the rest
+        // decode it over ROM, as UNROM does.
+        30 => header.submapper == 1 || !header.battery,
+        _ => false,
+    };
+    let discrete = |board, prg, chr| -> Option<Box<dyn Mapper>> {
+        Some(Box::new(
+            Discrete::new(board, prg, chr, mirroring)
+                .with_chr_ram_size(chr_ram)
+                .with_bus_conflicts(bus_conflicts),
+        ))
+    };
+
+    match header.mapper {
         0 => Some(Box::new(Nrom::new(prg, chr, mirroring))),
         // MMC1 and AxROM control mirroring themselves, so the header's value is only a starting
         // hint and is deliberately not passed along.
-        1 => Some(Box::new(Mmc1::new(prg, chr))),
-        2 => Some(Box::new(UxRom::new(prg, chr, mirroring))),
-        3 => Some(Box::new(CnRom::new(prg, chr, mirroring))),
-        4 => Some(Box::new(Mmc3::new(prg, chr, mirroring))),
-        7 => Some(Box::new(AxRom::new(prg, chr))),
+        1 => Some(Box::new(Mmc1::new(prg, chr).with_chr_ram_size(chr_ram))),
+        2 => Some(Box::new(UxRom::new(prg, chr, mirroring).with_bus_conflicts(bus_conflicts))),
+        3 => Some(Box::new(
+            CnRom::new(prg, chr, mirroring)
+                .with_chr_ram_size(chr_ram)
+                .with_bus_conflicts(bus_conflicts),
+        )),
+        // Which chip, or which rewiring of it, is the submapper's to say, or failing that the
+        // memory the header asks for. See `Mmc3Variant::from_header`.
+        4 | 118 | 119 => Some(Box::new(Mmc3::from_header(header, prg, chr, None))),
+        // MMC5 boards carried anything from no work RAM to 64 KB, and iNES cannot say which. All
+        // 64 KB is given where the header does not say: a game only ever sees the banks it uses.
+        5 => {
+            let prg_ram = if header.is_nes2() { header.prg_ram_total() } else { 64 * 1024 };
+            Some(Box::new(Mmc5::new(prg, chr, prg_ram).with_chr_ram_size(chr_ram)))
+        },
+        7 => Some(Box::new(AxRom::new(prg, chr).with_bus_conflicts(bus_conflicts))),
+        9 | 10 => Some(Box::new(Mmc2::new(prg, chr, header.mapper == 10).with_chr_ram_size(chr_ram))),
+        11 => discrete(DiscreteBoard::ColorDreams, prg, chr),
+        13 => discrete(DiscreteBoard::CpRom, prg, chr),
+        // Which chip, and which EEPROM if any, comes from the number and the submapper. See
+        // `FcgBoard::from_header`.
+        16 | 153 | 157 | 159 => {
+            let board = FcgBoard::from_header(header.mapper, header.submapper, header.is_nes2(), header.prg_nvram_size);
+            Some(Box::new(BandaiFcg::new(board, prg, chr).with_chr_ram_size(chr_ram)))
+        },
+        19 => Some(Box::new(Namco163::new(prg, chr).with_chr_ram_size(chr_ram))),
+        // The board, and with it which address lines reach the chip, comes from the submapper; the
+        // header's mirroring is ignored because the chip sets its own.
+        21 | 22 | 23 | 25 => Some(Box::new(Vrc24::from_header(header, prg, chr))),
+        24 | 26 => Some(Box::new(Vrc6::new(prg, chr, header.mapper == 26).with_chr_ram_size(chr_ram))),
+        // The battery bit marks the boards whose flash the game may rewrite, which is how they
+        // keep a save; the four-screen bit is part of the board's nametable wiring.
+        30 => {
+            let nametables = Unrom512Nametables::from_header(header.mirroring, header.four_screen);
+            Some(Box::new(
+                Unrom512::new(prg, chr, nametables, header.battery)
+                    .with_chr_ram_size(chr_ram)
+                    .with_bus_conflicts(bus_conflicts),
+            ))
+        },
+        // Two unrelated boards share 34. Where the submapper does not say which, BNROM has CHR RAM
+        // and NINA-001 switches CHR ROM, so more than one bank of it means NINA-001.
+        34 => match header.submapper {
+            1 => discrete(DiscreteBoard::Nina001, prg, chr),
+            2 => discrete(DiscreteBoard::Bnrom, prg, chr),
+            _ if chr.len() > 8 * 1024 => discrete(DiscreteBoard::Nina001, prg, chr),
+            _ => discrete(DiscreteBoard::Bnrom, prg, chr),
+        },
+        66 => discrete(DiscreteBoard::GxRom, prg, chr),
+        68 => Some(Box::new(Sunsoft4::new(prg, chr).with_chr_ram_size(chr_ram))),
+        // The work RAM is the board's own, because the same window can show ROM instead.
+        69 => Some(Box::new(Fme7::new(prg, chr, header.prg_ram_total()).with_chr_ram_size(chr_ram))),
+        71 => discrete(DiscreteBoard::Camerica { fire_hawk: header.submapper == 1 }, prg, chr),
+        79 => discrete(DiscreteBoard::Nina03, prg, chr),
+        85 => Some(Box::new(Vrc7::new(prg, chr, header.submapper).with_chr_ram_size(chr_ram))),
+        232 => discrete(DiscreteBoard::Quattro { aladdin: header.submapper == 1 }, prg, chr),
         _ => None,
     }
 }
@@ -909,6 +1269,59 @@ mod tests {
         assert_eq!(mapper.read_prg(0xFFFF), 0xBB);
     }
 
+    /// With bus conflicts, the bank a latch selects is the AND of the value written and the ROM
+    /// byte at the address written to — on all three boards that have a latch in ROM space.
+    #[test]
+    fn bus_conflicts_and_the_written_value_with_the_rom() {
+        // Eight 16 KB banks, each filled with its own number; the byte at $8000 reads $05.
+        let mut prg: Vec<u8> = (0..8u8).flat_map(|bank| vec![bank; 16 * 1024]).collect();
+        prg[0] = 0x05;
+
+        let mut uxrom = UxRom::new(prg.clone(), vec![], Mirroring::Vertical).with_bus_conflicts(true);
+        uxrom.write_prg(0x8000, 0x06);
+        assert_eq!(uxrom.read_prg(0x8001), 4, "UxROM: $06 & $05 is bank 4");
+        uxrom.set_bus_conflicts(false);
+        uxrom.write_prg(0x8000, 0x06);
+        assert_eq!(uxrom.read_prg(0x8001), 6, "UxROM: switched off, $06 is bank 6");
+
+        let chr: Vec<u8> = (0..8u8).flat_map(|bank| vec![bank; 8 * 1024]).collect();
+        let mut cnrom = CnRom::new(prg.clone(), chr, Mirroring::Vertical).with_bus_conflicts(true);
+        cnrom.write_prg(0x8000, 0x06);
+        assert_eq!(cnrom.read_chr(0x0000), 4, "CNROM: $06 & $05 is bank 4");
+
+        let mut axrom = AxRom::new(prg, vec![]).with_bus_conflicts(true);
+        axrom.write_prg(0x8000, 0x13);
+        assert_eq!(axrom.read_prg(0x8001), 2, "AxROM: $13 & $05 is bank 1, one 32 KB bank up");
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower, "and the screen bit is lost");
+    }
+
+    /// Which boards the factory builds with bus conflicts: the submapper where it says, and the
+    /// common board where it does not.
+    #[test]
+    fn the_header_decides_which_boards_have_bus_conflicts() {
+        // Four 16 KB banks, each filled with its own number, except that $8000 reads zero. With
+        // conflicts, writing $01 there selects bank 0.
+        let mut prg: Vec<u8> = (0..4u8).flat_map(|bank| vec![bank; 16 * 1024]).collect();
+        prg[0] = 0x00;
+
+        let conflicts = |mapper, submapper| {
+            let header = INesHeader {
+                submapper,
+                ..INesHeader::for_mapper(mapper)
+            };
+            let mut board = create(&header, prg.clone(), vec![]).expect("supported");
+            board.write_prg(0x8000, 0x01);
+            board.read_prg(0x8001) == 0
+        };
+
+        assert!(conflicts(2, 0), "UxROM, unspecified: UNROM's conflicts");
+        assert!(conflicts(2, 2), "UxROM, submapper 2");
+        assert!(!conflicts(2, 1), "UxROM, submapper 1: none");
+        assert!(!conflicts(7, 0), "AxROM, unspecified: ANROM, without");
+        assert!(conflicts(7, 2), "AxROM, submapper 2");
+        assert!(conflicts(34, 0), "BNROM");
+    }
+
     /// A 16 KB program appears at both $8000 and $C000, as it does on NROM.
     #[test]
     fn cnrom_mirrors_a_16k_program() {
@@ -1044,6 +1457,17 @@ mod tests {
         mapper.write_prg(0x8000, 2);
         assert_eq!(mapper.read_prg(0x8000), 2, "a write should switch the low bank");
         assert_eq!(mapper.read_prg(0xC000), 3, "the fixed bank must not move");
+        let windows = mapper.prg_windows();
+        assert_eq!(windows[0], BankWindow::new(0x8000, 16 * 1024, BankMemory::Rom, 2 * 16 * 1024));
+        assert_eq!((windows[1].start, windows[1].end(), windows[1].bank()), (0xC000, 0xFFFF, 3));
+    }
+
+    #[test]
+    fn a_bank_window_wraps_a_bank_past_the_end_of_the_rom() {
+        let window = BankWindow::banked(0x8000, PRG_BANK, BankMemory::Rom, 9, 4 * PRG_BANK);
+        assert_eq!(window.bank(), 1, "bank 9 of four wraps to bank 1, as the read does");
+        assert_eq!(window.offset_of(0x9FFF), Some(2 * PRG_BANK - 1));
+        assert_eq!(window.offset_of(0xA000), None);
     }
 
     #[test]
@@ -1228,7 +1652,7 @@ mod tests {
     fn every_advertised_mapper_can_actually_be_created() {
         for (number, mapper_name) in SUPPORTED {
             assert!(
-                create(number, vec![0; 32 * 1024], vec![], Mirroring::Horizontal).is_some(),
+                create(&INesHeader::for_mapper(number), vec![0; 32 * 1024], vec![]).is_some(),
                 "mapper {number} ({mapper_name}) is advertised but cannot be created"
             );
             assert_eq!(name(number), Some(mapper_name));
@@ -1239,14 +1663,36 @@ mod tests {
 
     #[test]
     fn unimplemented_mappers_are_reported_rather_than_guessed() {
-        assert!(create(0, vec![0; 16 * 1024], vec![], Mirroring::Horizontal).is_some());
-        assert!(create(1, vec![0; 16 * 1024], vec![], Mirroring::Horizontal).is_some());
-        assert!(create(4, vec![0; 16 * 1024], vec![], Mirroring::Horizontal).is_some());
-        assert!(create(7, vec![0; 16 * 1024], vec![], Mirroring::Horizontal).is_some());
-        assert!(
-            create(99, vec![0; 16 * 1024], vec![], Mirroring::Horizontal).is_none(),
-            "an unknown mapper must not silently fall back to NROM"
-        );
+        let build = |number| create(&INesHeader::for_mapper(number), vec![0; 16 * 1024], vec![]);
+        assert!(build(0).is_some());
+        assert!(build(1).is_some());
+        assert!(build(4).is_some());
+        assert!(build(5).is_some());
+        assert!(build(7).is_some());
+        assert!(build(99).is_none(), "an unknown mapper must not silently fall back to NROM");
+        assert!(build(256).is_none(), "nor one past the eight bits iNES could name");
+    }
+
+    /// An NES 2.0 header's CHR RAM size reaches the board, where iNES could only imply 8 KB.
+    ///
+    /// Visible through banking: with 32 KB, MMC3's bank 31 is its own kilobyte, and with the
+    /// assumed 8 KB it would wrap onto bank 7.
+    #[test]
+    fn the_headers_chr_ram_size_is_what_the_board_gets() {
+        let header = INesHeader {
+            chr_ram_size: 32 * 1024,
+            ..INesHeader::for_mapper(4)
+        };
+        let mut mapper = create(&header, vec![0; 32 * 1024], Vec::new()).expect("MMC3 is supported");
+
+        mapper.write_prg(0x8000, 2); // 1 KB window at $1000
+        mapper.write_prg(0x8001, 7);
+        mapper.write_chr(0x1000, 0x77);
+        mapper.write_prg(0x8001, 31);
+        assert_eq!(mapper.read_chr(0x1000), 0x00, "bank 31 must not wrap onto bank 7");
+        mapper.write_chr(0x1000, 0x1F);
+        mapper.write_prg(0x8001, 7);
+        assert_eq!(mapper.read_chr(0x1000), 0x77);
     }
 
     /// CHR ROM ignores writes; CHR RAM accepts them. A header saying zero CHR banks is the only
@@ -1259,11 +1705,11 @@ mod tests {
     /// and attributes read back correctly the whole time, and every pattern fetch came back zero.
     #[test]
     fn chr_rom_ignores_writes_and_chr_ram_accepts_them() {
-        for number in [0u8, 1, 2, 3, 4, 7] {
+        for (number, _) in SUPPORTED {
             // A board with real CHR ROM: one non-zero byte, which a write must not disturb.
             let mut chr = vec![0u8; 8 * 1024];
             chr[0] = 0xA5;
-            let mut rom = create(number, vec![0; 32 * 1024], chr, Mirroring::Horizontal)
+            let mut rom = create(&INesHeader::for_mapper(number), vec![0; 32 * 1024], chr)
                 .expect("mapper is supported");
             rom.write_chr(0x0000, 0x00);
             assert_eq!(
@@ -1273,7 +1719,7 @@ mod tests {
             );
 
             // The same board declared with CHR RAM — an empty vector — must take the write.
-            let mut ram = create(number, vec![0; 32 * 1024], Vec::new(), Mirroring::Horizontal)
+            let mut ram = create(&INesHeader::for_mapper(number), vec![0; 32 * 1024], Vec::new())
                 .expect("mapper is supported");
             ram.write_chr(0x0000, 0x5A);
             assert_eq!(
@@ -0,0 +1,380 @@
+//! Nintendo MMC2 and MMC4 (mappers 9 and 10).
+//!
+//! Two 4 KB CHR windows, each with *two* bank registers, and a latch per window that chooses
+//! between them. The CPU never flips the latches. The PPU does, by fetching tile `$FD` or `$FE`
+//! from that window: the fetch of the tile's second bitplane sets the latch, and every fetch from
+//! that window after it comes from the bank the latch now names.
+//!
+//! That is how *Mike Tyson's Punch-Out!!* draws a boxer bigger than one 4 KB bank can hold.
+//! A `$FD` or `$FE` tile placed in the nametable switches the bank partway along a line, at
+//! exactly the tile where it sits, with no CPU involvement and no interrupt. Switching once per
+//! scanline instead would put the seam in the wrong column.
+//!
+//! | Address | Register |
+//! |---|---|
+//! | `$A000` | PRG bank at `$8000`: 8 KB on MMC2, 16 KB on MMC4 |
+//! | `$B000`, `$C000` | `$0000` window: the bank for latch `$FD`, and for `$FE` |
+//! | `$D000`, `$E000` | `$1000` window: the bank for latch `$FD`, and for `$FE` |
+//! | `$F000` | Mirroring: vertical, then horizontal |
+//!
+//! The two chips differ in PRG layout, and in how much of the tile trips the `$0000` window's
+//! latch. MMC2 (*Punch-Out!!*) has three fixed 8 KB banks above its one switchable one, and its
+//! `$0000` latch watches only the first row — `$0FD8` and `$0FE8` — where its `$1000` latch
+//! watches all eight. MMC4 (*Fire Emblem*) switches 16 KB and watches all eight rows in both.
+//!
+//! The latch follows the PPU's fetch schedule dot for dot, and so does the picture: a board that
+//! watches the fetches is always drawn by the PPU's per-dot path, which reads each tile's pattern
+//! after the fetches before it have moved the latch. The line-at-a-time renderer would draw a
+//! whole line from the banks in effect as it begins.
+
+use super::{
+    mapper::{banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, Mapper, MapperRegister, PpuFetch, PRG_BANK},
+    Mirroring,
+};
+
+/// Bytes of state in a save.
+const STATE_LEN: usize = 9;
+
+/// A 4 KB CHR window.
+const CHR_WINDOW: usize = 4 * 1024;
+
+/// Nintendo MMC2 and MMC4 (mappers 9 and 10). See the module documentation.
+#[derive(Debug)]
+pub struct Mmc2 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    /// MMC4's 16 KB PRG banking and full-tile `$0000` latch, rather than MMC2's.
+    mmc4: bool,
+
+    prg_bank: u8,
+    /// Per window, the bank for latch `$FD` and the bank for `$FE`.
+    chr_banks: [[u8; 2]; 2],
+    /// Per window, whether the latch reads `$FE` rather than `$FD`.
+    latches: [bool; 2],
+    /// A latch change seen on the fetch just announced, which takes effect once that fetch is
+    /// over. Window, then the new value.
+    pending: Option<(usize, bool)>,
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    /// Build an MMC2 board, or with `mmc4` set an MMC4 one.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mmc4: bool) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            mmc4,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            // The power-on state is undefined. `$FE` is what games are written to expect, because
+            // they set both banks of a window before relying on either.
+            latches: [true; 2],
+            pending: None,
+            mirroring: Mirroring::Vertical,
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let last = (self.prg.len() / PRG_BANK).max(1) - 1;
+        let window = (address as usize - 0x8000) / PRG_BANK;
+        if self.mmc4 {
+            // 16 KB switchable then 16 KB fixed, counted here in 8 KB halves.
+            let bank = if window < 2 {
+                (self.prg_bank & 0x0F) as usize * 2
+            } else {
+                last - 1
+            };
+            bank + (window & 1)
+        } else if window == 0 {
+            (self.prg_bank & 0x0F) as usize
+        } else {
+            last - (3 - window)
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let window = (address as usize >> 12) & 1;
+        let banks = (self.chr.len() / CHR_WINDOW).max(1);
+        let bank = self.chr_banks[window][usize::from(self.latches[window])] as usize % banks;
+        bank * CHR_WINDOW + (address as usize & 0x0FFF)
+    }
+
+    /// The latch `address` sets, if fetching it sets one: the window, and whether to `$FE`.
+    fn latch_for(&self, address: u16) -> Option<(usize, bool)> {
+        let window = (address as usize >> 12) & 1;
+        let tile = (address & 0x0FF0) >> 4;
+        let second_plane = address & 0x0008 != 0;
+        let row = address & 0x0007;
+
+        if !second_plane || !matches!(tile, 0xFD | 0xFE) {
+            return None;
+        }
+        if window == 0 && !self.mmc4 && row != 0 {
+            return None;
+        }
+        Some((window, tile == 0xFE))
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x8000 {
+            return 0;
+        }
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        let value = value & 0x1F;
+        match address {
+            0xA000..=0xAFFF => self.prg_bank = value,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value,
+            0xF000..=0xFFFF => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn watches_ppu_fetches(&self) -> bool {
+        true
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        [0x0000, 0x1000]
+            .into_iter()
+            .map(|start| BankWindow::new(start, CHR_WINDOW, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![
+            MapperRegister::new("PRG bank", self.prg_bank),
+            MapperRegister::new("CHR $0000 FD", self.chr_banks[0][0]),
+            MapperRegister::new("CHR $0000 FE", self.chr_banks[0][1]),
+            MapperRegister::new("CHR $1000 FD", self.chr_banks[1][0]),
+            MapperRegister::new("CHR $1000 FE", self.chr_banks[1][1]),
+            MapperRegister::new("Latch $0000", if self.latches[0] { 0xFEu8 } else { 0xFD }),
+            MapperRegister::new("Latch $1000", if self.latches[1] { 0xFEu8 } else { 0xFD }),
+        ]
+    }
+
+    /// The latch is set by what is on the PPU's bus, so it is watched here rather than through
+    /// [`on_ppu_address`](Mapper::on_ppu_address), which hears only the edges of A12 that MMC3
+    /// counts.
+    ///
+    /// The call comes before the read, and the tile that trips the latch is itself still drawn
+    /// from the old bank. So a change is held until the next fetch announces itself, which is the
+    /// first one the new bank applies to.
+    fn on_ppu_fetch(&mut self, address: u16, fetch: PpuFetch) {
+        if let Some((window, latch)) = self.pending.take() {
+            self.latches[window] = latch;
+        }
+        if matches!(fetch, PpuFetch::Background | PpuFetch::Sprite) {
+            self.pending = self.latch_for(address);
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let (pending_window, pending_latch) = match self.pending {
+            Some((window, latch)) => (window as u8 + 1, u8::from(latch)),
+            None => (0, 0),
+        };
+        vec![
+            self.prg_bank,
+            self.chr_banks[0][0],
+            self.chr_banks[0][1],
+            self.chr_banks[1][0],
+            self.chr_banks[1][1],
+            u8::from(self.latches[0]) | (u8::from(self.latches[1]) << 1),
+            pending_window,
+            pending_latch,
+            self.mirroring as u8,
+        ]
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < STATE_LEN {
+            return;
+        }
+        self.prg_bank = state[0];
+        self.chr_banks = [[state[1], state[2]], [state[3], state[4]]];
+        self.latches = [state[5] & 0x01 != 0, state[5] & 0x02 != 0];
+        self.pending = match state[6] {
+            0 => None,
+            window => Some((((window - 1) & 1) as usize, state[7] != 0)),
+        };
+        self.mirroring = Mirroring::from_index(state[8] & 0x03);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(mmc4: bool) -> Mmc2 {
+        let mut mapper = Mmc2::new(numbered_banks(16, PRG_BANK), numbered_banks(32, CHR_WINDOW), mmc4);
+        // $FD banks 1 and 3, $FE banks 2 and 4.
+        for (address, value) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mapper.write_prg(address, value);
+        }
+        mapper
+    }
+
+    /// Both planes of one row of a tile, as the PPU fetches them.
+    fn fetch_tile(mapper: &mut Mmc2, table: u16, tile: u16, row: u16) {
+        let address = table | (tile << 4) | row;
+        mapper.on_ppu_fetch(address, PpuFetch::Background);
+        mapper.on_ppu_fetch(address + 8, PpuFetch::Background);
+    }
+
+    #[test]
+    fn prg_banking_differs_between_the_two() {
+        let mut mmc2 = board(false);
+        mmc2.write_prg(0xA000, 5);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mmc2.read_prg(a)),
+            [5, 13, 14, 15]
+        );
+
+        let mut mmc4 = board(true);
+        mmc4.write_prg(0xA000, 3);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mmc4.read_prg(a)),
+            [6, 7, 14, 15]
+        );
+
+        mmc4.write_prg(0xF000, 1);
+        assert_eq!(mmc4.mirroring(), Mirroring::Horizontal);
+        mmc4.write_prg(0xF000, 0);
+        assert_eq!(mmc4.mirroring(), Mirroring::Vertical);
+    }
+
+    /// The seam lands at the trigger tile: that tile is still drawn from the old bank, and the
+    /// very next fetch — not the next scanline — comes from the new one.
+    #[test]
+    fn the_latch_flips_at_the_fetch_after_the_trigger() {
+        let mut mapper = board(false);
+        assert_eq!(mapper.read_chr(0x1000), 4, "powers on at $FE");
+
+        fetch_tile(&mut mapper, 0x1000, 0x20, 3);
+        assert_eq!(mapper.read_chr(0x1000), 4, "an ordinary tile changes nothing");
+
+        let trigger = 0x1000 | (0xFD << 4) | 3;
+        mapper.on_ppu_fetch(trigger, PpuFetch::Background);
+        mapper.on_ppu_fetch(trigger + 8, PpuFetch::Background);
+        assert_eq!(
+            mapper.read_chr(trigger + 8),
+            4,
+            "the trigger's own second plane comes from the old bank"
+        );
+
+        mapper.on_ppu_fetch(0x2001, PpuFetch::Nametable);
+        assert_eq!(mapper.read_chr(0x1000), 3, "the next fetch sees $FD");
+        assert_eq!(mapper.read_chr(0x0000), 2, "the other window is untouched");
+
+        fetch_tile(&mut mapper, 0x1000, 0xFE, 7);
+        mapper.on_ppu_fetch(0x2002, PpuFetch::Nametable);
+        assert_eq!(mapper.read_chr(0x1000), 4);
+    }
+
+    /// MMC2's `$0000` latch hears only the first row of the tile; MMC4's hears all eight.
+    #[test]
+    fn mmc2_watches_only_the_first_row_of_the_lower_window() {
+        for (mmc4, expected) in [(false, 2), (true, 1)] {
+            let mut mapper = board(mmc4);
+            fetch_tile(&mut mapper, 0x0000, 0xFD, 5);
+            mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+            assert_eq!(mapper.read_chr(0x0000), expected, "mmc4: {mmc4}");
+        }
+
+        let mut mapper = board(false);
+        fetch_tile(&mut mapper, 0x0000, 0xFD, 0);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+    }
+
+    /// A sprite fetch trips the latch too, but a nametable byte that happens to be `$FD` does not:
+    /// only pattern fetches go near the addresses it watches.
+    #[test]
+    fn only_pattern_fetches_trip_the_latch() {
+        let mut mapper = board(true);
+        mapper.on_ppu_fetch(0x0FD8, PpuFetch::Dummy);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        assert_eq!(mapper.read_chr(0x0000), 2);
+
+        mapper.on_ppu_fetch(0x0FD8, PpuFetch::Sprite);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        assert_eq!(mapper.read_chr(0x0000), 1);
+    }
+
+    #[test]
+    fn save_state_round_trips_a_pending_latch() {
+        let mut mapper = board(false);
+        mapper.write_prg(0xA000, 7);
+        mapper.write_prg(0xF000, 1);
+        fetch_tile(&mut mapper, 0x1000, 0xFD, 0);
+        let saved = mapper.save_state();
+
+        let mut restored = board(false);
+        restored.load_state(&saved);
+        assert_eq!(restored.read_prg(0x8000), 7);
+        assert_eq!(restored.mirroring(), Mirroring::Horizontal);
+        assert_eq!(restored.read_chr(0x1000), 4, "still pending");
+        restored.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        assert_eq!(restored.read_chr(0x1000), 3);
+    }
+}
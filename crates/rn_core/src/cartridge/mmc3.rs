@@ -0,0 +1,778 @@
+//! Nintendo MMC3 (mapper 4), its relatives, and the boards that rewired it (mappers 118, 119).
+//!
+//! One register interface, four chips and boards that answer to it a little differently:
+//!
+//! - **MMC3B/C**, the MMC3 most games shipped with, and the one every other behaviour here is a
+//!   departure from.
+//! - **MMC3A**, the first revision. Its IRQ counter fires only on *reaching* zero — counting down
+//!   to it, or being reloaded with it through `$C001` — where the later chips fire whenever the
+//!   counter is zero after a clock, so a latch of zero fires on every line rather than once.
+//! - **MMC6**, used on *StarTropics* and its sequel: an MMC3 with 1 KB of work RAM inside the chip,
+//!   at `$7000-$7FFF` mirrored, split into two 512-byte halves each with its own read and write
+//!   enable. It has the first revision's IRQ.
+//! - **TxSROM** (mapper 118) takes CIRAM's A10 from bit 7 of the CHR bank rather than from the
+//!   mirroring register, so each nametable follows the 1 KB CHR bank of the matching slot.
+//! - **TQROM** (mapper 119) has both CHR ROM and 8 KB of CHR RAM, and bit 6 of each CHR bank says
+//!   which of the two it comes from.
+//!
+//! The revision and MMC6 share mapper 4; a NES 2.0 submapper tells them apart, and without one a
+//! header that asks for exactly 1 KB of work RAM can only mean MMC6, since no MMC3 board had that.
+//! An iNES header cannot say either, and is taken to mean the common chip.
+
+use parse_display::{Display, FromStr};
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    INesHeader, Mirroring,
+};
+
+/// Bytes of state every variant saves; MMC6 and TQROM add their RAM after it.
+const STATE_LEN: usize = 16;
+
+/// MMC6's internal work RAM, seen at `$7000-$7FFF` four times over.
+const MMC6_RAM: usize = 1024;
+
+/// TQROM's CHR RAM.
+const TQROM_CHR_RAM: usize = 8 * 1024;
+
+/// One of the chips and boards that present the MMC3's registers.
+///
+/// Parses from and prints as its lowercase name, `mmc6`, for choosing one on a command line when
+/// a ROM's header gets it wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr)]
+#[display(style = "lowercase")]
+pub enum Mmc3Variant {
+    Mmc3,
+    Mmc3a,
+    Mmc6,
+    Txsrom,
+    Tqrom,
+}
+
+impl Mmc3Variant {
+    pub const ALL: [Mmc3Variant; 5] = [
+        Mmc3Variant::Mmc3,
+        Mmc3Variant::Mmc3a,
+        Mmc3Variant::Mmc6,
+        Mmc3Variant::Txsrom,
+        Mmc3Variant::Tqrom,
+    ];
+
+    /// Whether a mapper number is one of these, and so something a forced variant replaces.
+    pub fn is_mmc3_mapper(mapper: u16) -> bool {
+        matches!(mapper, 4 | 118 | 119)
+    }
+
+    /// The variant a header describes, from its mapper number, its submapper where it has one,
+    /// and failing those the memory it asks for.
+    ///
+    /// A board with both CHR ROM and CHR RAM under mapper 4 is TQROM filed under the wrong number.
+    pub fn from_header(header: &INesHeader, chr_rom: &[u8]) -> Self {
+        match (header.mapper, header.submapper) {
+            (118, _) => Self::Txsrom,
+            (119, _) => Self::Tqrom,
+            (_, 1) => Self::Mmc6,
+            (_, 4) => Self::Mmc3a,
+            _ if header.is_nes2() && header.prg_ram_total() == MMC6_RAM => Self::Mmc6,
+            _ if !chr_rom.is_empty() && header.chr_ram_total() > 0 => Self::Tqrom,
+            _ => Self::Mmc3,
+        }
+    }
+
+    /// Whether the IRQ counter fires only on reaching zero, as the first revision's did.
+    fn fires_on_reaching_zero(self) -> bool {
+        matches!(self, Self::Mmc3a | Self::Mmc6)
+    }
+}
+
+/// MMC3 (mapper 4): eight banks plus a scanline counter.
+///
+/// Used by Super Mario Bros 3 among many others. Two PRG banks are switchable and two fixed; CHR
+/// is split into six windows. Its scanline IRQ is what games use to split the screen — SMB3's
+/// status bar is exactly that. The other chips and boards in the family are this with a
+/// [`Mmc3Variant`]; see the module documentation.
+#[derive(Debug)]
+pub struct Mmc3 {
+    variant: Mmc3Variant,
+
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    /// TQROM's CHR RAM, beside its ROM. Empty on every other board.
+    chr_ram: Vec<u8>,
+    /// MMC6's internal work RAM. Empty on every other chip, whose work RAM is the system's.
+    prg_ram: Vec<u8>,
+    /// MMC6's `$A001`: read and write enables for the upper half of its RAM in bits 7 and 6, and
+    /// for the lower half in bits 5 and 4.
+    ram_protect: u8,
+
+    /// Which of the eight bank registers the next `$8001` write targets, plus the mode bits.
+    bank_select: u8,
+    banks: [u8; 8],
+
+    mirroring: Mirroring,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    irq_reload: bool,
+    /// The last state of PPU address bit 12, so a rise can be told from a steady level.
+    a12_high: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+        Self {
+            variant: Mmc3Variant::Mmc3,
+            chr_is_ram,
+            prg,
+            chr,
+            chr_ram: Vec::new(),
+            prg_ram: Vec::new(),
+            ram_protect: 0,
+            bank_select: 0,
+            banks: [0; 8],
+            mirroring,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_reload: false,
+            a12_high: false,
+        }
+    }
+
+    /// Build the board a header describes, or `variant` whatever it says.
+    pub fn from_header(header: &INesHeader, prg: Vec<u8>, chr: Vec<u8>, variant: Option<Mmc3Variant>) -> Self {
+        let variant = variant.unwrap_or_else(|| Mmc3Variant::from_header(header, &chr));
+        Self::new(prg, chr, header.nametable_mirroring())
+            .with_chr_ram_size(header.chr_ram_total())
+            .with_variant(variant)
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Make this chip or board rather than the common MMC3, with whatever RAM that brings.
+    pub fn with_variant(mut self, variant: Mmc3Variant) -> Self {
+        self.variant = variant;
+        self.prg_ram = match variant {
+            Mmc3Variant::Mmc6 => vec![0; MMC6_RAM],
+            _ => Vec::new(),
+        };
+        self.chr_ram = match variant {
+            Mmc3Variant::Tqrom => vec![0; TQROM_CHR_RAM],
+            _ => Vec::new(),
+        };
+        self
+    }
+
+    fn prg_banks(&self) -> usize {
+        (self.prg.len() / PRG_BANK).max(1)
+    }
+
+    /// Which 8 KB PRG bank appears in each of the four windows.
+    ///
+    /// Bit 6 of the bank-select register swaps which of the two switchable windows is which; the
+    /// last bank is always fixed at `$E000`, so the vectors never move.
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let last = self.prg_banks() - 1;
+        let second_last = last.saturating_sub(1);
+        let swapped = (self.bank_select & 0x40) != 0;
+
+        match address {
+            0x8000..=0x9FFF => {
+                if swapped {
+                    second_last
+                } else {
+                    self.banks[6] as usize
+                }
+            },
+            0xA000..=0xBFFF => self.banks[7] as usize,
+            0xC000..=0xDFFF => {
+                if swapped {
+                    self.banks[6] as usize
+                } else {
+                    second_last
+                }
+            },
+            _ => last,
+        }
+    }
+
+    /// Which 1 KB CHR bank appears at `address`.
+    ///
+    /// Bit 7 of the bank-select register swaps the 2 KB and 1 KB halves of pattern space.
+    fn chr_bank_for(&self, address: u16) -> usize {
+        let address = address & 0x1FFF;
+        let inverted = (self.bank_select & 0x80) != 0;
+        let slot = if inverted {
+            (address ^ 0x1000) >> 10
+        } else {
+            address >> 10
+        };
+
+        match slot {
+            0 => (self.banks[0] & 0xFE) as usize,
+            1 => (self.banks[0] | 0x01) as usize,
+            2 => (self.banks[1] & 0xFE) as usize,
+            3 => (self.banks[1] | 0x01) as usize,
+            4 => self.banks[2] as usize,
+            5 => self.banks[3] as usize,
+            6 => self.banks[4] as usize,
+            _ => self.banks[5] as usize,
+        }
+    }
+
+    /// The 1 KB CHR bank at `address` as the pattern memory sees it. TxSROM spends bit 7 on the
+    /// nametables, so it never reaches the CHR ROM.
+    fn pattern_bank_for(&self, address: u16) -> usize {
+        let bank = self.chr_bank_for(address);
+        if self.variant == Mmc3Variant::Txsrom {
+            bank & 0x7F
+        } else {
+            bank
+        }
+    }
+
+    /// Whether a TQROM bank number names its CHR RAM rather than its ROM.
+    fn is_tqrom_ram(&self, bank: usize) -> bool {
+        self.variant == Mmc3Variant::Tqrom && bank & 0x40 != 0
+    }
+
+    /// The CIRAM page, 0 or 1, holding TxSROM's nametable at `address`.
+    ///
+    /// The board feeds the nametable's A10 and A11 to the chip as though they were a pattern
+    /// address in `$0000-$0FFF`, and wires the resulting bank's bit 7 to CIRAM, so the four
+    /// nametables follow whichever four 1 KB CHR slots sit there.
+    fn txsrom_page(&self, address: u16) -> usize {
+        (self.chr_bank_for(address & 0x0C00) >> 7) & 1
+    }
+
+    /// Where in MMC6's RAM `address` lands, if that half can be read, and `Some(None)` if the
+    /// other half can and this one reads as zero. `None` when nothing there answers at all.
+    fn mmc6_read_offset(&self, address: u16) -> Option<Option<usize>> {
+        if !self.mmc6_ram_enabled() || !(0x7000..0x8000).contains(&address) {
+            return None;
+        }
+        let offset = address as usize & (MMC6_RAM - 1);
+        let (low_readable, high_readable) = (self.ram_protect & 0x20 != 0, self.ram_protect & 0x80 != 0);
+        let readable = if offset < MMC6_RAM / 2 {
+            low_readable
+        } else {
+            high_readable
+        };
+        match (readable, low_readable || high_readable) {
+            (true, _) => Some(Some(offset)),
+            (false, true) => Some(None),
+            (false, false) => None,
+        }
+    }
+
+    /// Where in MMC6's RAM a write to `address` lands. A half is writable only while it is also
+    /// readable; its write enable alone does nothing.
+    fn mmc6_write_offset(&self, address: u16) -> Option<usize> {
+        let offset = self.mmc6_read_offset(address)??;
+        let writable = if offset < MMC6_RAM / 2 { 0x10 } else { 0x40 };
+        (self.ram_protect & writable != 0).then_some(offset)
+    }
+
+    /// Bit 5 of MMC6's bank select, without which its RAM is not there and `$A001` is ignored.
+    fn mmc6_ram_enabled(&self) -> bool {
+        self.variant == Mmc3Variant::Mmc6 && self.bank_select & 0x20 != 0
+    }
+
+    /// One step of the scanline counter, however it was clocked.
+    ///
+    /// Reload is deferred rather than immediate: writing $C001 does not load the latch there and
+    /// then, it arranges for the *next* clock to load it. That is why a game rewrites the latch
+    /// and then waits, rather than expecting the new value to take effect at once.
+    fn clock_irq_counter(&mut self) {
+        // On the first revision, a counter that was already zero and reloads with zero has not
+        // reached zero, it has stayed there, and does not fire again.
+        let reaching_zero = self.irq_counter != 0 || self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fires = reaching_zero || !self.variant.fires_on_reaching_zero();
+        if self.irq_counter == 0 && self.irq_enabled && fires {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x8000 {
+            return match self.mmc6_read_offset(address) {
+                Some(Some(offset)) => self.prg_ram[offset],
+                _ => 0,
+            };
+        }
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if let Some(offset) = self.mmc6_write_offset(address) {
+                self.prg_ram[offset] = value;
+            }
+            return;
+        }
+
+        // Registers are selected by the address's high bits and whether it is even or odd.
+        match (address & 0xE001, address) {
+            (0x8000, _) => self.bank_select = value,
+            (0x8001, _) => {
+                let index = (self.bank_select & 0x07) as usize;
+                self.banks[index] = value;
+            },
+            // A four-screen board — TVROM, and the MMC3 boards Tengen made — does not connect the
+            // chip's mirroring output, so the register changes nothing.
+            (0xA000, _) if self.mirroring == Mirroring::FourScreen => {},
+            (0xA000, _) => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            },
+            // MMC3's own PRG-RAM protect guards RAM the system provides and is not modelled; MMC6's
+            // guards its own, and only while that RAM is switched on.
+            (0xA001, _) if self.mmc6_ram_enabled() => self.ram_protect = value,
+            (0xC000, _) => self.irq_latch = value,
+            (0xC001, _) => self.irq_reload = true,
+            (0xE000, _) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            },
+            (0xE001, _) => self.irq_enabled = true,
+            _ => {},
+        }
+    }
+
+    /// MMC6's RAM is inside the chip, so `$6000-$7FFF` is its to answer or leave undriven.
+    fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+        self.variant == Mmc3Variant::Mmc6 && address >= 0x6000
+    }
+
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        if address < 0x8000 && self.mmc6_read_offset(address).is_none() {
+            0xFF
+        } else {
+            0
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        let bank = self.pattern_bank_for(address);
+        let offset = address as usize & 0x03FF;
+        if self.is_tqrom_ram(bank) {
+            banked(&self.chr_ram, bank, CHR_BANK, offset)
+        } else {
+            banked(&self.chr, bank, CHR_BANK, offset)
+        }
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        let bank = self.pattern_bank_for(address);
+        let offset = address as usize & 0x03FF;
+        if self.is_tqrom_ram(bank) {
+            let banks = self.chr_ram.len() / CHR_BANK;
+            self.chr_ram[(bank % banks) * CHR_BANK + offset] = value;
+            return;
+        }
+
+        // Only a board with CHR *RAM* accepts this; one with CHR ROM ignores it. The comment here
+        // said so for a long time while the code wrote regardless, and that is not harmless: a
+        // cartridge whose tiles are in ROM, running a program that clears all of VRAM at startup,
+        // had its own character data overwritten with zeros and drew a blank screen for ever
+        // after. `ny2011` is such a program — its nametables and attributes were right, its
+        // pattern fetches all zero — and it is why this is now checked rather than described.
+        if !self.chr_is_ram {
+            return;
+        }
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let index = (bank % banks) * CHR_BANK + offset;
+        if index < self.chr.len() {
+            self.chr[index] = value;
+        }
+    }
+
+    /// On TxSROM, the nearest fixed arrangement to where the CHR banks put the four nametables —
+    /// for display; the nametables themselves are placed exactly.
+    fn mirroring(&self) -> Mirroring {
+        if self.variant != Mmc3Variant::Txsrom {
+            return self.mirroring;
+        }
+        match [0x2000, 0x2400, 0x2800, 0x2C00].map(|address| self.txsrom_page(address)) {
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            [a, b, _, _] if a != b => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        if self.variant == Mmc3Variant::Txsrom {
+            return ciram[self.txsrom_page(address) * 0x400 + (address as usize & 0x3FF)];
+        }
+        ciram[self.mirroring.ciram_offset(address)]
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        if self.variant == Mmc3Variant::Txsrom {
+            ciram[self.txsrom_page(address) * 0x400 + (address as usize & 0x3FF)] = value;
+            return;
+        }
+        ciram[self.mirroring.ciram_offset(address)] = value;
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| {
+                let bank = self.pattern_bank_for(start);
+                if self.is_tqrom_ram(bank) {
+                    BankWindow::banked(start, CHR_BANK, BankMemory::Ram, bank, self.chr_ram.len())
+                } else {
+                    BankWindow::banked(start, CHR_BANK, chr_memory(self.chr_is_ram), bank, self.chr.len())
+                }
+            })
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("Bank select", self.bank_select)];
+        registers.extend(
+            self.banks
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("R{index}"), bank)),
+        );
+        if self.variant == Mmc3Variant::Mmc6 {
+            registers.push(MapperRegister::new("RAM protect", self.ram_protect));
+        }
+        registers
+    }
+
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.irq_counter.into(),
+            latch: self.irq_latch.into(),
+            enabled: self.irq_enabled,
+            pending: self.irq_pending,
+        })
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        (!self.prg_ram.is_empty()).then_some(&self.prg_ram[..])
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        (!self.prg_ram.is_empty()).then_some(&mut self.prg_ram[..])
+    }
+
+    /// The registers and counter, then MMC6's protect register and RAM and TQROM's CHR RAM where
+    /// the board has them. The RAM is here because the protect bits can hide it from the bus.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.bank_select];
+        state.extend_from_slice(&self.banks);
+        state.extend_from_slice(&[
+            self.irq_latch,
+            self.irq_counter,
+            u8::from(self.irq_enabled),
+            u8::from(self.irq_pending),
+            u8::from(self.irq_reload),
+            u8::from(self.a12_high),
+            self.mirroring as u8,
+        ]);
+        if self.variant == Mmc3Variant::Mmc6 {
+            state.push(self.ram_protect);
+            state.extend_from_slice(&self.prg_ram);
+        }
+        state.extend_from_slice(&self.chr_ram);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let extra = usize::from(self.variant == Mmc3Variant::Mmc6) + self.prg_ram.len() + self.chr_ram.len();
+        if state.len() < STATE_LEN + extra {
+            return;
+        }
+
+        self.bank_select = state[0];
+        self.banks.copy_from_slice(&state[1..9]);
+        self.irq_latch = state[9];
+        self.irq_counter = state[10];
+        self.irq_enabled = state[11] != 0;
+        self.irq_pending = state[12] != 0;
+        self.irq_reload = state[13] != 0;
+        self.a12_high = state[14] != 0;
+        self.mirroring = Mirroring::from_index(state[15]);
+
+        let mut rest = &state[STATE_LEN..];
+        if self.variant == Mmc3Variant::Mmc6 {
+            self.ram_protect = rest[0];
+            let (ram, after) = rest[1..].split_at(self.prg_ram.len());
+            self.prg_ram.copy_from_slice(ram);
+            rest = after;
+        }
+        let len = self.chr_ram.len();
+        self.chr_ram.copy_from_slice(&rest[..len]);
+    }
+
+    fn on_ppu_address(&mut self, address: u16) {
+        let high = (address & 0x1000) != 0;
+        // Only the transition counts. A run of fetches from the upper half of pattern memory is
+        // one rise, not one per fetch.
+        if high && !self.a12_high {
+            self.clock_irq_counter();
+        }
+        self.a12_high = high;
+    }
+
+    /// Count down one scanline, raising the IRQ when the counter reaches zero.
+    ///
+    /// This is what a game uses to know it has reached a particular line — the mechanism behind
+    /// a status bar that stays put while the playfield scrolls.
+    fn on_scanline(&mut self) {
+        self.clock_irq_counter();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(variant: Mmc3Variant) -> Mmc3 {
+        let chr = (0..256 * 1024).map(|i| (i / CHR_BANK) as u8).collect();
+        Mmc3::new(vec![0; 128 * 1024], chr, Mirroring::Vertical).with_variant(variant)
+    }
+
+    fn set_bank(mapper: &mut Mmc3, register: u8, value: u8) {
+        mapper.write_prg(0x8000, register);
+        mapper.write_prg(0x8001, value);
+    }
+
+    /// Rising edges on A12, as the PPU makes once a line.
+    fn clock(mapper: &mut Mmc3) {
+        mapper.on_ppu_address(0x0000);
+        mapper.on_ppu_address(0x1000);
+    }
+
+    #[test]
+    fn the_header_picks_the_variant_and_names_round_trip() {
+        let header = |mapper, submapper| INesHeader {
+            submapper,
+            ..INesHeader::for_mapper(mapper)
+        };
+        assert_eq!(Mmc3Variant::from_header(&header(4, 0), &[]), Mmc3Variant::Mmc3);
+        assert_eq!(Mmc3Variant::from_header(&header(4, 1), &[]), Mmc3Variant::Mmc6);
+        assert_eq!(Mmc3Variant::from_header(&header(4, 4), &[]), Mmc3Variant::Mmc3a);
+        assert_eq!(Mmc3Variant::from_header(&header(118, 0), &[]), Mmc3Variant::Txsrom);
+        assert_eq!(Mmc3Variant::from_header(&header(119, 0), &[]), Mmc3Variant::Tqrom);
+
+        let one_kb = INesHeader {
+            format: super::super::HeaderFormat::Nes2,
+            prg_nvram_size: 1024,
+            ..header(4, 0)
+        };
+        assert_eq!(
+            Mmc3Variant::from_header(&one_kb, &[]),
+            Mmc3Variant::Mmc6,
+            "only MMC6 had 1 KB"
+        );
+        let both = INesHeader {
+            chr_ram_size: 8 * 1024,
+            ..header(4, 0)
+        };
+        assert_eq!(Mmc3Variant::from_header(&both, &[0; 8192]), Mmc3Variant::Tqrom);
+
+        for variant in Mmc3Variant::ALL {
+            assert_eq!(variant.to_string().parse::<Mmc3Variant>(), Ok(variant));
+        }
+    }
+
+    /// With a latch of zero, the later chips fire on every clock; the first revision fires once,
+    /// when the counter gets there, and again only after `$C001` asks for a reload.
+    #[test]
+    fn revision_a_fires_only_on_reaching_zero() {
+        for (variant, expected) in [
+            (Mmc3Variant::Mmc3, [true, true, true]),
+            (Mmc3Variant::Mmc3a, [false, false, true]),
+            (Mmc3Variant::Mmc6, [false, false, true]),
+        ] {
+            let mut mapper = board(variant);
+            mapper.write_prg(0xC000, 1);
+            mapper.write_prg(0xC001, 0);
+            mapper.write_prg(0xE001, 0);
+            clock(&mut mapper); // reload to 1
+            clock(&mut mapper); // 1 -> 0: every chip fires
+            assert!(mapper.irq_pending(), "{variant}: counting down to zero");
+            mapper.write_prg(0xE000, 0);
+            mapper.write_prg(0xE001, 0);
+            mapper.write_prg(0xC000, 0);
+
+            let mut fired = [false; 3];
+            clock(&mut mapper); // 0 -> reload with 0
+            fired[0] = mapper.irq_pending();
+            mapper.acknowledge_irq();
+            clock(&mut mapper);
+            fired[1] = mapper.irq_pending();
+            mapper.acknowledge_irq();
+            mapper.write_prg(0xC001, 0);
+            clock(&mut mapper); // an asked-for reload, with zero
+            fired[2] = mapper.irq_pending();
+            assert_eq!(fired, expected, "{variant}");
+        }
+    }
+
+    #[test]
+    fn mmc6_ram_has_a_read_and_write_enable_per_half() {
+        let mut mapper = board(Mmc3Variant::Mmc6);
+        assert!(mapper.maps_cpu_address(0x7000, false));
+        assert_eq!(mapper.open_bus_mask(0x7000), 0xFF, "disabled at power-on");
+
+        mapper.write_prg(0xA001, 0xF0);
+        mapper.write_prg(0x7000, 0x11);
+        assert_eq!(mapper.ram_protect, 0, "$A001 is ignored until the RAM is enabled");
+
+        mapper.write_prg(0x8000, 0x20);
+        mapper.write_prg(0xA001, 0xF0);
+        mapper.write_prg(0x7001, 0x11);
+        mapper.write_prg(0x7201, 0x22);
+        assert_eq!(mapper.read_prg(0x7401), 0x11, "mirrored every 1 KB");
+        assert_eq!(mapper.read_prg(0x7E01), 0x22);
+        assert_eq!(mapper.open_bus_mask(0x6000), 0xFF, "nothing at $6000");
+
+        // Only the lower half readable, and not writable: the upper reads zero.
+        mapper.write_prg(0xA001, 0x20);
+        mapper.write_prg(0x7001, 0x33);
+        assert_eq!(mapper.read_prg(0x7001), 0x11, "write-protected");
+        assert_eq!(mapper.read_prg(0x7201), 0x00, "the unreadable half reads as zero");
+        assert_eq!(mapper.open_bus_mask(0x7201), 0);
+
+        // Writable but not readable is not writable.
+        mapper.write_prg(0xA001, 0x40);
+        mapper.write_prg(0x7201, 0x44);
+        assert_eq!(mapper.open_bus_mask(0x7201), 0xFF, "neither half readable is open bus");
+        assert_eq!(mapper.prg_ram().unwrap()[0x201], 0x22);
+
+        let saved = mapper.save_state();
+        let mut other = board(Mmc3Variant::Mmc6);
+        other.load_state(&saved);
+        assert_eq!(other.save_state(), saved);
+    }
+
+    #[test]
+    fn txsrom_takes_each_nametable_from_its_chr_bank() {
+        let mut mapper = board(Mmc3Variant::Txsrom);
+        let mut ciram = vec![0; 0x800];
+        set_bank(&mut mapper, 0, 0x80); // $0000-$07FF: nametables 0 and 1 on page 1
+        set_bank(&mut mapper, 1, 0x00); // $0800-$0FFF: nametables 2 and 3 on page 0
+        mapper.write_prg(0xA000, 0); // ignored
+
+        mapper.write_nametable(0x2005, 0xAA, &mut ciram);
+        mapper.write_nametable(0x2805, 0xBB, &mut ciram);
+        assert_eq!((ciram[0x405], ciram[0x005]), (0xAA, 0xBB));
+        assert_eq!(mapper.read_nametable(0x2405, &ciram), 0xAA);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        assert_eq!(mapper.read_chr(0x0000), 0x00, "bit 7 is not a CHR address line");
+
+        // With the CHR halves inverted, the 1 KB registers choose instead.
+        mapper.write_prg(0x8000, 0x80);
+        for register in 2..6 {
+            set_bank(&mut mapper, register | 0x80, if register % 2 == 0 { 0x80 } else { 0 });
+        }
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn tqrom_bit_6_of_a_chr_bank_picks_ram() {
+        let mut mapper = board(Mmc3Variant::Tqrom);
+        set_bank(&mut mapper, 2, 0x05);
+        set_bank(&mut mapper, 3, 0x45);
+        mapper.write_chr(0x1000, 0x99);
+        mapper.write_chr(0x1400, 0x77);
+        assert_eq!(mapper.read_chr(0x1000), 0x05, "ROM ignores the write");
+        assert_eq!(mapper.read_chr(0x1400), 0x77);
+        assert_eq!(mapper.chr_ram[5 * CHR_BANK], 0x77);
+
+        let saved = mapper.save_state();
+        let mut other = board(Mmc3Variant::Tqrom);
+        other.load_state(&saved);
+        assert_eq!(other.read_chr(0x1400), 0x77);
+    }
+
+    #[test]
+    fn the_windows_follow_the_bank_registers_and_the_mode_bits() {
+        let mut mapper = board(Mmc3Variant::Mmc3);
+        set_bank(&mut mapper, 6, 3);
+        set_bank(&mut mapper, 7, 5);
+        set_bank(&mut mapper, 2, 9);
+
+        let prg: Vec<_> = mapper.prg_windows().iter().map(|window| (window.start, window.bank())).collect();
+        assert_eq!(prg, [(0x8000, 3), (0xA000, 5), (0xC000, 14), (0xE000, 15)]);
+        assert_eq!(mapper.chr_windows()[4].bank(), 9);
+
+        // Swapping both halves moves the switchable PRG bank to $C000 and R2 to $0000.
+        mapper.write_prg(0x8000, 0xC0);
+        let prg: Vec<_> = mapper.prg_windows().iter().map(|window| window.bank()).collect();
+        assert_eq!(prg, [14, 5, 3, 15]);
+        assert_eq!(mapper.chr_windows()[0].bank(), 9);
+        assert_eq!(mapper.chr_windows()[0].offset, 9 * CHR_BANK);
+    }
+
+    #[test]
+    fn the_irq_counter_is_reported_as_it_counts() {
+        let mut mapper = board(Mmc3Variant::Mmc3);
+        mapper.write_prg(0xC000, 2);
+        mapper.write_prg(0xC001, 0);
+        mapper.write_prg(0xE001, 0);
+        clock(&mut mapper);
+        clock(&mut mapper);
+
+        let irq = mapper.irq_counter().unwrap();
+        assert_eq!((irq.counter, irq.latch, irq.enabled, irq.pending), (1, 2, true, false));
+        assert_eq!(mapper.registers()[0], MapperRegister::new("Bank select", 0u8));
+    }
+}
@@ -0,0 +1,1120 @@
+//! MMC5 (mapper 5), Nintendo's largest mapper.
+//!
+//! Everything other boards do one of, MMC5 does all of: four PRG banking modes with RAM that can
+//! be banked into ROM space, four CHR modes with a second register set for the background of
+//! 8x16-sprite games, 1 KB of extra RAM on the cartridge ("ExRAM") usable as a third nametable or
+//! as per-tile attributes and CHR banks, a fill mode that makes a nametable out of two registers,
+//! a vertical split screen, a scanline IRQ, an 8x8 multiplier and a sound chip. Castlevania III
+//! and the Koei strategy games are the well-known users.
+//!
+//! The hard part is that the chip has no line from the PPU telling it what is going on. It works
+//! out where the PPU is from the reads it sees: three reads of the same nametable address in a
+//! row happen only at the end of one line going into the next, so that is how it counts scanlines;
+//! a pause in reads means rendering has stopped; and where in a line's sequence of fetches a read
+//! falls says whether it is for the background or a sprite. This PPU says which fetch is which
+//! outright (see [`PpuFetch`]), so only the scanline detection is still done the chip's own way,
+//! from the addresses, which is what keeps the IRQ's timing the hardware's.
+
+use std::cell::Cell;
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, PpuFetch,
+        CHR_BANK, PRG_BANK,
+    },
+    Mirroring,
+};
+use crate::apu::{ExpansionChip, Mmc5Audio};
+
+/// Bytes of ExRAM.
+const EXRAM_SIZE: usize = 1024;
+
+/// CPU cycles without a PPU read after which the chip decides rendering has stopped.
+///
+/// While rendering, reads come at least every three dots, so a gap of three CPU cycles — nine
+/// dots — only happens once the PPU has reached vblank or had rendering turned off.
+const IDLE_CYCLES: u8 = 3;
+
+/// MMC5 (mapper 5). See the module documentation.
+#[derive(Debug)]
+pub struct Mmc5 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    prg_ram: Vec<u8>,
+    exram: Vec<u8>,
+
+    /// `$5100`: how `$8000..=$FFFF` is divided between the bank registers.
+    prg_mode: u8,
+    /// `$5101`: the size of a CHR bank, 8 KB down to 1 KB.
+    chr_mode: u8,
+    /// `$5102` and `$5103`. RAM accepts writes only while they hold `%10` and `%01`.
+    ram_protect: [u8; 2],
+    /// `$5104`: what ExRAM is for. See [`Mmc5::read_nametable`].
+    exram_mode: u8,
+    /// `$5105`: a source for each of the four nametables, two bits apiece.
+    nametables: u8,
+    /// `$5106` and `$5107`: the tile and palette a fill-mode nametable is made of.
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// `$5113..=$5117`: the RAM bank at `$6000`, then the four PRG bank registers.
+    prg_banks: [u8; 5],
+    /// `$5120..=$5127`, the set sprites use, and `$5128..=$512B`, the set the background uses
+    /// in 8x16 mode. Ten bits each, the top two from `$5130` as it stood when the bank was written.
+    chr_a: [u16; 8],
+    chr_b: [u16; 4],
+    /// `$5130`: upper bits for the next CHR bank written, and for extended-attribute banks.
+    chr_upper: u8,
+    /// Whether `$5128..=$512B` was written more recently than `$5120..=$5127`. Outside rendering
+    /// that, and nothing else, decides which set `$2007` sees.
+    last_wrote_b: bool,
+
+    /// `$5200..=$5202`: split enable, side and delimiter; its scroll; its CHR bank.
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    /// `$5203`: the scanline the IRQ fires on.
+    irq_target: u8,
+    irq_enabled: bool,
+    /// A cell because reading `$5204` acknowledges it, and reads go through `&self`.
+    irq_pending: Cell<bool>,
+    /// Whether the chip believes the PPU is drawing, and which line it is on.
+    in_frame: bool,
+    scanline: u8,
+
+    /// `$5205` and `$5206`: the multiplier's operands, whose product reads back at the same
+    /// addresses.
+    multiplicand: u8,
+    multiplier: u8,
+
+    /// `$2000` bit 5, snooped from the CPU bus.
+    sprites_8x16: bool,
+
+    /// The last PPU read, how many times in a row it has been repeated, and how many CPU cycles
+    /// are left before silence means rendering has stopped.
+    last_read: u16,
+    repeats: u8,
+    idle: u8,
+
+    /// The fetch the PPU is about to make, and what the chip decided for the tile it belongs to:
+    /// which column it is, whether it lies in the split, the split row it shows, and its ExRAM byte.
+    fetch: Option<PpuFetch>,
+    next_tile: u8,
+    tile: u8,
+    in_split: bool,
+    split_y: u8,
+    ext_attribute: u8,
+
+    audio: Mmc5Audio,
+}
+
+impl Mmc5 {
+    /// Build the board with `prg_ram_size` bytes of work RAM.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, prg_ram_size: usize) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            prg_ram: vec![0; prg_ram_size],
+            exram: vec![0; EXRAM_SIZE],
+            // Mode 3 with `$5117` at `$FF` puts the last 8 KB at `$E000`, which is the only state
+            // the chip guarantees at power-on — enough to reach the reset vector.
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_a: [0; 8],
+            chr_b: [0; 4],
+            chr_upper: 0,
+            last_wrote_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            sprites_8x16: false,
+            last_read: 0,
+            repeats: 0,
+            idle: 0,
+            fetch: None,
+            next_tile: 0,
+            tile: 0,
+            in_split: false,
+            split_y: 0,
+            ext_attribute: 0,
+            audio: Mmc5Audio::new(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Which of `prg_banks` is behind `address` in `$8000..=$FFFF`, and how many 8 KB banks
+    /// its window spans.
+    fn prg_window(&self, address: u16) -> (usize, usize) {
+        let quarter = ((address >> 13) & 0x03) as usize;
+        match self.prg_mode & 0x03 {
+            0 => (4, 4),
+            1 => (2 + 2 * (quarter / 2), 2),
+            2 if quarter < 2 => (2, 2),
+            _ => (1 + quarter, 1),
+        }
+    }
+
+    /// Where `address` in `$8000..=$FFFF` lands: in ROM, or at an offset into work RAM.
+    ///
+    /// Bit 7 of a bank register chooses ROM, except for `$5117`, which is ROM whatever it says so
+    /// that the vectors can never be switched out from under the CPU. A window wider than 8 KB
+    /// ignores the bank number's low bits, as the address lines take over for them.
+    fn prg_target(&self, address: u16) -> PrgTarget {
+        let (index, span) = self.prg_window(address);
+        let register = self.prg_banks[index];
+        let bank = (register as usize & 0x7F & !(span - 1)) + ((address as usize >> 13) & (span - 1));
+        let offset = address as usize & 0x1FFF;
+        if register & 0x80 != 0 || index == 4 {
+            PrgTarget::Rom(bank, offset)
+        } else {
+            match self.ram_offset(bank as u8) {
+                Some(base) => PrgTarget::Ram(base + offset),
+                None => PrgTarget::None,
+            }
+        }
+    }
+
+    /// Where 8 KB RAM bank `bank` starts in `prg_ram`, if the board has any RAM.
+    ///
+    /// The chip drives three bank bits and a chip select for two RAM chips. Boards with 16 KB
+    /// fitted it as two 8 KB chips, so there it is bit 2 that chooses between them; with one
+    /// chip the low bits simply wrap.
+    fn ram_offset(&self, bank: u8) -> Option<usize> {
+        let banks = self.prg_ram.len() / PRG_BANK;
+        if banks == 0 {
+            return None;
+        }
+        let bank = if banks == 2 {
+            (bank as usize >> 2) & 1
+        } else {
+            (bank as usize & 0x07) % banks
+        };
+        Some(bank * PRG_BANK)
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_protect[0] & 0x03 == 0x02 && self.ram_protect[1] & 0x03 == 0x01
+    }
+
+    /// Whether the background set, `$5128..=$512B`, answers the next pattern read.
+    ///
+    /// Only 8x16 sprites need the second set, so with 8x8 sprites it is never used. While the PPU
+    /// is drawing, sprite fetches use the first set and background fetches the second; outside
+    /// rendering there is no fetch to go by, and whichever set was written last answers.
+    fn use_set_b(&self) -> bool {
+        if !self.sprites_8x16 {
+            return false;
+        }
+        if self.in_frame {
+            return self.fetch != Some(PpuFetch::Sprite);
+        }
+        self.last_wrote_b
+    }
+
+    /// The 1 KB CHR bank for `address`, from one set or the other.
+    fn chr_bank(&self, address: u16, set_b: bool) -> usize {
+        let slot = (address as usize >> 10) & 0x07;
+        let (size, a, b) = match self.chr_mode & 0x03 {
+            0 => (8, 7, 3),
+            1 => (4, (slot / 4) * 4 + 3, 3),
+            2 => (2, (slot / 2) * 2 + 1, ((slot & 3) / 2) * 2 + 1),
+            _ => (1, slot, slot & 3),
+        };
+        let register = if set_b { self.chr_b[b] } else { self.chr_a[a] };
+        register as usize * size + (slot % size)
+    }
+
+    /// The CHR offset a pattern read at `address` resolves to.
+    ///
+    /// The split and extended attributes each supply a 4 KB bank of their own for background
+    /// tiles, in place of the registers: the split's from `$5202`, the attribute's from its ExRAM
+    /// byte. Anything else goes through the register sets.
+    fn chr_offset(&self, address: u16) -> usize {
+        let address = address & 0x1FFF;
+        let four_kb = 4 * 1024;
+        let banks = |size: usize| (self.chr.len() / size).max(1);
+
+        if self.fetch == Some(PpuFetch::Background) {
+            if self.in_split {
+                let row = (address as usize & 0x0FF8) | (self.split_y as usize & 0x07);
+                return (self.split_bank as usize % banks(four_kb)) * four_kb + row;
+            }
+            if self.exram_mode == 1 {
+                let bank = (self.ext_attribute as usize & 0x3F) | ((self.chr_upper as usize & 0x03) << 6);
+                return (bank % banks(four_kb)) * four_kb + (address as usize & 0x0FFF);
+            }
+        }
+
+        let bank = self.chr_bank(address, self.use_set_b());
+        (bank % banks(CHR_BANK)) * CHR_BANK + (address as usize & 0x03FF)
+    }
+
+    /// The split's nametable byte for the current tile, or its attribute.
+    fn split_nametable(&self, attribute: bool) -> u8 {
+        let column = self.tile as usize & 0x1F;
+        let row = self.split_y as usize >> 3;
+        if !attribute {
+            return self.exram[(row << 5) | column];
+        }
+        let byte = self.exram[0x3C0 | ((row >> 2) << 3) | (column >> 2)];
+        let shift = ((row & 0x02) << 1) | (column & 0x02);
+        ((byte >> shift) & 0x03) * 0x55
+    }
+
+    /// Follow the PPU's reads the chip's way, from the addresses alone: three reads of the same
+    /// nametable address in a row mean a new line is starting.
+    fn detect_scanline(&mut self, address: u16) {
+        if (0x2000..=0x2FFF).contains(&address) && address == self.last_read {
+            self.repeats += 1;
+        } else {
+            self.repeats = 0;
+        }
+        self.last_read = address;
+        self.idle = IDLE_CYCLES;
+
+        if self.repeats != 2 {
+            return;
+        }
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_target {
+                self.irq_pending.set(true);
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+    }
+
+    /// Decide everything about a background tile when its nametable byte is fetched: which column
+    /// it is, whether the split covers it, and, for extended attributes, its ExRAM byte.
+    fn begin_tile(&mut self, address: u16) {
+        self.tile = self.next_tile;
+        self.next_tile = self.next_tile.saturating_add(1);
+
+        // The two tiles fetched at the end of a line belong to the next one.
+        let line = if self.in_frame {
+            self.scanline as u16 + u16::from(self.tile < 2)
+        } else {
+            0
+        };
+        let delimiter = self.split_control & 0x1F;
+        let right_side = self.split_control & 0x40 != 0;
+        let column = self.tile & 0x1F;
+        self.in_split = self.split_control & 0x80 != 0
+            && self.exram_mode <= 1
+            && if right_side {
+                column >= delimiter
+            } else {
+                column < delimiter
+            };
+        if self.in_split {
+            self.split_y = ((self.split_scroll as u16 + line) % 240) as u8;
+        }
+
+        self.ext_attribute = self.exram[address as usize & 0x03FF];
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5015 => self.audio.write(address, value),
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.ram_protect[0] = value,
+            0x5103 => self.ram_protect[1] = value,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(address - 0x5113) as usize] = value,
+            0x5120..=0x5127 => {
+                self.chr_a[(address - 0x5120) as usize] = value as u16 | ((self.chr_upper as u16) << 8);
+                self.last_wrote_b = false;
+            },
+            0x5128..=0x512B => {
+                self.chr_b[(address - 0x5128) as usize] = value as u16 | ((self.chr_upper as u16) << 8);
+                self.last_wrote_b = true;
+            },
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                // As a nametable, ExRAM is the PPU's while it draws, and the CPU's writes land
+                // then. Outside rendering, the chip writes zero instead — a quirk `exram` tests.
+                let value = match self.exram_mode {
+                    0 | 1 if !self.in_frame => 0,
+                    3 => return,
+                    _ => value,
+                };
+                self.exram[address as usize & 0x03FF] = value;
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Where a CPU access to `$8000..=$FFFF` lands.
+enum PrgTarget {
+    Rom(usize, usize),
+    Ram(usize),
+    None,
+}
+
+impl Mapper for Mmc5 {
+    fn maps_cpu_address(&self, address: u16, write: bool) -> bool {
+        match address {
+            0x6000..=0x7FFF => true,
+            0x5C00..=0x5FFF => write || self.exram_mode >= 2,
+            0x5010 | 0x5015 | 0x5204..=0x5206 => true,
+            0x5000..=0x5015 | 0x5100..=0x5130 | 0x5200..=0x5203 => write,
+            _ => false,
+        }
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            0x5010 | 0x5015 => self.audio.read(address),
+            0x5204 => {
+                let pending = self.irq_pending.replace(false);
+                (u8::from(pending) << 7) | (u8::from(self.in_frame) << 6)
+            },
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[address as usize & 0x03FF],
+            0x6000..=0x7FFF => match self.ram_offset(self.prg_banks[0]) {
+                Some(base) => self.prg_ram[base + (address as usize & 0x1FFF)],
+                None => 0,
+            },
+            0x8000..=0xFFFF => {
+                let value = match self.prg_target(address) {
+                    PrgTarget::Rom(bank, offset) => banked(&self.prg, bank, PRG_BANK, offset),
+                    PrgTarget::Ram(offset) => self.prg_ram[offset],
+                    PrgTarget::None => 0,
+                };
+                // The PCM channel's read mode samples whatever the CPU reads from this half.
+                if address < 0xC000 {
+                    self.audio.observe_cpu_read(value);
+                }
+                value
+            },
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF => {
+                if let (true, Some(base)) = (self.ram_writable(), self.ram_offset(self.prg_banks[0])) {
+                    self.prg_ram[base + (address as usize & 0x1FFF)] = value;
+                }
+            },
+            0x8000..=0xFFFF => {
+                if let (true, PrgTarget::Ram(offset)) = (self.ram_writable(), self.prg_target(address)) {
+                    self.prg_ram[offset] = value;
+                }
+            },
+            _ => self.write_register(address, value),
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address) % self.chr.len()]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_offset(address) % self.chr.len();
+        self.chr[index] = value;
+    }
+
+    /// The nearest fixed arrangement to `$5105`, for anything that asks.
+    ///
+    /// The PPU does not: it reads through [`read_nametable`](Mapper::read_nametable), which can
+    /// express everything `$5105` can.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables {
+            0x44 => Mirroring::Vertical,
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    /// Each of the four nametables comes from one of four places, as `$5105` says: either page of
+    /// the console's RAM, ExRAM, or fill mode, which answers every tile with `$5106` and every
+    /// attribute with `$5107`.
+    ///
+    /// ExRAM as a nametable reads as zero unless it is in one of the two nametable modes. The
+    /// split and extended attributes override all of this for the fetches they apply to.
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        let attribute = (address & 0x03FF) >= 0x03C0;
+        match self.fetch {
+            Some(PpuFetch::Nametable) if self.in_split => return self.split_nametable(false),
+            Some(PpuFetch::Attribute) if self.in_split => return self.split_nametable(true),
+            Some(PpuFetch::Attribute) if self.exram_mode == 1 => return (self.ext_attribute >> 6) * 0x55,
+            _ => {},
+        }
+
+        let quadrant = (address >> 10) & 0x03;
+        match (self.nametables >> (quadrant * 2)) & 0x03 {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + (address as usize & 0x03FF)],
+            2 if self.exram_mode <= 1 => self.exram[address as usize & 0x03FF],
+            2 => 0,
+            _ if attribute => self.fill_attribute * 0x55,
+            _ => self.fill_tile,
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        let quadrant = (address >> 10) & 0x03;
+        match (self.nametables >> (quadrant * 2)) & 0x03 {
+            page @ (0 | 1) => ciram[page as usize * 0x400 + (address as usize & 0x03FF)] = value,
+            2 if self.exram_mode <= 1 => self.exram[address as usize & 0x03FF] = value,
+            // Fill mode has nothing behind it to write to.
+            _ => {},
+        }
+    }
+
+    fn watches_ppu_fetches(&self) -> bool {
+        true
+    }
+
+    fn on_ppu_fetch(&mut self, address: u16, fetch: PpuFetch) {
+        self.detect_scanline(address);
+        self.fetch = Some(fetch);
+        match fetch {
+            PpuFetch::Nametable => self.begin_tile(address),
+            // The first of a line's unused reads marks its end: the next tile is column 0 of the
+            // following line.
+            PpuFetch::Dummy => self.next_tile = 0,
+            _ => {},
+        }
+    }
+
+    fn on_ppu_register_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x2000 => self.sprites_8x16 = value & 0x20 != 0,
+            // Rendering switched off ends the frame at once, without waiting out the silence.
+            0x2001 if value & 0x18 == 0 => self.in_frame = false,
+            _ => {},
+        }
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.audio.tick();
+
+        if self.idle > 0 {
+            self.idle -= 1;
+            if self.idle == 0 {
+                self.in_frame = false;
+                self.last_read = 0;
+                self.repeats = 0;
+                self.fetch = None;
+            }
+        }
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Mmc5)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn irq_pending(&self) -> bool {
+        (self.irq_enabled && self.irq_pending.get()) || self.audio.irq_pending()
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending.set(false);
+    }
+
+    /// Windows as wide as the PRG mode makes them. One switched to RAM that the board does not
+    /// have is left out, as nothing answers there.
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let mut windows = Vec::new();
+        if let Some(base) = self.ram_offset(self.prg_banks[0]) {
+            windows.push(BankWindow::new(0x6000, PRG_BANK, BankMemory::Ram, base));
+        }
+        let mut start = 0x8000u32;
+        while start <= 0xFFFF {
+            let address = start as u16;
+            let size = self.prg_window(address).1 * PRG_BANK;
+            let window = match self.prg_target(address) {
+                PrgTarget::Rom(bank, _) => {
+                    Some(BankWindow::banked(address, PRG_BANK, BankMemory::Rom, bank, self.prg.len()))
+                },
+                PrgTarget::Ram(offset) => Some(BankWindow::new(address, PRG_BANK, BankMemory::Ram, offset)),
+                PrgTarget::None => None,
+            };
+            windows.extend(window.map(|window| BankWindow { size, ..window }));
+            start += size as u32;
+        }
+        windows
+    }
+
+    /// The register set the next read outside the split would use. The split's bank and the
+    /// extended attributes' are per tile, and among the registers instead.
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        let size = CHR_BANK << (3 - (self.chr_mode & 0x03));
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let set_b = self.use_set_b();
+        (0x0000..0x2000)
+            .step_by(size)
+            .map(|start| {
+                let offset = (self.chr_bank(start, set_b) % banks) * CHR_BANK;
+                BankWindow::new(start, size, memory, offset)
+            })
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![
+            MapperRegister::new("PRG mode", self.prg_mode),
+            MapperRegister::new("CHR mode", self.chr_mode),
+            MapperRegister::new("RAM protect 1", self.ram_protect[0]),
+            MapperRegister::new("RAM protect 2", self.ram_protect[1]),
+            MapperRegister::new("ExRAM mode", self.exram_mode),
+            MapperRegister::new("Nametables", self.nametables),
+            MapperRegister::new("Fill tile", self.fill_tile),
+            MapperRegister::new("Fill attribute", self.fill_attribute),
+        ];
+        registers.extend(
+            self.prg_banks
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("${:04X}", 0x5113 + index), bank)),
+        );
+        registers.extend(
+            self.chr_a
+                .iter()
+                .chain(&self.chr_b)
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("${:04X}", 0x5120 + index), bank)),
+        );
+        registers.extend([
+            MapperRegister::new("CHR upper", self.chr_upper),
+            MapperRegister::new("Split control", self.split_control),
+            MapperRegister::new("Split scroll", self.split_scroll),
+            MapperRegister::new("Split bank", self.split_bank),
+        ]);
+        registers
+    }
+
+    /// The counter is the scanline the chip believes the PPU is on, and the latch the one it
+    /// fires on.
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.scanline.into(),
+            latch: self.irq_target.into(),
+            enabled: self.irq_enabled,
+            pending: self.irq_pending.get(),
+        })
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    /// Registers, then the PPU-tracking state, then ExRAM, all of the work RAM, CHR RAM if the
+    /// board has it, and the sound chip.
+    ///
+    /// RAM is included here rather than left to the `$6000` window a save state otherwise reads,
+    /// because only one bank of it is visible there at a time.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.prg_mode,
+            self.chr_mode,
+            self.ram_protect[0],
+            self.ram_protect[1],
+            self.exram_mode,
+            self.nametables,
+            self.fill_tile,
+            self.fill_attribute,
+        ];
+        state.extend_from_slice(&self.prg_banks);
+        for bank in self.chr_a.iter().chain(&self.chr_b) {
+            state.extend_from_slice(&bank.to_le_bytes());
+        }
+        state.extend_from_slice(&[
+            self.chr_upper,
+            u8::from(self.last_wrote_b),
+            self.split_control,
+            self.split_scroll,
+            self.split_bank,
+            self.irq_target,
+            u8::from(self.irq_enabled),
+            u8::from(self.irq_pending.get()),
+            u8::from(self.in_frame),
+            self.scanline,
+            self.multiplicand,
+            self.multiplier,
+            u8::from(self.sprites_8x16),
+        ]);
+        state.extend_from_slice(&self.last_read.to_le_bytes());
+        state.extend_from_slice(&[self.repeats, self.idle, self.next_tile]);
+        state.extend_from_slice(&self.exram);
+        state.extend_from_slice(&self.prg_ram);
+        if self.chr_is_ram {
+            state.extend_from_slice(&self.chr);
+        }
+        self.audio.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let chr_len = if self.chr_is_ram { self.chr.len() } else { 0 };
+        let expected = REGISTER_STATE_LEN + EXRAM_SIZE + self.prg_ram.len() + chr_len + Mmc5Audio::STATE_LEN;
+        if state.len() < expected {
+            return;
+        }
+
+        let (registers, rest) = state.split_at(REGISTER_STATE_LEN);
+        self.prg_mode = registers[0];
+        self.chr_mode = registers[1];
+        self.ram_protect = [registers[2], registers[3]];
+        self.exram_mode = registers[4];
+        self.nametables = registers[5];
+        self.fill_tile = registers[6];
+        self.fill_attribute = registers[7];
+        self.prg_banks.copy_from_slice(&registers[8..13]);
+        let banks: Vec<u16> = registers[13..37]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        self.chr_a.copy_from_slice(&banks[..8]);
+        self.chr_b.copy_from_slice(&banks[8..]);
+        let flags = &registers[37..];
+        self.chr_upper = flags[0];
+        self.last_wrote_b = flags[1] != 0;
+        self.split_control = flags[2];
+        self.split_scroll = flags[3];
+        self.split_bank = flags[4];
+        self.irq_target = flags[5];
+        self.irq_enabled = flags[6] != 0;
+        self.irq_pending.set(flags[7] != 0);
+        self.in_frame = flags[8] != 0;
+        self.scanline = flags[9];
+        self.multiplicand = flags[10];
+        self.multiplier = flags[11];
+        self.sprites_8x16 = flags[12] != 0;
+        self.last_read = u16::from_le_bytes([flags[13], flags[14]]);
+        self.repeats = flags[15];
+        self.idle = flags[16];
+        self.next_tile = flags[17];
+
+        let (exram, rest) = rest.split_at(EXRAM_SIZE);
+        self.exram.copy_from_slice(exram);
+        let (prg_ram, rest) = rest.split_at(self.prg_ram.len());
+        self.prg_ram.copy_from_slice(prg_ram);
+        let (chr, rest) = rest.split_at(chr_len);
+        if self.chr_is_ram {
+            self.chr.copy_from_slice(chr);
+        }
+        self.audio.load(rest);
+    }
+}
+
+/// Bytes of [`Mmc5::save_state`] before ExRAM.
+const REGISTER_STATE_LEN: usize = 8 + 5 + 24 + 13 + 2 + 3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board() -> Mmc5 {
+        Mmc5::new(numbered_banks(64, PRG_BANK), numbered_banks(256, CHR_BANK), 64 * 1024)
+    }
+
+    /// Run the PPU's fetches for the end of one line and the start of the next: the two unused
+    /// reads and then the first tile, which together are the three identical reads the chip counts.
+    fn line_boundary(mapper: &mut Mmc5) {
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Dummy);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Dummy);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        mapper.on_ppu_fetch(0x23C0, PpuFetch::Attribute);
+    }
+
+    #[test]
+    fn powers_on_with_the_last_bank_at_the_top() {
+        let mapper = board();
+        assert_eq!(mapper.read_prg(0xFFFC), 63);
+    }
+
+    #[test]
+    fn every_prg_mode_maps_its_windows() {
+        let mut mapper = board();
+        for (register, value) in [(0x5114, 0x81), (0x5115, 0x86), (0x5116, 0x8A), (0x5117, 0x8F)] {
+            mapper.write_prg(register, value);
+        }
+
+        // Mode 0: $5117 as one 32 KB bank, low two bits ignored.
+        mapper.write_prg(0x5100, 0);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [0x0C, 0x0D, 0x0E, 0x0F]
+        );
+
+        // Mode 1: $5115 and $5117 as 16 KB banks, low bit ignored.
+        mapper.write_prg(0x5100, 1);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [6, 7, 0x0E, 0x0F]
+        );
+
+        // Mode 2: 16 KB from $5115, then 8 KB each from $5116 and $5117.
+        mapper.write_prg(0x5100, 2);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [6, 7, 0x0A, 0x0F]
+        );
+
+        // Mode 3: four 8 KB banks.
+        mapper.write_prg(0x5100, 3);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [1, 6, 0x0A, 0x0F]
+        );
+    }
+
+    #[test]
+    fn prg_ram_is_banked_and_write_protected() {
+        let mut mapper = board();
+        mapper.write_prg(0x5113, 2);
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(
+            mapper.read_prg(0x6000),
+            0,
+            "RAM is protected until $5102/$5103 unlock it"
+        );
+
+        mapper.write_prg(0x5102, 0x02);
+        mapper.write_prg(0x5103, 0x01);
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+        assert_eq!(mapper.prg_ram().unwrap()[2 * PRG_BANK], 0x42);
+
+        // The same bank switched into ROM space, with bit 7 clear.
+        mapper.write_prg(0x5114, 0x02);
+        assert_eq!(mapper.read_prg(0x8000), 0x42);
+        mapper.write_prg(0x8001, 0x43);
+        assert_eq!(mapper.read_prg(0x6001), 0x43);
+    }
+
+    #[test]
+    fn sixteen_kb_of_ram_is_two_chips_chosen_by_bit_2() {
+        let mut mapper = Mmc5::new(vec![0; 4 * PRG_BANK], Vec::new(), 16 * 1024);
+        assert_eq!(mapper.ram_offset(0), Some(0));
+        assert_eq!(mapper.ram_offset(3), Some(0));
+        assert_eq!(mapper.ram_offset(4), Some(PRG_BANK));
+        mapper.prg_ram = Vec::new();
+        assert_eq!(mapper.ram_offset(4), None);
+    }
+
+    #[test]
+    fn every_chr_mode_maps_its_windows() {
+        let mut mapper = board();
+        for register in 0x5120..=0x5127u16 {
+            mapper.write_prg(register, 0x10 + (register - 0x5120) as u8);
+        }
+
+        mapper.write_prg(0x5101, 0);
+        assert_eq!(mapper.read_chr(0x0000), 0x17 * 8);
+        assert_eq!(mapper.read_chr(0x1C00), 0x17 * 8 + 7);
+
+        mapper.write_prg(0x5101, 1);
+        assert_eq!(mapper.read_chr(0x0400), 0x13 * 4 + 1);
+        assert_eq!(mapper.read_chr(0x1000), 0x17 * 4);
+
+        mapper.write_prg(0x5101, 2);
+        assert_eq!(mapper.read_chr(0x0C00), 0x13 * 2 + 1);
+        assert_eq!(mapper.read_chr(0x1800), 0x17 * 2);
+
+        mapper.write_prg(0x5101, 3);
+        assert_eq!(mapper.read_chr(0x0800), 0x12);
+        assert_eq!(mapper.read_chr(0x1400), 0x15);
+    }
+
+    #[test]
+    fn chr_upper_bits_apply_when_the_bank_is_written() {
+        // 1 MB, the most the chip can address, with each 1 KB bank holding its top two bits.
+        let chr = (0..1024 * CHR_BANK).map(|n| ((n / CHR_BANK) >> 8) as u8).collect();
+        let mut mapper = Mmc5::new(vec![0; 4 * PRG_BANK], chr, 0);
+        mapper.write_prg(0x5101, 3);
+        mapper.write_prg(0x5130, 0x02);
+        mapper.write_prg(0x5120, 0x05);
+        mapper.write_prg(0x5130, 0x00);
+        assert_eq!(mapper.chr_a[0], 0x205);
+        assert_eq!(mapper.read_chr(0x0000), 0x02);
+    }
+
+    /// 8x16 sprites give the background its own set; sprites keep the first.
+    #[test]
+    fn large_sprites_split_the_character_sets() {
+        let mut mapper = board();
+        mapper.write_prg(0x5101, 3);
+        mapper.write_prg(0x5120, 0x20);
+        mapper.write_prg(0x5128, 0x30);
+        assert_eq!(mapper.read_chr(0x0000), 0x20, "8x8 sprites: set A for everything");
+
+        mapper.on_ppu_register_write(0x2000, 0x20);
+        assert_eq!(
+            mapper.read_chr(0x0000),
+            0x30,
+            "outside rendering the last-written set answers"
+        );
+
+        line_boundary(&mut mapper);
+        mapper.on_ppu_fetch(0x0000, PpuFetch::Background);
+        assert_eq!(mapper.read_chr(0x0000), 0x30);
+        mapper.on_ppu_fetch(0x0000, PpuFetch::Sprite);
+        assert_eq!(mapper.read_chr(0x0000), 0x20);
+    }
+
+    #[test]
+    fn nametables_come_from_ciram_exram_or_fill() {
+        let mut mapper = board();
+        let mut ciram = vec![0u8; 0x800];
+        ciram[0x005] = 0xA0;
+        ciram[0x405] = 0xA1;
+        mapper.exram[0x005] = 0xE0;
+        mapper.write_prg(0x5106, 0x77);
+        mapper.write_prg(0x5107, 0x02);
+        // $2000: page 0, $2400: page 1, $2800: ExRAM, $2C00: fill.
+        mapper.write_prg(0x5105, 0b11_10_01_00);
+
+        assert_eq!(mapper.read_nametable(0x2005, &ciram), 0xA0);
+        assert_eq!(mapper.read_nametable(0x2405, &ciram), 0xA1);
+        assert_eq!(mapper.read_nametable(0x2805, &ciram), 0xE0);
+        assert_eq!(mapper.read_nametable(0x2C05, &ciram), 0x77);
+        assert_eq!(mapper.read_nametable(0x2FC0, &ciram), 0xAA);
+
+        // ExRAM in a mode that is not for nametables reads as zero there.
+        mapper.write_prg(0x5104, 2);
+        assert_eq!(mapper.read_nametable(0x2805, &ciram), 0);
+
+        // Writes follow the same map, and fill mode takes none.
+        mapper.write_nametable(0x2C05, 0x11, &mut ciram);
+        mapper.write_nametable(0x2405, 0x12, &mut ciram);
+        assert_eq!(ciram[0x405], 0x12);
+        assert_eq!(mapper.read_nametable(0x2C05, &ciram), 0x77);
+    }
+
+    #[test]
+    fn exram_cpu_access_depends_on_its_mode() {
+        let mut mapper = board();
+
+        // Mode 2 is plain RAM.
+        mapper.write_prg(0x5104, 2);
+        assert!(mapper.maps_cpu_address(0x5C10, false));
+        mapper.write_prg(0x5C10, 0x99);
+        assert_eq!(mapper.read_prg(0x5C10), 0x99);
+
+        // Mode 3 reads but ignores writes.
+        mapper.write_prg(0x5104, 3);
+        mapper.write_prg(0x5C10, 0x55);
+        assert_eq!(mapper.read_prg(0x5C10), 0x99);
+
+        // Modes 0 and 1 cannot be read back, and a write outside rendering stores zero.
+        mapper.write_prg(0x5104, 1);
+        assert!(!mapper.maps_cpu_address(0x5C10, false));
+        assert!(mapper.maps_cpu_address(0x5C10, true));
+        mapper.write_prg(0x5C10, 0x66);
+        assert_eq!(mapper.exram[0x10], 0);
+
+        line_boundary(&mut mapper);
+        mapper.write_prg(0x5C10, 0x66);
+        assert_eq!(mapper.exram[0x10], 0x66);
+    }
+
+    #[test]
+    fn extended_attributes_give_each_tile_a_palette_and_bank() {
+        let mut mapper = board();
+        let ciram = vec![0u8; 0x800];
+        mapper.write_prg(0x5104, 1);
+        mapper.write_prg(0x5130, 0x01);
+        mapper.exram[0x021] = 0b1000_0011;
+
+        mapper.on_ppu_fetch(0x2021, PpuFetch::Nametable);
+        mapper.on_ppu_fetch(0x23C0, PpuFetch::Attribute);
+        assert_eq!(mapper.read_nametable(0x23C0, &ciram), 0xAA);
+
+        mapper.on_ppu_fetch(0x0123, PpuFetch::Background);
+        // 4 KB bank 0x43 is 1 KB bank 0x10C, which wraps to 0x0C in 256 banks.
+        assert_eq!(mapper.read_chr(0x0123), ((0x43 * 4) % 256) as u8);
+    }
+
+    #[test]
+    fn the_irq_fires_on_its_scanline_and_reads_back_in_5204() {
+        let mut mapper = board();
+        mapper.write_prg(0x5203, 3);
+        mapper.write_prg(0x5204, 0x80);
+
+        line_boundary(&mut mapper);
+        assert_eq!(mapper.read_prg(0x5204), 0x40, "in frame, line 0");
+        for _ in 0..2 {
+            line_boundary(&mut mapper);
+        }
+        assert!(!mapper.irq_pending());
+        line_boundary(&mut mapper);
+        assert!(mapper.irq_pending(), "line 3 should fire");
+
+        assert_eq!(mapper.read_prg(0x5204), 0xC0);
+        assert!(!mapper.irq_pending(), "reading $5204 acknowledges");
+    }
+
+    #[test]
+    fn the_irq_waits_for_its_enable() {
+        let mut mapper = board();
+        mapper.write_prg(0x5203, 1);
+        line_boundary(&mut mapper);
+        line_boundary(&mut mapper);
+        assert!(!mapper.irq_pending());
+        mapper.write_prg(0x5204, 0x80);
+        assert!(mapper.irq_pending(), "the flag was set all along; enabling exposes it");
+    }
+
+    #[test]
+    fn silence_from_the_ppu_ends_the_frame() {
+        let mut mapper = board();
+        line_boundary(&mut mapper);
+        assert!(mapper.in_frame);
+        for _ in 0..IDLE_CYCLES {
+            mapper.cpu_cycle();
+        }
+        assert!(!mapper.in_frame);
+        assert_eq!(mapper.read_prg(0x5204) & 0x40, 0);
+    }
+
+    #[test]
+    fn the_split_replaces_tiles_left_of_the_delimiter() {
+        let mut mapper = board();
+        let ciram = vec![0u8; 0x800];
+        // Split the two leftmost columns, scrolled down 16 lines, from 4 KB bank 5.
+        mapper.write_prg(0x5200, 0x80 | 2);
+        mapper.write_prg(0x5201, 16);
+        mapper.write_prg(0x5202, 5);
+        mapper.exram[(2 << 5) | 1] = 0x3C;
+        mapper.exram[0x3C0] = 0b0011_0000;
+
+        // Line 0 starts; its tiles 0 and 1 were fetched at the end of the line before.
+        line_boundary(&mut mapper);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Dummy);
+        mapper.on_ppu_fetch(0x2000, PpuFetch::Nametable);
+        mapper.on_ppu_fetch(0x2001, PpuFetch::Nametable);
+        assert!(mapper.in_split);
+        assert_eq!(mapper.tile, 1);
+        // Tile 1 belongs to line 1: row 16 + 1 is coarse row 2, fine row 1.
+        assert_eq!(mapper.read_nametable(0x2001, &ciram), 0x3C);
+        mapper.on_ppu_fetch(0x23C0, PpuFetch::Attribute);
+        assert_eq!(mapper.read_nametable(0x23C0, &ciram), 0xFF);
+        mapper.on_ppu_fetch(0x03C0, PpuFetch::Background);
+        assert_eq!(mapper.read_chr(0x03C0), ((5 * 4 * CHR_BANK + 0x3C1) / CHR_BANK) as u8);
+
+        // Column 2 is outside it.
+        mapper.on_ppu_fetch(0x2002, PpuFetch::Nametable);
+        assert!(!mapper.in_split);
+    }
+
+    #[test]
+    fn the_multiplier_reads_back_its_product() {
+        let mut mapper = board();
+        mapper.write_prg(0x5205, 200);
+        mapper.write_prg(0x5206, 100);
+        assert_eq!(mapper.read_prg(0x5205), (20000u16 & 0xFF) as u8);
+        assert_eq!(mapper.read_prg(0x5206), (20000u16 >> 8) as u8);
+    }
+
+    #[test]
+    fn only_readable_registers_are_claimed_for_reads() {
+        let mapper = board();
+        for address in [0x5204, 0x5205, 0x5206, 0x5010, 0x5015, 0x6000] {
+            assert!(mapper.maps_cpu_address(address, false), "{address:04X}");
+        }
+        for address in [0x5100, 0x5120, 0x5203, 0x5000, 0x5011] {
+            assert!(!mapper.maps_cpu_address(address, false), "{address:04X}");
+            assert!(mapper.maps_cpu_address(address, true), "{address:04X}");
+        }
+        assert!(!mapper.maps_cpu_address(0x4800, true));
+    }
+
+    #[test]
+    fn pcm_read_mode_hears_reads_of_the_lower_rom_half() {
+        let mut mapper = board();
+        mapper.write_prg(0x5114, 0x80);
+        mapper.write_prg(0x5010, 0x81);
+        mapper.read_prg(0x8000);
+        assert!(mapper.irq_pending(), "bank 0 is all zeros, which ends a sample");
+        assert_eq!(mapper.read_prg(0x5010), 0x80);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn save_state_round_trips_registers_and_all_ram() {
+        let mut mapper = board();
+        mapper.write_prg(0x5102, 0x02);
+        mapper.write_prg(0x5103, 0x01);
+        mapper.write_prg(0x5100, 1);
+        mapper.write_prg(0x5113, 5);
+        mapper.write_prg(0x6123, 0x5A);
+        mapper.write_prg(0x5104, 2);
+        mapper.write_prg(0x5C00, 0xA5);
+        mapper.write_prg(0x5130, 0x01);
+        mapper.write_prg(0x512A, 0x33);
+        mapper.write_prg(0x5203, 9);
+        mapper.write_prg(0x5000, 0x3F);
+        line_boundary(&mut mapper);
+
+        let state = mapper.save_state();
+        let mut restored = board();
+        restored.load_state(&state);
+
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0x6123), 0x5A);
+        assert_eq!(restored.read_prg(0x5C00), 0xA5);
+        assert_eq!(restored.chr_b[2], 0x133);
+        assert!(restored.in_frame);
+    }
+}
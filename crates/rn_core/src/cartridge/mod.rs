@@ -1,54 +1,269 @@
+mod archive;
+mod bandai_fcg;
+mod battery;
+mod database;
+mod discrete;
+mod eeprom;
+mod fds;
+mod fds_disk;
+mod fme7;
 mod loader;
 mod mapper;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod namco163;
+mod nsf;
+mod nsf_player;
+mod patch;
 mod pattern_table;
+mod sunsoft4;
+mod test_board;
+mod unif;
+mod unrom512;
+mod vrc24;
+mod vrc6;
+mod vrc7;
 
-use std::path::Path;
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-pub use loader::{load_chr_rom, load_rom, INesHeader, Rom, RomLoadError};
-pub use mapper::{create as create_mapper, name as mapper_name, supported_list as supported_mappers, Mapper};
+pub use archive::{is_archive, read_rom_file, rom_entries as archive_rom_entries, unpack as unpack_archive};
+pub use battery::BatteryFile;
+pub use database::{crc32, lookup as lookup_database, sha1_digest, AppliedCorrection, Correction, DatabaseEntry};
+pub use fds::{Fds, BIOS_SIZE as FDS_BIOS_SIZE};
+pub use fds_disk::FdsDisk;
+pub use loader::{
+    load_chr_rom,
+    load_fds_bios,
+    load_rom,
+    load_rom_from_archive,
+    load_rom_with_patch,
+    parse_rom,
+    ConsoleType,
+    ExpansionDevice,
+    HeaderFormat,
+    INesHeader,
+    Rom,
+    RomLoadError,
+    Timing,
+};
+use log::{debug, info};
+pub use mapper::{
+    create as create_mapper,
+    name as mapper_name,
+    supported_list as supported_mappers,
+    BankMemory,
+    BankWindow,
+    IrqCounter,
+    Mapper,
+    MapperRegister,
+    PpuFetch,
+};
+pub use mmc3::{Mmc3, Mmc3Variant};
+pub use nsf::{load_nsf, Nsf, NSFE_MAGIC, NSF_MAGIC};
+pub use nsf_player::NsfPlayer;
+pub use patch::{apply as apply_patch, beside as patch_beside};
 pub use pattern_table::PatternTable;
+pub use test_board::TestBoard;
+pub use unif::{board_mapper as unif_board_mapper, UNIF_MAGIC};
+pub use vrc24::VrcVariant;
 
+use crate::errors::NesError;
 // Re-exported so the mapper layer and the PPU agree on one Mirroring type.
 pub use crate::ppu::Mirroring;
 
-/// Basic NES cartridge implementation
+/// A cartridge, shared between the parts of the machine that reach it: the bus, the PPU and the
+/// APU all hold one of these, and they all hold the same one.
+pub type CartridgeHandle = Rc<RefCell<Cartridge>>;
+
+/// Everything in the cartridge slot: the board, what its header said, the work RAM at `$6000`,
+/// the battery that keeps that RAM, and where the image came from.
 ///
-/// Handles loading and access to PRG ROM (program data) and CHR ROM (graphics data)
-#[derive(Clone, Debug, Default)]
+/// The board — the [`Mapper`] — owns the PRG and CHR memory, because only it knows how they are
+/// banked. This is the one object the rest of the machine talks to, so that a hand-assembled
+/// program on a [`TestBoard`] and a game on an MMC3 are reached in the same way. They used to be
+/// two: a mapper wired into the bus and the PPU separately, and a pattern table the PPU kept for
+/// assembled programs, which drifted apart more than once.
+#[derive(Debug)]
 pub struct Cartridge {
-    /// Pattern table containing CHR ROM data
-    pattern_table: PatternTable,
-    // PRG ROM and other components would go here in a complete implementation
+    header: INesHeader,
+    mapper: Box<dyn Mapper>,
+
+    /// The 8 KB at `$6000..=$7FFF`, for a board whose mapper does not map that range itself.
+    /// Most boards with work RAM wire it straight to the bus with no mapper involvement at all;
+    /// one whose mapper banks or protects it keeps its own, and this goes unused.
+    prg_ram: Vec<u8>,
+
+    /// Where the image was read from. `None` for one that never came from a file.
+    path: Option<PathBuf>,
+    /// What the cartridge database changed in `header`, if anything.
+    correction: Option<AppliedCorrection>,
+
+    /// Where the battery-backed RAM is kept between runs, if the cartridge has a battery.
+    battery: Option<BatteryFile>,
+    /// How much of the RAM the battery keeps: the NVRAM size from the header, up to all of it.
+    battery_size: usize,
+}
+
+impl Default for Cartridge {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cartridge {
-    /// Create a new empty cartridge
+    /// A [`TestBoard`], for hand-assembled programs and for tests: RAM on both sides, horizontal
+    /// mirroring, nothing loaded.
     pub fn new() -> Self {
+        Self::test_board(Mirroring::Horizontal)
+    }
+
+    /// A [`TestBoard`] with its nametables wired as `mirroring`.
+    pub fn test_board(mirroring: Mirroring) -> Self {
+        let mut header = INesHeader::for_mapper(0);
+        header.mirroring = mirroring == Mirroring::Vertical;
+        Self::with_mapper(header, Box::new(TestBoard::new(mirroring)))
+    }
+
+    /// A cartridge built around `mapper`, with nothing said about where it came from.
+    pub fn with_mapper(header: INesHeader, mapper: Box<dyn Mapper>) -> Self {
         Self {
-            pattern_table: PatternTable::new(),
+            header,
+            mapper,
+            prg_ram: vec![0; 0x2000],
+            path: None,
+            correction: None,
+            battery: None,
+            battery_size: 0,
         }
     }
 
-    /// Load CHR ROM data from a slice
-    pub fn load_chr_rom(&mut self, data: &[u8]) {
-        self.pattern_table.load(data);
+    /// A cartridge for `rom`, on the board already built for it from `header`.
+    ///
+    /// Takes the board rather than building it, because which board to build is not always the
+    /// header's decision: a forced VRC or MMC3 variant, or a disk's BIOS, is chosen by the machine.
+    /// A forced VRC board changes the mapper and submapper too, so `header` is the one the board
+    /// was built from rather than the file's.
+    pub fn from_rom(rom: &Rom, header: INesHeader, mapper: Box<dyn Mapper>) -> Self {
+        Self {
+            path: rom.path.clone(),
+            correction: rom.correction.clone(),
+            ..Self::with_mapper(header, mapper)
+        }
     }
 
-    /// Load CHR ROM data from an iNES ROM file
-    pub fn load_chr_rom_from_file(&mut self, path: &Path) -> Result<(), RomLoadError> {
-        let chr_data = load_chr_rom(path)?;
-        self.load_chr_rom(&chr_data);
-        Ok(())
+    /// Wrap the cartridge for sharing.
+    pub fn shared(self) -> CartridgeHandle {
+        Rc::new(RefCell::new(self))
+    }
+
+    pub fn header(&self) -> &INesHeader {
+        &self.header
+    }
+
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn correction(&self) -> Option<&AppliedCorrection> {
+        self.correction.as_ref()
+    }
+
+    /// Whether the cartridge answers a CPU access to `address`: everything from `$6000` up, and
+    /// whatever below that its mapper says it maps.
+    pub fn maps_cpu_address(&self, address: u16, write: bool) -> bool {
+        address >= 0x6000 || (address >= 0x4020 && self.mapper.maps_cpu_address(address, write))
+    }
+
+    /// Read the byte the CPU sees at `address`, from the mapper or from the work RAM.
+    pub fn read_cpu(&self, address: u16) -> u8 {
+        if self.uses_own_ram(address, false) {
+            return self.prg_ram[address as usize & 0x1FFF];
+        }
+        self.mapper.read_prg(address)
+    }
+
+    /// Write a byte from the CPU, to the mapper or to the work RAM.
+    pub fn write_cpu(&mut self, address: u16, value: u8) {
+        if self.uses_own_ram(address, true) {
+            self.prg_ram[address as usize & 0x1FFF] = value;
+            return;
+        }
+        self.mapper.write_prg(address, value);
+    }
+
+    /// Which bits of a read at `address` the cartridge leaves undriven. See
+    /// [`Mapper::open_bus_mask`]; the work RAM drives all of them.
+    pub fn open_bus_mask(&self, address: u16) -> u8 {
+        if self.uses_own_ram(address, false) {
+            return 0;
+        }
+        self.mapper.open_bus_mask(address)
+    }
+
+    /// Where each part of CPU space points: the mapper's windows, with the work RAM at `$6000`
+    /// wherever it is the cartridge's own RAM that answers there.
+    pub fn prg_windows(&self) -> Vec<BankWindow> {
+        let mut windows = self.mapper.prg_windows();
+        if self.uses_own_ram(0x6000, false) && !windows.iter().any(|window| window.contains(0x6000)) {
+            windows.insert(0, BankWindow::new(0x6000, self.prg_ram.len(), BankMemory::Ram, 0));
+        }
+        windows
+    }
+
+    /// The window `address` falls in, if the mapper says where it points.
+    pub fn prg_window_at(&self, address: u16) -> Option<BankWindow> {
+        self.prg_windows().into_iter().find(|window| window.contains(address))
+    }
+
+    fn uses_own_ram(&self, address: u16, write: bool) -> bool {
+        (0x6000..0x8000).contains(&address) && !self.mapper.maps_cpu_address(address, write)
+    }
+
+    /// The mapper's state, for a save state. The work RAM goes in separately, read through the bus.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.mapper.save_state()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) {
+        self.mapper.load_state(state);
     }
 
-    /// Read a byte from the pattern table
+    /// Read a byte of character memory, through the mapper's banking.
     pub fn read_pattern_table(&self, address: u16) -> u8 {
-        self.pattern_table.read_byte(address)
+        self.mapper.read_chr(address & 0x1FFF)
     }
 
-    /// Write a byte to the pattern table (for cartridges with CHR RAM)
+    /// Write a byte of character memory. A board with CHR ROM ignores it.
     pub fn write_pattern_table(&mut self, address: u16, value: u8) {
-        self.pattern_table.write_byte(address, value);
+        self.mapper.write_chr(address & 0x1FFF, value);
+    }
+
+    /// Copy up to 8 KB of tiles into character memory, as an assembled program's `CHARS`
+    /// segment is. Only a board with CHR RAM keeps them.
+    pub fn load_chr_rom(&mut self, data: &[u8]) {
+        for (address, byte) in data.iter().take(0x2000).enumerate() {
+            self.write_pattern_table(address as u16, *byte);
+        }
+    }
+
+    /// Load the CHR ROM of the iNES file at `path` into character memory.
+    pub fn load_chr_rom_from_file(&mut self, path: &Path) -> Result<(), RomLoadError> {
+        let chr_data = load_chr_rom(path)?;
+        self.load_chr_rom(&chr_data);
+        Ok(())
     }
 
     /// Get pixel data for a specific tile in the pattern table
@@ -61,7 +276,11 @@ impl Cartridge {
     ///
     /// An array of 64 values (0-3) representing the 8x8 pixel data for the tile
     pub fn get_tile_pixels(&self, tile_index: u16) -> [u8; 64] {
-        self.pattern_table.get_tile_pixels(tile_index)
+        let mut pixels = [0u8; 64];
+        for (row, chunk) in pixels.chunks_exact_mut(8).enumerate() {
+            chunk.copy_from_slice(&self.get_tile_row(tile_index, row));
+        }
+        pixels
     }
 
     /// Get a single row of pixels from a tile in the pattern table
@@ -75,7 +294,129 @@ impl Cartridge {
     ///
     /// An array of 8 values (0-3) representing the pixel data for the row
     pub fn get_tile_row(&self, tile_index: u16, row: usize) -> [u8; 8] {
-        self.pattern_table.get_tile_row(tile_index, row)
+        let address = tile_index * 16 + row as u16;
+        let low_byte = self.read_pattern_table(address);
+        let high_byte = self.read_pattern_table(address + 8);
+
+        let mut row_pixels = [0u8; 8];
+        for (bit, pixel) in row_pixels.iter_mut().enumerate() {
+            let low_bit = (low_byte >> (7 - bit)) & 0x01;
+            let high_bit = (high_byte >> (7 - bit)) & 0x01;
+            *pixel = (high_bit << 1) | low_bit;
+        }
+        row_pixels
+    }
+
+    /// Bring back the save a battery-backed cartridge left behind, before its first instruction.
+    ///
+    /// Restored before the reset vector is even read, which is the order hardware has it in: the
+    /// RAM was holding its contents the whole time the console was off. Nothing happens for a
+    /// cartridge without a battery, or one that did not come from a file.
+    pub fn restore_battery(&mut self, read_only: bool) -> Result<(), NesError> {
+        let Some(path) = self.path.clone().filter(|_| self.header.battery) else {
+            return Ok(());
+        };
+
+        // A board that saves into its flash has a file of its own, and no RAM for the battery to
+        // keep: the battery bit is how its header says the flash may be written.
+        // A disk's is the same kind of thing, under a name of its own.
+        if self.mapper.flash_save().is_some() {
+            let mut battery = if self.header.format == HeaderFormat::Fds {
+                BatteryFile::disk_beside(&path, read_only)
+            } else {
+                BatteryFile::flash_beside(&path, read_only)
+            };
+            let saved = battery
+                .read()
+                .map_err(|error| NesError::BatterySave(battery.path().to_path_buf(), error))?;
+            if let Some(saved) = saved {
+                self.mapper.restore_flash_save(&saved);
+                info!("Flash save restored from {}", battery.path().display());
+            }
+            self.battery = Some(battery);
+            return Ok(());
+        }
+
+        // A mapper that holds its own PRG RAM can bank more of it than `$6000` shows at once, so
+        // the whole of it is saved, straight from the mapper; otherwise it is the window.
+        let ram_size = self.mapper.prg_ram().map_or(0x2000, <[u8]>::len);
+
+        // iNES says only that there is a battery, which means all of the 8 KB; NES 2.0 says how
+        // much it keeps, and a zero there means it backs something other than this RAM.
+        self.battery_size = match (self.header.is_nes2(), self.header.prg_nvram_size) {
+            (true, 0) => return Ok(()),
+            (true, size) => size.clamp(1, ram_size),
+            (false, _) => ram_size,
+        };
+
+        let mut battery = BatteryFile::beside(&path, read_only);
+        let saved = battery
+            .read()
+            .map_err(|error| NesError::BatterySave(battery.path().to_path_buf(), error))?;
+
+        if let Some(saved) = saved {
+            // A save of the wrong size — from a differently-headered dump of the same game, say —
+            // restores as much as fits rather than nothing.
+            let saved = &saved[..saved.len().min(self.battery_size)];
+            if let Some(ram) = self.mapper.prg_ram_mut() {
+                for (slot, byte) in ram.iter_mut().zip(saved) {
+                    *slot = *byte;
+                }
+            } else {
+                for (offset, byte) in saved.iter().enumerate() {
+                    self.write_cpu(0x6000 + offset as u16, *byte);
+                }
+            }
+            info!("Battery save restored from {}", battery.path().display());
+        }
+
+        self.battery = Some(battery);
+        Ok(())
+    }
+
+    /// Write battery-backed RAM to its file, if the cartridge has one and it has changed.
+    ///
+    /// Safe to call at any time, as often as wanted, since an unchanged RAM writes nothing.
+    pub fn flush_battery(&mut self) -> Result<(), NesError> {
+        if self.battery.is_none() {
+            return Ok(());
+        }
+
+        // From the mapper if it keeps the save itself, in flash or in RAM; otherwise as the CPU
+        // reads it, so this is the RAM the game sees.
+        let from_mapper = self.mapper.flash_save().or_else(|| {
+            self.mapper
+                .prg_ram()
+                .map(|ram| ram[..self.battery_size.min(ram.len())].to_vec())
+        });
+        let data: Vec<u8> = from_mapper.unwrap_or_else(|| {
+            (0..self.battery_size)
+                .map(|offset| self.read_cpu(0x6000 + offset as u16))
+                .collect()
+        });
+
+        let Some(battery) = self.battery.as_mut() else {
+            return Ok(());
+        };
+        if battery
+            .write(&data)
+            .map_err(|error| NesError::BatterySave(battery.path().to_path_buf(), error))?
+        {
+            debug!("Battery save written to {}", battery.path().display());
+        }
+        Ok(())
+    }
+
+    /// The `.sav`, `.flash` or `.fdsdiff` file the battery is kept in, if there is a battery.
+    pub fn battery_path(&self) -> Option<&Path> {
+        self.battery.as_ref().map(BatteryFile::path)
+    }
+
+    /// Stop writing the battery file, or start again. See [`BatteryFile::set_read_only`].
+    pub fn set_battery_read_only(&mut self, read_only: bool) {
+        if let Some(battery) = self.battery.as_mut() {
+            battery.set_read_only(read_only);
+        }
     }
 }
 
@@ -119,7 +460,7 @@ mod tests {
 
         // Create a new PPU and connect the cartridge
         let mut ppu = Ppu::new();
-        ppu.connect_cartridge(cartridge);
+        ppu.connect_cartridge(cartridge.shared());
 
         // Verify we can read several addresses in the pattern table range
         for addr in [0x0000u16, 0x0100u16, 0x0800u16, 0x1000u16, 0x1F00u16] {
@@ -129,4 +470,58 @@ mod tests {
 
         Ok(())
     }
+
+    /// Work RAM is the cartridge's unless its mapper claims the range, and then it is the mapper's.
+    #[test]
+    fn work_ram_answers_only_where_the_mapper_does_not() {
+        let mut plain = Cartridge::with_mapper(
+            INesHeader::for_mapper(0),
+            create_mapper(&INesHeader::for_mapper(0), vec![0xEA; 0x4000], Vec::new()).unwrap(),
+        );
+        plain.write_cpu(0x6001, 0x42);
+        assert_eq!(plain.read_cpu(0x6001), 0x42);
+        assert_eq!(plain.read_cpu(0x8000), 0xEA);
+        assert!(plain.maps_cpu_address(0x7FFF, false));
+        assert!(!plain.maps_cpu_address(0x5000, false));
+
+        /// A board whose mapper keeps the work RAM itself.
+        #[derive(Debug)]
+        struct OwnRam([u8; 0x2000]);
+        impl Mapper for OwnRam {
+            fn read_prg(&self, address: u16) -> u8 {
+                self.0[address as usize & 0x1FFF]
+            }
+            fn write_prg(&mut self, address: u16, value: u8) {
+                self.0[address as usize & 0x1FFF] = value;
+            }
+            fn read_chr(&self, _address: u16) -> u8 {
+                0
+            }
+            fn write_chr(&mut self, _address: u16, _value: u8) {}
+            fn mirroring(&self) -> Mirroring {
+                Mirroring::Horizontal
+            }
+            fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+                address >= 0x6000
+            }
+        }
+
+        let mut own = Cartridge::with_mapper(INesHeader::for_mapper(0), Box::new(OwnRam([0; 0x2000])));
+        own.write_cpu(0x6001, 0x42);
+        assert_eq!(own.mapper().read_prg(0x6001), 0x42, "written to the mapper's own RAM");
+        assert_eq!(own.prg_ram[1], 0, "and not to the cartridge's");
+    }
+
+    #[test]
+    fn the_windows_include_the_work_ram_the_cartridge_answers_with() {
+        let nrom = Cartridge::with_mapper(
+            INesHeader::for_mapper(0),
+            create_mapper(&INesHeader::for_mapper(0), vec![0xEA; 0x4000], Vec::new()).unwrap(),
+        );
+        let ram = nrom.prg_window_at(0x7000).unwrap();
+        assert_eq!((ram.start, ram.memory), (0x6000, BankMemory::Ram));
+        let mirror = nrom.prg_window_at(0xC123).unwrap();
+        assert_eq!((mirror.start, mirror.offset_of(0xC123)), (0xC000, Some(0x0123)));
+        assert_eq!(nrom.prg_window_at(0x4020), None);
+    }
 }
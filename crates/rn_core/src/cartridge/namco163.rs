@@ -0,0 +1,458 @@
+//! Namco 163 (mapper 19).
+//!
+//! Three switchable 8 KB PRG banks ahead of a fixed last one, eight 1 KB CHR banks, four more
+//! registers that choose what each nametable is, a 15-bit IRQ counter that counts CPU cycles, and
+//! a wavetable sound chip, in [`crate::apu::N163Audio`]. *Megami Tensei II*, *Final Lap* and
+//! *King of Kings* are among its thirty-odd games.
+//!
+//! The nametable registers are what set it apart. Each of `$C000`, `$C800`, `$D000` and `$D800`
+//! names a 1 KB bank of CHR ROM for one of the four nametables, and values `$E0` and up name a page
+//! of the console's own RAM instead — so a game can draw a title screen straight out of ROM,
+//! without copying it, and still have RAM nametables for everything else. Mirroring is nothing
+//! more than a choice of those four values.
+//!
+//! The eight pattern registers take `$E0` and up the same way, as a page of the console's RAM
+//! used for tiles, unless `$E800` bits 6 and 7 forbid it for that half. Nothing is known to draw
+//! tiles from there, and the pattern tables are read without the console's RAM in reach, so those
+//! values are treated as ordinary CHR banks.
+//!
+//! Several other Namco chips — 129, 175, 340 — share mapper 19's neighbourhood but differ in the
+//! details. Only the 163 is modelled; NES 2.0 gives the others mapper 210.
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip, N163Audio};
+
+/// Bytes of register state in a save, before the IRQ's and the sound chip's.
+const REGISTER_STATE_LEN: usize = 15;
+
+/// Bytes of IRQ state in a save.
+const IRQ_STATE_LEN: usize = 3;
+
+/// The value the IRQ counter stops at, raising its interrupt.
+const IRQ_COUNTER_MAX: u16 = 0x7FFF;
+
+/// Namco 163 (mapper 19). See the module documentation.
+#[derive(Debug)]
+pub struct Namco163 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    /// `$E000`, `$E800` and `$F000`. Bits 0-5 are the 8 KB banks at `$8000`, `$A000` and `$C000`;
+    /// the rest are `$E000`'s sound disable and `$E800`'s two pattern-table bits.
+    prg_registers: [u8; 3],
+    /// `$8000..=$B800`, the pattern tables' 1 KB banks.
+    chr_banks: [u8; 8],
+    /// `$C000..=$D800`, the four nametables.
+    nametables: [u8; 4],
+
+    /// Counts up once a CPU cycle while enabled, and stops at [`IRQ_COUNTER_MAX`].
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: N163Audio,
+}
+
+impl Namco163 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            prg_registers: [0; 3],
+            chr_banks: [0; 8],
+            // Power-on contents are undefined; these are the console's two pages, mirrored
+            // vertically, which is harmless until the game sets its own.
+            nametables: [0xE0, 0xE1, 0xE0, 0xE1],
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            audio: N163Audio::new(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        match address {
+            0x8000..=0xDFFF => (self.prg_registers[(address as usize - 0x8000) / PRG_BANK] & 0x3F) as usize,
+            _ => (self.prg.len() / PRG_BANK).max(1) - 1,
+        }
+    }
+
+    fn chr_offset(&self, bank: u8, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        (bank as usize % banks) * CHR_BANK + (address as usize & 0x03FF)
+    }
+
+    /// Where nametable `address` comes from: a page of the console's RAM, or an offset into CHR.
+    fn nametable_source(&self, address: u16) -> NametableSource {
+        let register = self.nametables[(address as usize >> 10) & 0x03];
+        if register >= 0xE0 {
+            NametableSource::Ciram((register as usize & 0x01) * 0x400 + (address as usize & 0x03FF))
+        } else {
+            NametableSource::Chr(self.chr_offset(register, address))
+        }
+    }
+}
+
+/// What a nametable register selects.
+enum NametableSource {
+    Ciram(usize),
+    Chr(usize),
+}
+
+impl Mapper for Namco163 {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.audio.read(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (u8::from(self.irq_enabled) << 7) | (self.irq_counter >> 8) as u8,
+            _ => banked(
+                &self.prg,
+                self.prg_bank_for(address),
+                PRG_BANK,
+                address as usize & 0x1FFF,
+            ),
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800..=0x4FFF => self.audio.write(value),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16 & 0x7F) << 8);
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0x8000..=0xBFFF => self.chr_banks[(address as usize - 0x8000) >> 11] = value,
+            0xC000..=0xDFFF => self.nametables[(address as usize - 0xC000) >> 11] = value,
+            0xE000..=0xE7FF => {
+                self.prg_registers[0] = value;
+                self.audio.set_disabled(value & 0x40 != 0);
+            },
+            0xE800..=0xEFFF => self.prg_registers[1] = value,
+            0xF000..=0xF7FF => self.prg_registers[2] = value,
+            // Bits 4-7 also guard the work RAM against writes, but nothing needs the guard, and
+            // the bus's RAM at `$6000` stays writable.
+            0xF800..=0xFFFF => self.audio.set_address(value),
+            _ => {},
+        }
+    }
+
+    fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+        (0x4800..=0x5FFF).contains(&address)
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(self.chr_banks[(address as usize >> 10) & 0x07], address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_offset(self.chr_banks[(address as usize >> 10) & 0x07], address);
+        self.chr[index] = value;
+    }
+
+    /// The nearest fixed arrangement to the nametable registers, for anything that asks.
+    ///
+    /// The PPU does not: it reads through [`read_nametable`](Mapper::read_nametable), which can
+    /// express everything the registers can.
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables.map(|register| register >= 0xE0 && register & 1 != 0) {
+            [false, true, false, true] => Mirroring::Vertical,
+            [false, false, false, false] => Mirroring::SingleScreenLower,
+            [true, true, true, true] => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        match self.nametable_source(address) {
+            NametableSource::Ciram(offset) => ciram[offset],
+            NametableSource::Chr(offset) => self.chr[offset],
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        match self.nametable_source(address) {
+            NametableSource::Ciram(offset) => ciram[offset] = value,
+            NametableSource::Chr(offset) if self.chr_is_ram => self.chr[offset] = value,
+            NametableSource::Chr(_) => {},
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq_pending = false;
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| {
+                let offset = self.chr_offset(self.chr_banks[(start as usize >> 10) & 0x07], start);
+                BankWindow::new(start, CHR_BANK, memory, offset)
+            })
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers: Vec<_> = self
+            .chr_banks
+            .iter()
+            .enumerate()
+            .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank))
+            .collect();
+        registers.extend(
+            self.nametables
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("Nametable {index}"), bank)),
+        );
+        registers.extend(
+            self.prg_registers
+                .iter()
+                .zip(["$E000", "$E800", "$F000"])
+                .map(|(&value, name)| MapperRegister::new(name, value)),
+        );
+        registers
+    }
+
+    /// The counter counts up to its limit rather than down from a latch, so the latch shown is
+    /// the counter again.
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(IrqCounter {
+            counter: self.irq_counter.into(),
+            latch: self.irq_counter.into(),
+            enabled: self.irq_enabled,
+            pending: self.irq_pending,
+        })
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        if self.irq_enabled && self.irq_counter < IRQ_COUNTER_MAX {
+            self.irq_counter += 1;
+            if self.irq_counter == IRQ_COUNTER_MAX {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Namco163)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.audio.channels()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        self.audio.set_channel_enabled(index, enabled);
+    }
+
+    /// Banks, the IRQ, and the sound chip — whose state is mostly its 128 bytes of RAM, waveforms
+    /// and channel registers alike.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.prg_registers.to_vec();
+        state.extend_from_slice(&self.chr_banks);
+        state.extend_from_slice(&self.nametables);
+        state.extend_from_slice(&self.irq_counter.to_le_bytes());
+        state.push(u8::from(self.irq_enabled) | (u8::from(self.irq_pending) << 1));
+        self.audio.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < REGISTER_STATE_LEN + IRQ_STATE_LEN + N163Audio::STATE_LEN {
+            return;
+        }
+
+        self.prg_registers.copy_from_slice(&state[..3]);
+        self.chr_banks.copy_from_slice(&state[3..11]);
+        self.nametables.copy_from_slice(&state[11..15]);
+        let irq = &state[REGISTER_STATE_LEN..];
+        self.irq_counter = u16::from_le_bytes([irq[0], irq[1]]) & IRQ_COUNTER_MAX;
+        self.irq_enabled = irq[2] & 0x01 != 0;
+        self.irq_pending = irq[2] & 0x02 != 0;
+        self.audio.load(&state[REGISTER_STATE_LEN + IRQ_STATE_LEN..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board() -> Namco163 {
+        Namco163::new(numbered_banks(32, PRG_BANK), numbered_banks(256, CHR_BANK))
+    }
+
+    #[test]
+    fn three_prg_banks_then_the_fixed_last() {
+        let mut mapper = board();
+        mapper.write_prg(0xE000, 0x45);
+        mapper.write_prg(0xE800, 0xC6);
+        mapper.write_prg(0xF000, 7);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [5, 6, 7, 31]
+        );
+    }
+
+    #[test]
+    fn eight_1k_chr_banks() {
+        let mut mapper = board();
+        for window in 0..8u16 {
+            mapper.write_prg(0x8000 + window * 0x800, 0x30 + window as u8);
+        }
+        assert_eq!(
+            (0..8u16).map(|w| mapper.read_chr(w * 0x400)).collect::<Vec<_>>(),
+            [0x30, 0x31, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37]
+        );
+    }
+
+    /// A nametable register below `$E0` puts a bank of CHR ROM there, read-only; `$E0` and up put
+    /// a page of the console's RAM.
+    #[test]
+    fn nametables_come_from_console_ram_or_chr_rom() {
+        let mut mapper = board();
+        let mut ciram = vec![0u8; 0x800];
+        ciram[0x400 + 0x12] = 0xAB;
+
+        mapper.write_prg(0xC000, 0x42);
+        mapper.write_prg(0xC800, 0xE1);
+        mapper.write_prg(0xD000, 0xE0);
+        mapper.write_prg(0xD800, 0xFF);
+
+        assert_eq!(mapper.read_nametable(0x2012, &ciram), 0x42, "CHR bank $42");
+        assert_eq!(mapper.read_nametable(0x2412, &ciram), 0xAB, "the second page");
+        assert_eq!(
+            mapper.read_nametable(0x2C12, &ciram),
+            0xAB,
+            "odd values are the second page"
+        );
+
+        mapper.write_nametable(0x2812, 0x77, &mut ciram);
+        assert_eq!(ciram[0x12], 0x77);
+        mapper.write_nametable(0x2012, 0x99, &mut ciram);
+        assert_eq!(mapper.read_nametable(0x2012, &ciram), 0x42, "CHR ROM ignores the write");
+    }
+
+    #[test]
+    fn mirroring_is_reported_from_the_nametable_registers() {
+        let mut mapper = board();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        for (address, value) in [(0xC000, 0xE0), (0xC800, 0xE0), (0xD000, 0xE1), (0xD800, 0xE1)] {
+            mapper.write_prg(address, value);
+        }
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn the_irq_counts_cpu_cycles_up_to_7fff() {
+        let mut mapper = board();
+        mapper.write_prg(0x5000, 0xFD);
+        mapper.write_prg(0x5800, 0xFF);
+        assert_eq!([mapper.read_prg(0x5000), mapper.read_prg(0x5800)], [0xFD, 0xFF]);
+
+        mapper.cpu_cycle();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_cycle();
+        assert!(mapper.irq_pending());
+        mapper.cpu_cycle();
+        assert_eq!(mapper.read_prg(0x5000), 0xFF, "and stays there");
+        assert!(mapper.irq_pending(), "reading does not acknowledge");
+
+        mapper.write_prg(0x5000, 0);
+        assert!(!mapper.irq_pending(), "writing does");
+    }
+
+    #[test]
+    fn the_sound_chip_is_reached_through_the_board() {
+        let mut mapper = board();
+        assert!(mapper.maps_cpu_address(0x4800, false) && mapper.maps_cpu_address(0x5FFF, true));
+        assert!(!mapper.maps_cpu_address(0x6000, false));
+
+        mapper.write_prg(0xF800, 0x80);
+        for value in [0x10, 0x32, 0x54] {
+            mapper.write_prg(0x4800, value);
+        }
+        mapper.write_prg(0xF800, 0x81);
+        assert_eq!([mapper.read_prg(0x4800), mapper.read_prg(0x4800)], [0x32, 0x54]);
+        assert_eq!(mapper.audio_channels().len(), 8);
+    }
+
+    /// The wavetable RAM travels in the save state: it is where the channels' registers and
+    /// waveforms both live.
+    #[test]
+    fn save_state_round_trips_with_the_wavetable_ram() {
+        let mut mapper = board();
+        mapper.write_prg(0xE800, 3);
+        mapper.write_prg(0xC800, 0x21);
+        mapper.write_prg(0x5800, 0x80);
+        mapper.write_prg(0xF800, 0x80);
+        for value in 0..128u8 {
+            mapper.write_prg(0x4800, value.wrapping_mul(37));
+        }
+        for _ in 0..1000 {
+            mapper.cpu_cycle();
+        }
+
+        let state = mapper.save_state();
+        let mut restored = board();
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0xA000), 3);
+        assert_eq!(restored.read_prg(0x5000), mapper.read_prg(0x5000));
+        restored.write_prg(0xF800, 0x85);
+        mapper.write_prg(0xF800, 0x85);
+        assert_eq!(restored.read_prg(0x4800), mapper.read_prg(0x4800));
+        assert_eq!(restored.audio_output(), mapper.audio_output());
+    }
+}
@@ -0,0 +1,362 @@
+//! NSF and NSFe music files: a game's sound driver and its music, lifted out of the game.
+//!
+//! An NSF is not a recording. It is the 6502 code a game ran to play its music, with the data it
+//! played, and three addresses: where to load it, a routine to call once to start a track, and a
+//! routine to call once a frame after that. Playing one means building just enough of a console
+//! around that code to run it, which is [`NsfPlayer`](super::NsfPlayer)'s job. This module only
+//! reads the files.
+//!
+//! The original format is a 128-byte header followed by the data. NSFe is the same information
+//! in tagged chunks, with room for what the header had none for: a name and a length for each
+//! track. NSF2 is an NSF header with NSFe's metadata chunks appended after the data, and is read
+//! as both.
+//!
+//! Large NSFs are bankswitched. The data is then treated as 4 KB banks, placed so that the load
+//! address falls at the right offset within its bank, and the header gives the bank each 4 KB
+//! window of `$8000..=$FFFF` starts on. A header whose eight bank bytes are all zero is not
+//! bankswitched, and its data simply sits at the load address.
+
+use std::{path::Path, time::Duration};
+
+use super::{archive, RomLoadError};
+use crate::{apu::ExpansionChip, region::Region};
+
+/// How every NSF begins: `NESM` and an MS-DOS end of file, as iNES does it.
+pub const NSF_MAGIC: [u8; 5] = *b"NESM\x1A";
+pub const NSFE_MAGIC: [u8; 4] = *b"NSFE";
+
+const NSF_HEADER_SIZE: usize = 0x80;
+
+/// Play rates for a file that gives none: the frame rates of the two consoles, in microseconds.
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+/// The expansion chips in the order of the header's bits.
+const CHIP_BITS: [ExpansionChip; 6] = [
+    ExpansionChip::Vrc6,
+    ExpansionChip::Vrc7,
+    ExpansionChip::Fds,
+    ExpansionChip::Mmc5,
+    ExpansionChip::Namco163,
+    ExpansionChip::Sunsoft5b,
+];
+
+/// A parsed NSF or NSFe file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    /// The code and music, to load at `load_address` or to bankswitch.
+    pub data: Vec<u8>,
+    /// The bank each 4 KB window of `$8000..=$FFFF` starts on, for a bankswitched file.
+    pub banks: Option<[u8; 8]>,
+
+    /// How often to call the play routine, in microseconds, on each console.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    /// The console the music was written for, or `None` for a file that plays on either.
+    pub region: Option<Region>,
+    pub chips: Vec<ExpansionChip>,
+
+    pub songs: u8,
+    /// The track to start on, counting from zero.
+    pub first_song: u8,
+    /// NSFe's names for the tracks, where the file gives them.
+    pub track_labels: Vec<String>,
+    /// NSFe's lengths for the tracks, where the file gives them.
+    pub track_lengths: Vec<Option<Duration>>,
+}
+
+impl Nsf {
+    /// Read an NSF, NSF2 or NSFe file from its bytes.
+    pub fn parse(bytes: &[u8]) -> Result<Self, RomLoadError> {
+        if bytes.starts_with(&NSF_MAGIC) {
+            Self::parse_nsf(bytes)
+        } else if bytes.starts_with(&NSFE_MAGIC) {
+            let mut nsf = Nsf::default();
+            nsf.read_chunks(&bytes[NSFE_MAGIC.len()..], true)?;
+            Ok(nsf)
+        } else {
+            Err(RomLoadError::InvalidFormat("not an NSF or NSFe file"))
+        }
+    }
+
+    fn parse_nsf(bytes: &[u8]) -> Result<Self, RomLoadError> {
+        if bytes.len() < NSF_HEADER_SIZE {
+            return Err(RomLoadError::InvalidFormat("NSF header too small"));
+        }
+        let word = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let text = |at: usize| string(&bytes[at..at + 32]);
+        let banks: [u8; 8] = bytes[0x70..0x78].try_into().unwrap_or_default();
+
+        // NSF2 can say where the data ends, with metadata chunks after it.
+        let version = bytes[0x05];
+        let data_len = u32::from_le_bytes([bytes[0x7D], bytes[0x7E], bytes[0x7F], 0]) as usize;
+        let data_end = if version >= 2 && data_len > 0 {
+            (NSF_HEADER_SIZE + data_len).min(bytes.len())
+        } else {
+            bytes.len()
+        };
+
+        let mut nsf = Nsf {
+            title: text(0x0E),
+            artist: text(0x2E),
+            copyright: text(0x4E),
+            load_address: word(0x08),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+            data: bytes[NSF_HEADER_SIZE..data_end].to_vec(),
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            region: region(bytes[0x7A]),
+            chips: chips(bytes[0x7B]),
+            songs: bytes[0x06].max(1),
+            first_song: bytes[0x07].saturating_sub(1),
+            ..Nsf::default()
+        };
+        if data_end < bytes.len() {
+            nsf.read_chunks(&bytes[data_end..], false)?;
+        }
+        Ok(nsf)
+    }
+
+    /// Read NSFe chunks: a 32-bit length, a four-letter name, and the contents. `whole` is for a
+    /// file that is nothing but chunks, which has to carry its addresses and data in them.
+    ///
+    /// A chunk this does not know is skipped if its name starts in lowercase, and refused if it
+    /// starts in uppercase: that is how the format marks a chunk the music cannot play without.
+    fn read_chunks(&mut self, mut bytes: &[u8], whole: bool) -> Result<(), RomLoadError> {
+        let (mut info, mut data) = (false, false);
+        while bytes.len() >= 8 {
+            let len = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+            let id = &bytes[4..8];
+            let chunk = bytes.get(8..8 + len).ok_or(RomLoadError::InvalidFormat(
+                "an NSFe chunk runs past the end of the file",
+            ))?;
+            bytes = &bytes[8 + len..];
+            match id {
+                b"INFO" => {
+                    if chunk.len() < 8 {
+                        return Err(RomLoadError::InvalidFormat("NSFe INFO chunk too small"));
+                    }
+                    let word = |at: usize| u16::from_le_bytes([chunk[at], chunk[at + 1]]);
+                    self.load_address = word(0);
+                    self.init_address = word(2);
+                    self.play_address = word(4);
+                    self.region = region(chunk[6]);
+                    self.chips = chips(chunk[7]);
+                    self.songs = chunk.get(8).copied().unwrap_or(1).max(1);
+                    self.first_song = chunk.get(9).copied().unwrap_or(0);
+                    info = true;
+                },
+                b"DATA" => {
+                    self.data = chunk.to_vec();
+                    data = true;
+                },
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, &byte) in banks.iter_mut().zip(chunk) {
+                        *bank = byte;
+                    }
+                    self.banks = Some(banks);
+                },
+                b"RATE" => {
+                    let word = |at: usize| chunk.get(at..at + 2).map(|word| u16::from_le_bytes([word[0], word[1]]));
+                    self.ntsc_speed = word(0).unwrap_or(self.ntsc_speed);
+                    self.pal_speed = word(2).unwrap_or(self.pal_speed);
+                },
+                b"auth" => {
+                    let mut fields = chunk.split(|&byte| byte == 0).map(string);
+                    self.title = fields.next().unwrap_or_default();
+                    self.artist = fields.next().unwrap_or_default();
+                    self.copyright = fields.next().unwrap_or_default();
+                },
+                b"tlbl" => {
+                    self.track_labels = chunk.split(|&byte| byte == 0).map(string).collect();
+                    self.track_labels.truncate(self.songs as usize);
+                },
+                b"time" => {
+                    self.track_lengths = chunk
+                        .chunks_exact(4)
+                        .map(|ms| i32::from_le_bytes([ms[0], ms[1], ms[2], ms[3]]))
+                        .map(|ms| u64::try_from(ms).ok().map(Duration::from_millis))
+                        .collect();
+                },
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(RomLoadError::InvalidFormat(
+                        "the NSFe file needs a chunk this player does not know",
+                    ));
+                },
+                _ => {},
+            }
+        }
+        if whole && !(info && data) {
+            return Err(RomLoadError::InvalidFormat(
+                "an NSFe file needs an INFO and a DATA chunk",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The play routine's period on `region`, in microseconds. A Dendy runs its music at PAL's
+    /// frame rate.
+    pub fn speed(&self, region: Region) -> u32 {
+        let (speed, default) = match region {
+            Region::Ntsc => (self.ntsc_speed, DEFAULT_NTSC_SPEED),
+            Region::Pal | Region::Dendy => (self.pal_speed, DEFAULT_PAL_SPEED),
+        };
+        if speed == 0 {
+            default as u32
+        } else {
+            speed as u32
+        }
+    }
+
+    /// The name of `track`, counting from zero, if the file gives it one.
+    pub fn track_label(&self, track: u8) -> Option<&str> {
+        self.track_labels
+            .get(track as usize)
+            .map(String::as_str)
+            .filter(|label| !label.is_empty())
+    }
+
+    /// How long `track` lasts, if the file says.
+    pub fn track_length(&self, track: u8) -> Option<Duration> {
+        self.track_lengths.get(track as usize).copied().flatten()
+    }
+}
+
+/// Load an NSF, NSF2 or NSFe file, zipped or gzipped or not.
+pub fn load_nsf(path: &Path) -> Result<Nsf, RomLoadError> {
+    Nsf::parse(&archive::read_rom_file(path, None)?)
+}
+
+/// Text up to its first zero, which the fixed-size NSF fields pad with.
+fn string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// The header's region byte: bit 0 PAL, bit 1 either.
+fn region(flags: u8) -> Option<Region> {
+    match flags & 0x03 {
+        0 => Some(Region::Ntsc),
+        1 => Some(Region::Pal),
+        _ => None,
+    }
+}
+
+fn chips(flags: u8) -> Vec<ExpansionChip> {
+    CHIP_BITS
+        .iter()
+        .enumerate()
+        .filter(|&(bit, _)| flags & (1 << bit) != 0)
+        .map(|(_, &chip)| chip)
+        .collect()
+}
+
+/// An NSF of `data` at `load`, with init and play routines at `init` and `play`, for tests here
+/// and of the player.
+#[cfg(test)]
+pub(super) fn nsf_file(load: u16, init: u16, play: u16, data: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; NSF_HEADER_SIZE];
+    file[..5].copy_from_slice(&NSF_MAGIC);
+    file[0x05] = 1;
+    file[0x06] = 3;
+    file[0x07] = 2;
+    file[0x08..0x0A].copy_from_slice(&load.to_le_bytes());
+    file[0x0A..0x0C].copy_from_slice(&init.to_le_bytes());
+    file[0x0C..0x0E].copy_from_slice(&play.to_le_bytes());
+    file[0x0E..0x13].copy_from_slice(b"Title");
+    file[0x6E..0x70].copy_from_slice(&DEFAULT_NTSC_SPEED.to_le_bytes());
+    file.extend_from_slice(data);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn an_nsf_header_is_read_field_by_field() {
+        let mut file = nsf_file(0x8000, 0x8003, 0x8006, &[1, 2, 3]);
+        file[0x7A] = 0x01;
+        file[0x7B] = 0x21;
+        let nsf = Nsf::parse(&file).unwrap();
+
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(
+            (nsf.load_address, nsf.init_address, nsf.play_address),
+            (0x8000, 0x8003, 0x8006)
+        );
+        assert_eq!(nsf.data, [1, 2, 3]);
+        assert_eq!((nsf.songs, nsf.first_song), (3, 1), "the header counts tracks from one");
+        assert_eq!(nsf.banks, None, "eight zero banks is not bankswitched");
+        assert_eq!(nsf.region, Some(Region::Pal));
+        assert_eq!(nsf.chips, [ExpansionChip::Vrc6, ExpansionChip::Sunsoft5b]);
+        assert_eq!(nsf.speed(Region::Ntsc), 16639);
+        assert_eq!(
+            nsf.speed(Region::Pal),
+            19997,
+            "no PAL rate in the header falls back to 50 Hz"
+        );
+    }
+
+    #[test]
+    fn an_nsfe_file_carries_the_same_in_chunks_and_names_its_tracks() {
+        let mut file = NSFE_MAGIC.to_vec();
+        file.extend(chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x02, 0x04, 2, 1]));
+        file.extend(chunk(b"DATA", &[0xEA; 16]));
+        file.extend(chunk(b"BANK", &[0, 1]));
+        file.extend(chunk(b"auth", b"Game\0Composer\0Publisher\0Ripper\0"));
+        file.extend(chunk(b"tlbl", b"Overworld\0Castle\0"));
+        file.extend(chunk(b"time", &[0xE8, 0x03, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        file.extend(chunk(b"mine", &[1, 2, 3]));
+        file.extend(chunk(b"NEND", &[]));
+        let nsf = Nsf::parse(&file).unwrap();
+
+        assert_eq!((nsf.title.as_str(), nsf.artist.as_str()), ("Game", "Composer"));
+        assert_eq!(nsf.init_address, 0x8003);
+        assert_eq!(nsf.data.len(), 16);
+        assert_eq!(nsf.banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.region, None, "bit 1 plays on either");
+        assert_eq!(nsf.chips, [ExpansionChip::Fds]);
+        assert_eq!((nsf.songs, nsf.first_song), (2, 1), "NSFe counts from zero");
+        assert_eq!(nsf.track_label(1), Some("Castle"));
+        assert_eq!(nsf.track_length(0), Some(Duration::from_secs(1)));
+        assert_eq!(nsf.track_length(1), None, "a negative time is no time");
+
+        let mut required = NSFE_MAGIC.to_vec();
+        required.extend(chunk(b"INFO", &[0; 8]));
+        required.extend(chunk(b"DATA", &[0]));
+        required.extend(chunk(b"WHAT", &[]));
+        assert!(Nsf::parse(&required).is_err(), "an unknown uppercase chunk is required");
+        assert!(Nsf::parse(&NSFE_MAGIC).is_err(), "no INFO or DATA");
+    }
+
+    #[test]
+    fn an_nsf2_file_keeps_its_metadata_after_the_data() {
+        let mut file = nsf_file(0x8000, 0x8000, 0x8000, &[0x60; 4]);
+        file[0x05] = 2;
+        file[0x7D] = 4;
+        file.extend(chunk(b"tlbl", b"One\0Two\0Three\0"));
+        let nsf = Nsf::parse(&file).unwrap();
+
+        assert_eq!(nsf.data, [0x60; 4]);
+        assert_eq!(nsf.track_label(2), Some("Three"));
+    }
+}
@@ -0,0 +1,617 @@
+//! The cartridge an NSF player builds around a music file.
+//!
+//! Hardware NSF players were real cartridges: a small ROM with a driver in it, RAM, a bankswitcher
+//! and whichever sound chips the music wanted, and this is one of those. The driver resets the
+//! APU, clears RAM, calls the file's init routine with the track number in A and the region in X,
+//! and then waits on a timer and calls its play routine each time the timer fires — at the rate
+//! the file asks for rather than at vblank, since not every game's music ran at sixty hertz.
+//!
+//! The driver is a page of 6502 at `$4100`, out of the way of everything an NSF may touch, and
+//! the vectors at `$FFFA..=$FFFF` are pointed into it whatever the banks hold there. Three
+//! registers sit at the end of that page for it to read: the track, the region, and whether the
+//! timer has fired since the last look, which is cleared by looking.
+//!
+//! Everything else is decoded as the boards the music came from decoded it, so the driver code in
+//! the file finds its sound chips where it expects them. A file that declares several chips gets
+//! all of them, each on its own addresses. Writes that two chips share — `$E000` and up is both
+//! the 5B's data port and the Namco 163's address latch — go to both, as they would on a player
+//! built with both. The chips are mixed here, before the APU's per-chip level is applied, so that
+//! level is the first declared chip's and covers the rest too; a file with more than one chip is
+//! rare enough that one level for all of them serves.
+//!
+//! A Disk System file has RAM, not ROM, at `$6000..=$FFFF`, so a bank switch there copies the bank
+//! in rather than mapping it, and the file's code can write over itself as disk games did.
+
+use std::cell::Cell;
+
+use super::{
+    mapper::{BankMemory, BankWindow, Mapper, MapperRegister},
+    nsf::Nsf,
+    Mirroring,
+};
+use crate::{
+    apu::{ExpansionChannel, ExpansionChip, FdsAudio, Mmc5Audio, N163Audio, Sunsoft5bAudio, Vrc6Audio, Vrc7Audio},
+    region::Region,
+};
+
+const BANK_SIZE: usize = 4 * 1024;
+/// Windows from `$6000` to the end of the address space, a bank each.
+const WINDOWS: usize = 10;
+/// A window with nothing of the file in it, below an unbankswitched load address.
+const NO_BANK: u16 = 0xFFFF;
+
+/// The page the driver runs from, and its registers at the end of it.
+const DRIVER_PAGE: u16 = 0x4100;
+const TRACK_REGISTER: u16 = 0x41F0;
+const REGION_REGISTER: u16 = 0x41F1;
+const PLAY_REGISTER: u16 = 0x41F2;
+
+/// Where in the driver the play loop and the interrupt return are, for the vectors.
+const RESET_ENTRY: u16 = DRIVER_PAGE;
+const INTERRUPT_ENTRY: u16 = DRIVER_PAGE + 0x4B;
+
+/// Bytes of register state in a save, ahead of the RAM and the sound chips.
+const REGISTER_STATE_LEN: usize = 2 * WINDOWS + 8 + 1 + 2;
+const EXRAM_SIZE: usize = 1024;
+
+/// The driver, with the file's init and play routines called where it says so.
+fn driver(init: u16, play: u16) -> Vec<u8> {
+    let [init_low, init_high] = init.to_le_bytes();
+    let [play_low, play_high] = play.to_le_bytes();
+    #[rustfmt::skip]
+    let code = [
+        0x78,                         // $4100  SEI
+        0xD8,                         //        CLD
+        0xA2, 0xFF,                   //        LDX #$FF
+        0x9A,                         //        TXS
+        0xE8,                         //        INX
+        0x8A,                         //        TXA
+        0x9D, 0x00, 0x00,             // $4107  STA $0000,X   clear the console's RAM
+        0x9D, 0x00, 0x01,             //        STA $0100,X
+        0x9D, 0x00, 0x02,             //        STA $0200,X
+        0x9D, 0x00, 0x03,             //        STA $0300,X
+        0x9D, 0x00, 0x04,             //        STA $0400,X
+        0x9D, 0x00, 0x05,             //        STA $0500,X
+        0x9D, 0x00, 0x06,             //        STA $0600,X
+        0x9D, 0x00, 0x07,             //        STA $0700,X
+        0xE8,                         //        INX
+        0xD0, 0xE5,                   //        BNE $4107
+        0xA2, 0x13,                   //        LDX #$13
+        0x9D, 0x00, 0x40,             // $4124  STA $4000,X   and the APU's registers
+        0xCA,                         //        DEX
+        0x10, 0xFA,                   //        BPL $4124
+        0x8D, 0x15, 0x40,             //        STA $4015
+        0xA9, 0x0F,                   //        LDA #$0F
+        0x8D, 0x15, 0x40,             //        STA $4015
+        0xA9, 0x40,                   //        LDA #$40
+        0x8D, 0x17, 0x40,             //        STA $4017
+        0xAD, 0xF0, 0x41,             //        LDA track
+        0xAE, 0xF1, 0x41,             //        LDX region
+        0x20, init_low, init_high,    //        JSR init
+        0xAD, 0xF2, 0x41,             // $4140  LDA play      wait for the timer
+        0xF0, 0xFB,                   //        BEQ $4140
+        0x20, play_low, play_high,    //        JSR play
+        0x4C, 0x40, 0x41,             //        JMP $4140
+        0x40,                         // $414B  RTI
+    ];
+    let mut page = code.to_vec();
+    page.resize(0x100, 0);
+    page
+}
+
+/// An NSF file, on the cartridge built to play it. See the module documentation.
+#[derive(Debug)]
+pub struct NsfPlayer {
+    /// The file's data, padded at the front so that bank `n` starts `n` banks in.
+    data: Vec<u8>,
+    driver: Vec<u8>,
+    /// The bank in each window of `$6000..=$FFFF`.
+    banks: [u16; WINDOWS],
+    /// A Disk System file, with RAM for the whole of `$6000..=$FFFF`.
+    fds: bool,
+    ram: Vec<u8>,
+
+    track: u8,
+    region: Region,
+    /// The play routine's period, in CPU cycles times a million: microseconds times the clock.
+    play_period: u64,
+    /// How far towards the next play call, in the same units.
+    play_clock: u64,
+    /// Set when the timer fires and cleared when the driver reads it; a cell for that read.
+    play_pending: Cell<bool>,
+
+    /// The MMC5's multiplier and its RAM, which its music drivers use as scratch.
+    multiplicands: [u8; 2],
+    exram: Vec<u8>,
+
+    vrc6: Option<Vrc6Audio>,
+    vrc7: Option<Vrc7Audio>,
+    fds_audio: Option<FdsAudio>,
+    mmc5: Option<Mmc5Audio>,
+    n163: Option<N163Audio>,
+    sunsoft5b: Option<Sunsoft5bAudio>,
+    /// The chips the file declares, in its order, and which of them are muted.
+    chips: Vec<ExpansionChip>,
+    muted: Vec<bool>,
+}
+
+impl NsfPlayer {
+    /// A player for `nsf`, about to start `track` (counting from zero) on `region`.
+    pub fn new(nsf: &Nsf, track: u8, region: Region) -> Self {
+        let fds = nsf.chips.contains(&ExpansionChip::Fds);
+        let padding = nsf.load_address as usize % BANK_SIZE;
+        let mut data = vec![0; padding];
+        data.extend_from_slice(&nsf.data);
+
+        let mut banks = [NO_BANK; WINDOWS];
+        match nsf.banks {
+            Some(initial) => {
+                for (window, &bank) in banks[2..].iter_mut().zip(&initial) {
+                    *window = bank as u16;
+                }
+                // A disk file's first two windows start on the banks of its last two, as the
+                // header has nowhere else to say.
+                if fds {
+                    banks[0] = initial[6] as u16;
+                    banks[1] = initial[7] as u16;
+                }
+            },
+            None => {
+                let first = nsf.load_address as i32 / BANK_SIZE as i32 - 6;
+                for (window, bank) in banks.iter_mut().enumerate() {
+                    if let Ok(number) = u16::try_from(window as i32 - first) {
+                        *bank = number;
+                    }
+                }
+            },
+        }
+
+        let clock = region.cpu_clock_rate().round() as u64;
+        let has = |chip: ExpansionChip| nsf.chips.contains(&chip);
+        let mut player = Self {
+            data,
+            driver: driver(nsf.init_address, nsf.play_address),
+            banks,
+            fds,
+            ram: vec![0; if fds { WINDOWS * BANK_SIZE } else { 2 * BANK_SIZE }],
+            track: track.min(nsf.songs.saturating_sub(1)),
+            region,
+            play_period: nsf.speed(region) as u64 * clock,
+            play_clock: 0,
+            play_pending: Cell::new(false),
+            multiplicands: [0; 2],
+            exram: vec![0; EXRAM_SIZE],
+            vrc6: has(ExpansionChip::Vrc6).then(Vrc6Audio::new),
+            vrc7: has(ExpansionChip::Vrc7).then(Vrc7Audio::new),
+            fds_audio: fds.then(FdsAudio::new),
+            mmc5: has(ExpansionChip::Mmc5).then(Mmc5Audio::new),
+            n163: has(ExpansionChip::Namco163).then(N163Audio::new),
+            sunsoft5b: has(ExpansionChip::Sunsoft5b).then(Sunsoft5bAudio::new),
+            chips: nsf.chips.clone(),
+            muted: vec![false; nsf.chips.len()],
+        };
+        if fds {
+            for window in 0..WINDOWS {
+                player.load_window(window);
+            }
+        }
+        player
+    }
+
+    /// The track the driver starts, counting from zero.
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    fn rom(&self, bank: u16, offset: usize) -> u8 {
+        if bank == NO_BANK {
+            return 0;
+        }
+        self.data.get(bank as usize * BANK_SIZE + offset).copied().unwrap_or(0)
+    }
+
+    /// Copy a window's bank into the RAM behind it, for a disk file.
+    fn load_window(&mut self, window: usize) {
+        let bank = self.banks[window];
+        for offset in 0..BANK_SIZE {
+            self.ram[window * BANK_SIZE + offset] = self.rom(bank, offset);
+        }
+    }
+
+    fn switch_bank(&mut self, window: usize, bank: u8) {
+        self.banks[window] = bank as u16;
+        if self.fds {
+            self.load_window(window);
+        }
+    }
+
+    fn driver_register(&self, address: u16) -> u8 {
+        match address {
+            TRACK_REGISTER => self.track,
+            REGION_REGISTER => u8::from(self.region != Region::Ntsc),
+            PLAY_REGISTER => u8::from(self.play_pending.replace(false)),
+            _ => self.driver[(address - DRIVER_PAGE) as usize],
+        }
+    }
+}
+
+impl Mapper for NsfPlayer {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x4097 => self
+                .fds_audio
+                .as_ref()
+                .and_then(|audio| audio.read(address))
+                .unwrap_or(0),
+            0x4100..=0x41FF => self.driver_register(address),
+            0x4800..=0x4FFF => self.n163.as_ref().map_or(0, N163Audio::read),
+            0x5010 | 0x5015 => self.mmc5.as_ref().map_or(0, |audio| audio.read(address)),
+            0x5205 => self.multiplicands[0].wrapping_mul(self.multiplicands[1]),
+            0x5206 => ((self.multiplicands[0] as u16 * self.multiplicands[1] as u16) >> 8) as u8,
+            0x5C00..=0x5FF5 => self.exram[address as usize - 0x5C00],
+            0xFFFA..=0xFFFF => {
+                let vector = if address & 0xFFFE == 0xFFFC {
+                    RESET_ENTRY
+                } else {
+                    INTERRUPT_ENTRY
+                };
+                vector.to_le_bytes()[address as usize & 1]
+            },
+            0x6000..=0x7FFF => self.ram[address as usize - 0x6000],
+            0x8000..=0xFFFF if self.fds => self.ram[address as usize - 0x6000],
+            0x8000..=0xFFFF => {
+                let window = (address as usize - 0x6000) / BANK_SIZE;
+                self.rom(self.banks[window], address as usize % BANK_SIZE)
+            },
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x5205 | 0x5206 => self.multiplicands[address as usize - 0x5205] = value,
+            0x5C00..=0x5FF5 => self.exram[address as usize - 0x5C00] = value,
+            0x5FF6..=0x5FF7 if self.fds => self.switch_bank(address as usize - 0x5FF6, value),
+            0x5FF8..=0x5FFF => self.switch_bank(address as usize - 0x5FF6, value),
+            0x6000..=0x7FFF => self.ram[address as usize - 0x6000] = value,
+            0x8000..=0xFFFF if self.fds => self.ram[address as usize - 0x6000] = value,
+            _ => {},
+        }
+
+        // Each chip at its own board's addresses, which is more than one chip for some.
+        if let (Some(audio), 0x4040..=0x408A) = (self.fds_audio.as_mut(), address) {
+            audio.write(address, value);
+        }
+        if let (Some(audio), 0x4800..=0x4FFF) = (self.n163.as_mut(), address) {
+            audio.write(value);
+        }
+        if let (Some(audio), 0xF800..=0xFFFF) = (self.n163.as_mut(), address) {
+            audio.set_address(value);
+        }
+        if let (Some(audio), 0x5000..=0x5015) = (self.mmc5.as_mut(), address) {
+            audio.write(address, value);
+        }
+        if let (Some(audio), 0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) = (self.vrc6.as_mut(), address) {
+            audio.write(address, value);
+        }
+        match (self.vrc7.as_mut(), address) {
+            (Some(audio), 0x9010) => audio.select(value),
+            (Some(audio), 0x9030) => audio.write(value),
+            _ => {},
+        }
+        match (self.sunsoft5b.as_mut(), address) {
+            (Some(audio), 0xC000..=0xDFFF) => audio.select(value),
+            (Some(audio), 0xE000..=0xFFFF) => audio.write(value),
+            _ => {},
+        }
+    }
+
+    fn maps_cpu_address(&self, address: u16, write: bool) -> bool {
+        let mmc5 = self.mmc5.is_some();
+        match address {
+            0x4100..=0x41FF => !write,
+            0x4040..=0x407F => self.fds,
+            0x4080..=0x408A => write && self.fds,
+            0x4090 | 0x4092 => !write && self.fds,
+            0x4800..=0x4FFF => self.n163.is_some(),
+            0x5000..=0x5015 => mmc5 && (write || matches!(address, 0x5010 | 0x5015)),
+            0x5205..=0x5206 | 0x5C00..=0x5FF5 => mmc5,
+            0x5FF6..=0x5FF7 => write && self.fds,
+            0x5FF8..=0x5FFF => write,
+            0x6000..=0x7FFF => true,
+            _ => false,
+        }
+    }
+
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x407F | 0x4090 | 0x4092 if self.fds => 0xC0,
+            _ => 0,
+        }
+    }
+
+    fn read_chr(&self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write_chr(&mut self, _address: u16, _value: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    /// The file's 4 KB banks as the ROM, less any window below its load address. A Disk System
+    /// file is all RAM.
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let mut windows = vec![BankWindow::new(0x6000, 2 * BANK_SIZE, BankMemory::Ram, 0)];
+        if self.fds {
+            windows.push(BankWindow::new(0x8000, 8 * BANK_SIZE, BankMemory::Ram, 2 * BANK_SIZE));
+            return windows;
+        }
+        windows.extend(
+            self.banks
+                .iter()
+                .enumerate()
+                .skip(2)
+                .filter(|(_, &bank)| bank != NO_BANK)
+                .map(|(window, &bank)| {
+                    let start = (0x6000 + window * BANK_SIZE) as u16;
+                    BankWindow::new(start, BANK_SIZE, BankMemory::Rom, bank as usize * BANK_SIZE)
+                }),
+        );
+        windows
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![MapperRegister::new("Track", self.track)];
+        registers.extend(
+            self.banks
+                .iter()
+                .enumerate()
+                .skip(2)
+                .map(|(window, &bank)| MapperRegister::new(format!("${:04X}", 0x5FF6 + window), bank)),
+        );
+        registers
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.play_clock += 1_000_000;
+        if self.play_clock >= self.play_period {
+            self.play_clock -= self.play_period;
+            self.play_pending.set(true);
+        }
+
+        if let Some(audio) = self.vrc6.as_mut() {
+            audio.tick();
+        }
+        if let Some(audio) = self.vrc7.as_mut() {
+            audio.tick();
+        }
+        if let Some(audio) = self.fds_audio.as_mut() {
+            audio.tick();
+        }
+        if let Some(audio) = self.mmc5.as_mut() {
+            audio.tick();
+        }
+        if let Some(audio) = self.n163.as_mut() {
+            audio.tick();
+        }
+        if let Some(audio) = self.sunsoft5b.as_mut() {
+            audio.tick();
+        }
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        self.chips.first().copied()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.chips
+            .iter()
+            .zip(&self.muted)
+            .filter(|&(_, &muted)| !muted)
+            .map(|(&chip, _)| match chip {
+                ExpansionChip::Vrc6 => self.vrc6.as_ref().map_or(0.0, Vrc6Audio::output),
+                ExpansionChip::Vrc7 => self.vrc7.as_ref().map_or(0.0, Vrc7Audio::output),
+                ExpansionChip::Fds => self.fds_audio.as_ref().map_or(0.0, FdsAudio::output),
+                ExpansionChip::Mmc5 => self.mmc5.as_ref().map_or(0.0, Mmc5Audio::output),
+                ExpansionChip::Namco163 => self.n163.as_ref().map_or(0.0, N163Audio::output),
+                ExpansionChip::Sunsoft5b => self.sunsoft5b.as_ref().map_or(0.0, Sunsoft5bAudio::output),
+            })
+            .sum()
+    }
+
+    /// One entry per chip rather than per channel, and switching one off mutes the chip. A music
+    /// driver rewrites its channels' enable bits on every note, so the per-channel switches a game
+    /// cartridge offers would last a frame here.
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.chips
+            .iter()
+            .zip(&self.muted)
+            .map(|(chip, &muted)| ExpansionChannel {
+                label: chip.label(),
+                enabled: !muted,
+            })
+            .collect()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(muted) = self.muted.get_mut(index) {
+            *muted = !enabled;
+        }
+    }
+
+    /// The banks and the play timer, the RAM, and each sound chip in the order the file declares
+    /// them. The track and region are the player's, not state, and a save is only ever loaded
+    /// back into a player for the same track.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        for bank in self.banks {
+            state.extend_from_slice(&bank.to_le_bytes());
+        }
+        state.extend_from_slice(&self.play_clock.to_le_bytes());
+        state.push(u8::from(self.play_pending.get()));
+        state.extend_from_slice(&self.multiplicands);
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.exram);
+        if let Some(audio) = &self.vrc6 {
+            audio.save(&mut state);
+        }
+        if let Some(audio) = &self.vrc7 {
+            audio.save(&mut state);
+        }
+        if let Some(audio) = &self.fds_audio {
+            audio.save(&mut state);
+        }
+        if let Some(audio) = &self.mmc5 {
+            audio.save(&mut state);
+        }
+        if let Some(audio) = &self.n163 {
+            audio.save(&mut state);
+        }
+        if let Some(audio) = &self.sunsoft5b {
+            audio.save(&mut state);
+        }
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        let ram_end = REGISTER_STATE_LEN + self.ram.len();
+        if state.len() < ram_end + EXRAM_SIZE {
+            return;
+        }
+        for (window, bank) in self.banks.iter_mut().enumerate() {
+            *bank = u16::from_le_bytes([state[2 * window], state[2 * window + 1]]);
+        }
+        let timer = 2 * WINDOWS;
+        self.play_clock = u64::from_le_bytes(state[timer..timer + 8].try_into().unwrap_or_default());
+        self.play_pending.set(state[timer + 8] != 0);
+        self.multiplicands
+            .copy_from_slice(&state[timer + 9..REGISTER_STATE_LEN]);
+        self.ram.copy_from_slice(&state[REGISTER_STATE_LEN..ram_end]);
+        self.exram.copy_from_slice(&state[ram_end..ram_end + EXRAM_SIZE]);
+
+        let mut at = ram_end + EXRAM_SIZE;
+        if let Some(audio) = self.vrc6.as_mut() {
+            at += audio.load(&state[at..]).unwrap_or(0);
+        }
+        if let Some(audio) = self.vrc7.as_mut() {
+            at += audio.load(&state[at..]).unwrap_or(0);
+        }
+        if let Some(audio) = self.fds_audio.as_mut() {
+            at += audio.load(&state[at..]).unwrap_or(0);
+        }
+        if let Some(audio) = self.mmc5.as_mut() {
+            at += audio.load(&state[at..]).unwrap_or(0);
+        }
+        if let Some(audio) = self.n163.as_mut() {
+            at += audio.load(&state[at..]).unwrap_or(0);
+        }
+        if let Some(audio) = self.sunsoft5b.as_mut() {
+            audio.load(&state[at..]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::nsf::nsf_file, *};
+
+    fn player(load: u16, data: &[u8], banks: Option<[u8; 8]>) -> NsfPlayer {
+        let mut nsf = Nsf::parse(&nsf_file(load, 0x8123, 0x8456, data)).unwrap();
+        nsf.banks = banks;
+        NsfPlayer::new(&nsf, 1, Region::Ntsc)
+    }
+
+    #[test]
+    fn the_driver_calls_init_and_play_through_its_vectors() {
+        let player = player(0x8000, &[0xEA], None);
+        let word = |address: u16| u16::from_le_bytes([player.read_prg(address), player.read_prg(address + 1)]);
+
+        assert_eq!(word(0xFFFC), 0x4100);
+        assert_eq!(player.read_prg(word(0xFFFA)), 0x40, "NMI returns at once");
+        assert_eq!(player.read_prg(0x413D), 0x20);
+        assert_eq!(word(0x413E), 0x8123, "JSR init");
+        assert_eq!(word(0x4146), 0x8456, "JSR play");
+        assert_eq!(player.read_prg(TRACK_REGISTER), 1);
+        assert_eq!(player.read_prg(REGION_REGISTER), 0);
+    }
+
+    #[test]
+    fn an_unbanked_file_sits_at_its_load_address() {
+        let player = player(0x8F00, &[1, 2, 3], None);
+        assert_eq!(player.read_prg(0x8EFF), 0);
+        assert_eq!(player.read_prg(0x8F00), 1);
+        assert_eq!(player.read_prg(0x8F02), 3);
+    }
+
+    /// Bank 0 starts a bank below the load address's offset into its own bank, so the first
+    /// byte of the file is that far into bank 0, wherever bank 0 is put.
+    #[test]
+    fn a_banked_file_switches_four_kilobytes_at_a_time() {
+        let mut data = vec![0; 3 * BANK_SIZE];
+        data[0] = 0xA0;
+        data[BANK_SIZE] = 0xB1;
+        let mut player = player(0x8010, &data, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+
+        assert_eq!(player.read_prg(0x8010), 0xA0);
+        assert_eq!(player.read_prg(0x9010), 0xB1);
+        assert!(player.maps_cpu_address(0x5FFF, true));
+        player.write_prg(0x5FFF, 1);
+        assert_eq!(player.read_prg(0xF010), 0xB1);
+        assert_eq!(player.read_prg(0xFFFC), 0x00, "the vectors stay the driver's");
+    }
+
+    #[test]
+    fn the_play_timer_fires_at_the_file_s_rate_and_is_cleared_by_reading() {
+        let mut player = player(0x8000, &[0x60], None);
+        let cycles = (0..40_000)
+            .find(|_| {
+                player.cpu_cycle();
+                player.read_prg(PLAY_REGISTER) != 0
+            })
+            .unwrap();
+        // 16639 µs at 1789773 Hz is 29780.03 cycles.
+        assert_eq!(cycles + 1, 29781);
+        assert_eq!(player.read_prg(PLAY_REGISTER), 0);
+    }
+
+    #[test]
+    fn each_declared_chip_is_on_its_board_s_addresses_and_can_be_muted() {
+        let mut nsf = Nsf::parse(&nsf_file(0x8000, 0x8000, 0x8000, &[0x60])).unwrap();
+        nsf.chips = vec![ExpansionChip::Vrc6, ExpansionChip::Fds];
+        let mut player = NsfPlayer::new(&nsf, 0, Region::Pal);
+
+        assert_eq!(player.audio_chip(), Some(ExpansionChip::Vrc6));
+        assert_eq!(player.read_prg(REGION_REGISTER), 1);
+        assert!(player.maps_cpu_address(0x4080, true));
+        assert!(!player.maps_cpu_address(0x4800, true), "no Namco 163 declared");
+
+        // A disk file's whole space is RAM, so its code can be written over.
+        player.write_prg(0xC000, 0x42);
+        assert_eq!(player.read_prg(0xC000), 0x42);
+
+        // A square wave on the VRC6's first pulse, at full volume and duty.
+        player.write_prg(0x9000, 0x7F | 0x80);
+        player.write_prg(0x9001, 0x10);
+        player.write_prg(0x9002, 0x80);
+        for _ in 0..100 {
+            player.cpu_cycle();
+        }
+        assert!(player.audio_output() > 0.0);
+        player.set_audio_channel_enabled(0, false);
+        assert_eq!(player.audio_output(), 0.0, "the FDS is silent and the VRC6 muted");
+        assert_eq!(player.audio_channels()[0].label, "VRC6");
+        assert!(!player.audio_channels()[0].enabled);
+
+        let state = player.save_state();
+        let mut restored = NsfPlayer::new(&nsf, 0, Region::Pal);
+        restored.load_state(&state);
+        assert_eq!(restored.read_prg(0xC000), 0x42);
+        assert_eq!(restored.save_state(), state);
+    }
+}
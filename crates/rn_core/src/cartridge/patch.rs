@@ -0,0 +1,413 @@
+//! IPS, UPS and BPS patches, the way translations and ROM hacks are distributed.
+//!
+//! A hack cannot be shared as a ROM, so it is shared as the difference from one. All three formats
+//! are applied here in memory to the whole file, header included, and the result is loaded as if
+//! it had been the file all along.
+//!
+//! - **IPS** is a list of records, each an offset, a length and the bytes to put there, or a byte
+//!   to repeat. It carries no checksum, so a patch applied to the wrong ROM produces a broken game
+//!   rather than an error — which is why the other two exist.
+//! - **UPS** XORs the target over the source and records only where they differ. It ends with the
+//!   CRC32 of the source, the target and the patch itself, and all three are checked.
+//! - **BPS** builds the target from copies out of the source, out of the target so far, and out of
+//!   the patch, so it survives data that moved as well as data that changed. It is checked the
+//!   same way as UPS.
+
+use std::path::{Path, PathBuf};
+
+use super::{database::crc32, RomLoadError};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// The most a UPS or BPS patch may declare its result to be. Far beyond any real cartridge, and
+/// there so that a patch claiming gigabytes is refused rather than allocated: its checksums are
+/// its maker's to set, so they are no protection.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+/// The extensions a patch beside a ROM is looked for under, in the order they are tried.
+const EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+/// The patch sitting beside `rom` under the ROM's own name, if there is one.
+pub fn beside(rom: &Path) -> Option<PathBuf> {
+    EXTENSIONS
+        .iter()
+        .map(|extension| rom.with_extension(extension))
+        .find(|patch| patch.is_file())
+}
+
+/// Apply `patch` to `source`, whichever of the three formats it is.
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomLoadError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(source, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(source, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(source, patch)
+    } else {
+        Err(RomLoadError::InvalidFormat("not an IPS, UPS or BPS patch"))
+    }
+}
+
+fn truncated() -> RomLoadError {
+    RomLoadError::InvalidFormat("the patch ends in the middle of a record")
+}
+
+/// Reads a patch from front to back.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], position: usize) -> Self {
+        Self { bytes, position }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], RomLoadError> {
+        let taken = self
+            .bytes
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or_else(truncated)?;
+        self.position += count;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, RomLoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A big-endian number `count` bytes wide, as IPS writes them.
+    fn big_endian(&mut self, count: usize) -> Result<usize, RomLoadError> {
+        Ok(self
+            .take(count)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    /// UPS's and BPS's variable-length number: seven bits to a byte, least significant first, with
+    /// the top bit marking the last byte. Each continuation also adds one, so that no number has
+    /// two encodings.
+    fn number(&mut self) -> Result<usize, RomLoadError> {
+        let (mut value, mut shift) = (0usize, 1usize);
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|add| value.checked_add(add))
+                .ok_or(RomLoadError::InvalidFormat("a number in the patch is too large"))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift
+                .checked_shl(7)
+                .ok_or(RomLoadError::InvalidFormat("a number in the patch is too large"))?;
+            value = value.saturating_add(shift);
+        }
+    }
+}
+
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomLoadError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        if patch[reader.position..].starts_with(IPS_EOF) {
+            reader.position += IPS_EOF.len();
+            break;
+        }
+        let offset = reader.big_endian(3)?;
+        let (length, fill) = match reader.big_endian(2)? {
+            // A zero length marks a run: a count and the one byte to repeat.
+            0 => {
+                let count = reader.big_endian(2)?;
+                (count, Some(reader.byte()?))
+            },
+            length => (length, None),
+        };
+        // A record may write past the end of the ROM, which grows it.
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        match fill {
+            Some(byte) => target[offset..offset + length].fill(byte),
+            None => target[offset..offset + length].copy_from_slice(reader.take(length)?),
+        }
+    }
+    // An extension some tools write: three bytes after the end saying how long the result is.
+    if let Ok(length) = reader.big_endian(3) {
+        target.truncate(length);
+    }
+    Ok(target)
+}
+
+/// The three CRC32s UPS and BPS end with, checking the patch's own before anything is applied.
+fn checksums(patch: &[u8]) -> Result<(u32, u32), RomLoadError> {
+    let Some(body) = patch.len().checked_sub(12) else {
+        return Err(truncated());
+    };
+    let word = |at: usize| u32::from_le_bytes([patch[at], patch[at + 1], patch[at + 2], patch[at + 3]]);
+    if crc32(&[&patch[..body + 8]]) != word(body + 8) {
+        return Err(RomLoadError::InvalidFormat(
+            "the patch is damaged (its own checksum does not match)",
+        ));
+    }
+    Ok((word(body), word(body + 4)))
+}
+
+/// The size of the result, as a UPS or BPS patch declares it, within [`MAX_TARGET_SIZE`].
+fn target_size(reader: &mut Reader) -> Result<usize, RomLoadError> {
+    let size = reader.number()?;
+    if size > MAX_TARGET_SIZE {
+        return Err(RomLoadError::InvalidFormat(
+            "the patch declares a result too large to be a ROM",
+        ));
+    }
+    Ok(size)
+}
+
+/// Check the input is what the patch was made from, before anything is built from it.
+fn check_source(source: &[u8], expected: u32) -> Result<(), RomLoadError> {
+    if crc32(&[source]) != expected {
+        return Err(RomLoadError::InvalidFormat(
+            "the patch was made for a different ROM (the source checksum does not match)",
+        ));
+    }
+    Ok(())
+}
+
+/// Check the output is what the patch was made to produce.
+fn check_target(target: &[u8], expected: u32) -> Result<(), RomLoadError> {
+    if crc32(&[target]) != expected {
+        return Err(RomLoadError::InvalidFormat(
+            "the patched ROM is not what the patch promised (the target checksum does not match)",
+        ));
+    }
+    Ok(())
+}
+
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomLoadError> {
+    let (source_crc, target_crc) = checksums(patch)?;
+    check_source(source, source_crc)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], UPS_MAGIC.len());
+    let _source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut offset = 0usize;
+    while reader.position < end {
+        offset = offset.saturating_add(reader.number()?);
+        // XOR bytes up to a zero, which ends the hunk and stands for one unchanged byte.
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                offset = offset.saturating_add(1);
+                break;
+            }
+            if let Some(output) = target.get_mut(offset) {
+                *output ^= byte;
+            }
+            offset = offset.saturating_add(1);
+        }
+    }
+
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, RomLoadError> {
+    let (source_crc, target_crc) = checksums(patch)?;
+    check_source(source, source_crc)?;
+    let end = patch.len() - 12;
+    let mut reader = Reader::new(&patch[..end], BPS_MAGIC.len());
+    let _source_size = reader.number()?;
+    let target_size = target_size(&mut reader)?;
+    let metadata = reader.number()?;
+    reader.take(metadata)?;
+
+    let out_of_range = || RomLoadError::InvalidFormat("the patch copies from outside the ROM");
+    let too_long = || RomLoadError::InvalidFormat("the patch writes past the size it declares");
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    // A relative move: the low bit is the sign, the rest the distance.
+    let step = |offset: usize, data: usize| {
+        if data & 1 != 0 {
+            offset.checked_sub(data >> 1)
+        } else {
+            offset.checked_add(data >> 1)
+        }
+    };
+
+    while reader.position < end {
+        let data = reader.number()?;
+        let length = (data >> 2) + 1;
+        // Checked before anything is copied, as a copy out of the output could otherwise go on
+        // for as long as the patch says.
+        if target.len().checked_add(length).is_none_or(|end| end > target_size) {
+            return Err(too_long());
+        }
+        match data & 3 {
+            // The source's byte at the same place in the output.
+            0 => {
+                let at = target.len();
+                target.extend_from_slice(source.get(at..at + length).ok_or_else(out_of_range)?);
+            },
+            // Bytes carried in the patch itself.
+            1 => target.extend_from_slice(reader.take(length)?),
+            // Bytes from elsewhere in the source.
+            2 => {
+                source_offset = step(source_offset, reader.number()?).ok_or_else(out_of_range)?;
+                let copied_end = source_offset.checked_add(length).ok_or_else(out_of_range)?;
+                let copied = source.get(source_offset..copied_end).ok_or_else(out_of_range)?;
+                target.extend_from_slice(copied);
+                source_offset = copied_end;
+            },
+            // Bytes from earlier in the output, one at a time because the copy may overlap itself.
+            _ => {
+                target_offset = step(target_offset, reader.number()?).ok_or_else(out_of_range)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or_else(out_of_range)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+    if target.len() != target_size {
+        return Err(RomLoadError::InvalidFormat(
+            "the patch does not produce the size it declares",
+        ));
+    }
+
+    check_target(&target, target_crc)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(low | 0x80);
+                return bytes;
+            }
+            bytes.push(low);
+            value -= 1;
+        }
+    }
+
+    /// A UPS or BPS patch with its three checksums appended.
+    fn sealed(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(&[source]).to_le_bytes());
+        patch.extend_from_slice(&crc32(&[target]).to_le_bytes());
+        patch.extend_from_slice(&crc32(&[&patch]).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn numbers_round_trip_through_the_variable_length_encoding() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 20] {
+            let bytes = number(value);
+            assert_eq!(Reader::new(&bytes, 0).number().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn ips_records_and_runs_are_applied_and_can_grow_the_rom() {
+        let source = [0u8; 8];
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xAA, 0xBB]); // two bytes at 1
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 4, 0xCC]); // four $CC at 6, past the end
+        patch.extend_from_slice(IPS_EOF);
+
+        let target = apply(&source, &patch).unwrap();
+        assert_eq!(target, [0, 0xAA, 0xBB, 0, 0, 0, 0xCC, 0xCC, 0xCC, 0xCC]);
+    }
+
+    #[test]
+    fn a_ups_patch_is_applied_and_its_checksums_are_checked() {
+        let source = b"Hello, world".to_vec();
+        let expected = b"Hello, WORLD!".to_vec();
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(expected.len()));
+        patch.extend(number(7)); // skip to "world"
+        patch.extend(source[7..].iter().zip(&expected[7..12]).map(|(a, b)| a ^ b));
+        patch.push(b'!'); // past the source's end, so against zero
+        patch.push(0);
+        let patch = sealed(patch, &source, &expected);
+
+        assert_eq!(apply(&source, &patch).unwrap(), expected);
+
+        let error = apply(b"Goodbye", &patch).unwrap_err();
+        assert!(error.to_string().contains("different ROM"), "{error}");
+
+        let mut damaged = patch.clone();
+        damaged[6] ^= 1;
+        let error = apply(&source, &damaged).unwrap_err();
+        assert!(error.to_string().contains("damaged"), "{error}");
+    }
+
+    #[test]
+    fn a_bps_patch_copies_from_the_source_the_patch_and_itself() {
+        let source = b"ABCDEFGH".to_vec();
+        let expected = b"ABCDxyxyxyEF".to_vec();
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(expected.len()));
+        patch.extend(number(0)); // no metadata
+        patch.extend(number((4 - 1) << 2)); // SourceRead "ABCD"
+        patch.extend(number((2 - 1) << 2 | 1)); // TargetRead "xy"
+        patch.extend_from_slice(b"xy");
+        patch.extend(number((4 - 1) << 2 | 3)); // TargetCopy four bytes from offset 4, overlapping
+        patch.extend(number(4 << 1));
+        patch.extend(number((2 - 1) << 2 | 2)); // SourceCopy "EF" from offset 4
+        patch.extend(number(4 << 1));
+        let patch = sealed(patch, &source, &expected);
+
+        assert_eq!(apply(&source, &patch).unwrap(), expected);
+    }
+
+    #[test]
+    fn a_patch_declaring_a_huge_result_is_refused_before_anything_is_allocated() {
+        let source = b"ROM".to_vec();
+        for magic in [UPS_MAGIC, BPS_MAGIC] {
+            let mut patch = magic.to_vec();
+            patch.extend(number(source.len()));
+            patch.extend(number(usize::MAX >> 8));
+            patch.extend(number(0));
+            let patch = sealed(patch, &source, &source);
+
+            let error = apply(&source, &patch).unwrap_err();
+            assert!(matches!(error, RomLoadError::InvalidFormat(_)), "{error}");
+            assert!(error.to_string().contains("too large"), "{error}");
+        }
+    }
+
+    #[test]
+    fn a_bps_copy_past_the_end_of_the_address_space_is_refused() {
+        let source = b"ABCD".to_vec();
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(number(source.len()));
+        patch.extend(number(2));
+        patch.extend(number(0));
+        patch.extend(number((2 - 1) << 2 | 2)); // SourceCopy two bytes from as far on as can be
+        patch.extend(number((usize::MAX >> 1) << 1));
+        let patch = sealed(patch, &source, b"AB");
+
+        assert!(matches!(apply(&source, &patch), Err(RomLoadError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn something_that_is_not_a_patch_is_refused() {
+        assert!(apply(b"ROM", b"not a patch").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Sunsoft-4 (mapper 68).
+//!
+//! A 16 KB PRG bank ahead of a fixed last one, four 2 KB CHR banks, and the one trick that makes
+//! it worth a module of its own: the nametables can be put in CHR ROM. *After Burner* draws its
+//! backgrounds that way, scrolling through landscapes far larger than the console's 2 KB of
+//! nametable RAM could ever hold without copying a byte.
+//!
+//! The board still decides mirroring as any other would, choosing which of two pages each logical
+//! nametable gets. What `$E000` bit 4 changes is what a page is: the console's RAM, or the 1 KB
+//! bank of CHR ROM named by `$C000` for the first page and `$D000` for the second. Only the upper
+//! half of a 256 KB CHR ROM can be reached that way, because the board forces the bank's top bit.
+//!
+//! `$F000` bit 4 enables the work RAM at `$6000`, which is left to the bus and stays writable;
+//! nothing is known to rely on the guard. The licensing chip on *Nantettatte!! Baseball*'s board,
+//! which pages in a sub-cartridge, is not modelled.
+
+use super::{
+    mapper::{banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, Mapper, MapperRegister, CHR_BANK},
+    Mirroring,
+};
+
+const PRG_BANK: usize = 16 * 1024;
+const CHR_WINDOW: usize = 2 * 1024;
+
+/// Bytes of register state in a save.
+const STATE_LEN: usize = 8;
+
+/// Sunsoft-4 (mapper 68). See the module documentation.
+#[derive(Debug)]
+pub struct Sunsoft4 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    /// `$8000..=$B000`, the pattern tables' 2 KB banks.
+    chr_banks: [u8; 4],
+    /// `$C000` and `$D000`, the 1 KB CHR ROM banks behind the two nametable pages.
+    nametable_banks: [u8; 2],
+    /// `$E000`: bits 0-1 mirroring, bit 4 nametables from CHR ROM.
+    control: u8,
+    /// `$F000`: bits 0-3 the PRG bank at `$8000`, bit 4 the work RAM enable.
+    prg_bank: u8,
+}
+
+impl Sunsoft4 {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            chr_banks: [0; 4],
+            nametable_banks: [0; 2],
+            control: 0,
+            prg_bank: 0,
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_WINDOW).max(1);
+        let bank = self.chr_banks[(address as usize >> 11) & 0x03] as usize;
+        (bank % banks) * CHR_WINDOW + (address as usize & 0x07FF)
+    }
+
+    fn chr_rom_nametables(&self) -> bool {
+        self.control & 0x10 != 0
+    }
+
+    /// The CHR offset behind nametable `address`, when the nametables are in CHR ROM.
+    fn nametable_chr_offset(&self, address: u16) -> usize {
+        let page = self.mirroring().ciram_offset(address) / 0x400;
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let bank = (self.nametable_banks[page] | 0x80) as usize;
+        (bank % banks) * CHR_BANK + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Sunsoft4 {
+    fn read_prg(&self, address: u16) -> u8 {
+        let bank = match address {
+            0x8000..=0xBFFF => (self.prg_bank & 0x0F) as usize,
+            _ => (self.prg.len() / PRG_BANK).max(1) - 1,
+        };
+        banked(&self.prg, bank, PRG_BANK, address as usize & 0x3FFF)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0xBFFF => self.chr_banks[(address as usize >> 12) & 0x03] = value,
+            0xC000..=0xDFFF => self.nametable_banks[(address as usize >> 12) & 0x01] = value & 0x7F,
+            0xE000..=0xEFFF => self.control = value,
+            0xF000..=0xFFFF => self.prg_bank = value,
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_offset(address);
+            self.chr[index] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        let last = (self.prg.len() / PRG_BANK).max(1) - 1;
+        vec![
+            BankWindow::banked(0x8000, PRG_BANK, BankMemory::Rom, (self.prg_bank & 0x0F) as usize, self.prg.len()),
+            BankWindow::banked(0xC000, PRG_BANK, BankMemory::Rom, last, self.prg.len()),
+        ]
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_WINDOW)
+            .map(|start| BankWindow::new(start, CHR_WINDOW, memory, self.chr_offset(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers: Vec<_> = self
+            .chr_banks
+            .iter()
+            .enumerate()
+            .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank))
+            .collect();
+        registers.push(MapperRegister::new("Nametable bank 0", self.nametable_banks[0]));
+        registers.push(MapperRegister::new("Nametable bank 1", self.nametable_banks[1]));
+        registers.push(MapperRegister::new("Control", self.control));
+        registers.push(MapperRegister::new("PRG bank", self.prg_bank));
+        registers
+    }
+
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        if self.chr_rom_nametables() {
+            self.chr[self.nametable_chr_offset(address)]
+        } else {
+            ciram[self.mirroring().ciram_offset(address)]
+        }
+    }
+
+    /// Writes to nametables in CHR ROM go nowhere: the board has no way to write the ROM, and
+    /// leaves the console's RAM unselected.
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        if !self.chr_rom_nametables() {
+            ciram[self.mirroring().ciram_offset(address)] = value;
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.chr_banks.to_vec();
+        state.extend_from_slice(&self.nametable_banks);
+        state.extend_from_slice(&[self.control, self.prg_bank]);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < STATE_LEN {
+            return;
+        }
+        self.chr_banks.copy_from_slice(&state[..4]);
+        self.nametable_banks = [state[4] & 0x7F, state[5] & 0x7F];
+        self.control = state[6];
+        self.prg_bank = state[7];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board() -> Sunsoft4 {
+        Sunsoft4::new(numbered_banks(8, PRG_BANK), numbered_banks(256, CHR_BANK))
+    }
+
+    #[test]
+    fn one_prg_bank_then_the_fixed_last_and_four_2k_chr_banks() {
+        let mut mapper = board();
+        mapper.write_prg(0xF000, 0x13);
+        assert_eq!([mapper.read_prg(0x8000), mapper.read_prg(0xC000)], [3, 7]);
+
+        for (window, bank) in [5u8, 6, 7, 8].into_iter().enumerate() {
+            mapper.write_prg(0x8000 + window as u16 * 0x1000, bank);
+        }
+        assert_eq!(
+            [0x0000, 0x0400, 0x0800, 0x1C00].map(|a| mapper.read_chr(a)),
+            [10, 11, 12, 17],
+            "2 KB bank n is 1 KB banks 2n and 2n + 1"
+        );
+    }
+
+    /// With `$E000` bit 4 set, each page of the mirroring reads the upper half of CHR ROM, and
+    /// writes are dropped; with it clear, the console's RAM is back.
+    #[test]
+    fn nametables_can_come_from_chr_rom() {
+        let mut mapper = board();
+        let mut ciram = vec![0u8; 0x800];
+        mapper.write_prg(0xC000, 0x05);
+        mapper.write_prg(0xD000, 0x86);
+        mapper.write_prg(0xE000, 0x11);
+
+        assert_eq!(mapper.read_nametable(0x2010, &ciram), 0x85);
+        assert_eq!(
+            mapper.read_nametable(0x2410, &ciram),
+            0x85,
+            "horizontal: both on page 0"
+        );
+        assert_eq!(
+            mapper.read_nametable(0x2810, &ciram),
+            0x86,
+            "the top bit is forced anyway"
+        );
+        mapper.write_nametable(0x2010, 0x99, &mut ciram);
+        assert!(ciram.iter().all(|&byte| byte == 0));
+
+        mapper.write_prg(0xE000, 0x00);
+        mapper.write_nametable(0x2410, 0x99, &mut ciram);
+        assert_eq!(mapper.read_nametable(0x2C10, &ciram), 0x99, "vertical, from RAM");
+
+        let mut restored = board();
+        restored.load_state(&mapper.save_state());
+        assert_eq!(restored.read_nametable(0x2C10, &ciram), 0x99);
+        restored.write_prg(0xE000, 0x13);
+        assert_eq!(restored.read_nametable(0x2010, &ciram), 0x86, "single-screen upper");
+    }
+}
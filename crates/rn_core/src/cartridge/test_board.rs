@@ -0,0 +1,78 @@
+//! The board a hand-assembled program runs on.
+//!
+//! An assembled snippet is not a ROM image. It is poked into `$8000` through the bus, reset
+//! vector and all, and its tiles are loaded into pattern memory afterwards — so the board it runs
+//! on has RAM where a cartridge has ROM, on both sides. That used to be the bus's own RAM standing
+//! in for cartridge space plus a pattern table the PPU held on the side, which worked, but meant
+//! every test program reached the PPU by a path no real game took.
+
+use super::{BankMemory, BankWindow, Mapper, Mirroring, PatternTable};
+
+/// 32 KB of program RAM at `$8000` and 8 KB of character RAM, with no registers.
+#[derive(Debug, Clone)]
+pub struct TestBoard {
+    prg: Vec<u8>,
+    chr: PatternTable,
+    mirroring: Mirroring,
+}
+
+impl TestBoard {
+    pub fn new(mirroring: Mirroring) -> Self {
+        Self {
+            prg: vec![0; 0x8000],
+            chr: PatternTable::new(),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for TestBoard {
+    fn read_prg(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg[address as usize - 0x8000],
+            _ => 0,
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg[address as usize - 0x8000] = value;
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr.read_byte(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr.write_byte(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x8000, 0x8000, BankMemory::Ram, 0)]
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        vec![BankWindow::new(0x0000, 0x2000, BankMemory::Ram, 0)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_sides_are_writable() {
+        let mut board = TestBoard::new(Mirroring::Vertical);
+        board.write_prg(0xFFFC, 0x34);
+        board.write_chr(0x1FFF, 0xAB);
+
+        assert_eq!(board.read_prg(0xFFFC), 0x34);
+        assert_eq!(board.read_chr(0x1FFF), 0xAB);
+        assert_eq!(board.read_prg(0x6000), 0, "nothing below $8000");
+    }
+}
@@ -0,0 +1,262 @@
+//! UNIF cartridge images, the format many multicart and pirate dumps exist only in.
+//!
+//! Where iNES numbers a board, UNIF names it. A file is a 32-byte header — `UNIF`, a revision and
+//! padding — and then chunks, each a four-letter name, a 32-bit little-endian length and that many
+//! bytes. `MAPR` holds the board's name, `PRG0` to `PRGF` and `CHR0` to `CHRF` the ROM chips in
+//! order, and `MIRR`, `BATR` and `TVCI` what an iNES header keeps in its flags. Chunks this loader
+//! has no use for, such as `NAME`, `READ` and the checksums, are skipped.
+//!
+//! The name is turned back into one of our mapper numbers and the whole file into the same
+//! [`INesHeader`] an iNES image produces, so nothing after the loader knows where a ROM came from.
+//! A board with no number here is refused by name rather than guessed at.
+
+use super::{HeaderFormat, INesHeader, RomLoadError, Timing};
+
+/// How every UNIF file begins.
+pub const UNIF_MAGIC: [u8; 4] = *b"UNIF";
+
+const UNIF_HEADER_SIZE: usize = 32;
+
+/// What comes before the board's own name, saying who made it. Several makers' boards share a
+/// name, so the prefix is dropped before looking the name up.
+const PREFIXES: [&str; 8] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "AVE-", "CAMERICA-", "KONAMI-"];
+
+/// The boards this loader knows, as `(name, mapper, submapper)`.
+///
+/// Only boards whose mapper is implemented are listed: a name mapped to a number that `create`
+/// then refuses says less than refusing the name.
+const BOARDS: [(&str, u16, u8); 56] = [
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("RROM", 0, 0),
+    ("RROM-128", 0, 0),
+    ("SAROM", 1, 0),
+    ("SBROM", 1, 0),
+    ("SCROM", 1, 0),
+    ("SEROM", 1, 0),
+    ("SGROM", 1, 0),
+    ("SKROM", 1, 0),
+    ("SLROM", 1, 0),
+    ("SL1ROM", 1, 0),
+    ("SNROM", 1, 0),
+    ("SOROM", 1, 0),
+    ("SUROM", 1, 0),
+    ("SXROM", 1, 0),
+    ("UNROM", 2, 0),
+    ("UOROM", 2, 0),
+    ("CNROM", 3, 0),
+    ("TBROM", 4, 0),
+    ("TEROM", 4, 0),
+    ("TFROM", 4, 0),
+    ("TGROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("TLROM", 4, 0),
+    ("TR1ROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("HKROM", 4, 1),
+    ("EKROM", 5, 0),
+    ("ELROM", 5, 0),
+    ("ETROM", 5, 0),
+    ("EWROM", 5, 0),
+    ("AMROM", 7, 2),
+    ("ANROM", 7, 1),
+    ("AN1ROM", 7, 1),
+    ("AOROM", 7, 0),
+    ("PNROM", 9, 0),
+    ("PEEOROM", 9, 0),
+    ("FJROM", 10, 0),
+    ("FKROM", 10, 0),
+    ("CPROM", 13, 0),
+    ("BNROM", 34, 2),
+    ("NINA-01", 34, 1),
+    ("NINA-001", 34, 1),
+    ("NINA-03", 79, 0),
+    ("NINA-06", 79, 0),
+    ("GNROM", 66, 0),
+    ("MHROM", 66, 0),
+    ("BTR", 69, 0),
+    ("JLROM", 69, 0),
+    ("JSROM", 69, 0),
+    ("BF9093", 71, 0),
+    ("TLSROM", 118, 0),
+    ("TKSROM", 118, 0),
+    ("TQROM", 119, 0),
+];
+
+/// The mapper and submapper for a UNIF board name, ignoring case and the maker's prefix.
+pub fn board_mapper(name: &str) -> Option<(u16, u8)> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = PREFIXES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(&name);
+    BOARDS
+        .iter()
+        .find(|(board, _, _)| *board == name)
+        .map(|&(_, mapper, submapper)| (mapper, submapper))
+}
+
+/// Read a whole UNIF file into a header and its PRG and CHR images.
+pub(super) fn parse(bytes: &[u8]) -> Result<(INesHeader, Vec<u8>, Vec<u8>), RomLoadError> {
+    if !bytes.starts_with(&UNIF_MAGIC) {
+        return Err(RomLoadError::InvalidFormat("not a UNIF file"));
+    }
+    if bytes.len() < UNIF_HEADER_SIZE {
+        return Err(RomLoadError::InvalidFormat("UNIF header too small"));
+    }
+
+    let mut board = None;
+    let mut prg: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = None;
+    let mut battery = false;
+    let mut timing = Timing::Ntsc;
+
+    let mut rest = &bytes[UNIF_HEADER_SIZE..];
+    while rest.len() >= 8 {
+        let id = &rest[0..4];
+        let len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let chunk = rest
+            .get(8..8usize.saturating_add(len))
+            .ok_or(RomLoadError::InvalidFormat(
+                "a UNIF chunk runs past the end of the file",
+            ))?;
+        rest = &rest[8 + len..];
+
+        match id {
+            b"MAPR" => {
+                let name = chunk.split(|&byte| byte == 0).next().unwrap_or_default();
+                board = Some(String::from_utf8_lossy(name).into_owned());
+            },
+            [b'P', b'R', b'G', digit] | [b'C', b'H', b'R', digit] => {
+                let index = (*digit as char).to_digit(16).ok_or(RomLoadError::InvalidFormat(
+                    "a UNIF ROM chunk is numbered outside 0 to F",
+                ))?;
+                let chips = if id.starts_with(b"PRG") { &mut prg } else { &mut chr };
+                chips[index as usize] = Some(chunk);
+            },
+            b"MIRR" => mirroring = chunk.first().copied(),
+            // The chunk's presence is what says so; its one byte is always zero.
+            b"BATR" => battery = true,
+            b"TVCI" => {
+                timing = match chunk.first() {
+                    Some(1) => Timing::Pal,
+                    Some(2) => Timing::MultiRegion,
+                    _ => Timing::Ntsc,
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let board = board.ok_or(RomLoadError::InvalidFormat(
+        "the UNIF file names no board (no MAPR chunk)",
+    ))?;
+    let (mapper, submapper) = board_mapper(&board).ok_or_else(|| RomLoadError::UnsupportedBoard(board.clone()))?;
+
+    let prg_rom: Vec<u8> = prg.iter().flatten().flat_map(|chip| chip.iter().copied()).collect();
+    let chr_rom: Vec<u8> = chr.iter().flatten().flat_map(|chip| chip.iter().copied()).collect();
+    if prg_rom.is_empty() {
+        return Err(RomLoadError::InvalidFormat("the UNIF file has no PRG ROM"));
+    }
+
+    // What UNIF does not say is what iNES does not say either, so it is filled in the same way: 8
+    // KB of work RAM, battery-backed if there is a battery, and 8 KB of CHR RAM without CHR ROM.
+    // Mirroring values 2 and 3 are single-screen, which only a mapper can select, and 5 says the
+    // mapper controls it, so they all start from horizontal like an iNES header with the bit clear.
+    let prg_ram = 8 * 1024;
+    let header = INesHeader {
+        format: HeaderFormat::Unif,
+        prg_rom_size: prg_rom.len(),
+        chr_rom_size: chr_rom.len(),
+        mapper,
+        submapper,
+        mirroring: mirroring == Some(1),
+        battery,
+        trainer: false,
+        four_screen: mirroring == Some(4),
+        prg_ram_size: if battery { 0 } else { prg_ram },
+        prg_nvram_size: if battery { prg_ram } else { 0 },
+        chr_ram_size: if chr_rom.is_empty() { 8 * 1024 } else { 0 },
+        chr_nvram_size: 0,
+        timing,
+        region: timing.region(),
+        ..INesHeader::default()
+    };
+    Ok((header, prg_rom, chr_rom))
+}
+
+/// A UNIF file for `board` with the given chunks after its `MAPR`.
+#[cfg(test)]
+pub(super) fn unif_file(board: &str, chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut file = UNIF_MAGIC.to_vec();
+    file.extend_from_slice(&7u32.to_le_bytes());
+    file.resize(UNIF_HEADER_SIZE, 0);
+    let name = [board.as_bytes(), &[0]].concat();
+    for (id, data) in std::iter::once((b"MAPR", &name[..])).chain(chunks.iter().copied()) {
+        file.extend_from_slice(id);
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend_from_slice(data);
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Region;
+
+    #[test]
+    fn board_names_are_matched_without_their_prefix_or_case() {
+        assert_eq!(board_mapper("NES-SLROM"), Some((1, 0)));
+        assert_eq!(board_mapper("hvc-tlrom"), Some((4, 0)));
+        assert_eq!(board_mapper("NES-HKROM"), Some((4, 1)), "MMC6 is submapper 1");
+        assert_eq!(board_mapper("AVE-NINA-01"), Some((34, 1)));
+        assert_eq!(board_mapper("NES-TLSROM"), Some((118, 0)), "not TLROM's number");
+        assert_eq!(board_mapper("BMC-Super24in1SC03"), None);
+    }
+
+    /// PRG and CHR chips are joined in the order of their numbers, not the order of the chunks.
+    #[test]
+    fn a_unif_file_becomes_the_header_an_ines_file_would() {
+        let file = unif_file(
+            "NES-UNROM",
+            &[
+                (b"PRG1", &[2; 16 * 1024]),
+                (b"PRG0", &[1; 16 * 1024]),
+                (b"MIRR", &[1]),
+                (b"BATR", &[0]),
+                (b"TVCI", &[1]),
+            ],
+        );
+        let (header, prg, chr) = parse(&file).unwrap();
+
+        assert_eq!(header.format, HeaderFormat::Unif);
+        assert_eq!((header.mapper, header.submapper), (2, 0));
+        assert_eq!(prg.len(), 32 * 1024);
+        assert_eq!((prg[0], prg[16 * 1024]), (1, 2));
+        assert!(chr.is_empty());
+        assert_eq!(header.chr_ram_total(), 8 * 1024, "no CHR ROM means CHR RAM");
+        assert!(header.mirroring, "MIRR 1 is vertical");
+        assert!(header.battery);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(header.region, Region::Pal);
+    }
+
+    #[test]
+    fn an_unknown_board_is_refused_by_name() {
+        let file = unif_file("UNL-Sachen-8259A", &[(b"PRG0", &[0; 32 * 1024])]);
+        match parse(&file) {
+            Err(RomLoadError::UnsupportedBoard(board)) => assert_eq!(board, "UNL-Sachen-8259A"),
+            other => panic!("expected the board to be refused, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_truncated_chunk_is_an_error_rather_than_a_short_rom() {
+        let mut file = unif_file("NES-NROM-256", &[(b"PRG0", &[0; 32 * 1024])]);
+        file.truncate(file.len() - 1);
+        assert!(matches!(parse(&file), Err(RomLoadError::InvalidFormat(_))));
+    }
+}
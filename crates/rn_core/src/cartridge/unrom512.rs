@@ -0,0 +1,481 @@
+//! UNROM 512 (mapper 30), the board much of today's homebrew ships on.
+//!
+//! UxROM's banking stretched to 512 KB, with 32 KB of CHR RAM in four banks and a one-screen
+//! mirroring bit in the same register:
+//!
+//! | Bits | Meaning |
+//! |---|---|
+//! | 0-4 | 16 KB PRG bank at `$8000`; `$C000` is fixed to the last |
+//! | 5-6 | 8 KB CHR RAM bank |
+//! | 7 | Which nametable one-screen mirroring shows, on boards wired for it |
+//!
+//! The header's two mirroring bits, the usual one and four-screen's, say how the board is wired:
+//! horizontal, vertical, one-screen under the register's control, or four nametables kept in the
+//! last 8 KB of the CHR RAM.
+//!
+//! What sets the board apart is that its PRG is flash, an SST39SF040 or a smaller sibling, and the
+//! game can rewrite it. A battery in the header marks the boards built for that: their register
+//! moves up to `$C000-$FFFF`, and writes to `$8000-$BFFF` go to the flash's own command interface,
+//! through the bank that register selects. Games save their progress by erasing a 4 KB sector and
+//! programming it again. Boards without the battery bit ignore those writes, decode the register
+//! across all of `$8000-$FFFF`, and have bus conflicts there.
+//!
+//! A save is the flash's sectors that differ from the ROM as shipped, kept in a `.flash` file
+//! beside it and put back on the next load. Each is a little-endian `u32` offset into PRG and then
+//! the sector's 4 KB, one after another — so a file from a game that saves in one sector stays
+//! 4 KB, where the whole chip would be half a megabyte of mostly the ROM again.
+
+use super::{
+    mapper::{banked, chr_memory, latched, resize_chr_ram, BankMemory, BankWindow, Mapper, MapperRegister},
+    Mirroring,
+};
+
+/// The flash's erase unit, and so the unit of a save.
+const SECTOR: usize = 4 * 1024;
+
+/// The 16 KB PRG bank.
+const PRG_WINDOW: usize = 16 * 1024;
+
+/// The 8 KB CHR RAM bank.
+const CHR_WINDOW: usize = 8 * 1024;
+
+/// Bytes of state before the CHR RAM and the flash's modified sectors.
+const STATE_LEN: usize = 2;
+
+/// How the board wires CIRAM, from the header's two mirroring bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unrom512Nametables {
+    Fixed(Mirroring),
+    /// One screen, the lower or upper by bit 7 of the register.
+    OneScreen,
+    /// All four nametables in the top 8 KB of CHR RAM, none in CIRAM.
+    FourScreen,
+}
+
+impl Unrom512Nametables {
+    /// The wiring the header's mirroring bit and four-screen bit describe.
+    pub fn from_header(vertical: bool, four_screen: bool) -> Self {
+        match (four_screen, vertical) {
+            (false, false) => Self::Fixed(Mirroring::Horizontal),
+            (false, true) => Self::Fixed(Mirroring::Vertical),
+            (true, false) => Self::OneScreen,
+            (true, true) => Self::FourScreen,
+        }
+    }
+}
+
+/// Where the flash's command interface is in its sequence.
+///
+/// Every command is three writes — `$AA` to `$5555`, `$55` to `$2AAA`, then the command to
+/// `$5555` — and erasing takes that twice. Anything out of sequence starts it over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct FlashCommand {
+    /// Writes of the unlock sequence seen so far, up to two.
+    unlocked: u8,
+    /// The first half of an erase has been given, and the second unlock names what to erase.
+    erase_armed: bool,
+    /// The next write anywhere programs that byte.
+    program_next: bool,
+    /// Reads give the chip's identification rather than its contents.
+    identifying: bool,
+}
+
+impl FlashCommand {
+    fn to_byte(self) -> u8 {
+        self.unlocked
+            | u8::from(self.erase_armed) << 2
+            | u8::from(self.program_next) << 3
+            | u8::from(self.identifying) << 4
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Self {
+            unlocked: byte & 0x03,
+            erase_armed: byte & 0x04 != 0,
+            program_next: byte & 0x08 != 0,
+            identifying: byte & 0x10 != 0,
+        }
+    }
+}
+
+/// UNROM 512 (mapper 30). See the module documentation.
+#[derive(Debug)]
+pub struct Unrom512 {
+    /// The flash as it is now.
+    prg: Vec<u8>,
+    /// The flash as the ROM file shipped it, to tell which sectors a save has to keep.
+    original: Vec<u8>,
+    /// Whether this board's character memory is RAM, as on every UNROM 512 made; a ROM file that
+    /// carries CHR data anyway gets it as ROM.
+    chr_is_ram: bool,
+    chr: Vec<u8>,
+    nametables: Unrom512Nametables,
+    /// Whether the game can rewrite its flash. Only boards built for saving let it.
+    flashable: bool,
+    bus_conflicts: bool,
+
+    register: u8,
+    command: FlashCommand,
+}
+
+impl Unrom512 {
+    /// The board as built, with 32 KB of CHR RAM unless `chr` has ROM in it instead.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, nametables: Unrom512Nametables, flashable: bool) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 4 * CHR_WINDOW] } else { chr };
+        Self {
+            original: prg.clone(),
+            prg,
+            chr_is_ram,
+            chr,
+            nametables,
+            flashable,
+            bus_conflicts: !flashable,
+            register: 0,
+            command: FlashCommand::default(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM, where a header asks for more than 32 KB. See
+    /// [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// Build the board with bus conflicts or without, whatever its flash would imply. See
+    /// [`Mapper::set_bus_conflicts`].
+    pub fn with_bus_conflicts(mut self, enabled: bool) -> Self {
+        self.bus_conflicts = enabled;
+        self
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        if address < 0xC000 {
+            (self.register & 0x1F) as usize
+        } else {
+            (self.prg.len() / PRG_WINDOW).saturating_sub(1)
+        }
+    }
+
+    /// Where a CPU address lands in the flash.
+    fn flash_offset(&self, address: u16) -> usize {
+        (self.prg_bank_for(address) * PRG_WINDOW + (address as usize & 0x3FFF)) % self.prg.len()
+    }
+
+    /// The device ID the flash answers with, by its size: SST39SF010A, 020A or 040.
+    fn device_id(&self) -> u8 {
+        match self.prg.len() {
+            0..=0x20000 => 0xB5,
+            0x20001..=0x40000 => 0xB6,
+            _ => 0xB7,
+        }
+    }
+
+    /// One write to the flash's command interface.
+    ///
+    /// Programming and erasing happen at once. The real chip takes tens of microseconds to a
+    /// tenth of a second and answers reads with a toggling status bit meanwhile; games wait for
+    /// the data to read back as written, which here it already does.
+    fn write_flash(&mut self, offset: usize, value: u8) {
+        if self.command.program_next {
+            // Programming can only clear bits; setting them again takes an erase.
+            self.prg[offset] &= value;
+            self.command.program_next = false;
+            return;
+        }
+
+        let unlocked = self.command.unlocked;
+        let erase_armed = self.command.erase_armed;
+        self.command.unlocked = 0;
+        self.command.erase_armed = false;
+        match (unlocked, offset & 0x7FFF, value) {
+            (_, _, 0xF0) => self.command.identifying = false,
+            (0, 0x5555, 0xAA) => {
+                self.command.unlocked = 1;
+                self.command.erase_armed = erase_armed;
+            },
+            (1, 0x2AAA, 0x55) => {
+                self.command.unlocked = 2;
+                self.command.erase_armed = erase_armed;
+            },
+            (2, 0x5555, 0xA0) => self.command.program_next = true,
+            (2, 0x5555, 0x80) => self.command.erase_armed = true,
+            (2, 0x5555, 0x90) => self.command.identifying = true,
+            (2, 0x5555, 0x10) if erase_armed => self.prg.fill(0xFF),
+            (2, _, 0x30) if erase_armed => {
+                let start = offset / SECTOR * SECTOR;
+                self.prg[start..start + SECTOR].fill(0xFF);
+            },
+            _ => {},
+        }
+    }
+
+    /// The sectors that differ from the ROM, in the format of the save file.
+    fn modified_sectors(&self) -> Vec<u8> {
+        let mut save = Vec::new();
+        for (index, (now, shipped)) in self.prg.chunks(SECTOR).zip(self.original.chunks(SECTOR)).enumerate() {
+            if now != shipped {
+                save.extend_from_slice(&((index * SECTOR) as u32).to_le_bytes());
+                save.extend_from_slice(now);
+            }
+        }
+        save
+    }
+
+    /// Put back sectors [`modified_sectors`](Self::modified_sectors) saved, over the ROM as
+    /// shipped. A record past the end of this ROM, or cut short, is left out.
+    fn restore_sectors(&mut self, save: &[u8]) {
+        self.prg.copy_from_slice(&self.original);
+        for record in save.chunks_exact(4 + SECTOR) {
+            let offset = u32::from_le_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            if offset.is_multiple_of(SECTOR) && offset + SECTOR <= self.prg.len() {
+                self.prg[offset..offset + SECTOR].copy_from_slice(&record[4..]);
+            }
+        }
+    }
+
+    /// Where in CHR RAM the four-screen board keeps nametable `address`.
+    fn four_screen_offset(&self, address: u16) -> usize {
+        self.chr.len() - CHR_WINDOW + (address as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if self.command.identifying {
+            return if address & 0x01 == 0 { 0xBF } else { self.device_id() };
+        }
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_WINDOW,
+            address as usize & 0x3FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if self.flashable && address < 0xC000 {
+            let offset = self.flash_offset(address);
+            self.write_flash(offset, value);
+        } else {
+            self.register = latched(value, self.read_prg(address), self.bus_conflicts);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        let bank = (self.register >> 5 & 0x03) as usize;
+        banked(&self.chr, bank, CHR_WINDOW, address as usize & 0x1FFF)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let banks = self.chr.len() / CHR_WINDOW;
+        let bank = (self.register >> 5 & 0x03) as usize % banks;
+        self.chr[bank * CHR_WINDOW + (address as usize & 0x1FFF)] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.nametables {
+            Unrom512Nametables::Fixed(mirroring) => mirroring,
+            Unrom512Nametables::OneScreen if self.register & 0x80 != 0 => Mirroring::SingleScreenUpper,
+            Unrom512Nametables::OneScreen => Mirroring::SingleScreenLower,
+            // The nearest a fixed arrangement comes, for display; the nametables are the board's.
+            Unrom512Nametables::FourScreen => Mirroring::Vertical,
+        }
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        [0x8000, 0xC000]
+            .into_iter()
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_WINDOW, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let bank = (self.register >> 5 & 0x03) as usize;
+        let memory = chr_memory(self.chr_is_ram);
+        vec![BankWindow::banked(0x0000, CHR_WINDOW, memory, bank, self.chr.len())]
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        vec![MapperRegister::new("Bank", self.register)]
+    }
+
+    fn read_nametable(&self, address: u16, ciram: &[u8]) -> u8 {
+        match self.nametables {
+            Unrom512Nametables::FourScreen => self.chr[self.four_screen_offset(address)],
+            _ => ciram[self.mirroring().ciram_offset(address)],
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8, ciram: &mut [u8]) {
+        match self.nametables {
+            Unrom512Nametables::FourScreen => {
+                let offset = self.four_screen_offset(address);
+                self.chr[offset] = value;
+            },
+            _ => ciram[self.mirroring().ciram_offset(address)] = value,
+        }
+    }
+
+    fn flash_save(&self) -> Option<Vec<u8>> {
+        self.flashable.then(|| self.modified_sectors())
+    }
+
+    fn restore_flash_save(&mut self, save: &[u8]) {
+        if self.flashable {
+            self.restore_sectors(save);
+        }
+    }
+
+    /// The register, the command sequence, the CHR RAM, then the modified sectors as a save file
+    /// holds them. The sectors are here as well as in the file so a state restores the progress it
+    /// was made with rather than whatever the game has saved since.
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.register, self.command.to_byte()];
+        state.extend_from_slice(&self.chr);
+        state.extend_from_slice(&self.modified_sectors());
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < STATE_LEN + self.chr.len() {
+            return;
+        }
+        self.register = state[0];
+        self.command = FlashCommand::from_byte(state[1]);
+        let (chr, sectors) = state[STATE_LEN..].split_at(self.chr.len());
+        self.chr.copy_from_slice(chr);
+        self.restore_sectors(sectors);
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(flashable: bool) -> Unrom512 {
+        Unrom512::new(numbered_banks(32, PRG_WINDOW), Vec::new(), Unrom512Nametables::OneScreen, flashable)
+    }
+
+    /// Select `bank`, then write `value` at flash offset `offset` within it, as a game does.
+    fn flash_write(mapper: &mut Unrom512, offset: usize, value: u8) {
+        mapper.write_prg(0xC000, (offset / PRG_WINDOW) as u8);
+        mapper.write_prg(0x8000 | (offset % PRG_WINDOW) as u16, value);
+    }
+
+    fn command(mapper: &mut Unrom512, value: u8) {
+        flash_write(mapper, 0x5555, 0xAA);
+        flash_write(mapper, 0x2AAA, 0x55);
+        flash_write(mapper, 0x5555, value);
+    }
+
+    #[test]
+    fn the_register_banks_prg_and_chr_and_picks_the_screen() {
+        let mut mapper = board(false).with_bus_conflicts(false);
+        assert_eq!(mapper.read_prg(0xFFFF), 31, "the last bank is fixed");
+
+        mapper.write_prg(0x8000, 0x85);
+        assert_eq!(mapper.read_prg(0x8000), 5);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+        mapper.write_chr(0x0010, 0x42);
+        mapper.write_prg(0x8000, 0x45);
+        assert_eq!(mapper.read_chr(0x0010), 0x00, "another CHR bank");
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.write_prg(0x8000, 0x05);
+        assert_eq!(mapper.read_chr(0x0010), 0x42);
+    }
+
+    /// Without flash writes the register is written over ROM, which answers too.
+    #[test]
+    fn a_board_without_flash_writes_has_bus_conflicts() {
+        let mut mapper = board(false);
+        mapper.write_prg(0xC000, 0xE5);
+        assert_eq!(mapper.read_prg(0x8000), 5, "$E5 AND bank 31's $1F");
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(mapper.read_prg(0x8000), mapper.prg[5 * PRG_WINDOW]);
+    }
+
+    #[test]
+    fn a_sector_is_erased_and_programmed_through_the_command_sequence() {
+        let mut mapper = board(true);
+        let sector = 3 * PRG_WINDOW + 2 * SECTOR;
+
+        // Not a command: the board ignores a write to flash out of sequence.
+        flash_write(&mut mapper, sector, 0x00);
+        assert_eq!(mapper.prg[sector], 3);
+
+        command(&mut mapper, 0x80);
+        flash_write(&mut mapper, 0x5555, 0xAA);
+        flash_write(&mut mapper, 0x2AAA, 0x55);
+        flash_write(&mut mapper, sector + 0x123, 0x30);
+        assert!(mapper.prg[sector..sector + SECTOR].iter().all(|&byte| byte == 0xFF));
+        assert_eq!(mapper.prg[sector - 1], 3, "the sector before is untouched");
+        assert_eq!(mapper.prg[sector + SECTOR], 3, "and the one after");
+
+        command(&mut mapper, 0xA0);
+        flash_write(&mut mapper, sector + 7, 0x5A);
+        mapper.write_prg(0xC000, 3);
+        assert_eq!(mapper.read_prg(0x8000 + (2 * SECTOR + 7) as u16), 0x5A);
+
+        command(&mut mapper, 0xA0);
+        flash_write(&mut mapper, sector + 7, 0xF0);
+        assert_eq!(mapper.prg[sector + 7], 0x50, "programming only clears bits");
+
+        command(&mut mapper, 0x90);
+        assert_eq!((mapper.read_prg(0x8000), mapper.read_prg(0x8001)), (0xBF, 0xB7));
+        flash_write(&mut mapper, 0, 0xF0);
+        assert_eq!(mapper.read_prg(0xFFFF), 31, "back to reading the array");
+    }
+
+    #[test]
+    fn only_the_modified_sectors_are_saved_and_they_come_back() {
+        let mut mapper = board(true);
+        assert_eq!(mapper.flash_save(), Some(Vec::new()), "nothing saved yet");
+
+        command(&mut mapper, 0xA0);
+        flash_write(&mut mapper, 0x4_1234, 0x00);
+        let save = mapper.flash_save().expect("a flashable board saves");
+        assert_eq!(save.len(), 4 + SECTOR, "one sector");
+        assert_eq!(save[..4], 0x4_1000u32.to_le_bytes());
+
+        let mut next_run = board(true);
+        next_run.restore_flash_save(&save);
+        assert_eq!(next_run.prg[0x4_1234], 0x00);
+        assert_eq!(next_run.flash_save(), Some(save.clone()));
+
+        let mut state_board = board(true);
+        state_board.load_state(&mapper.save_state());
+        assert_eq!(state_board.flash_save(), Some(save));
+
+        assert_eq!(
+            board(false).flash_save(),
+            None,
+            "a board without flash writes has no save"
+        );
+    }
+
+    #[test]
+    fn four_screen_nametables_live_in_the_last_chr_bank() {
+        let mut mapper = Unrom512::new(vec![0; 512 * 1024], Vec::new(), Unrom512Nametables::FourScreen, true);
+        let mut ciram = vec![0; 0x800];
+        mapper.write_nametable(0x2C05, 0x99, &mut ciram);
+        assert_eq!(mapper.read_nametable(0x2C05, &ciram), 0x99);
+        assert_eq!(mapper.read_nametable(0x2405, &ciram), 0x00, "four distinct nametables");
+        assert!(ciram.iter().all(|&byte| byte == 0), "CIRAM is not used");
+        mapper.write_prg(0xC000, 0x60);
+        assert_eq!(mapper.read_chr(0x0C05), 0x99);
+    }
+}
@@ -0,0 +1,731 @@
+//! Konami VRC2 and VRC4 (mappers 21, 22, 23 and 25).
+//!
+//! Two generations of one design: two switchable 8 KB PRG banks, eight 1 KB CHR banks written a
+//! nibble at a time, and mirroring control. VRC4 adds a second PRG layout, single-screen mirroring,
+//! a ninth CHR bank bit and an IRQ counter; VRC2 instead has a one-bit latch at `$6000` on boards
+//! without work RAM, which a few games read back as a copy-protection check.
+//!
+//! What makes the family awkward is the board rather than the chip. Each chip decodes its register
+//! number from two pins, and each board wired those pins to different CPU address lines — A0 and A1
+//! on one, A6 and A7 on another — so the same register is `$9002` for one game and `$9080` for the
+//! next. iNES gave the wirings only four mapper numbers between nine of them, which is why the
+//! number is not enough: NES 2.0's submapper says which, and without one the two wirings sharing a
+//! number are both listened to at once, which works because no game writes to the addresses the
+//! other wiring would use.
+
+use parse_display::{Display, FromStr};
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    INesHeader, Mirroring,
+};
+
+/// One of the nine boards, by chip and wiring.
+///
+/// Parses from and prints as its lowercase name, `vrc4e`, for choosing one on a command line when
+/// a ROM's header gets it wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr)]
+#[display(style = "lowercase")]
+pub enum VrcVariant {
+    Vrc2a,
+    Vrc2b,
+    Vrc2c,
+    Vrc4a,
+    Vrc4b,
+    Vrc4c,
+    Vrc4d,
+    Vrc4e,
+    Vrc4f,
+}
+
+impl VrcVariant {
+    pub const ALL: [VrcVariant; 9] = [
+        VrcVariant::Vrc2a,
+        VrcVariant::Vrc2b,
+        VrcVariant::Vrc2c,
+        VrcVariant::Vrc4a,
+        VrcVariant::Vrc4b,
+        VrcVariant::Vrc4c,
+        VrcVariant::Vrc4d,
+        VrcVariant::Vrc4e,
+        VrcVariant::Vrc4f,
+    ];
+
+    /// Whether `mapper` is one of the numbers this family is filed under.
+    pub fn is_vrc_mapper(mapper: u16) -> bool {
+        matches!(mapper, 21 | 22 | 23 | 25)
+    }
+
+    /// The board a header names, if it names one. `None` for a mapper shared by two boards and a
+    /// header that does not say which — an iNES one, or NES 2.0 with submapper zero.
+    pub fn from_header(mapper: u16, submapper: u8) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|variant| variant.mapper() == mapper && variant.submapper() == submapper)
+    }
+
+    /// The iNES mapper number this board is filed under.
+    pub fn mapper(self) -> u16 {
+        match self {
+            Self::Vrc4a | Self::Vrc4c => 21,
+            Self::Vrc2a => 22,
+            Self::Vrc2b | Self::Vrc4e | Self::Vrc4f => 23,
+            Self::Vrc2c | Self::Vrc4b | Self::Vrc4d => 25,
+        }
+    }
+
+    /// The NES 2.0 submapper that picks this board out from the others under its number.
+    ///
+    /// Mapper 22 has only the one board, which is submapper zero.
+    pub fn submapper(self) -> u8 {
+        match self {
+            Self::Vrc2a => 0,
+            Self::Vrc4a | Self::Vrc4f | Self::Vrc4b => 1,
+            Self::Vrc4c | Self::Vrc4e | Self::Vrc4d => 2,
+            Self::Vrc2b | Self::Vrc2c => 3,
+        }
+    }
+
+    pub fn is_vrc2(self) -> bool {
+        matches!(self, Self::Vrc2a | Self::Vrc2b | Self::Vrc2c)
+    }
+
+    /// Which CPU address lines the board connects to the chip's two register-select pins, as
+    /// masks for the chip's low pin and its high one.
+    fn lines(self) -> (u16, u16) {
+        match self {
+            Self::Vrc2b | Self::Vrc4f => (0x01, 0x02),
+            Self::Vrc2a | Self::Vrc2c | Self::Vrc4b => (0x02, 0x01),
+            Self::Vrc4a => (0x02, 0x04),
+            Self::Vrc4c => (0x40, 0x80),
+            Self::Vrc4d => (0x08, 0x04),
+            Self::Vrc4e => (0x04, 0x08),
+        }
+    }
+}
+
+/// The IRQ counter Konami put in VRC4, and again in VRC6 and VRC7.
+///
+/// An 8-bit counter counting up from a latch, firing and reloading as it overflows. It runs from
+/// the CPU's clock, not the PPU's: in cycle mode it counts CPU cycles, and in scanline mode a
+/// prescaler turns them into lines by counting 341 dots' worth in steps of three. There is no
+/// view of the PPU at all, so a "scanline" here is a fixed length of time that only matches the
+/// picture while rendering runs to the NTSC schedule.
+#[derive(Debug, Clone, Default)]
+pub(super) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    /// Bytes [`save`](Self::save) writes.
+    pub const STATE_LEN: usize = 7;
+
+    /// Set the latch's low or high four bits, which is how VRC4 takes it.
+    pub fn write_latch_nibble(&mut self, high: bool, value: u8) {
+        self.latch = if high {
+            (self.latch & 0x0F) | ((value & 0x0F) << 4)
+        } else {
+            (self.latch & 0xF0) | (value & 0x0F)
+        };
+    }
+
+    /// Set the whole latch at once, which is how VRC6 and VRC7 take it.
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// The control register. Enabling reloads the counter and restarts the prescaler; any write
+    /// acknowledges an IRQ already raised.
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0x01 != 0;
+        self.enabled = value & 0x02 != 0;
+        self.cycle_mode = value & 0x04 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+        self.pending = false;
+    }
+
+    /// Acknowledge, and put back the enable the control register set aside for this.
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn cpu_cycle(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+            return;
+        }
+        self.prescaler -= 3;
+        if self.prescaler <= 0 {
+            self.prescaler += 341;
+            self.clock();
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn counter(&self) -> IrqCounter {
+        IrqCounter {
+            counter: self.counter.into(),
+            latch: self.latch.into(),
+            enabled: self.enabled,
+            pending: self.pending,
+        }
+    }
+
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.latch, self.counter]);
+        state.extend_from_slice(&self.prescaler.to_le_bytes());
+        state.push(u8::from(self.enabled) | (u8::from(self.enable_after_ack) << 1) | (u8::from(self.cycle_mode) << 2));
+        state.push(u8::from(self.pending));
+        state.push(0);
+    }
+
+    pub fn load(&mut self, state: &[u8]) {
+        self.latch = state[0];
+        self.counter = state[1];
+        self.prescaler = i16::from_le_bytes([state[2], state[3]]);
+        self.enabled = state[4] & 0x01 != 0;
+        self.enable_after_ack = state[4] & 0x02 != 0;
+        self.cycle_mode = state[4] & 0x04 != 0;
+        self.pending = state[5] != 0;
+    }
+}
+
+/// VRC2 and VRC4 (mappers 21, 22, 23 and 25). See the module documentation.
+#[derive(Debug)]
+pub struct Vrc24 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    /// Whether the chip is a VRC2, which lacks VRC4's second PRG layout, its single-screen
+    /// mirroring, its ninth CHR bit and its IRQ.
+    vrc2: bool,
+    /// The address lines behind the chip's two register-select pins. Where the board is not
+    /// known these are both candidates' lines together.
+    lines: (u16, u16),
+    /// VRC2a leaves the bottom bit of the CHR bank number unconnected, so its banks count in
+    /// 2 KB steps of a register that still looks as if it selects 1 KB.
+    chr_shift: u8,
+    /// VRC2's one-bit latch at `$6000..=$6FFF`, on boards that have no RAM there.
+    latch: Option<u8>,
+
+    prg_banks: [u8; 2],
+    /// VRC4's `$9002` bit 1: fix the second-last bank at `$8000` rather than `$C000`.
+    prg_swapped: bool,
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+
+    irq: VrcIrq,
+}
+
+impl Vrc24 {
+    /// Build the board for `mapper`, as `variant` if the board is known and from the number's
+    /// candidates together if not.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mapper: u16, variant: Option<VrcVariant>) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        let lines = match (variant, mapper) {
+            (Some(variant), _) => variant.lines(),
+            // VRC4a or VRC4c.
+            (None, 21) => (0x42, 0x84),
+            // VRC2b, VRC4e or VRC4f.
+            (None, 23) => (0x05, 0x0A),
+            // VRC2c, VRC4b or VRC4d.
+            (None, 25) => (0x0A, 0x05),
+            (None, _) => VrcVariant::Vrc2a.lines(),
+        };
+        let variant = variant.or((mapper == 22).then_some(VrcVariant::Vrc2a));
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            // A board that cannot be told apart is run as a VRC4, whose registers are a superset:
+            // a VRC2 game never writes the ones VRC2 lacks.
+            vrc2: variant.is_some_and(VrcVariant::is_vrc2),
+            lines,
+            chr_shift: u8::from(variant == Some(VrcVariant::Vrc2a)),
+            latch: None,
+            prg_banks: [0; 2],
+            prg_swapped: false,
+            chr_banks: [0; 8],
+            mirroring: Mirroring::Vertical,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    /// Build the board a header describes.
+    ///
+    /// VRC2 boards without work RAM get the `$6000` latch in its place. An NES 2.0 header says so
+    /// outright; for iNES, which always implies 8 KB, a VRC2 without a battery is taken to be one
+    /// of them, since none of those boards carried RAM that did not keep.
+    pub fn from_header(header: &INesHeader, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let variant = VrcVariant::from_header(header.mapper, header.submapper);
+        let mut board = Self::new(prg, chr, header.mapper, variant).with_chr_ram_size(header.chr_ram_total());
+        let no_ram = if header.is_nes2() {
+            header.prg_ram_total() == 0
+        } else {
+            !header.battery
+        };
+        if board.vrc2 && no_ram {
+            board.latch = Some(0);
+        }
+        board
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// The register a write to `address` reaches: its top nibble and the two select pins.
+    fn register(&self, address: u16) -> u16 {
+        let low = u16::from(address & self.lines.0 != 0);
+        let high = u16::from(address & self.lines.1 != 0);
+        (address & 0xF000) | low | (high << 1)
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        let banks = (self.prg.len() / PRG_BANK).max(1);
+        let second_last = banks.saturating_sub(2);
+        match (address & 0x6000, self.prg_swapped) {
+            (0x0000, false) | (0x4000, true) => self.prg_banks[0] as usize,
+            (0x0000, true) | (0x4000, false) => second_last,
+            (0x2000, _) => self.prg_banks[1] as usize,
+            _ => banks - 1,
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = (self.chr_banks[(address as usize >> 10) & 0x07] >> self.chr_shift) as usize;
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        (bank % banks) * CHR_BANK + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Vrc24 {
+    fn maps_cpu_address(&self, address: u16, _write: bool) -> bool {
+        self.latch.is_some() && (0x6000..=0x6FFF).contains(&address)
+    }
+
+    /// Only bit 0 of the latch is driven; the rest of the byte is whatever the bus held.
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        if self.maps_cpu_address(address, false) {
+            0xFE
+        } else {
+            0
+        }
+    }
+
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x8000 {
+            return self.latch.unwrap_or(0);
+        }
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            if let Some(latch) = self.latch.as_mut() {
+                *latch = value & 0x01;
+            }
+            return;
+        }
+
+        match self.register(address) {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => {
+                self.mirroring = if value & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            },
+            0x9000 | 0x9001 => {
+                self.mirroring = match value & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            },
+            0x9002 => self.prg_swapped = value & 0x02 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            register @ 0xB000..=0xE003 => {
+                // Two banks to each register group, each written as a low nibble and a high one.
+                let index = (((register - 0xB000) >> 12) * 2 + ((register & 0x02) >> 1)) as usize;
+                let bank = &mut self.chr_banks[index];
+                if register & 0x01 == 0 {
+                    *bank = (*bank & 0x1F0) | (value as u16 & 0x0F);
+                } else {
+                    let high_bits = if self.vrc2 { 0x0F } else { 0x1F };
+                    *bank = (*bank & 0x0F) | ((value as u16 & high_bits) << 4);
+                }
+            },
+            0xF000 if !self.vrc2 => self.irq.write_latch_nibble(false, value),
+            0xF001 if !self.vrc2 => self.irq.write_latch_nibble(true, value),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq.acknowledge();
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| BankWindow::new(start, CHR_BANK, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![
+            MapperRegister::new("PRG bank 0", self.prg_banks[0]),
+            MapperRegister::new("PRG bank 1", self.prg_banks[1]),
+        ];
+        if !self.vrc2 {
+            registers.push(MapperRegister::new("PRG swap", self.prg_swapped));
+        }
+        registers.extend(
+            self.chr_banks
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank)),
+        );
+        if let Some(latch) = self.latch {
+            registers.push(MapperRegister::new("Latch", latch));
+        }
+        registers
+    }
+
+    /// VRC2 has none.
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        (!self.vrc2).then(|| self.irq.counter())
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![
+            self.prg_banks[0],
+            self.prg_banks[1],
+            u8::from(self.prg_swapped),
+            self.mirroring as u8,
+            self.latch.unwrap_or(0),
+        ];
+        for bank in self.chr_banks {
+            state.extend_from_slice(&bank.to_le_bytes());
+        }
+        self.irq.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < 5 + 16 + VrcIrq::STATE_LEN {
+            return;
+        }
+
+        self.prg_banks = [state[0], state[1]];
+        self.prg_swapped = state[2] != 0;
+        self.mirroring = Mirroring::from_index(state[3]);
+        if let Some(latch) = self.latch.as_mut() {
+            *latch = state[4];
+        }
+        for (index, bank) in self.chr_banks.iter_mut().enumerate() {
+            *bank = u16::from_le_bytes([state[5 + index * 2], state[6 + index * 2]]);
+        }
+        self.irq.load(&state[21..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(mapper: u16, variant: Option<VrcVariant>) -> Vrc24 {
+        Vrc24::new(numbered_banks(32, PRG_BANK), numbered_banks(512, CHR_BANK), mapper, variant)
+    }
+
+    /// Where `variant` puts register `index` (0 to 3) of the group at `base`.
+    fn address(variant: VrcVariant, base: u16, index: u16) -> u16 {
+        let (low, high) = variant.lines();
+        base | if index & 1 != 0 { low } else { 0 } | if index & 2 != 0 { high } else { 0 }
+    }
+
+    #[test]
+    fn variants_round_trip_through_their_numbers_and_names() {
+        for variant in VrcVariant::ALL {
+            assert_eq!(
+                VrcVariant::from_header(variant.mapper(), variant.submapper()),
+                Some(variant)
+            );
+            assert_eq!(variant.to_string().parse::<VrcVariant>(), Ok(variant));
+        }
+        assert_eq!("vrc4e".parse::<VrcVariant>(), Ok(VrcVariant::Vrc4e));
+        assert_eq!(
+            VrcVariant::from_header(23, 0),
+            None,
+            "mapper 23 alone does not say which board"
+        );
+    }
+
+    #[test]
+    fn prg_banks_and_the_swap_mode() {
+        let mut mapper = board(21, Some(VrcVariant::Vrc4a));
+        mapper.write_prg(0x8000, 3);
+        mapper.write_prg(0xA000, 5);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [3, 5, 30, 31]
+        );
+
+        mapper.write_prg(address(VrcVariant::Vrc4a, 0x9000, 2), 0x02);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [30, 5, 3, 31]
+        );
+    }
+
+    /// Every wiring reaches every CHR register at its own addresses.
+    #[test]
+    fn each_wiring_decodes_its_own_address_lines() {
+        for variant in VrcVariant::ALL.into_iter().filter(|v| !v.is_vrc2()) {
+            let mut mapper = board(variant.mapper(), Some(variant));
+            for (slot, base) in [0xB000u16, 0xB000, 0xC000, 0xC000, 0xD000, 0xD000, 0xE000, 0xE000]
+                .into_iter()
+                .enumerate()
+            {
+                let pair = (slot as u16 & 1) * 2;
+                let bank = 0x40 + slot as u16 * 0x11;
+                mapper.write_prg(address(variant, base, pair), bank as u8 & 0x0F);
+                mapper.write_prg(address(variant, base, pair + 1), (bank >> 4) as u8);
+            }
+            for slot in 0..8u16 {
+                let expected = (0x40 + slot * 0x11) as u8;
+                assert_eq!(mapper.read_chr(slot * 0x400), expected, "{variant} slot {slot}");
+            }
+        }
+    }
+
+    /// With no submapper, both of a number's wirings are listened to at once.
+    #[test]
+    fn the_heuristic_answers_to_every_wiring_under_the_number() {
+        for variant in VrcVariant::ALL.into_iter().filter(|v| v.mapper() != 22) {
+            let mut mapper = board(variant.mapper(), None);
+            mapper.write_prg(address(variant, 0xB000, 3), 0x01);
+            mapper.write_prg(address(variant, 0xB000, 2), 0x02);
+            assert_eq!(mapper.read_chr(0x0400), 0x12, "{variant} under the heuristic");
+        }
+    }
+
+    /// VRC2a drops the bottom bit of each CHR bank; `m22chrbankingtest` checks exactly this.
+    #[test]
+    fn vrc2a_counts_chr_banks_in_halves() {
+        let mut mapper = board(22, None);
+        mapper.write_prg(0xB000, 0x06);
+        mapper.write_prg(0xB002, 0x00);
+        assert_eq!(mapper.read_chr(0x0000), 3);
+    }
+
+    /// VRC2's high nibble has four bits; VRC4's has five.
+    #[test]
+    fn only_vrc4_has_a_ninth_chr_bank_bit() {
+        let mut vrc4 = board(23, Some(VrcVariant::Vrc4f));
+        vrc4.write_prg(0xB001, 0x1F);
+        assert_eq!(vrc4.chr_banks[0], 0x1F0);
+
+        let mut vrc2 = board(23, Some(VrcVariant::Vrc2b));
+        vrc2.write_prg(0xB001, 0x1F);
+        assert_eq!(vrc2.chr_banks[0], 0x0F0);
+    }
+
+    #[test]
+    fn mirroring_has_two_bits_on_vrc4_and_one_on_vrc2() {
+        let mut vrc4 = board(25, Some(VrcVariant::Vrc4b));
+        vrc4.write_prg(0x9000, 0x03);
+        assert_eq!(vrc4.mirroring(), Mirroring::SingleScreenUpper);
+
+        let mut vrc2 = board(25, Some(VrcVariant::Vrc2c));
+        vrc2.write_prg(0x9000, 0x03);
+        assert_eq!(vrc2.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn a_vrc2_without_ram_has_a_one_bit_latch() {
+        let mut header = INesHeader::for_mapper(22);
+        let mut mapper = Vrc24::from_header(&header, vec![0; 4 * PRG_BANK], Vec::new());
+        assert!(mapper.maps_cpu_address(0x6000, false));
+        assert!(!mapper.maps_cpu_address(0x7000, false));
+        mapper.write_prg(0x6000, 0xFF);
+        assert_eq!(mapper.read_prg(0x6000), 0x01);
+        assert_eq!(mapper.open_bus_mask(0x6000), 0xFE);
+
+        header.battery = true;
+        let mapper = Vrc24::from_header(&header, vec![0; 4 * PRG_BANK], Vec::new());
+        assert!(
+            !mapper.maps_cpu_address(0x6000, false),
+            "a battery means RAM, not the latch"
+        );
+    }
+
+    #[test]
+    fn the_irq_counts_cpu_cycles_in_cycle_mode() {
+        let mut mapper = board(21, Some(VrcVariant::Vrc4a));
+        let lines = VrcVariant::Vrc4a;
+        mapper.write_prg(address(lines, 0xF000, 0), 0x0C);
+        mapper.write_prg(address(lines, 0xF000, 1), 0x0F);
+        mapper.write_prg(address(lines, 0xF000, 2), 0x07);
+
+        // From $FC: $FD, $FE, $FF, then the overflow.
+        for _ in 0..3 {
+            mapper.cpu_cycle();
+        }
+        assert!(!mapper.irq_pending());
+        mapper.cpu_cycle();
+        assert!(mapper.irq_pending());
+
+        // Acknowledging keeps the enable, because bit 0 of the control write asked for that.
+        mapper.write_prg(address(lines, 0xF000, 3), 0);
+        assert!(!mapper.irq_pending());
+        for _ in 0..4 {
+            mapper.cpu_cycle();
+        }
+        assert!(mapper.irq_pending());
+    }
+
+    /// In scanline mode the counter steps once every 341 dots, 113⅔ CPU cycles.
+    #[test]
+    fn the_irq_counts_lines_in_scanline_mode() {
+        let mut mapper = board(23, Some(VrcVariant::Vrc4f));
+        mapper.write_prg(0xF000, 0x0E);
+        mapper.write_prg(0xF001, 0x0F);
+        mapper.write_prg(0xF002, 0x02);
+
+        // $FE, then $FF after a line, then the overflow after a second.
+        let mut cycles = 0;
+        while !mapper.irq_pending() {
+            mapper.cpu_cycle();
+            cycles += 1;
+            assert!(cycles < 1000, "the IRQ never fired");
+        }
+        assert_eq!(
+            cycles, 228,
+            "two lines of 341 dots is 227⅓ cycles, rounded up by the prescaler"
+        );
+
+        // Without acknowledge-enable, acknowledging disables.
+        mapper.write_prg(0xF003, 0);
+        for _ in 0..1000 {
+            mapper.cpu_cycle();
+        }
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn vrc2_has_no_irq() {
+        let mut mapper = board(23, Some(VrcVariant::Vrc2b));
+        mapper.write_prg(0xF002, 0x07);
+        for _ in 0..300 {
+            mapper.cpu_cycle();
+        }
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        let mut mapper = board(25, Some(VrcVariant::Vrc4d));
+        let lines = VrcVariant::Vrc4d;
+        mapper.write_prg(0x8000, 7);
+        mapper.write_prg(address(lines, 0x9000, 2), 0x02);
+        mapper.write_prg(address(lines, 0xD000, 1), 0x13);
+        mapper.write_prg(address(lines, 0xF000, 0), 0x05);
+        mapper.write_prg(address(lines, 0xF000, 2), 0x06);
+        for _ in 0..10 {
+            mapper.cpu_cycle();
+        }
+
+        let state = mapper.save_state();
+        let mut restored = board(25, Some(VrcVariant::Vrc4d));
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0xC000), 7);
+    }
+}
@@ -0,0 +1,391 @@
+//! Konami VRC6 (mappers 24 and 26).
+//!
+//! A 16 KB and an 8 KB switchable PRG bank ahead of a fixed last 8 KB, eight 1 KB CHR registers
+//! with a handful of ways of combining them, the VRC IRQ counter, and a sound chip — two pulses and
+//! a sawtooth, in [`crate::apu::Vrc6Audio`].
+//!
+//! The two mapper numbers are the same chip on two boards that swap the CPU's A0 and A1 on the way
+//! to it: *Akumajou Densetsu* is mapper 24 and writes `$9002` where *Madara* and *Esper Dream 2*,
+//! mapper 26, write `$9001`. Everything here is decoded in mapper 24's terms after undoing the swap.
+//!
+//! `$B003` can also put CHR ROM behind the nametables. No game does, and it is not modelled: the
+//! nametables always come from the console's RAM, arranged by the register's mirroring bits. Its
+//! bit 7 gates the work RAM at `$6000`, which every VRC6 game leaves enabled, so the bus's RAM
+//! stays there unconditionally.
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    vrc24::VrcIrq,
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip, Vrc6Audio};
+
+/// Bytes of register state in a save, before the IRQ's and the sound chip's.
+const REGISTER_STATE_LEN: usize = 11;
+
+/// VRC6 (mappers 24 and 26). See the module documentation.
+#[derive(Debug)]
+pub struct Vrc6 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    /// Mapper 26's board: A0 and A1 reach the chip the other way round.
+    swapped_lines: bool,
+
+    /// The 16 KB bank at `$8000`, counted in 16 KB.
+    prg_16k: u8,
+    /// The 8 KB bank at `$C000`.
+    prg_8k: u8,
+    chr_registers: [u8; 8],
+    /// `$B003`: CHR layout in bits 0-1, mirroring in bits 2-3, and how 2 KB banks take their
+    /// lowest bit in bit 5.
+    ppu_control: u8,
+
+    irq: VrcIrq,
+    audio: Vrc6Audio,
+}
+
+impl Vrc6 {
+    /// Build the board: mapper 24, or 26 when `swapped_lines`.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, swapped_lines: bool) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            swapped_lines,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr_registers: [0; 8],
+            ppu_control: 0,
+            irq: VrcIrq::default(),
+            audio: Vrc6Audio::new(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// The register a write to `address` reaches, in mapper 24's numbering.
+    fn register(&self, address: u16) -> u16 {
+        let (a0, a1) = if self.swapped_lines {
+            ((address >> 1) & 1, address & 1)
+        } else {
+            (address & 1, (address >> 1) & 1)
+        };
+        (address & 0xF000) | a0 | (a1 << 1)
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        match address {
+            0x8000..=0xBFFF => self.prg_16k as usize * 2 + usize::from(address >= 0xA000),
+            0xC000..=0xDFFF => self.prg_8k as usize,
+            _ => (self.prg.len() / PRG_BANK).max(1) - 1,
+        }
+    }
+
+    /// The 1 KB bank behind each of the eight CHR windows.
+    ///
+    /// Layout 0 is the eight registers as eight 1 KB banks, and the one every game uses. Layout 1
+    /// makes the first four registers 2 KB banks; layouts 2 and 3 keep four 1 KB banks in the
+    /// lower half and make registers 4 and 5 2 KB banks in the upper. A 2 KB bank takes its second
+    /// half from bit 0 of the register, forced to one, when `$B003` bit 5 is set, and repeats the
+    /// same 1 KB in both halves when it is not.
+    fn chr_bank(&self, window: usize) -> usize {
+        let pair = |register: u8, half: usize| {
+            if self.ppu_control & 0x20 != 0 {
+                (register & 0xFE) as usize | half
+            } else {
+                register as usize
+            }
+        };
+        let registers = &self.chr_registers;
+        match self.ppu_control & 0x03 {
+            0 => registers[window] as usize,
+            1 => pair(registers[window / 2], window & 1),
+            _ if window < 4 => registers[window] as usize,
+            _ => pair(registers[4 + (window - 4) / 2], window & 1),
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let bank = self.chr_bank((address as usize >> 10) & 0x07) % banks;
+        bank * CHR_BANK + (address as usize & 0x03FF)
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&self, address: u16) -> u8 {
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            return;
+        }
+
+        match self.register(address) {
+            0x8000..=0x8003 => self.prg_16k = value & 0x0F,
+            register @ (0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002) => self.audio.write(register, value),
+            0xB003 => self.ppu_control = value,
+            0xC000..=0xC003 => self.prg_8k = value & 0x1F,
+            register @ 0xD000..=0xE003 => {
+                let index = ((register - 0xD000) >> 12) as usize * 4 + (register & 0x03) as usize;
+                self.chr_registers[index] = value;
+            },
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.ppu_control >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq.acknowledge();
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| BankWindow::new(start, CHR_BANK, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers = vec![
+            MapperRegister::new("PRG 16K bank", self.prg_16k),
+            MapperRegister::new("PRG 8K bank", self.prg_8k),
+        ];
+        registers.extend(
+            self.chr_registers
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("CHR register {index}"), bank)),
+        );
+        registers.push(MapperRegister::new("PPU control", self.ppu_control));
+        registers
+    }
+
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(self.irq.counter())
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        self.audio.tick();
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Vrc6)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.audio.channels()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        self.audio.set_channel_enabled(index, enabled);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.prg_16k, self.prg_8k, self.ppu_control];
+        state.extend_from_slice(&self.chr_registers);
+        self.irq.save(&mut state);
+        self.audio.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < REGISTER_STATE_LEN + VrcIrq::STATE_LEN + Vrc6Audio::STATE_LEN {
+            return;
+        }
+
+        self.prg_16k = state[0] & 0x0F;
+        self.prg_8k = state[1] & 0x1F;
+        self.ppu_control = state[2];
+        self.chr_registers.copy_from_slice(&state[3..REGISTER_STATE_LEN]);
+        self.irq.load(&state[REGISTER_STATE_LEN..]);
+        self.audio.load(&state[REGISTER_STATE_LEN + VrcIrq::STATE_LEN..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(swapped_lines: bool) -> Vrc6 {
+        Vrc6::new(numbered_banks(32, PRG_BANK), numbered_banks(256, CHR_BANK), swapped_lines)
+    }
+
+    #[test]
+    fn prg_is_16k_then_8k_then_the_fixed_last_bank() {
+        let mut mapper = board(false);
+        mapper.write_prg(0x8000, 3);
+        mapper.write_prg(0xC000, 9);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [6, 7, 9, 31]
+        );
+    }
+
+    /// Mapper 26 reaches `$9001` at `$9002`: the same chip, with two address lines crossed.
+    #[test]
+    fn mapper_26_swaps_a0_and_a1() {
+        let mut mapper = board(true);
+        mapper.write_prg(0xD001, 0x21);
+        mapper.write_prg(0xD002, 0x12);
+        assert_eq!(mapper.chr_registers[1..3], [0x12, 0x21]);
+
+        mapper.write_prg(0xB003, 0x24);
+        assert_eq!(
+            mapper.mirroring(),
+            Mirroring::Horizontal,
+            "$B003 on mapper 24 is $B003 on 26"
+        );
+        mapper.write_prg(0xB003, 0x20);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    #[test]
+    fn chr_layouts() {
+        let mut mapper = board(false);
+        for (index, address) in [0xD000u16, 0xD001, 0xD002, 0xD003, 0xE000, 0xE001, 0xE002, 0xE003]
+            .into_iter()
+            .enumerate()
+        {
+            mapper.write_prg(address, 0x10 + index as u8 * 2);
+        }
+        let windows = |mapper: &Vrc6| (0..8u16).map(|w| mapper.read_chr(w * 0x400)).collect::<Vec<_>>();
+
+        mapper.write_prg(0xB003, 0x20);
+        assert_eq!(windows(&mapper), [0x10, 0x12, 0x14, 0x16, 0x18, 0x1A, 0x1C, 0x1E]);
+
+        mapper.write_prg(0xB003, 0x21);
+        assert_eq!(windows(&mapper), [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]);
+
+        mapper.write_prg(0xB003, 0x22);
+        assert_eq!(windows(&mapper), [0x10, 0x12, 0x14, 0x16, 0x18, 0x19, 0x1A, 0x1B]);
+
+        // Without bit 5, a 2 KB bank repeats its register's 1 KB.
+        mapper.write_prg(0xB003, 0x01);
+        assert_eq!(windows(&mapper), [0x10, 0x10, 0x12, 0x12, 0x14, 0x14, 0x16, 0x16]);
+    }
+
+    #[test]
+    fn the_irq_takes_a_whole_byte_latch() {
+        let mut mapper = board(false);
+        mapper.write_prg(0xF000, 0xFD);
+        mapper.write_prg(0xF001, 0x06);
+        mapper.cpu_cycle();
+        mapper.cpu_cycle();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_cycle();
+        assert!(mapper.irq_pending());
+        mapper.write_prg(0xF002, 0);
+        assert!(!mapper.irq_pending());
+    }
+
+    #[test]
+    fn the_sound_chip_is_reached_through_the_board_and_listed() {
+        let mut mapper = board(true);
+        assert!(mapper.audio_channels().iter().all(|channel| !channel.enabled));
+
+        // Pulse 1 at constant volume 15, enabled by $9002 — which mapper 26 decodes from $9001.
+        mapper.write_prg(0x9000, 0x8F);
+        mapper.write_prg(0x9001, 0x80);
+        mapper.cpu_cycle();
+        assert!(mapper.audio_output() > 0.0);
+        let channels = mapper.audio_channels();
+        assert_eq!(channels.len(), 3);
+        assert!(channels[0].enabled && !channels[1].enabled && !channels[2].enabled);
+
+        mapper.set_audio_channel_enabled(0, false);
+        assert_eq!(mapper.audio_output(), 0.0);
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        let mut mapper = board(false);
+        mapper.write_prg(0x8000, 5);
+        mapper.write_prg(0xC000, 11);
+        mapper.write_prg(0xB003, 0x2C);
+        mapper.write_prg(0xE002, 0x44);
+        mapper.write_prg(0xF000, 0x80);
+        mapper.write_prg(0xF001, 0x02);
+        mapper.write_prg(0xB000, 0x2A);
+        mapper.write_prg(0xB002, 0x80);
+        for _ in 0..500 {
+            mapper.cpu_cycle();
+        }
+
+        let state = mapper.save_state();
+        let mut restored = board(false);
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0x8000), 10);
+        assert_eq!(restored.mirroring(), Mirroring::SingleScreenUpper);
+        assert_eq!(restored.audio_output(), mapper.audio_output());
+    }
+}
@@ -0,0 +1,388 @@
+//! Konami VRC7 (mapper 85).
+//!
+//! Three switchable 8 KB PRG banks ahead of a fixed last one, eight 1 KB CHR banks, the VRC IRQ
+//! counter, and a six-channel FM synthesizer, in [`crate::apu::Vrc7Audio`].
+//!
+//! Two boards carry it and differ in the address line that picks the second register at each
+//! address: *Lagrange Point* (VRC7a, submapper 2) uses A4, so `$8010`, and *Tiny Toon Adventures 2*
+//! (VRC7b, submapper 1) uses A3, so `$8008`. A header that does not say, submapper 0, gets both
+//! lines ORed, which decodes either game correctly because neither writes the other's addresses.
+//! Registers are decoded here in VRC7a's numbering.
+//!
+//! The sound chip sits at `$9010` and `$9030` on both boards — only Lagrange Point's is wired to
+//! the cartridge connector, but nothing is lost by letting the other play into silence.
+//!
+//! `$E000` bit 6 gates the work RAM at `$6000`. Both games enable it before using it and never
+//! rely on it being off, so, as on VRC6, the bus's RAM stays there unconditionally.
+
+use super::{
+    mapper::{
+        banked, chr_memory, resize_chr_ram, BankMemory, BankWindow, IrqCounter, Mapper, MapperRegister, CHR_BANK,
+        PRG_BANK,
+    },
+    vrc24::VrcIrq,
+    Mirroring,
+};
+use crate::apu::{ExpansionChannel, ExpansionChip, Vrc7Audio};
+
+/// Bytes of register state in a save, before the IRQ's and the sound chip's.
+const REGISTER_STATE_LEN: usize = 12;
+
+/// VRC7 (mapper 85). See the module documentation.
+#[derive(Debug)]
+pub struct Vrc7 {
+    /// Whether this board's character memory is RAM. A header saying zero CHR banks means
+    /// CHR RAM, and only RAM accepts writes; ROM ignores them.
+    chr_is_ram: bool,
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+
+    /// The address bits that select a register's second half: A4 on VRC7a, A3 on VRC7b.
+    select_lines: u16,
+
+    /// The 8 KB banks at `$8000`, `$A000` and `$C000`.
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    /// `$E000`: mirroring in bits 0-1, work RAM enable in bit 6, sound reset in bit 7.
+    control: u8,
+
+    irq: VrcIrq,
+    audio: Vrc7Audio,
+}
+
+impl Vrc7 {
+    /// Build the board for NES 2.0 `submapper`: 1 for VRC7b, 2 for VRC7a, anything else for both.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, submapper: u8) -> Self {
+        let chr_is_ram = chr.is_empty();
+        let chr = if chr_is_ram { vec![0; 8 * 1024] } else { chr };
+        let select_lines = match submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+
+        Self {
+            chr_is_ram,
+            prg,
+            chr,
+            select_lines,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::default(),
+            audio: Vrc7Audio::new(),
+        }
+    }
+
+    /// Give the board `bytes` of CHR RAM rather than 8 KB. See [`resize_chr_ram`].
+    pub fn with_chr_ram_size(mut self, bytes: usize) -> Self {
+        resize_chr_ram(self.chr_is_ram, &mut self.chr, bytes);
+        self
+    }
+
+    /// The register a write to `address` reaches, in VRC7a's numbering.
+    fn register(&self, address: u16) -> u16 {
+        let second = if address & self.select_lines != 0 { 0x10 } else { 0 };
+        (address & 0xF000) | second
+    }
+
+    fn prg_bank_for(&self, address: u16) -> usize {
+        match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) / PRG_BANK] as usize,
+            _ => (self.prg.len() / PRG_BANK).max(1) - 1,
+        }
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK).max(1);
+        let bank = self.chr_banks[(address as usize >> 10) & 0x07] as usize % banks;
+        bank * CHR_BANK + (address as usize & 0x03FF)
+    }
+
+    /// Everything but the sound chip's ports.
+    fn write_register(&mut self, address: u16, value: u8) {
+        match self.register(address) {
+            0x8000 => self.prg_banks[0] = value & 0x3F,
+            0x8010 => self.prg_banks[1] = value & 0x3F,
+            0x9000 => self.prg_banks[2] = value & 0x3F,
+            register @ 0xA000..=0xD010 => {
+                let index = ((register - 0xA000) >> 12) as usize * 2 + usize::from(register & 0x10 != 0);
+                self.chr_banks[index] = value;
+            },
+            0xE000 => self.write_control(value),
+            0xE010 => self.irq.write_latch(value),
+            0xF000 => self.irq.write_control(value),
+            0xF010 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.audio.set_reset(value & 0x80 != 0);
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read_prg(&self, address: u16) -> u8 {
+        banked(
+            &self.prg,
+            self.prg_bank_for(address),
+            PRG_BANK,
+            address as usize & 0x1FFF,
+        )
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x8000 {
+            return;
+        }
+
+        // The sound chip's two ports decode A4 and A5 on both boards, whatever the registers use.
+        match address & 0xF030 {
+            0x9010 => self.audio.select(value),
+            0x9030 => self.audio.write(value),
+            _ => self.write_register(address, value),
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if !self.chr_is_ram {
+            return;
+        }
+        let index = self.chr_index(address);
+        self.chr[index] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn acknowledge_irq(&mut self) {
+        self.irq.acknowledge();
+    }
+
+    fn prg_windows(&self) -> Vec<BankWindow> {
+        (0x8000..=0xE000)
+            .step_by(PRG_BANK)
+            .map(|start| {
+                let bank = self.prg_bank_for(start);
+                BankWindow::banked(start, PRG_BANK, BankMemory::Rom, bank, self.prg.len())
+            })
+            .collect()
+    }
+
+    fn chr_windows(&self) -> Vec<BankWindow> {
+        let memory = chr_memory(self.chr_is_ram);
+        (0x0000..0x2000)
+            .step_by(CHR_BANK)
+            .map(|start| BankWindow::new(start, CHR_BANK, memory, self.chr_index(start)))
+            .collect()
+    }
+
+    fn registers(&self) -> Vec<MapperRegister> {
+        let mut registers: Vec<_> = self
+            .prg_banks
+            .iter()
+            .zip(["PRG bank $8000", "PRG bank $A000", "PRG bank $C000"])
+            .map(|(&bank, name)| MapperRegister::new(name, bank))
+            .collect();
+        registers.extend(
+            self.chr_banks
+                .iter()
+                .enumerate()
+                .map(|(index, &bank)| MapperRegister::new(format!("CHR bank {index}"), bank)),
+        );
+        registers.push(MapperRegister::new("Control", self.control));
+        registers
+    }
+
+    fn irq_counter(&self) -> Option<IrqCounter> {
+        Some(self.irq.counter())
+    }
+
+    fn counts_cpu_cycles(&self) -> bool {
+        true
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        self.audio.tick();
+    }
+
+    fn audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::Vrc7)
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn audio_channels(&self) -> Vec<ExpansionChannel> {
+        self.audio.channels()
+    }
+
+    fn set_audio_channel_enabled(&mut self, index: usize, enabled: bool) {
+        self.audio.set_channel_enabled(index, enabled);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = self.prg_banks.to_vec();
+        state.extend_from_slice(&self.chr_banks);
+        state.push(self.control);
+        self.irq.save(&mut state);
+        self.audio.save(&mut state);
+        state
+    }
+
+    fn load_state(&mut self, state: &[u8]) {
+        if state.len() < REGISTER_STATE_LEN + VrcIrq::STATE_LEN + Vrc7Audio::STATE_LEN {
+            return;
+        }
+
+        for (bank, &saved) in self.prg_banks.iter_mut().zip(&state[..3]) {
+            *bank = saved & 0x3F;
+        }
+        self.chr_banks.copy_from_slice(&state[3..11]);
+        self.control = state[11];
+        self.irq.load(&state[REGISTER_STATE_LEN..]);
+        // The chip's saved state already records whether it was held in reset.
+        self.audio.load(&state[REGISTER_STATE_LEN + VrcIrq::STATE_LEN..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::mapper::numbered_banks;
+
+    fn board(submapper: u8) -> Vrc7 {
+        Vrc7::new(numbered_banks(64, PRG_BANK), numbered_banks(256, CHR_BANK), submapper)
+    }
+
+    #[test]
+    fn three_prg_banks_then_the_fixed_last() {
+        let mut mapper = board(2);
+        mapper.write_prg(0x8000, 3);
+        mapper.write_prg(0x8010, 9);
+        mapper.write_prg(0x9000, 0x4A);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|a| mapper.read_prg(a)),
+            [3, 9, 10, 63]
+        );
+    }
+
+    /// VRC7b's second registers are at `$x008`, where VRC7a's `$x010` does nothing.
+    #[test]
+    fn the_boards_select_with_different_lines() {
+        let mut vrc7b = board(1);
+        vrc7b.write_prg(0xA008, 7);
+        vrc7b.write_prg(0xA010, 8);
+        assert_eq!(vrc7b.chr_banks[..2], [8, 7], "on VRC7b, $A010 is $A000");
+
+        let mut either = board(0);
+        either.write_prg(0xA008, 7);
+        either.write_prg(0xB010, 5);
+        assert_eq!(either.chr_banks[..4], [0, 7, 0, 5]);
+    }
+
+    #[test]
+    fn eight_1k_chr_banks_and_mirroring() {
+        let mut mapper = board(2);
+        for (index, address) in [0xA000u16, 0xA010, 0xB000, 0xB010, 0xC000, 0xC010, 0xD000, 0xD010]
+            .into_iter()
+            .enumerate()
+        {
+            mapper.write_prg(address, 0x20 + index as u8);
+        }
+        assert_eq!(
+            (0..8u16).map(|w| mapper.read_chr(w * 0x400)).collect::<Vec<_>>(),
+            [0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27]
+        );
+
+        mapper.write_prg(0xE000, 0x01);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.write_prg(0xE000, 0x03);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn the_irq_takes_a_whole_byte_latch() {
+        let mut mapper = board(2);
+        mapper.write_prg(0xE010, 0xFE);
+        mapper.write_prg(0xF000, 0x06);
+        mapper.cpu_cycle();
+        assert!(!mapper.irq_pending());
+        mapper.cpu_cycle();
+        assert!(mapper.irq_pending());
+        mapper.write_prg(0xF010, 0);
+        assert!(!mapper.irq_pending());
+    }
+
+    /// Key a note on channel 3 through the board and hear it; hold the chip in reset and it stops.
+    #[test]
+    fn the_sound_chip_is_reached_through_the_board_and_listed() {
+        let mut mapper = board(2);
+        for (register, value) in [(0x32, 0x40), (0x12, 0x21), (0x22, 0x19)] {
+            mapper.write_prg(0x9010, register);
+            mapper.write_prg(0x9030, value);
+        }
+        let channels = mapper.audio_channels();
+        assert_eq!(channels.len(), 6);
+        assert_eq!(
+            channels.iter().map(|c| c.enabled).collect::<Vec<_>>(),
+            [false, false, true, false, false, false]
+        );
+
+        let heard = (0..36 * 200).any(|_| {
+            mapper.cpu_cycle();
+            mapper.audio_output() != 0.0
+        });
+        assert!(heard);
+
+        mapper.write_prg(0xE000, 0x80);
+        assert_eq!(mapper.audio_output(), 0.0);
+        assert!(
+            mapper.audio_channels().iter().all(|channel| !channel.enabled),
+            "reset clears the keys"
+        );
+    }
+
+    #[test]
+    fn save_state_round_trips() {
+        let mut mapper = board(2);
+        mapper.write_prg(0x8010, 5);
+        mapper.write_prg(0xC010, 0x44);
+        mapper.write_prg(0xE000, 0x42);
+        mapper.write_prg(0xE010, 0x80);
+        mapper.write_prg(0xF000, 0x02);
+        mapper.write_prg(0x9010, 0x30);
+        mapper.write_prg(0x9030, 0x70);
+        mapper.write_prg(0x9010, 0x20);
+        mapper.write_prg(0x9030, 0x18);
+        for _ in 0..5000 {
+            mapper.cpu_cycle();
+        }
+
+        let state = mapper.save_state();
+        let mut restored = board(2);
+        restored.load_state(&state);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.read_prg(0xA000), 5);
+        assert_eq!(restored.mirroring(), Mirroring::SingleScreenLower);
+        assert_eq!(restored.audio_output(), mapper.audio_output());
+    }
+}
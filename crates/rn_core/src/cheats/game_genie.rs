@@ -0,0 +1,147 @@
+//! Game Genie codes, which patch what the CPU reads from cartridge space.
+//!
+//! The Game Genie sat between the cartridge and the console and answered some reads itself. A
+//! code is six or eight letters from a sixteen-letter alphabet, each standing for four bits, and
+//! the bits are shuffled across the letters so that a code cannot be read by eye. Six letters give
+//! an address in `$8000-$FFFF` and the value to put there; eight add a compare value, and the
+//! replacement is only made while the cartridge really has that value there — which is how a code
+//! for a bank-switched game patches one bank and leaves the others alone.
+//!
+//! The third letter's high bit marks an eight-letter code on the device. Decoding goes by length
+//! instead, which every code list agrees with.
+
+/// The alphabet, in the order of the values the letters stand for.
+const LETTERS: [u8; 16] = *b"APZLGITYEOXUKSVN";
+
+/// One decoded code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameGenieCode {
+    /// Where in `$8000-$FFFF` the code answers.
+    pub address: u16,
+    /// What a read there returns instead.
+    pub value: u8,
+    /// What has to be there for the replacement to be made, for an eight-letter code.
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    /// What a read of `address` returns with this code in, given what the cartridge put there.
+    pub fn patch(&self, address: u16, read: u8) -> u8 {
+        if address == self.address && self.compare.is_none_or(|compare| compare == read) {
+            self.value
+        } else {
+            read
+        }
+    }
+}
+
+fn letter_value(letter: char) -> Option<u8> {
+    let letter = letter.to_ascii_uppercase() as u8;
+    LETTERS
+        .iter()
+        .position(|&known| known == letter)
+        .map(|value| value as u8)
+}
+
+/// Decode a six- or eight-letter code, ignoring case. `None` for anything else.
+pub fn decode(code: &str) -> Option<GameGenieCode> {
+    let n: Vec<u16> = code
+        .chars()
+        .map(|letter| letter_value(letter).map(u16::from))
+        .collect::<Option<_>>()?;
+    if n.len() != 6 && n.len() != 8 {
+        return None;
+    }
+
+    let address = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+    Some(if n.len() == 6 {
+        GameGenieCode {
+            address,
+            value: (value | (n[5] & 8)) as u8,
+            compare: None,
+        }
+    } else {
+        GameGenieCode {
+            address,
+            value: (value | (n[7] & 8)) as u8,
+            compare: Some((((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8)) as u8),
+        }
+    })
+}
+
+/// The letters for `code`: six without a compare value, eight with one.
+pub fn encode(code: &GameGenieCode) -> String {
+    let (address, value) = (code.address, u16::from(code.value));
+    // Whichever byte's fourth bit the sixth letter carries: the value's on a six-letter code, the
+    // compare value's on an eight-letter one.
+    let sixth = code.compare.map_or(value, u16::from);
+
+    let mut n = vec![
+        (value & 7) | ((value >> 4) & 8),
+        ((value >> 4) & 7) | ((address >> 4) & 8),
+        ((address >> 4) & 7) | if code.compare.is_some() { 8 } else { 0 },
+        ((address >> 12) & 7) | (address & 8),
+        (address & 7) | ((address >> 8) & 8),
+        ((address >> 8) & 7) | (sixth & 8),
+    ];
+    if let Some(compare) = code.compare.map(u16::from) {
+        n.push((compare & 7) | ((compare >> 4) & 8));
+        n.push(((compare >> 4) & 7) | (value & 8));
+    }
+    n.into_iter().map(|nibble| LETTERS[nibble as usize] as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The worked example from the Game Genie's own code format notes.
+    #[test]
+    fn a_six_letter_code_decodes_to_an_address_and_value() {
+        let code = decode("GOSSIP").unwrap();
+        assert_eq!(
+            code,
+            GameGenieCode {
+                address: 0xD1DD,
+                value: 0x14,
+                compare: None
+            }
+        );
+        assert_eq!(decode("gossip"), Some(code), "case does not matter");
+    }
+
+    #[test]
+    fn an_eight_letter_code_only_patches_when_the_compare_value_is_there() {
+        let code = decode("ZEXPYGLA").unwrap();
+        assert_eq!(code.address, 0x94A7);
+        assert_eq!((code.value, code.compare), (0x02, Some(0x03)));
+
+        assert_eq!(code.patch(0x94A7, 0x03), 0x02);
+        assert_eq!(code.patch(0x94A7, 0x07), 0x07, "another bank is left alone");
+        assert_eq!(code.patch(0x94A8, 0x03), 0x03);
+    }
+
+    #[test]
+    fn codes_survive_encoding_and_decoding() {
+        for code in ["ZEXPYGLA", "SXIOPO", "AAAAAA", "NNNNNNNN"] {
+            assert_eq!(encode(&decode(code).unwrap()), code);
+        }
+    }
+
+    #[test]
+    fn anything_but_six_or_eight_genie_letters_is_refused() {
+        assert_eq!(decode("GOSSI"), None);
+        assert_eq!(decode("GOSSIPA"), None);
+        assert_eq!(decode("GOSSIB"), None, "B is not in the alphabet");
+        assert_eq!(decode(""), None);
+    }
+}
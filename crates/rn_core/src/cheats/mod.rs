@@ -0,0 +1,311 @@
+//! Cheat codes: Game Genie patches on cartridge reads, and RAM held at a value every frame.
+//!
+//! The two work on different sides of the machine. A Game Genie code changes what the CPU reads
+//! from `$8000-$FFFF`, so it alters the program itself and costs nothing until that address is
+//! read. A Pro Action Replay code — written `AAAA:VV` — names a byte of RAM and a value, and the
+//! value is written back once a frame, so whatever the game does to its lives counter in between,
+//! it starts every frame where the code put it. Only RAM can be held that way: `$0000-$1FFF` and
+//! the cartridge's work RAM at `$6000-$7FFF`. Writing a register once a frame would not hold it,
+//! it would drive it.
+//!
+//! Each ROM's list lives in a `.cht` file beside it, one code to a line:
+//!
+//! ```text
+//! # Super Mario Bros.
+//! on  SXIOPO   Infinite lives
+//! off 075A:09  Nine lives
+//! ```
+//!
+//! `on` or `off`, the code, then a description running to the end of the line. Blank lines and
+//! lines starting with `#` are skipped, and a list is written back in the same form.
+
+mod game_genie;
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+pub use game_genie::{decode as decode_game_genie, encode as encode_game_genie, GameGenieCode};
+
+/// What a code does, once decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatEffect {
+    /// A Game Genie code, patching reads of cartridge space.
+    GameGenie(GameGenieCode),
+    /// A byte of RAM written back every frame.
+    Freeze { address: u16, value: u8 },
+}
+
+impl CheatEffect {
+    /// Decode `code` as either kind: six or eight Game Genie letters, or `AAAA:VV` in hexadecimal.
+    ///
+    /// The colon may be left out, which is how Pro Action Replay printed them. A code of six
+    /// characters that are both Game Genie letters and hexadecimal digits, such as `AAAAAA`, is
+    /// taken for a Game Genie code; writing the colon says otherwise.
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim();
+        if let Some(genie) = game_genie::decode(code) {
+            return Ok(Self::GameGenie(genie));
+        }
+
+        let hex = code.replace(':', "");
+        let unrecognised = || CheatError::Unrecognised(code.to_string());
+        if hex.len() != 6 || !hex.chars().all(|digit| digit.is_ascii_hexdigit()) {
+            return Err(unrecognised());
+        }
+        let address = u16::from_str_radix(&hex[..4], 16).map_err(|_| unrecognised())?;
+        let value = u8::from_str_radix(&hex[4..], 16).map_err(|_| unrecognised())?;
+        if !is_ram(address) {
+            return Err(CheatError::NotRam(address));
+        }
+        Ok(Self::Freeze { address, value })
+    }
+}
+
+impl std::fmt::Display for CheatEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::GameGenie(GameGenieCode {
+                address,
+                value,
+                compare: Some(compare),
+            }) => write!(f, "${address:04X} = ${value:02X} if ${compare:02X}"),
+            Self::GameGenie(GameGenieCode { address, value, .. }) => write!(f, "${address:04X} = ${value:02X}"),
+            Self::Freeze { address, value } => write!(f, "${address:04X} held at ${value:02X}"),
+        }
+    }
+}
+
+/// Whether a byte at `address` stays where it is put: work RAM or cartridge RAM.
+fn is_ram(address: u16) -> bool {
+    address < 0x2000 || (0x6000..0x8000).contains(&address)
+}
+
+/// Why a code or a list could not be read.
+#[derive(Debug, Error)]
+pub enum CheatError {
+    #[error("\"{0}\" is not a cheat code: expected 6 or 8 Game Genie letters, or AAAA:VV in hexadecimal")]
+    Unrecognised(String),
+
+    /// A RAM-freeze code aimed somewhere that is not RAM. See the module notes.
+    #[error("${0:04X} is not RAM, so it cannot be held at a value; only $0000-$1FFF and $6000-$7FFF can")]
+    NotRam(u16),
+
+    #[error("line {line}: {reason}")]
+    Line { line: usize, reason: String },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// One code in a list, as the user typed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    /// The code, in capitals.
+    pub code: String,
+    pub description: String,
+    pub enabled: bool,
+    pub effect: CheatEffect,
+}
+
+impl Cheat {
+    pub fn new(code: &str, description: &str) -> Result<Self, CheatError> {
+        Ok(Self {
+            effect: CheatEffect::parse(code)?,
+            code: code.trim().to_ascii_uppercase(),
+            description: description.trim().to_string(),
+            enabled: true,
+        })
+    }
+}
+
+/// The `.cht` file for the ROM at `rom_path`.
+pub fn file_beside(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("cht")
+}
+
+/// The codes for one game, in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Add a code, switched on, and return where it went.
+    pub fn add(&mut self, code: &str, description: &str) -> Result<usize, CheatError> {
+        self.cheats.push(Cheat::new(code, description)?);
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &CheatEffect> {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| &cheat.effect)
+    }
+
+    /// What a CPU read of cartridge space returns once the enabled Game Genie codes have had
+    /// their say. `read` is what the cartridge answered.
+    pub fn patch_read(&self, address: u16, read: u8) -> u8 {
+        self.enabled().fold(read, |value, effect| match effect {
+            CheatEffect::GameGenie(code) => code.patch(address, value),
+            CheatEffect::Freeze { .. } => value,
+        })
+    }
+
+    /// The RAM the enabled codes hold, as `(address, value)`.
+    pub fn freezes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.enabled().filter_map(|effect| match *effect {
+            CheatEffect::Freeze { address, value } => Some((address, value)),
+            CheatEffect::GameGenie(_) => None,
+        })
+    }
+
+    /// Read a list in the `.cht` form described in the module notes.
+    pub fn parse(text: &str) -> Result<Self, CheatError> {
+        let mut list = Self::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at_line = |reason: String| CheatError::Line {
+                line: index + 1,
+                reason,
+            };
+
+            let (state, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let enabled = match state {
+                "on" => true,
+                "off" => false,
+                other => return Err(at_line(format!("expected on or off, found \"{other}\""))),
+            };
+            let rest = rest.trim_start();
+            if rest.is_empty() {
+                return Err(at_line("the code is missing".to_string()));
+            }
+            let (code, description) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let mut cheat = Cheat::new(code, description).map_err(|error| at_line(error.to_string()))?;
+            cheat.enabled = enabled;
+            list.cheats.push(cheat);
+        }
+        Ok(list)
+    }
+
+    /// Write the list in the form [`parse`](Self::parse) reads.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for cheat in &self.cheats {
+            let state = if cheat.enabled { "on " } else { "off" };
+            let line = format!("{state} {:<8} {}", cheat.code, cheat.description);
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Read the list at `path`. A missing file is an empty list, which is what every game has
+    /// until a code is added to it.
+    pub fn load(path: &Path) -> Result<Self, CheatError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Write the list to `path`, or remove the file once the list is empty rather than leave an
+    /// empty one beside the ROM.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if self.is_empty() {
+            return match fs::remove_file(path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+                _ => Ok(()),
+            };
+        }
+        fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_of_either_kind_are_recognised() {
+        assert!(matches!(CheatEffect::parse("sxiopo"), Ok(CheatEffect::GameGenie(_))));
+        assert_eq!(
+            CheatEffect::parse("075A:09").unwrap(),
+            CheatEffect::Freeze {
+                address: 0x075A,
+                value: 0x09
+            }
+        );
+        assert_eq!(
+            CheatEffect::parse("075a09").unwrap(),
+            CheatEffect::parse("075A:09").unwrap()
+        );
+        assert!(matches!(CheatEffect::parse("2001:1E"), Err(CheatError::NotRam(0x2001))));
+        assert!(matches!(CheatEffect::parse("hello"), Err(CheatError::Unrecognised(_))));
+    }
+
+    #[test]
+    fn only_enabled_codes_take_effect() {
+        let mut list = CheatList::new();
+        let genie = list.add("SXIOPO", "Infinite lives").unwrap();
+        list.add("6000:42", "").unwrap();
+        let CheatEffect::GameGenie(code) = list.cheats()[genie].effect else {
+            panic!("a Game Genie code");
+        };
+
+        assert_eq!(list.patch_read(code.address, 0xCE), code.value);
+        assert_eq!(list.patch_read(code.address + 1, 0xCE), 0xCE);
+        assert_eq!(list.freezes().collect::<Vec<_>>(), [(0x6000, 0x42)]);
+
+        list.set_enabled(genie, false);
+        assert_eq!(list.patch_read(code.address, 0xCE), 0xCE);
+    }
+
+    #[test]
+    fn a_list_is_written_in_the_form_it_is_read() {
+        let text = "# Super Mario Bros.\non  SXIOPO   Infinite lives\n\noff 075A:09  Nine lives\n";
+        let list = CheatList::parse(text).unwrap();
+        assert_eq!(list.cheats().len(), 2);
+        assert!(list.cheats()[0].enabled);
+        assert_eq!(list.cheats()[1].description, "Nine lives");
+        assert!(!list.cheats()[1].enabled);
+
+        assert_eq!(CheatList::parse(&list.to_text()).unwrap(), list);
+    }
+
+    #[test]
+    fn a_bad_line_is_reported_by_number() {
+        let error = CheatList::parse("on SXIOPO\nmaybe SXIOPO\n").unwrap_err();
+        assert!(matches!(error, CheatError::Line { line: 2, .. }), "{error}");
+    }
+}
@@ -31,7 +31,25 @@ pub enum NesError {
     /// Reported rather than approximated: running a game with the wrong banking produces
     /// confusing nonsense instead of an obvious failure.
     #[error("Mapper {0} is not implemented (supported: {1})")]
-    UnsupportedMapper(u8, String),
+    UnsupportedMapper(u16, String),
+
+    /// A Famicom Disk System image was loaded with no BIOS to run it.
+    ///
+    /// The BIOS is Nintendo's and does not come with the emulator, so this is the error every
+    /// user sees on their first disk, and it says what to do about it.
+    #[error("FDS images need the Disk System BIOS; supply an 8 KB disksys.rom with --fds-bios")]
+    MissingFdsBios,
+
+    /// A battery save could not be read or written.
+    ///
+    /// Its own variant, naming the file, because the fix is nearly always about the file — a
+    /// read-only directory, a full disk — and not about the emulator.
+    #[error("Battery save {0}: {1}")]
+    BatterySave(std::path::PathBuf, #[source] std::io::Error),
+
+    /// A cheat list could not be written beside its ROM.
+    #[error("Cheat list {0}: {1}")]
+    CheatFile(std::path::PathBuf, #[source] std::io::Error),
 
     /// Error for input-related issues
     #[error("Input error: {0}")]
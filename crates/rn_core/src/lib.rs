@@ -1,6 +1,7 @@
 pub mod apu;
 pub mod audio;
 pub mod cartridge;
+pub mod cheats;
 pub mod cpu;
 pub mod errors;
 pub mod helpers;
@@ -7,7 +7,7 @@ use std::{
 pub mod palette;
 
 use crate::{
-    cartridge::{Cartridge, Mapper},
+    cartridge::{CartridgeHandle, PpuFetch},
     errors::NesError,
     memory::Addressable,
 };
@@ -92,14 +92,14 @@ impl PpuWrapper {
         ppu.tick();
     }
 
-    pub(crate) fn has_cartridge(&self) -> bool {
+    pub fn has_cartridge(&self) -> bool {
         let ppu = self.ppu.borrow();
         ppu.cartridge().is_some()
     }
 
-    pub fn connect_cartridge(&self, cart: Cartridge) {
+    pub fn connect_cartridge(&self, cartridge: CartridgeHandle) {
         let mut ppu = self.ppu.borrow_mut();
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cartridge);
     }
 
     pub fn force_render_frame(&self) {
@@ -124,10 +124,10 @@ impl PpuWrapper {
     }
 
     pub fn load_chr_rom(&self, chr_data: &[u8]) -> Result<(), NesError> {
-        let mut ppu = self.ppu.borrow_mut();
+        let ppu = self.ppu.borrow();
 
-        if let Some(cart_mut) = ppu.cartridge_mut() {
-            cart_mut.load_chr_rom(chr_data);
+        if let Some(cartridge) = &ppu.cartridge {
+            cartridge.borrow_mut().load_chr_rom(chr_data);
             Ok(())
         } else {
             Err(NesError::MemoryAccessError(0)) // Use an existing error type
@@ -139,7 +139,8 @@ impl PpuWrapper {
         ppu.frame_buffer().to_vec()
     }
 
-    pub fn cartridge(&self) -> Option<Cartridge> {
+    /// The cartridge connected to the PPU, if there is one.
+    pub fn cartridge(&self) -> Option<CartridgeHandle> {
         let ppu = self.ppu.borrow();
         ppu.cartridge()
     }
@@ -180,7 +181,7 @@ impl PpuWrapper {
     }
 
     /// Get the control register value
-    /// Point this PPU at a console: NTSC or PAL. See [`Region`](crate::region::Region).
+    /// Point this PPU at a console: NTSC, PAL or Dendy. See [`Region`](crate::region::Region).
     pub fn set_region(&self, region: crate::region::Region) {
         self.ppu.borrow_mut().region = region;
     }
@@ -235,19 +236,16 @@ impl PpuWrapper {
         self.ppu.borrow().active_nametable()
     }
 
-    /// The cartridge's nametable mirroring.
+    /// The cartridge's nametable mirroring, as it stands now.
+    ///
+    /// From the mapper when there is a cartridge, since it can change partway through a game;
+    /// the PPU's own value is only what it falls back on without one.
     pub fn mirroring(&self) -> Mirroring {
-        self.ppu.borrow().mirroring
-    }
-
-    /// Connect the cartridge's mapper, so pattern-table reads follow CHR banking.
-    pub fn connect_mapper(&self, mapper: Rc<RefCell<Box<dyn Mapper>>>) {
-        self.ppu.borrow_mut().mapper = Some(mapper);
-    }
-
-    /// Set the nametable mirroring, from the cartridge header.
-    pub fn set_mirroring(&self, mirroring: Mirroring) {
-        self.ppu.borrow_mut().mirroring = mirroring;
+        let ppu = self.ppu.borrow();
+        match &ppu.cartridge {
+            Some(cartridge) => cartridge.borrow().mapper().mirroring(),
+            None => ppu.mirroring,
+        }
     }
 
     /// The state of the /NMI line the PPU is driving, right now.
@@ -269,7 +267,8 @@ impl PpuWrapper {
     ///
     /// The per-dot path is the accurate one and is off by default; see
     /// [`per_dot_pixels`](Ppu::per_dot_pixels). Exposed so the two can be run against each other on
-    /// a real ROM rather than only on the synthetic scene the unit tests use.
+    /// a real ROM rather than only on the synthetic scene the unit tests use. A board that switches
+    /// banks on the PPU's fetches is drawn per dot either way.
     pub fn set_per_dot_pixels(&self, per_dot: bool) {
         self.ppu.borrow_mut().per_dot_pixels = per_dot;
     }
@@ -383,7 +382,7 @@ impl PpuInterface for PpuWrapper {
 #[derive(Debug)]
 pub struct Ppu {
     // Memory components
-    vram: [u8; 2048],  // 2KB of VRAM for nametables
+    vram: [u8; NAMETABLE_RAM], // Nametable RAM: the console's 2 KB, then a four-screen cartridge's
     palette: [u8; 32], // 32 bytes of palette memory
     oam: [u8; 256],    // 256 bytes of Object Attribute Memory for sprites
 
@@ -464,11 +463,16 @@ pub struct Ppu {
     /// Toggles seen so far in the frame being drawn.
     toggles_this_frame: u32,
 
-    /// The cartridge's mapper, when a ROM is loaded.
+    /// The cartridge in the slot, shared with the bus.
     ///
-    /// CHR reads go through it so bank switching is visible to rendering; without this the PPU
-    /// would keep drawing whichever bank happened to be loaded first.
-    mapper: Option<Rc<RefCell<Box<dyn Mapper>>>>,
+    /// Pattern and nametable reads go through its mapper, so bank switching and mirroring changes
+    /// are visible to rendering; without this the PPU would keep drawing whichever bank happened
+    /// to be loaded first.
+    cartridge: Option<CartridgeHandle>,
+
+    /// Whether the mapper wants to hear about each rendering read. See
+    /// [`Mapper::watches_ppu_fetches`](crate::cartridge::Mapper::watches_ppu_fetches).
+    mapper_watches_fetches: bool,
 
     scanline: i16,            // Current scanline (-1 to 261)
     cycle: u16,               // Current cycle (0 to 340)
@@ -531,6 +535,8 @@ pub struct Ppu {
     ///
     /// Kept as a field rather than becoming the only path, so the two can still be compared —
     /// which is what `the_two_pixel_paths_agree_on_a_static_scene` does.
+    ///
+    /// Not honoured for a mapper that watches the fetches; see [`draws_per_dot`](Self::draws_per_dot).
     per_dot_pixels: bool,
 
     /// The pattern address each of the eight output units fetches from.
@@ -583,11 +589,17 @@ pub struct Ppu {
     /// The last *completed* frame, which is what everything outside the PPU reads.
     frame_buffer: Vec<u8>,
     background_pixels: Vec<u8>, // Stores the background pixel values (0-3) for priority handling
-
-    // Cartridge reference (optional)
-    cartridge: Option<Cartridge>,
 }
 
+/// Bytes of nametable RAM the PPU passes to the mapper: the console's own 2 KB, followed by the
+/// 2 KB a four-screen board adds.
+///
+/// The second half is on the cartridge in hardware, and only four-screen boards have it. It is
+/// kept here all the same so that every mapper reaches it through the default nametable hooks
+/// without carrying RAM of its own, and so a save state takes it along with the rest of the
+/// nametables. Nothing but [`Mirroring::FourScreen`] addresses it.
+pub const NAMETABLE_RAM: usize = 4 * 1024;
+
 /// How the two physical nametables are mapped into the four logical ones.
 ///
 /// The cartridge wires this, and it decides how a scrolled background wraps. Assuming one layout
@@ -606,6 +618,13 @@ pub enum Mirroring {
     SingleScreenLower,
     /// All four map to the second physical table.
     SingleScreenUpper,
+    /// Four distinct tables, none shared.
+    ///
+    /// The console only has RAM for two, so a board wired this way brings another 2 KB for the
+    /// other pair — Gauntlet and Rad Racer II do, to scroll in both directions without a seam.
+    /// The header's four-screen bit says so. Only MMC3, the chip those games use, then ignores
+    /// its mirroring register; any other board with one would still switch away from this.
+    FourScreen,
 }
 
 impl Mirroring {
@@ -620,9 +639,34 @@ impl Mirroring {
             1 => Self::Vertical,
             2 => Self::SingleScreenLower,
             3 => Self::SingleScreenUpper,
+            4 => Self::FourScreen,
             _ => Self::Horizontal,
         }
     }
+
+    /// Where a nametable address lands in the console's 2 KB of nametable RAM.
+    ///
+    /// There are four logical nametables but only 2 KB of VRAM, so two pairs always alias. Which
+    /// pair depends on how the cartridge is wired, and getting it wrong sends a scrolling
+    /// background into the wrong screen.
+    ///
+    /// [`FourScreen`](Self::FourScreen) is the exception, and lands anywhere in the 4 KB of
+    /// [`NAMETABLE_RAM`]: the upper half is the cartridge's.
+    pub fn ciram_offset(self, address: u16) -> usize {
+        let offset = (address & 0x0FFF) as usize;
+        let table = offset / 0x0400;
+        let index = offset % 0x0400;
+
+        let physical = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+
+        physical * 0x0400 + index
+    }
 }
 
 /// What the PPU actually did over recent frames.
@@ -811,7 +855,7 @@ impl Ppu {
             suppress_vblank: Cell::new(false),
 
             // Initialize memory components
-            vram: [0; 2048],
+            vram: [0; NAMETABLE_RAM],
             palette: [0; 32],
             oam: [0; 256],
 
@@ -843,7 +887,8 @@ impl Ppu {
             rendering_was_enabled: false,
             scanlines_this_frame: 0,
             toggles_this_frame: 0,
-            mapper: None,
+            cartridge: None,
+            mapper_watches_fetches: false,
             // Line 0, not -1. The comment here used to say "start at pre-render scanline", but
             // the pre-render line in this PPU is 261 — `tick` treats it as such and nothing treats
             // -1 as anything — so -1 was a line that was neither drawn nor pre-render, run once at
@@ -867,7 +912,6 @@ impl Ppu {
             vram_writes_during_render_this_frame: 0,
             frame_buffer: vec![0; 256 * 240 * 3],
             background_pixels: vec![0; 256 * 240],
-            cartridge: None,
         }
     }
 
@@ -906,7 +950,7 @@ impl Ppu {
         // enables it — and paints by leaving `v` inside palette memory, which `emit_pixel` then
         // shows entry by entry. With no pixels emitted at all there was nothing to show it with.
         if !rendering_now
-            && self.per_dot_pixels
+            && self.draws_per_dot()
             && (1..=256).contains(&self.cycle)
             && (0..240).contains(&self.scanline)
         {
@@ -960,7 +1004,7 @@ impl Ppu {
         // read falls. Setting the flag when the scanline advanced put it one dot early, so every
         // such program saw the previous answer.
         if self.cycle == 1 {
-            if self.scanline == 241 {
+            if self.scanline == self.region.vblank_scanline() {
                 if self.suppress_vblank.replace(false) {
                     // A read of $2002 on the previous dot took this frame's vblank with it.
                     self.end_frame();
@@ -1049,7 +1093,7 @@ impl Ppu {
                 // partway down the frame survives that restore, because such a write sets `t` as
                 // well as `v` — which is what makes it a mid-frame scroll change rather than one
                 // that lasts a single line.
-                if !self.per_dot_pixels {
+                if !self.draws_per_dot() {
                     self.render_background_scanline(y);
                     self.render_sprites_for_scanline(y);
                 }
@@ -1263,14 +1307,22 @@ impl Ppu {
 
             257 => {
                 self.selected_sprites.clear();
+                self.announce_fetch(0x2000 | (self.ppu_addr.get() & 0x0FFF), PpuFetch::Dummy);
                 self.load_sprite_slot(0);
                 // OAMADDR is cleared throughout the sprite fetches, so a game that left it
                 // somewhere else finds it back at zero by the time the line ends.
                 self.oam_addr = 0;
             },
             258..=320 => {
-                if (self.cycle - 257).is_multiple_of(8) {
-                    self.load_sprite_slot(((self.cycle - 257) / 8) as usize);
+                // Each slot's group opens with two nametable reads nobody uses, on its first and
+                // third dots. Only a mapper hears them.
+                match (self.cycle - 257) % 8 {
+                    0 => {
+                        self.announce_fetch(0x2000 | (self.ppu_addr.get() & 0x0FFF), PpuFetch::Dummy);
+                        self.load_sprite_slot(((self.cycle - 257) / 8) as usize);
+                    },
+                    2 => self.announce_fetch(0x2000 | (self.ppu_addr.get() & 0x0FFF), PpuFetch::Dummy),
+                    _ => {},
                 }
                 self.oam_addr = 0;
             },
@@ -1405,6 +1457,8 @@ impl Ppu {
             // Nothing reached this slot. It still fetches, and tile $FF left by the clear phase is
             // what it fetches — but it can never draw, so it stays out of the drawing list.
             self.sprite_patterns[slot] = self.sprite_pattern_address(tile, attributes, 0);
+            self.announce_fetch(self.sprite_patterns[slot], PpuFetch::Sprite);
+            self.announce_fetch(self.sprite_patterns[slot] + 8, PpuFetch::Sprite);
             return;
         }
 
@@ -1466,8 +1520,12 @@ impl Ppu {
         // Guarded because a bare PPU with no graphics source has nothing to draw — but the address
         // is returned regardless, because the address bus does not depend on there being data.
         let mut tile_data = [0u8; 8];
-        if self.mapper.is_some() || self.cartridge.is_some() {
+        if self.cartridge.is_some() {
+            // Each plane is announced on its own. The second is at a different address, and it is
+            // that one, not the first, that trips MMC2's latch.
+            self.announce_fetch(pattern_address, PpuFetch::Sprite);
             let plane0 = self.read_ppu_memory(pattern_address);
+            self.announce_fetch(pattern_address + 8, PpuFetch::Sprite);
             let plane1 = self.read_ppu_memory(pattern_address + 8);
             for bit in 0..8usize {
                 let value = ((plane0 >> (7 - bit)) & 0x01) | (((plane1 >> (7 - bit)) & 0x01) << 1);
@@ -1566,10 +1624,31 @@ impl Ppu {
         }
     }
 
+    /// Whether pixels come from the per-dot path.
+    ///
+    /// Always, for a mapper that watches the fetches, whatever `per_dot_pixels` says. MMC2 switches
+    /// banks at the tile that trips its latch, and only the per-dot path reads each tile's pattern
+    /// after the fetches before it have moved the latch. The per-line renderer reads a whole line
+    /// from the banks in effect as the line begins, which puts *Punch-Out!!*'s seams a line late,
+    /// and its sprite pass makes a second set of fetches the latch would see as well.
+    fn draws_per_dot(&self) -> bool {
+        self.per_dot_pixels || self.mapper_watches_fetches
+    }
+
+    /// Tell a mapper that watches the PPU's reads which one is about to be made.
+    fn announce_fetch(&self, address: u16, fetch: PpuFetch) {
+        if !self.mapper_watches_fetches {
+            return;
+        }
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().mapper_mut().on_ppu_fetch(address & 0x3FFF, fetch);
+        }
+    }
+
     /// Tell a scanline-counting mapper what address is on the PPU's bus.
     fn notify_mapper_of_address(&self, address: u16) {
-        if let Some(mapper) = &self.mapper {
-            mapper.borrow_mut().on_ppu_address(address & 0x3FFF);
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().mapper_mut().on_ppu_address(address & 0x3FFF);
         }
     }
 
@@ -1596,6 +1675,13 @@ impl Ppu {
     fn advance_background_fetch(&mut self) {
         let fetching = (1..=256).contains(&self.cycle) || (321..=336).contains(&self.cycle);
         if !fetching {
+            // The line ends with two more reads of the nametable byte the next line starts with,
+            // both discarded. Nothing here needs them, but a mapper counting reads does: with the
+            // next line's first fetch they make three of the same address in a row, which is how
+            // MMC5 knows a line has begun.
+            if matches!(self.cycle, 337 | 339) {
+                self.announce_fetch(0x2000 | (self.ppu_addr.get() & 0x0FFF), PpuFetch::Dummy);
+            }
             return;
         }
 
@@ -1610,7 +1696,7 @@ impl Ppu {
         // Pixels come from here only when the per-dot path is switched on; see `emit_pixel` for
         // why it is off. The fetches themselves always run, because the address bus they drive is
         // what clocks the mapper.
-        if self.per_dot_pixels
+        if self.draws_per_dot()
             && (1..=256).contains(&self.cycle)
             && (0..240).contains(&self.scanline)
         {
@@ -1626,11 +1712,13 @@ impl Ppu {
         let v = self.ppu_addr.get();
         match self.cycle % 8 {
             1 => {
+                self.announce_fetch(0x2000 | (v & 0x0FFF), PpuFetch::Nametable);
                 self.fetch.latch_nametable = self.read_ppu_memory(0x2000 | (v & 0x0FFF));
             },
             3 => {
                 let address =
                     0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                self.announce_fetch(address, PpuFetch::Attribute);
                 let attribute = self.read_ppu_memory(address);
 
                 // One attribute byte covers four tiles by four; which two bits apply is decided by
@@ -1645,8 +1733,10 @@ impl Ppu {
                 let base = table + (self.fetch.latch_nametable as u16 * 16) + fine_y;
 
                 if self.cycle % 8 == 5 {
+                    self.announce_fetch(base, PpuFetch::Background);
                     self.fetch.latch_pattern_low = self.read_ppu_memory(base);
                 } else {
+                    self.announce_fetch(base + 8, PpuFetch::Background);
                     self.fetch.latch_pattern_high = self.read_ppu_memory(base + 8);
                 }
             },
@@ -2368,6 +2458,11 @@ impl Ppu {
         // refreshes every bit of the latch — including for the registers that ignore the write.
         self.refresh_io_latch(value, 0xFF);
 
+        // The cartridge is on the same bus and can watch the write go past.
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.borrow_mut().mapper_mut().on_ppu_register_write(0x2000 | (address & 0x7), value);
+        }
+
         match address & 0x7 {
             0x0 => self.write_control(value),
             0x1 => self.write_mask(value),
@@ -2403,7 +2498,7 @@ impl Ppu {
         // of which the ordinary path already does. It suppresses the interrupt for a reason rather
         // than by a rule: the line is down for less than a full CPU cycle, so the CPU's poll never
         // sees it. That used to be a case listed here, with the latch cleared by hand.
-        if self.scanline == 241 && self.cycle == 0 {
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 0 {
             self.suppress_vblank.set(true);
             self.write_toggle.set(false);
             return self.status.get() & !STATUS_VBLANK;
@@ -2692,15 +2787,10 @@ impl Ppu {
 
         match addr {
             0x0000..=0x1FFF => {
-                // Pattern tables. Through the mapper when a cartridge is loaded, so CHR bank
-                // switching is reflected; the Cartridge path remains for assembled test programs.
-                if let Some(mapper) = &self.mapper {
-                    return mapper.borrow().read_chr(addr);
-                }
-
-                if let Some(cart) = &self.cartridge {
-                    // Get the data from the cartridge
-                    cart.read_pattern_table(addr)
+                // Pattern tables. Through the cartridge's mapper, so CHR bank switching is
+                // reflected — an assembled test program's board included.
+                if let Some(cartridge) = &self.cartridge {
+                    cartridge.borrow().read_pattern_table(addr)
                 } else {
                     // Fallback to the temporary implementation if no cartridge is connected
                     // This is useful for tests and development
@@ -2735,8 +2825,8 @@ impl Ppu {
             // Pattern Tables (CHR ROM/RAM)
             0x0000..=0x1FFF => {
                 // Through the mapper, exactly as `read_ppu_memory` reads them. These two had
-                // drifted apart: reads came from the mapper and writes went to the `Cartridge`
-                // that a mapper-loaded ROM does not have, so every write to CHR RAM was dropped
+                // drifted apart: reads came from the mapper and writes went to a pattern table
+                // that a mapper-loaded ROM did not have, so every write to CHR RAM was dropped
                 // and could never be read back.
                 //
                 // Invisible with CHR ROM, where writes are meant to be ignored — which is most of
@@ -2744,10 +2834,8 @@ impl Ppu {
                 // whole point: the pattern tables stayed blank for the entire run. It is why
                 // `sprite_hit_tests` reported "sprite hit isn't working at all". There was nothing
                 // drawn to hit.
-                if let Some(mapper) = &self.mapper {
-                    mapper.borrow_mut().write_chr(addr, value);
-                } else if let Some(cart) = &mut self.cartridge {
-                    cart.write_pattern_table(addr, value);
+                if let Some(cartridge) = &self.cartridge {
+                    cartridge.borrow_mut().write_pattern_table(addr, value);
                 }
             },
 
@@ -2775,35 +2863,23 @@ impl Ppu {
     }
 
     /// Read from nametable memory (including mirrors)
-    fn read_nametable(&self, address: u16) -> u8 {
-        self.vram[self.mirror_nametable(address)]
-    }
-
-    /// Map a nametable address onto one of the two physical tables, honouring the cartridge's
-    /// mirroring.
     ///
-    /// There are four logical nametables but only 2 KB of VRAM, so two pairs always alias. Which
-    /// pair depends on how the cartridge is wired, and getting it wrong sends a scrolling
-    /// background into the wrong screen.
-    fn mirror_nametable(&self, address: u16) -> usize {
-        let offset = (address & 0x0FFF) as usize;
-        let table = offset / 0x0400;
-        let index = offset % 0x0400;
-
-        let physical = match self.mirroring {
-            Mirroring::Horizontal => table / 2,
-            Mirroring::Vertical => table % 2,
-            Mirroring::SingleScreenLower => 0,
-            Mirroring::SingleScreenUpper => 1,
-        };
-
-        physical * 0x0400 + index
+    /// Through the mapper when there is one, because where a nametable lives is the cartridge's
+    /// decision — and not only at load: MMC1 and MMC3 rewire the mirroring whenever a game asks,
+    /// and MMC5 can put a nametable somewhere other than the console's RAM altogether.
+    fn read_nametable(&self, address: u16) -> u8 {
+        match &self.cartridge {
+            Some(cartridge) => cartridge.borrow().mapper().read_nametable(address, &self.vram),
+            None => self.vram[self.mirroring.ciram_offset(address)],
+        }
     }
 
     /// Write to nametable memory (including mirrors)
     fn write_nametable(&mut self, address: u16, value: u8) {
-        let index = self.mirror_nametable(address);
-        self.vram[index] = value;
+        match &self.cartridge {
+            Some(cartridge) => cartridge.borrow_mut().mapper_mut().write_nametable(address, value, &mut self.vram),
+            None => self.vram[self.mirroring.ciram_offset(address)] = value,
+        }
     }
 
     /// Read from palette memory (including mirrors)
@@ -2861,24 +2937,25 @@ impl Ppu {
     }
 
     /// Connect a cartridge to the PPU
-    pub fn connect_cartridge(&mut self, cartridge: Cartridge) {
+    ///
+    /// Connected again whenever what is in the slot changes, even through the same handle: whether
+    /// the mapper watches rendering reads is asked here, once, rather than on every fetch.
+    pub fn connect_cartridge(&mut self, cartridge: CartridgeHandle) {
+        self.mapper_watches_fetches = cartridge.borrow().mapper().watches_ppu_fetches();
         self.cartridge = Some(cartridge);
     }
 
     /// Disconnect the cartridge from the PPU
     pub fn disconnect_cartridge(&mut self) {
         self.cartridge = None;
+        self.mapper_watches_fetches = false;
     }
 
     /// Get the current cartridge if one is connected
-    pub fn cartridge(&self) -> Option<Cartridge> {
+    pub fn cartridge(&self) -> Option<CartridgeHandle> {
         self.cartridge.clone()
     }
 
-    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
-        self.cartridge.as_mut()
-    }
-
     /// Direct test method to write a visible pattern to the frame buffer
     /// This bypasses all PPU rendering logic and directly sets pixels
     pub fn write_test_pattern(&mut self) {
@@ -2978,7 +3055,7 @@ impl Ppu {
         self.write_palette(0x3F13, 0x30); // White
 
         // Create a simple pattern in CHR ROM if we have a cartridge
-        if let Some(cart) = &mut self.cartridge {
+        if let Some(cartridge) = &self.cartridge {
             // Create a simple pattern (solid block)
             let mut pattern_data = vec![0; 0x2000]; // 8KB for pattern tables
 
@@ -2987,7 +3064,7 @@ impl Ppu {
             pattern_data[0..16].fill(0xFF);
 
             // Load the pattern data
-            cart.load_chr_rom(&pattern_data);
+            cartridge.borrow_mut().load_chr_rom(&pattern_data);
         }
 
         // Enable sprite rendering
@@ -3074,7 +3151,7 @@ impl Addressable for Ppu {
         self.frame_count = 0;
         self.scanline = -1;
         self.cycle = 0;
-        self.vram = [0; 2048];
+        self.vram = [0; NAMETABLE_RAM];
         self.palette = [0; 32];
         self.oam = [0; 256];
         self.working_frame = vec![0; 256 * 240 * 3];
@@ -3096,12 +3173,10 @@ mod tests {
     /// which is exactly why games write it early.
     #[test]
     fn a_scroll_change_partway_along_a_line_takes_effect_on_that_line() {
-        let mut ppu = ppu_with_solid_tile();
-
         // $2000 solid, $2400 empty — the two that are distinct memory under vertical mirroring,
         // where $2800 is another view of $2000 and filling it would undo the first. Every row,
         // because the vertical position has advanced by the time the line under test is reached.
-        ppu.mirroring = Mirroring::Vertical;
+        let mut ppu = ppu_with_solid_tile_and_mirroring(Mirroring::Vertical);
         for entry in 0..960u16 {
             ppu.write_ppu_memory(0x2000 + entry, 1);
             ppu.write_ppu_memory(0x2400 + entry, 0);
@@ -3603,13 +3678,13 @@ mod tests {
     /// Both halves were needed: NROM also had to stop keeping the empty vector the header implied.
     #[test]
     fn chr_ram_written_through_2007_reads_back() {
-        use crate::cartridge::create_mapper;
+        use crate::cartridge::{create_mapper, Cartridge, INesHeader};
 
         let mut ppu = Ppu::new();
         // Zero CHR banks in the header is CHR RAM, which is what an empty vector here means.
-        let mapper = create_mapper(0, vec![0; 16 * 1024], Vec::new(), Mirroring::Horizontal)
+        let mapper = create_mapper(&INesHeader::for_mapper(0), vec![0; 16 * 1024], Vec::new())
             .expect("NROM is supported");
-        ppu.mapper = Some(Rc::new(RefCell::new(mapper)));
+        ppu.connect_cartridge(Cartridge::with_mapper(INesHeader::for_mapper(0), mapper).shared());
 
         // Upload two bytes of a tile through $2006/$2007, as a game does at startup.
         ppu.write_register(0x2006, 0x00);
@@ -3935,7 +4010,7 @@ mod tests {
     }
 
     use super::*;
-    use crate::cartridge::Cartridge;
+    use crate::cartridge::{Cartridge, INesHeader, Mapper};
 
     #[test]
     fn test_ppu_init() {
@@ -4116,7 +4191,7 @@ mod tests {
         cart.load_chr_rom(&test_data);
 
         // Connect the cartridge to the PPU
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Test reading from the pattern table
         assert_eq!(ppu.read_ppu_memory(0x0000), 0xFF); // First byte of tile 0
@@ -4179,7 +4254,7 @@ mod tests {
         cart.load_chr_rom(&test_data);
 
         // Connect the cartridge to the PPU
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Verify the pattern data can be read from the PPU
         assert_eq!(ppu.read_ppu_memory(0x0010), 0xFF, "Pattern data not correctly loaded");
@@ -4242,7 +4317,7 @@ mod tests {
         cart.load_chr_rom(&test_data);
 
         // Connect the cartridge to the PPU
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Enable sprites in PPUMASK
         ppu.mask = MASK_SHOW_SPRITES;
@@ -4309,7 +4384,7 @@ mod tests {
         // Create and connect a cartridge with our test pattern
         let mut cartridge = Cartridge::new();
         cartridge.load_chr_rom(&pattern_data);
-        ppu.connect_cartridge(cartridge);
+        ppu.connect_cartridge(cartridge.shared());
 
         // Set up sprite palette 0 with a specific color
         ppu.write_ppu_memory(0x3F10, 0x30); // Set sprite palette 0 color 0 to white
@@ -4414,7 +4489,7 @@ mod tests {
         }
 
         // Connect the cartridge to the PPU
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Set up sprite palette
         ppu.write_palette(0x3F10, 0x30); // Background color (Gray)
@@ -4567,7 +4642,7 @@ mod tests {
         }
 
         // Connect the cartridge to the PPU
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Set up palettes
         // Background palette
@@ -4675,11 +4750,11 @@ mod tests {
         // Create and connect cartridge
         let mut cart = Cartridge::new();
         cart.load_chr_rom(&pattern_data);
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Verify pattern table data
-        let pattern_data_0 = ppu.cartridge().unwrap().read_pattern_table(0);
-        let pattern_data_8 = ppu.cartridge().unwrap().read_pattern_table(8);
+        let pattern_data_0 = ppu.cartridge().unwrap().borrow().read_pattern_table(0);
+        let pattern_data_8 = ppu.cartridge().unwrap().borrow().read_pattern_table(8);
         assert_eq!(pattern_data_0, 0xFF, "Pattern data at 0x0000 should be 0xFF");
         assert_eq!(pattern_data_8, 0xFF, "Pattern data at 0x0008 should be 0xFF");
 
@@ -4764,7 +4839,7 @@ mod tests {
         // Create and connect cartridge
         let mut cart = Cartridge::new();
         cart.load_chr_rom(&pattern_data);
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Set up distinct colors in the sprite palette
         ppu.write_palette(0x3F10, 0x0F); // Universal background (transparent for sprites)
@@ -4886,8 +4961,13 @@ mod tests {
 
     /// Build a PPU whose tile 1 is fully opaque, so overlap is easy to arrange.
     fn ppu_with_solid_tile() -> Ppu {
+        ppu_with_solid_tile_and_mirroring(Mirroring::Horizontal)
+    }
+
+    /// [`ppu_with_solid_tile`], on a board whose nametables are wired as `mirroring`.
+    fn ppu_with_solid_tile_and_mirroring(mirroring: Mirroring) -> Ppu {
         let mut ppu = Ppu::new();
-        let mut cart = Cartridge::new();
+        let mut cart = Cartridge::test_board(mirroring);
 
         // Tile 1: both bit planes set, i.e. every pixel colour 3.
         let mut chr = vec![0u8; 8 * 1024];
@@ -4895,7 +4975,7 @@ mod tests {
             *byte = 0xFF;
         }
         cart.load_chr_rom(&chr);
-        ppu.connect_cartridge(cart);
+        ppu.connect_cartridge(cart.shared());
 
         // Palettes: anything non-zero so pixels are visible.
         ppu.write_palette(0x3F00, 0x0F);
@@ -5147,11 +5227,10 @@ mod tests {
     #[test]
     fn the_two_pixel_paths_agree_on_a_static_scene() {
         let frame_with = |per_dot: bool| {
-            let mut ppu = ppu_with_solid_tile();
+            let mut ppu = ppu_with_solid_tile_and_mirroring(Mirroring::Vertical);
             ppu.per_dot_pixels = per_dot;
 
             // A patterned background, so a displacement of even one tile shows up.
-            ppu.mirroring = Mirroring::Vertical;
             for entry in 0..960u16 {
                 ppu.write_ppu_memory(0x2000 + entry, u8::from(entry % 3 == 0));
             }
@@ -5447,7 +5526,7 @@ mod tests {
             rises: rises.clone(),
             at: at.clone(),
         });
-        ppu.mapper = Some(Rc::new(RefCell::new(mapper)));
+        ppu.connect_cartridge(Cartridge::with_mapper(INesHeader::for_mapper(4), mapper).shared());
 
         // A sprite on every line, so the sprite fetches have real addresses rather than the
         // tile-$FF ones an empty slot uses. Either would rise; using a real one proves the
@@ -5558,7 +5637,7 @@ mod tests {
                 rises: rises.clone(),
                 at: Rc::new(Cell::new((0, 0))),
             });
-            ppu.mapper = Some(Rc::new(RefCell::new(mapper)));
+            ppu.connect_cartridge(Cartridge::with_mapper(INesHeader::for_mapper(4), mapper).shared());
 
             // $0FFF: bit 12 clear, and one below the address that sets it.
             ppu.write_register(0x2006, 0x0F);
@@ -5576,6 +5655,102 @@ mod tests {
         }
     }
 
+    /// MMC2's latch flips partway along a line, at the fetch after the trigger tile's second
+    /// plane, and not at a line boundary.
+    ///
+    /// *Punch-Out!!* depends on this for the boxer: a `$FD` tile in the nametable changes the bank
+    /// for every tile to its right. A model that only looked at the latch once a line would move
+    /// that seam to the start of the next line.
+    #[test]
+    fn mmc2_latch_flips_at_the_fetch_after_the_trigger_tile() {
+        use crate::cartridge::{create_mapper, INesHeader};
+
+        let mut ppu = Ppu::new();
+        // Each 4 KB CHR bank filled with its own number, so a read names the bank it came from.
+        let chr = (0..32u8).flat_map(|bank| vec![bank; 4 * 1024]).collect();
+        let mapper = create_mapper(&INesHeader::for_mapper(9), vec![0; 128 * 1024], chr)
+            .expect("MMC2 is supported");
+        ppu.connect_cartridge(Cartridge::with_mapper(INesHeader::for_mapper(9), mapper).shared());
+        let cartridge = ppu.cartridge().unwrap();
+        // The $1000 window: bank 3 for $FD, bank 4 for $FE, which it powers on at.
+        cartridge.borrow_mut().mapper_mut().write_prg(0xD000, 3);
+        cartridge.borrow_mut().mapper_mut().write_prg(0xE000, 4);
+
+        // Background from $1000, with tile $FD at column 10 of the top row and $FE at column 20,
+        // which puts the latch back for every line after.
+        ppu.write_ppu_memory(0x200A, 0xFD);
+        ppu.write_ppu_memory(0x2014, 0xFE);
+        ppu.ctrl = CTRL_BACKGROUND_PATTERN;
+        ppu.mask = MASK_SHOW_BACKGROUND;
+        let pre_render = ppu.region.pre_render_scanline();
+        run_to(&mut ppu, pre_render, 0);
+        run_to(&mut ppu, 0, 0);
+
+        let mut bank = cartridge.borrow().read_pattern_table(0x1000);
+        assert_eq!(bank, 4, "the line starts at $FE");
+        let mut flips = Vec::new();
+        while ppu.scanline == 0 {
+            ppu.tick();
+            let now = cartridge.borrow().read_pattern_table(0x1000);
+            if now != bank {
+                flips.push((ppu.cycle, now));
+                bank = now;
+            }
+        }
+
+        // Columns 0 and 1 were fetched on the line before, so column 10's group starts at dot 65
+        // and reads its second plane on dot 71; the nametable fetch on dot 73 is the next one.
+        // Column 20 is eighty dots further on.
+        assert_eq!(flips, [(73, 3), (153, 4)]);
+    }
+
+    /// And the picture follows the latch: the tiles either side of a trigger come from different
+    /// banks on the same line.
+    ///
+    /// Asked of the per-line path on purpose. That path reads a whole line from the banks in effect
+    /// as it begins, so it drew the seam a line late, and a board like this is now drawn per dot
+    /// whichever path is chosen.
+    #[test]
+    fn mmc2_draws_the_tiles_after_a_trigger_from_the_other_bank() {
+        use crate::cartridge::{create_mapper, INesHeader};
+
+        let mut ppu = Ppu::new();
+        // Bank 3 draws every pixel in colour 3, bank 4 in colour 1: both planes set, or the first.
+        let mut chr = vec![0u8; 32 * 4 * 1024];
+        for (offset, byte) in chr.iter_mut().enumerate().skip(3 * 4 * 1024).take(2 * 4 * 1024) {
+            let bank = offset / (4 * 1024);
+            *byte = if bank == 3 || offset & 0x08 == 0 { 0xFF } else { 0x00 };
+        }
+        let mapper = create_mapper(&INesHeader::for_mapper(9), vec![0; 128 * 1024], chr)
+            .expect("MMC2 is supported");
+        ppu.connect_cartridge(Cartridge::with_mapper(INesHeader::for_mapper(9), mapper).shared());
+        let cartridge = ppu.cartridge().unwrap();
+        cartridge.borrow_mut().mapper_mut().write_prg(0xD000, 3);
+        cartridge.borrow_mut().mapper_mut().write_prg(0xE000, 4);
+
+        // The same row as above: `$FD` at column 10, `$FE` at column 20, tile 0 everywhere else.
+        ppu.write_ppu_memory(0x200A, 0xFD);
+        ppu.write_ppu_memory(0x2014, 0xFE);
+        ppu.ctrl = CTRL_BACKGROUND_PATTERN;
+        ppu.mask = MASK_SHOW_BACKGROUND | MASK_SHOW_LEFT_BACKGROUND;
+        ppu.per_dot_pixels = false;
+        run_to(&mut ppu, 5, 0);
+
+        // Line 4, away from the frame's first line. The trigger tiles themselves are skipped: each
+        // is drawn from the bank in effect before it.
+        let colour_of_column = |column: usize| {
+            let pixels = &ppu.background_pixels[4 * 256 + column * 8..4 * 256 + column * 8 + 8];
+            assert!(pixels.iter().all(|&p| p == pixels[0]), "column {column} is not one colour: {pixels:?}");
+            pixels[0]
+        };
+        for column in (0..10).chain(21..32) {
+            assert_eq!(colour_of_column(column), 1, "column {column} should come from the $FE bank");
+        }
+        for column in 11..20 {
+            assert_eq!(colour_of_column(column), 3, "column {column} should come from the $FD bank");
+        }
+    }
+
     #[test]
     fn greyscale_mode_forces_colours_onto_the_grey_column() {
         let mut ppu = Ppu::new();
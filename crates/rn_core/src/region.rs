@@ -1,4 +1,4 @@
-//! Which console the cartridge expects: an American NES or a European one.
+//! Which console the cartridge expects: an American NES, a European one, or a Dendy.
 //!
 //! Not a cosmetic difference. The two machines run the *same* CPU against *differently* clocked
 //! video, so every piece of timing a game counts in CPU cycles lands somewhere else on the screen:
@@ -15,6 +15,12 @@
 //! `super-mario-3-eu.nes`, its handler ends in a delay loop the developers tuned for 3.2 dots to a
 //! cycle, and running it at 3.0 puts the whole burst 41 dots early — out of hblank and into the
 //! visible line, where the CPU's genuine interrupt latency becomes something you can see.
+//!
+//! The Dendy — the Famicom clone sold across the former Soviet Union — is a third answer, and the
+//! one that catches out anyone who assumes it is "PAL". Its frame is PAL's 312 lines, but its CPU
+//! is divided down so that each cycle is exactly three dots, as on NTSC, and vblank does not start
+//! until line 291. Its APU is an NTSC 2A03's, rate tables and all. Games written for it count on
+//! every one of those, and it is only ever identified by an NES 2.0 header's timing field.
 
 use serde::{Deserialize, Serialize};
 
@@ -23,6 +29,7 @@ pub enum Region {
     #[default]
     Ntsc,
     Pal,
+    Dendy,
 }
 
 impl Region {
@@ -33,7 +40,7 @@ impl Region {
     /// dot and a game counting cycles can see it.
     pub const fn dots_per_cycle(self) -> (u32, u32) {
         match self {
-            Region::Ntsc => (3, 1),
+            Region::Ntsc | Region::Dendy => (3, 1),
             Region::Pal => (16, 5),
         }
     }
@@ -42,7 +49,19 @@ impl Region {
     pub const fn pre_render_scanline(self) -> i16 {
         match self {
             Region::Ntsc => 261,
-            Region::Pal => 311,
+            Region::Pal | Region::Dendy => 311,
+        }
+    }
+
+    /// The line whose second dot raises the vblank flag.
+    ///
+    /// 241 on both NTSC and PAL — PAL's extra lines all come after it. The Dendy puts fifty of its
+    /// own *before* it instead, so that a game's vblank handler gets NTSC's amount of time with
+    /// PAL's frame around it.
+    pub const fn vblank_scanline(self) -> i16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
         }
     }
 
@@ -54,6 +73,19 @@ impl Region {
         matches!(self, Region::Ntsc)
     }
 
+    /// CPU cycles per second: the master clock divided by twelve, sixteen or fifteen.
+    ///
+    /// Nothing inside the machine needs this, since everything there counts cycles. It is for the
+    /// edges where cycles meet seconds — turning a cycle's sound into samples for the sound card,
+    /// or a music file's play rate in microseconds into a number of cycles.
+    pub const fn cpu_clock_rate(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
     /// What the iNES header claims, which is worth reading and worth not trusting.
     ///
     /// Byte 9 bit 0 is the TV system, and plenty of European releases ship with it clear —
@@ -137,6 +169,18 @@ mod tests {
         assert!(!Region::Pal.skips_a_dot_on_odd_frames());
     }
 
+    /// A Dendy frame is PAL's length at NTSC's dot rate, with vblank pushed fifty lines later.
+    #[test]
+    fn dendy_is_pal_framing_at_ntsc_speed() {
+        assert_eq!(Region::Dendy.dots_per_cycle(), (3, 1));
+        assert_eq!(Region::Dendy.pre_render_scanline(), 311);
+        assert!(!Region::Dendy.skips_a_dot_on_odd_frames());
+        assert_eq!(Region::Dendy.vblank_scanline(), 291);
+        assert_eq!(Region::Pal.vblank_scanline(), 241);
+        assert!(Region::Dendy.cpu_clock_rate() > Region::Pal.cpu_clock_rate());
+        assert!(Region::Dendy.cpu_clock_rate() < Region::Ntsc.cpu_clock_rate());
+    }
+
     /// The header is read, and the default when it says nothing is NTSC.
     #[test]
     fn the_header_is_a_starting_point() {
@@ -113,6 +113,17 @@ impl Bus {
         self.open_bus_accesses.get()
     }
 
+    /// Store a byte from outside the machine, as a cheat device does.
+    ///
+    /// Unlike [`write_byte`](Addressable::write_byte) this leaves the open bus alone: no CPU cycle
+    /// put the value on the lines, so a later undriven read must not see it.
+    pub fn poke_byte(&mut self, address: u16, value: u8) -> Result<(), NesError> {
+        match self.find_component_for_address_mut(address) {
+            Some(component) => component.write_byte(address, value),
+            None => Ok(()),
+        }
+    }
+
     /// Returns a debugging string showing all attached components and their address ranges
     pub fn debug_memory_map(&self) -> String {
         let mut result = String::new();
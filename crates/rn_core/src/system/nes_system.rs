@@ -1,4 +1,8 @@
-use std::{cell::Cell, cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use log::{debug, error, info, warn};
 
@@ -6,43 +10,85 @@ use super::{dma::DmaControllerWrapper, DmaController};
 use crate::{
     apu::{Apu, ApuWrapper},
     audio::SampleProducer,
-    cartridge::{create_mapper, mapper_name, supported_mappers, Cartridge, Mapper, Mirroring, Rom},
+    cartridge::{
+        create_mapper, mapper_name, supported_mappers, Cartridge, CartridgeHandle, Fds, FdsDisk, HeaderFormat,
+        INesHeader, Mapper, Mmc3, Mmc3Variant, Nsf, NsfPlayer, Rom, VrcVariant,
+    },
+    cheats::{self, CheatList},
     cpu::{ClockPhase, Cpu, CpuRegisters, CpuWrapper, DmaHalt},
     errors::NesError,
     input::{ControllerHandlerWrapper, ControllerState},
-    memory::{Addressable, Ram},
+    memory::Addressable,
     ppu::{Ppu, PpuState, PpuWrapper},
     system::Bus,
 };
 
-/// Cartridge space on the bus, backed by the ROM's mapper.
+/// Cartridge space on the bus, backed by the cartridge in the slot.
 ///
-/// Replaces the RAM that used to stand in for `$8000..=$FFFF`. Writes there are not discarded
+/// Replaces the RAM that used to stand in for `$6000..=$FFFF`. Writes there are not discarded
 /// stores to read-only memory — they are how a game drives its mapper, so they must reach it.
 #[derive(Debug)]
 struct CartridgeSpace {
-    mapper: MapperHandle,
+    cartridge: CartridgeHandle,
+    /// Game Genie codes sit between the cartridge and the CPU, so they see every read of it.
+    cheats: Rc<RefCell<CheatList>>,
 }
 
-/// A mapper, shared between the parts of the system that reach it.
-type MapperHandle = Rc<RefCell<Box<dyn Mapper>>>;
+/// What the cartridge wants from each CPU cycle, asked once when it is connected. See
+/// [`Mapper::counts_cpu_cycles`].
+#[derive(Debug, Clone, Copy, Default)]
+struct CartridgeClock {
+    /// Call [`Mapper::cpu_cycle`].
+    cycles: bool,
+    /// Collect [`Mapper::audio_output`] for the APU.
+    sound: bool,
+}
 
-/// Somewhere to put a mapper once a ROM supplies one, shareable before that happens.
-type MapperSlot = Rc<RefCell<Option<MapperHandle>>>;
+/// Run the cartridge's share of a CPU cycle: advance whatever on it counts cycles, and pass its
+/// sound to the APU before the cycle's sample is taken.
+///
+/// A board with neither is not even borrowed, which is most of them; this runs for every cycle.
+fn clock_cartridge(cartridge: &CartridgeHandle, clock: CartridgeClock, apu: &ApuWrapper) {
+    if !clock.cycles && !clock.sound {
+        return;
+    }
+    let mut cartridge = cartridge.borrow_mut();
+    let mapper = cartridge.mapper_mut();
+    if clock.cycles {
+        mapper.cpu_cycle();
+    }
+    if clock.sound {
+        apu.set_expansion_audio(mapper.audio_output());
+    }
+}
 
 impl Addressable for CartridgeSpace {
+    // Below `$6000` the cartridge answers only where its mapper says it does. Most leave
+    // `$4020..=$5FFF` unmapped, but one with registers down there has to see those accesses.
     fn handles_address(&self, address: u16) -> bool {
-        address >= 0x8000
+        self.cartridge.borrow().maps_cpu_address(address, false)
+    }
+
+    fn handles_write(&self, address: u16) -> bool {
+        self.cartridge.borrow().maps_cpu_address(address, true)
     }
 
     fn read_byte(&self, address: u16) -> Result<u8, NesError> {
-        Ok(self.mapper.borrow().read_prg(address))
+        let value = self.cartridge.borrow().read_cpu(address);
+        if address < 0x8000 {
+            return Ok(value);
+        }
+        Ok(self.cheats.borrow().patch_read(address, value))
     }
 
     fn write_byte(&mut self, address: u16, value: u8) -> Result<(), NesError> {
-        self.mapper.borrow_mut().write_prg(address, value);
+        self.cartridge.borrow_mut().write_cpu(address, value);
         Ok(())
     }
+
+    fn open_bus_mask(&self, address: u16) -> u8 {
+        self.cartridge.borrow().open_bus_mask(address)
+    }
 }
 
 /// The possible states of the NES system
@@ -100,6 +146,9 @@ pub struct NesSystem {
     /// The get/put half of the APU's divider, mirrored for the DMA. See `ApuWrapper::is_odd_cycle`.
     odd_cycle: Rc<Cell<bool>>,
 
+    /// What the loaded cartridge needs from each CPU cycle, shared with the CPU's clock closure.
+    cartridge_clock: Rc<Cell<CartridgeClock>>,
+
     /// Set when a sprite DMA ends with a DMC fetch still pending — one that came due in the
     /// transfer's final pair, too late to take a slot inside it. The stall that then serves it is
     /// two cycles short of a cold one, because the transfer's cycles already stood in for the
@@ -118,13 +167,14 @@ pub struct NesSystem {
     /// Error message if in Error state
     error_message: Option<String>,
 
-    /// The loaded cartridge's mapper, shared with the bus component that serves it.
-    /// The cartridge's mapper, in a slot rather than an `Option` field.
+    /// The cartridge in the slot, shared with the bus component that serves it, the PPU and the
+    /// APU.
     ///
-    /// A ROM arrives long after the system is built, so anything wanting the mapper cannot capture
-    /// it at construction — it has to hold the slot and look inside when it runs. The clock that
-    /// advances the system on each bus access is exactly such a thing.
-    mapper: MapperSlot,
+    /// There is always one: a bare system has a [`TestBoard`](crate::cartridge::TestBoard) in
+    /// the slot for hand-assembled programs. A ROM replaces what is behind the handle rather than
+    /// the handle itself, so everything that captured it at construction — the clock that advances
+    /// the system on each bus access is one — reaches the new board as soon as it is in.
+    cartridge: CartridgeHandle,
 
     /// The memory bus, retained so a cartridge can be attached after construction.
     bus: Rc<RefCell<Bus>>,
@@ -151,8 +201,49 @@ pub struct NesSystem {
     /// recorded as hangs for exactly that reason — the machine had stopped and the program counter
     /// sat on the `BRK` for the rest of the run.
     halt_on_brk: bool,
+
+    /// Never write a `.sav`, only read one. Kept here as well as on the file because it is chosen
+    /// before a ROM is loaded — by a command-line flag — and has to apply to the file made then.
+    battery_read_only: bool,
+
+    /// The VRC2/VRC4 board to build for mappers 21, 22, 23 and 25, whatever the header says.
+    /// Chosen before a ROM is loaded, like `battery_read_only`.
+    vrc_variant: Option<VrcVariant>,
+
+    /// The MMC3 chip or board to build for mappers 4, 118 and 119, whatever the header says.
+    /// Chosen before a ROM is loaded, like `vrc_variant`.
+    mmc3_variant: Option<Mmc3Variant>,
+
+    /// Bus conflicts on or off for every board, rather than as each board has them. Chosen before
+    /// a ROM is loaded, like `vrc_variant`.
+    bus_conflicts: Option<bool>,
+
+    /// The Disk System's BIOS, for disk images to boot through. The user's to supply, so chosen
+    /// before a disk is loaded, like `vrc_variant`.
+    fds_bios: Option<Vec<u8>>,
+
+    /// CPU cycles run since the battery was last flushed, for flushing periodically.
+    cycles_since_battery_flush: u64,
+
+    /// The cheat codes for the loaded ROM. Shared with cartridge space, which applies the Game
+    /// Genie ones to its reads; the RAM freezes are applied here, once a frame.
+    cheats: Rc<RefCell<CheatList>>,
+
+    /// Where the cheat list is kept, beside the ROM. `None` for anything not loaded from a file.
+    cheat_file: Option<PathBuf>,
+
+    /// The PPU frame the RAM freezes were last applied in.
+    cheat_frame: u64,
 }
 
+/// How often battery RAM is written out while a game runs: five seconds of emulated time.
+///
+/// A safety net rather than the main mechanism — unloading and exiting both flush — for the
+/// crash, the killed process and the laptop that runs out of charge. Counted in emulated cycles
+/// rather than wall time so a headless run that is faster than real time flushes on the same
+/// schedule as one that is not. Nothing is written unless the RAM has changed.
+const BATTERY_FLUSH_CYCLES: u64 = 5 * 1_789_773;
+
 /// A complete machine state, enough to resume exactly where it was left.
 ///
 /// What is *not* here is as deliberate as what is. The cartridge ROM is omitted because it cannot
@@ -240,12 +331,7 @@ impl NesSystem {
             prg_ram: read_range(0x6000, 0x2000),
             ppu: self.ppu.save_state(),
             apu: Some(self.apu.save_state()),
-            mapper: self
-                .mapper
-                .borrow()
-                .as_ref()
-                .map(|mapper| mapper.borrow().save_state())
-                .unwrap_or_default(),
+            mapper: self.cartridge.borrow().save_state(),
         }
     }
 
@@ -277,9 +363,7 @@ impl NesSystem {
             self.apu.load_state(apu);
         }
 
-        if let Some(mapper) = self.mapper.borrow().as_ref() {
-            mapper.borrow_mut().load_state(&state.mapper);
-        }
+        self.cartridge.borrow_mut().load_state(&state.mapper);
 
         self.state = SystemState::Running;
         Ok(())
@@ -292,21 +376,20 @@ impl NesSystem {
         // Create a PPU instance with RefCell for sharing
         let ppu = PpuWrapper::new(Ppu::new());
 
-        // Create and connect a cartridge to the PPU
-        ppu.connect_cartridge(Cartridge::new());
-
         // Create an APU instance
         let apu = ApuWrapper::new(Apu::new());
 
-        // Add ROM mapping for program memory (0x8000-0xFFFF)
-        let rom = Box::new(Ram::with_range(0x8000, 0xFFFF));
-
-        // Cartridge PRG-RAM (often battery-backed "save RAM") at $6000-$7FFF.
+        // The board hand-assembled programs run on, until a ROM takes its place: RAM in cartridge
+        // space, so a program can be poked into `$8000`, and RAM for its tiles.
         //
-        // Games use it for saves, but it also carries the protocol every blargg test ROM reports
-        // through: a status byte at $6000 and a message at $6004. Leaving it unmapped meant those
-        // ROMs could not communicate a result at all.
-        let prg_ram = Box::new(Ram::with_range(0x6000, 0x7FFF));
+        // Its work RAM at $6000-$7FFF is there for every cartridge, and games use it for saves,
+        // but it also carries the protocol every blargg test ROM reports through: a status byte at
+        // $6000 and a message at $6004. Leaving it unmapped meant those ROMs could not communicate
+        // a result at all.
+        let cartridge = Cartridge::new().shared();
+        ppu.connect_cartridge(cartridge.clone());
+        apu.connect_cartridge(cartridge.clone());
+        let cheats = Rc::new(RefCell::new(CheatList::new()));
 
         // Create the CPU with its bus
         let cpu = CpuWrapper::new(Cpu::new());
@@ -326,8 +409,10 @@ impl NesSystem {
             let mut bus = bus.borrow_mut();
             bus.attach_component(Box::new(ppu.clone()));
             bus.attach_component(Box::new(apu.clone()));
-            bus.attach_component(prg_ram);
-            bus.attach_component(rom);
+            bus.attach_component(Box::new(CartridgeSpace {
+                cartridge: cartridge.clone(),
+                cheats: cheats.clone(),
+            }));
             bus.attach_component(Box::new(dma.clone()));
             bus.attach_component(Box::new(controller_handler.clone()));
 
@@ -342,7 +427,7 @@ impl NesSystem {
         dma.connect_ppu(ppu.clone());
         cpu.connect_memory(bus.clone());
 
-        let mapper: MapperSlot = Rc::new(RefCell::new(None));
+        let cartridge_clock = Rc::new(Cell::new(CartridgeClock::default()));
 
         // Whether the CPU cycle now running is an odd one. Maintained here rather than read from
         // the CPU, because the sprite DMA needs it during a write — at which point the CPU is
@@ -364,8 +449,8 @@ impl NesSystem {
         // of an instruction's bus accesses sees the rest of the system where it actually stands.
         //
         // It captures shared handles only, never the CPU, because the CPU is borrowed while this
-        // runs — interrupts reach it through the shared lines instead. The mapper comes from the
-        // slot rather than being captured, since no ROM has been loaded yet.
+        // runs — interrupts reach it through the shared lines instead. The cartridge it captures is
+        // the handle, whose contents change when a ROM is loaded.
         #[cfg(test)]
         let forced_irq: Rc<Cell<Option<u64>>> = Rc::new(Cell::new(None));
 
@@ -373,7 +458,8 @@ impl NesSystem {
             let ppu = ppu.clone();
             let apu = apu.clone();
             let apu_for_dmc = apu.clone();
-            let mapper_slot = Rc::clone(&mapper);
+            let cartridge = Rc::clone(&cartridge);
+            let cartridge_clock = Rc::clone(&cartridge_clock);
             let lines = interrupts.clone();
             let odd_cycle = Rc::clone(&odd_cycle);
             let clock_dots = Rc::clone(&clock_dots);
@@ -398,6 +484,10 @@ impl NesSystem {
                 }
 
                 if phase == ClockPhase::BeforeAccess {
+                    // A mapper with a clock or a sound chip of its own runs off the CPU's clock
+                    // too, and its output joins the APU's before this cycle's sample is taken.
+                    clock_cartridge(&cartridge, cartridge_clock.get(), &apu);
+
                     // The APU is advanced *before* the access, not after it, so a read of `$4015`
                     // sees the state of the cycle it happens in rather than the one before.
                     //
@@ -423,10 +513,7 @@ impl NesSystem {
 
                 // A scanline-counting mapper is clocked by the PPU itself, from bit 12 of the
                 // address bus, so there is nothing to forward here — only its IRQ line to read.
-                let mut mapper_irq = false;
-                if let Some(mapper) = mapper_slot.borrow().as_ref() {
-                    mapper_irq = mapper.borrow().irq_pending();
-                }
+                let mapper_irq = cartridge.borrow().mapper().irq_pending();
 
                 #[cfg(test)]
                 let forced = tick_forced_irq(&forced_irq);
@@ -508,18 +595,28 @@ impl NesSystem {
             ppu,
             apu,
             odd_cycle,
+            cartridge_clock,
             clock_dots: Rc::clone(&clock_dots),
             dmc_tail_fetch: dmc_tail_fetch_shared,
             dma,
             controller_handler,
             state: SystemState::Ready,
             error_message: None,
-            mapper,
+            cartridge,
             bus,
             #[cfg(test)]
             forced_irq,
             // A bare system is driven by the debugger, which assembles snippets that end in BRK.
             halt_on_brk: true,
+            battery_read_only: false,
+            vrc_variant: None,
+            mmc3_variant: None,
+            bus_conflicts: None,
+            fds_bios: None,
+            cycles_since_battery_flush: 0,
+            cheats,
+            cheat_file: None,
+            cheat_frame: 0,
         }
     }
 
@@ -542,6 +639,11 @@ impl NesSystem {
         self.apu.clone()
     }
 
+    /// The cartridge in the slot: its header, its board and where it came from.
+    pub fn cartridge(&self) -> Ref<'_, Cartridge> {
+        self.cartridge.borrow()
+    }
+
     /// Point the whole machine at a console: NTSC or PAL.
     ///
     /// Both the PPU's frame shape and the rate the CPU's cycles are converted into dots have to
@@ -637,6 +739,7 @@ impl NesSystem {
         for _ in 0..dots {
             self.ppu.tick();
         }
+        clock_cartridge(&self.cartridge, self.cartridge_clock.get(), &self.apu);
         self.apu.tick();
         self.odd_cycle.set(self.apu.is_odd_cycle());
 
@@ -647,11 +750,7 @@ impl NesSystem {
 
         // IRQ is level-triggered and shared: the APU's frame counter and the cartridge's mapper
         // can each hold it, and the CPU sees only the combination.
-        let mapper_irq = self
-            .mapper
-            .borrow()
-            .as_ref()
-            .is_some_and(|mapper| mapper.borrow().irq_pending());
+        let mapper_irq = self.cartridge.borrow().mapper().irq_pending();
         #[cfg(test)]
         let forced = tick_forced_irq(&self.forced_irq);
         #[cfg(not(test))]
@@ -678,30 +777,69 @@ impl NesSystem {
         // behind it, not a program saying it has finished.
         self.halt_on_brk = false;
 
-        let mirroring = if rom.header.mirroring {
-            Mirroring::Vertical
-        } else {
-            Mirroring::Horizontal
-        };
+        // Whatever was in the slot before is being unplugged, and its save with it. A save that
+        // cannot be written is reported rather than returned: it says nothing about the cartridge
+        // going in, and refusing that one would leave no way to load anything at all.
+        if let Err(error) = self.flush_battery() {
+            error!("{error}");
+        }
 
-        // An unsupported mapper is reported rather than approximated: running a game with the
-        // wrong banking produces confusing nonsense instead of an obvious failure.
-        let mapper = create_mapper(rom.header.mapper, rom.prg_rom.clone(), rom.chr_rom.clone(), mirroring)
-            .ok_or_else(|| NesError::UnsupportedMapper(rom.header.mapper, supported_mappers()))?;
+        // A forced VRC board stands in for the header's mapper and submapper, which is the only
+        // thing telling the boards apart.
+        let mut header = rom.header.clone();
+        if let Some(variant) = self.vrc_variant.filter(|_| VrcVariant::is_vrc_mapper(header.mapper)) {
+            header.mapper = variant.mapper();
+            header.submapper = variant.submapper();
+        }
+
+        // A forced MMC3 variant is built directly, since for MMC6 and TQROM the header's memory
+        // sizes can decide as much as its numbers do.
+        let mmc3_variant = self.mmc3_variant.filter(|_| Mmc3Variant::is_mmc3_mapper(header.mapper));
 
-        let mapper = Rc::new(RefCell::new(mapper));
-        *self.mapper.borrow_mut() = Some(mapper.clone());
+        // A disk image is not a board at all, and goes to the Disk System, which has no use
+        // without its BIOS.
+        let mut mapper: Box<dyn Mapper> = if header.format == HeaderFormat::Fds {
+            let bios = self.fds_bios.clone().ok_or(NesError::MissingFdsBios)?;
+            Box::new(Fds::new(bios, FdsDisk::new(&rom.prg_rom)))
+        } else {
+            match mmc3_variant {
+                Some(variant) => Box::new(Mmc3::from_header(
+                    &header,
+                    rom.prg_rom.clone(),
+                    rom.chr_rom.clone(),
+                    Some(variant),
+                )),
+                // An unsupported mapper is reported rather than approximated: running a game with
+                // the wrong banking produces confusing nonsense instead of an obvious failure.
+                None => create_mapper(&header, rom.prg_rom.clone(), rom.chr_rom.clone())
+                    .ok_or_else(|| NesError::UnsupportedMapper(rom.header.mapper, supported_mappers()))?,
+            }
+        };
+        if let Some(enabled) = self.bus_conflicts {
+            mapper.set_bus_conflicts(enabled);
+        }
 
-        // Serve cartridge space from the mapper. Attached first so it takes precedence over the
-        // RAM region that previously stood in for it.
-        self.bus
-            .borrow_mut()
-            .attach_component_first(Box::new(CartridgeSpace { mapper: mapper.clone() }));
+        // An NES 2.0 header's timing was filled in on purpose by whoever wrote it, unlike iNES's
+        // one bit, which is clear on plenty of PAL cartridges — so only the newer format is taken
+        // at its word, along with a region the cartridge database put right. An iNES file stays on
+        // whatever region the machine was already set to.
+        let region_corrected = rom.correction.as_ref().is_some_and(|correction| correction.changed.region.is_some());
+        if rom.header.is_nes2() || region_corrected {
+            self.set_region(rom.header.region);
+        }
+        if let Some(correction) = &rom.correction {
+            info!("Header corrected from the cartridge database: {correction}");
+        }
 
-        self.ppu.connect_mapper(mapper.clone());
-        self.ppu.set_mirroring(mapper.borrow().mirroring());
+        let mut cartridge = Cartridge::from_rom(rom, header, mapper);
+        cartridge.restore_battery(self.battery_read_only)?;
+        self.plug_in(cartridge);
+        self.load_cheats(rom.path.as_deref());
 
-        let reset = u16::from_le_bytes([mapper.borrow().read_prg(0xFFFC), mapper.borrow().read_prg(0xFFFD)]);
+        let reset = {
+            let cartridge = self.cartridge.borrow();
+            u16::from_le_bytes([cartridge.read_cpu(0xFFFC), cartridge.read_cpu(0xFFFD)])
+        };
         self.cpu.set_pc(reset);
 
         self.settle_after_reset();
@@ -711,17 +849,245 @@ impl NesSystem {
         debug!("System state transition: {:?} -> {:?}", old_state, self.state);
         self.error_message = None;
         info!(
-            "ROM loaded: {} KB PRG, {} KB CHR, mapper {} ({}), reset vector ${:04X}",
+            "ROM loaded: {} KB PRG, {} KB CHR, mapper {}.{} ({}), {:?} header, {:?}, reset vector ${:04X}",
             rom.prg_rom.len() / 1024,
             rom.chr_rom.len() / 1024,
             rom.header.mapper,
-            mapper_name(rom.header.mapper).unwrap_or("unknown"),
+            rom.header.submapper,
+            mapper_name(rom.header.mapper)
+                .or((rom.header.format == HeaderFormat::Fds).then_some("FDS"))
+                .unwrap_or("unknown"),
+            rom.header.format,
+            self.region(),
             reset
         );
 
         Ok(())
     }
 
+    /// Put `cartridge` in the slot and tell everything that reaches it.
+    ///
+    /// The slot keeps one handle from construction on and only its contents change, so the bus,
+    /// the PPU and the APU reach the new board as soon as it is in. They are told all the same,
+    /// because each asks the board something once, when it is connected, rather than every cycle.
+    /// An NSF player reloads on every change of track.
+    fn plug_in(&mut self, cartridge: Cartridge) {
+        *self.cartridge.borrow_mut() = cartridge;
+        self.cycles_since_battery_flush = 0;
+
+        self.ppu.connect_cartridge(self.cartridge.clone());
+        self.apu.connect_cartridge(self.cartridge.clone());
+        let cartridge = self.cartridge.borrow();
+        self.cartridge_clock.set(CartridgeClock {
+            cycles: cartridge.mapper().counts_cpu_cycles(),
+            sound: cartridge.mapper().audio_chip().is_some(),
+        });
+    }
+
+    /// Build a player around an NSF file and start `track` on it, counting from zero.
+    ///
+    /// The machine is reset each time, as a hardware player's owner pressed reset between tracks:
+    /// the driver then clears RAM and the APU and calls the file's init routine from scratch. A
+    /// file written for one console switches the machine to that console, and one that plays on
+    /// either leaves it where it was, so a PAL rendering of a dual-region file is asked for with
+    /// [`set_region`](Self::set_region) beforehand.
+    pub fn load_nsf(&mut self, nsf: &Nsf, track: u8) -> Result<(), NesError> {
+        self.halt_on_brk = false;
+        // As in `load_rom`: the previous game's save failing to write does not stop this load.
+        if let Err(error) = self.flush_battery() {
+            error!("{error}");
+        }
+        if let Some(region) = nsf.region {
+            self.set_region(region);
+        }
+
+        self.load_cheats(None);
+
+        let player = NsfPlayer::new(nsf, track, self.region());
+        let track = player.track();
+        // Not a cartridge format, so there is no header to keep — only one saying nothing.
+        self.plug_in(Cartridge::with_mapper(INesHeader::default(), Box::new(player)));
+        self.reset()?;
+
+        self.state = SystemState::Loaded;
+        info!(
+            "NSF loaded: \"{}\" track {} of {}, {} KB, chips {:?}, {:?}",
+            nsf.title,
+            track + 1,
+            nsf.songs,
+            nsf.data.len() / 1024,
+            nsf.chips,
+            self.region()
+        );
+        Ok(())
+    }
+
+    /// Write battery-backed RAM to its `.sav` file, if the cartridge has one and it has changed.
+    ///
+    /// Called on unload, on exit and every few seconds of running; safe to call at any time, as
+    /// often as wanted, since an unchanged RAM writes nothing.
+    pub fn flush_battery(&mut self) -> Result<(), NesError> {
+        self.cycles_since_battery_flush = 0;
+        self.cartridge.borrow_mut().flush_battery()
+    }
+
+    /// Load battery saves without ever writing them back.
+    ///
+    /// For test runs: a save left behind by one run would otherwise change what the next one
+    /// starts from. Applies to the cartridge already loaded and to any loaded afterwards.
+    pub fn set_battery_read_only(&mut self, read_only: bool) {
+        self.battery_read_only = read_only;
+        self.cartridge.borrow_mut().set_battery_read_only(read_only);
+    }
+
+    /// Build VRC2 and VRC4 cartridges as `variant` rather than as their headers say.
+    ///
+    /// For iNES files, which share four mapper numbers between nine boards and so leave the wiring
+    /// to a guess that covers most games but not all. Applies to ROMs loaded afterwards; `None`
+    /// goes back to trusting the header.
+    pub fn set_vrc_variant(&mut self, variant: Option<VrcVariant>) {
+        self.vrc_variant = variant;
+    }
+
+    /// Build MMC3 cartridges as `variant` rather than as their headers say.
+    ///
+    /// For the MMC6 and first-revision MMC3 games an iNES header files as plain mapper 4, which
+    /// run but with the wrong work RAM or a mistimed IRQ. Applies to ROMs loaded afterwards; `None`
+    /// goes back to trusting the header.
+    pub fn set_mmc3_variant(&mut self, variant: Option<Mmc3Variant>) {
+        self.mmc3_variant = variant;
+    }
+
+    /// Emulate bus conflicts, or not, on every board that has a bank latch in ROM space.
+    ///
+    /// Each board otherwise does what the real one did, which is right for the games made for it
+    /// and wrong for the homebrew that was only ever tested on emulators that leave conflicts out.
+    /// `Some(false)` is for those. Applies to ROMs loaded afterwards; `None` goes back to the board.
+    pub fn set_bus_conflicts(&mut self, enabled: Option<bool>) {
+        self.bus_conflicts = enabled;
+    }
+
+    /// Boot disk images through `bios`, the Disk System's 8 KB ROM; see
+    /// [`load_fds_bios`](crate::cartridge::load_fds_bios). Applies to disks loaded afterwards;
+    /// without one, loading a disk fails with [`NesError::MissingFdsBios`].
+    pub fn set_fds_bios(&mut self, bios: Option<Vec<u8>>) {
+        self.fds_bios = bios;
+    }
+
+    /// How many disk sides the loaded image has, or zero for a cartridge.
+    pub fn disk_sides(&self) -> usize {
+        self.cartridge.borrow().mapper().disk_sides()
+    }
+
+    /// The disk side in the drive, or going into it. See [`Mapper::disk_side`].
+    pub fn disk_side(&self) -> Option<usize> {
+        self.cartridge.borrow().mapper().disk_side()
+    }
+
+    /// Eject the disk and insert `side` instead, or leave the drive empty for `None`. Nothing
+    /// happens with a cartridge loaded.
+    pub fn insert_disk(&mut self, side: Option<usize>) {
+        self.cartridge.borrow_mut().mapper_mut().insert_disk(side);
+    }
+
+    /// Turn the disk over, or go on to the next disk after the last side of this one: the side
+    /// after the one in the drive, wrapping round to the first. An empty drive gets the first.
+    pub fn flip_disk_side(&mut self) {
+        let sides = self.disk_sides();
+        if sides > 0 {
+            let next = self.disk_side().map_or(0, |side| (side + 1) % sides);
+            self.insert_disk(Some(next));
+        }
+    }
+
+    /// The loaded cartridge's `.sav` file, or `.flash` for a board that saves into its flash, if
+    /// it has a battery. A disk keeps its writes in `.fdsdiff`.
+    pub fn battery_path(&self) -> Option<PathBuf> {
+        self.cartridge.borrow().battery_path().map(Path::to_path_buf)
+    }
+
+    /// Count cycles towards the next periodic flush, and flush when it is due.
+    ///
+    /// A failure is logged rather than returned: a save that could not be written must not stop
+    /// the game, and the next flush will try again.
+    fn run_battery_clock(&mut self, cycles: u8) {
+        if self.cartridge.borrow().battery_path().is_none() {
+            return;
+        }
+
+        self.cycles_since_battery_flush += cycles as u64;
+        if self.cycles_since_battery_flush >= BATTERY_FLUSH_CYCLES {
+            if let Err(error) = self.flush_battery() {
+                warn!("{error}");
+            }
+        }
+    }
+
+    /// Replace the cheat list with the one kept beside `rom_path`, or with none.
+    ///
+    /// A list that cannot be read is reported and left off rather than failing the load: the game
+    /// runs without cheats, and the file is still there to be put right.
+    fn load_cheats(&mut self, rom_path: Option<&Path>) {
+        self.cheat_file = rom_path.map(cheats::file_beside);
+        let list = match &self.cheat_file {
+            Some(path) => CheatList::load(path).unwrap_or_else(|error| {
+                warn!("Cheats in {} not loaded: {error}", path.display());
+                CheatList::new()
+            }),
+            None => CheatList::new(),
+        };
+        if !list.is_empty() {
+            info!("{} cheat code(s) loaded", list.cheats().len());
+        }
+        *self.cheats.borrow_mut() = list;
+    }
+
+    /// The loaded ROM's cheat codes.
+    pub fn cheats(&self) -> Ref<'_, CheatList> {
+        self.cheats.borrow()
+    }
+
+    /// The loaded ROM's cheat codes, to change. Changes take effect on the next read and the next
+    /// frame; [`save_cheats`](Self::save_cheats) keeps them.
+    pub fn cheats_mut(&mut self) -> RefMut<'_, CheatList> {
+        self.cheats.borrow_mut()
+    }
+
+    /// Where the cheat list is saved, if the loaded ROM came from a file.
+    pub fn cheat_file(&self) -> Option<&Path> {
+        self.cheat_file.as_deref()
+    }
+
+    /// Write the cheat list beside the ROM it is for. Nothing to do for a ROM not loaded from a
+    /// file.
+    pub fn save_cheats(&self) -> Result<(), NesError> {
+        let Some(path) = &self.cheat_file else {
+            return Ok(());
+        };
+        self.cheats
+            .borrow()
+            .save(path)
+            .map_err(|error| NesError::CheatFile(path.clone(), error))
+    }
+
+    /// Write the frozen bytes back, once for each frame the PPU starts.
+    fn apply_ram_freezes(&mut self) {
+        let cheats = self.cheats.borrow();
+        if cheats.freezes().next().is_none() {
+            return;
+        }
+        let frame = self.ppu.frame_count();
+        if frame == self.cheat_frame {
+            return;
+        }
+        self.cheat_frame = frame;
+        let mut bus = self.bus.borrow_mut();
+        for (address, value) in cheats.freezes() {
+            // RAM accepts every write, so there is nothing here to fail.
+            let _ = bus.poke_byte(address, value);
+        }
+    }
+
     /// Run a single step of the CPU
     /// Run one instruction, plus any sprite DMA it triggers.
     ///
@@ -907,6 +1273,9 @@ impl NesSystem {
             }
         }
 
+        self.run_battery_clock(cpu_cycles);
+        self.apply_ram_freezes();
+
         // Return the number of cycles that the CPU executed
         Ok(cpu_cycles)
     }
@@ -973,12 +1342,9 @@ impl NesSystem {
     }
 
     /// Load CHR ROM data into the cartridge
+    ///
+    /// Only a board with CHR RAM keeps it, which the one a hand-assembled program runs on has.
     pub fn load_chr_rom(&mut self, chr_data: &[u8]) -> Result<(), NesError> {
-        // Create a cartridge if one doesn't exist
-        if !self.ppu.has_cartridge() {
-            self.ppu.connect_cartridge(Cartridge::new());
-        }
-
         self.ppu.load_chr_rom(chr_data)
     }
 
@@ -1025,6 +1391,18 @@ impl Default for NesSystem {
     }
 }
 
+/// Switching off flushes the battery, as unloading does.
+///
+/// The frontends flush explicitly too, where they can report a failure; this is for every way a
+/// system goes away that nobody thought to handle.
+impl Drop for NesSystem {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush_battery() {
+            error!("{error}");
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
@@ -1633,6 +2011,323 @@ mod tests {
         assert_eq!(frame_buffer[top_left_idx + 1], 0, "Top-left pixel should be red (G=0)");
         assert_eq!(frame_buffer[top_left_idx + 2], 0, "Top-left pixel should be red (B=0)");
     }
+
+    /// A UxROM write is ANDed with the ROM byte under it unless bus conflicts are switched off,
+    /// and switching them off reaches a board built from a header that says it has them.
+    #[test]
+    fn bus_conflicts_can_be_switched_off_for_a_rom() {
+        use crate::cartridge::INesHeader;
+
+        // Eight 16 KB banks, each filled with its own number, except that the byte a game would
+        // write its bank number over reads $01.
+        let mut prg: Vec<u8> = (0..8u8).flat_map(|bank| vec![bank; 16 * 1024]).collect();
+        prg[0] = 0x01;
+        let rom = Rom {
+            header: INesHeader::for_mapper(2),
+            prg_rom: prg,
+            chr_rom: Vec::new(),
+            path: None,
+            correction: None,
+        };
+
+        for (override_to, expected) in [(None, 1), (Some(true), 1), (Some(false), 3)] {
+            let mut system = NesSystem::new();
+            system.set_bus_conflicts(override_to);
+            system.load_rom(&rom).expect("UxROM is supported");
+
+            system.bus.borrow_mut().write_byte(0x8000, 0x03).expect("writing the latch");
+            let bank = system.bus.borrow().read_byte(0x8001).expect("reading the bank");
+            assert_eq!(bank, expected, "override {override_to:?}");
+        }
+    }
+
+    /// An assembled program and a ROM both sit in the one cartridge slot, and the PPU draws from
+    /// whichever is there: the program's tiles until the ROM goes in, then the ROM's.
+    #[test]
+    fn the_ppu_reads_whatever_cartridge_is_in_the_slot() {
+        use crate::cartridge::INesHeader;
+
+        let mut system = NesSystem::new();
+        system.load_program(&[0xEA], 0x8000).unwrap();
+        system.load_chr_rom(&[0x5A; 16]).unwrap();
+        assert_eq!(system.cartridge().read_cpu(0x8000), 0xEA, "the program is on the cartridge");
+        assert_eq!(system.ppu().cartridge().unwrap().borrow().read_pattern_table(0), 0x5A);
+
+        let rom = Rom {
+            header: INesHeader::for_mapper(0),
+            prg_rom: vec![0; 16 * 1024],
+            chr_rom: vec![0xC3; 8 * 1024],
+            path: None,
+            correction: None,
+        };
+        system.load_rom(&rom).expect("NROM is supported");
+        assert_eq!(system.cartridge().header().chr_rom_size, rom.header.chr_rom_size);
+        assert_eq!(system.ppu().cartridge().unwrap().borrow().read_pattern_table(0), 0xC3);
+    }
+
+    /// The list beside a ROM is loaded with it: its Game Genie code answers reads of cartridge
+    /// space, and its RAM freeze puts the byte back each frame however often the game changes it.
+    #[test]
+    fn cheats_beside_a_rom_patch_reads_and_hold_ram() {
+        use crate::{
+            cartridge::INesHeader,
+            cheats::{encode_game_genie, GameGenieCode},
+        };
+
+        // INC $0300; JMP $8000 — a game forever changing the byte the freeze holds.
+        let mut prg = vec![0u8; 32 * 1024];
+        prg[..6].copy_from_slice(&[0xEE, 0x00, 0x03, 0x4C, 0x00, 0x80]);
+        prg[0x1000] = 0x11;
+        prg[0x7FFD] = 0x80;
+        let path = std::env::temp_dir().join(format!("{}_rn_cheats.nes", std::process::id()));
+        let genie = encode_game_genie(&GameGenieCode {
+            address: 0x9000,
+            value: 0x99,
+            compare: None,
+        });
+        std::fs::write(cheats::file_beside(&path), format!("on {genie} patch\non 0300:42 held\n")).unwrap();
+
+        let mut system = NesSystem::new();
+        let rom = Rom {
+            header: INesHeader::for_mapper(0),
+            prg_rom: prg,
+            chr_rom: Vec::new(),
+            path: Some(path.clone()),
+            correction: None,
+        };
+        system.load_rom(&rom).expect("NROM is supported");
+        assert_eq!(system.cheats().cheats().len(), 2);
+        assert_eq!(system.bus.borrow().read_byte(0x9000).unwrap(), 0x99);
+
+        let frame = system.ppu.frame_count();
+        while system.ppu.frame_count() < frame + 2 {
+            system.step().unwrap();
+        }
+        let held = system.bus.borrow().read_byte(0x0300).unwrap();
+        assert!(held.wrapping_sub(0x42) < 0x10, "put back at the frame, then counted up from: {held:#04X}");
+
+        system.cheats_mut().set_enabled(0, false);
+        assert_eq!(system.bus.borrow().read_byte(0x9000).unwrap(), 0x11);
+
+        system.cheats_mut().remove(1);
+        system.cheats_mut().remove(0);
+        system.save_cheats().unwrap();
+        assert!(!cheats::file_beside(&path).exists(), "an empty list leaves no file");
+    }
+
+    /// A homebrew save on UNROM 512 is a rewritten flash sector, and has to be there after a restart.
+    #[test]
+    fn a_flash_save_survives_unloading_and_reloading() {
+        use crate::cartridge::INesHeader;
+
+        let path = std::env::temp_dir().join(format!("{}_unrom512_save.nes", std::process::id()));
+        let _ = std::fs::remove_file(path.with_extension("flash"));
+        let rom = Rom {
+            header: INesHeader {
+                battery: true,
+                ..INesHeader::for_mapper(30)
+            },
+            prg_rom: vec![0xEA; 64 * 1024],
+            chr_rom: Vec::new(),
+            path: Some(path.clone()),
+            correction: None,
+        };
+
+        let mut system = NesSystem::new();
+        system.load_rom(&rom).expect("UNROM 512 is supported");
+        {
+            // Program $00 at flash offset $1234, through the bank that puts each address under
+            // `$8000-$BFFF`.
+            let mut bus = system.bus.borrow_mut();
+            for (bank, address, value) in [(1, 0x9555, 0xAA), (0, 0xAAAA, 0x55), (1, 0x9555, 0xA0), (0, 0x9234, 0x00)] {
+                bus.write_byte(0xC000, bank).expect("selecting the bank");
+                bus.write_byte(address, value).expect("writing the flash");
+            }
+        }
+        system.flush_battery().expect("saving");
+        assert_eq!(system.battery_path(), Some(path.with_extension("flash")));
+
+        let mut restarted = NesSystem::new();
+        restarted.load_rom(&rom).expect("reloading");
+        restarted.bus.borrow_mut().write_byte(0xC000, 0).expect("selecting bank 0");
+        assert_eq!(restarted.bus.borrow().read_byte(0x9234).expect("reading"), 0x00);
+        assert_eq!(restarted.bus.borrow().read_byte(0x9235).expect("reading"), 0xEA);
+
+        let _ = std::fs::remove_file(path.with_extension("flash"));
+    }
+
+    /// An iNES header cannot say MMC6, so a game that needs its RAM protection has to be told.
+    #[test]
+    fn an_mmc3_variant_can_be_forced_for_a_rom() {
+        use crate::cartridge::INesHeader;
+
+        let rom = Rom {
+            header: INesHeader::for_mapper(4),
+            prg_rom: vec![0; 32 * 1024],
+            chr_rom: vec![0; 8 * 1024],
+            path: None,
+            correction: None,
+        };
+
+        for (variant, expected) in [(None, 0x5A), (Some(Mmc3Variant::Mmc6), 0x00)] {
+            let mut system = NesSystem::new();
+            system.set_mmc3_variant(variant);
+            system.load_rom(&rom).expect("MMC3 is supported");
+
+            // Written with MMC6's RAM switched on but its halves still protected.
+            let mut bus = system.bus.borrow_mut();
+            bus.write_byte(0x8000, 0x20).expect("selecting");
+            bus.write_byte(0x7000, 0x5A).expect("writing the RAM");
+            bus.write_byte(0xA001, 0x20).expect("making the lower half readable");
+            assert_eq!(bus.read_byte(0x7000).expect("reading the RAM"), expected, "{variant:?}");
+        }
+    }
+
+    /// The cartridge reports the board it is running, not the one the file named.
+    #[test]
+    fn a_forced_vrc_board_is_the_one_the_cartridge_reports() {
+        use crate::cartridge::INesHeader;
+
+        let rom = Rom {
+            header: INesHeader::for_mapper(21),
+            prg_rom: vec![0; 32 * 1024],
+            chr_rom: vec![0; 8 * 1024],
+            path: None,
+            correction: None,
+        };
+        let mut system = NesSystem::new();
+        system.set_vrc_variant(Some(VrcVariant::Vrc2b));
+        system.load_rom(&rom).expect("VRC2 is supported");
+
+        let cartridge = system.cartridge();
+        assert_eq!((cartridge.header().mapper, cartridge.header().submapper), (23, 3));
+        assert_eq!(rom.header.mapper, 21, "the file's own header is left alone");
+    }
+
+    /// A board asks to be clocked only if something on it counts cycles, and the APU is told which
+    /// chip, if any, it is mixing.
+    #[test]
+    fn only_boards_that_count_cycles_are_clocked() {
+        use crate::{apu::ExpansionChip, cartridge::INesHeader};
+
+        for (mapper, cycles, chip) in [(0, false, None), (21, true, None), (24, true, Some(ExpansionChip::Vrc6))] {
+            let rom = Rom {
+                header: INesHeader::for_mapper(mapper),
+                prg_rom: vec![0; 32 * 1024],
+                chr_rom: vec![0; 8 * 1024],
+                path: None,
+                correction: None,
+            };
+            let mut system = NesSystem::new();
+            system.load_rom(&rom).expect("supported");
+            let clock = system.cartridge_clock.get();
+            assert_eq!((clock.cycles, clock.sound), (cycles, chip.is_some()), "mapper {mapper}");
+            assert_eq!(system.apu.expansion_chip(), chip, "mapper {mapper}");
+        }
+    }
+
+    /// Rad Racer II is MMC3 on TVROM: four nametables of its own, and a mirroring register that
+    /// goes nowhere.
+    #[test]
+    fn a_four_screen_board_keeps_four_separate_nametables() {
+        use crate::cartridge::INesHeader;
+
+        let rom = Rom {
+            header: INesHeader {
+                four_screen: true,
+                ..INesHeader::for_mapper(4)
+            },
+            prg_rom: vec![0; 32 * 1024],
+            chr_rom: vec![0; 8 * 1024],
+            path: None,
+            correction: None,
+        };
+        let mut system = NesSystem::new();
+        system.load_rom(&rom).expect("MMC3 is supported");
+        system.bus.borrow_mut().write_byte(0xA000, 0x01).expect("asking for horizontal");
+
+        for (table, address) in [0x2000u16, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            system.ppu.write_register(0x2006, (address >> 8) as u8);
+            system.ppu.write_register(0x2006, address as u8);
+            system.ppu.write_register(0x2007, 0x10 + table as u8);
+        }
+
+        assert_eq!(system.ppu.mirroring(), crate::ppu::Mirroring::FourScreen);
+        assert_eq!(
+            [0x2000, 0x2400, 0x2800, 0x2C00].map(|address| system.ppu.read_vram(address)),
+            [0x10, 0x11, 0x12, 0x13]
+        );
+    }
+
+    /// A disk needs the BIOS, boots from its reset vector, and turns over one side at a time,
+    /// wrapping after the last.
+    #[test]
+    fn a_disk_boots_through_the_bios_and_flips_sides() {
+        use crate::cartridge::{HeaderFormat, INesHeader, FDS_BIOS_SIZE};
+
+        let mut disk = b"\x01*NINTENDO-HVC*".to_vec();
+        disk.resize(2 * 65500, 0);
+        let rom = Rom {
+            header: INesHeader {
+                format: HeaderFormat::Fds,
+                ..INesHeader::for_mapper(20)
+            },
+            prg_rom: disk,
+            chr_rom: Vec::new(),
+            path: None,
+            correction: None,
+        };
+        let mut system = NesSystem::new();
+        assert!(matches!(system.load_rom(&rom), Err(NesError::MissingFdsBios)));
+
+        let mut bios = vec![0xEA; FDS_BIOS_SIZE];
+        bios[FDS_BIOS_SIZE - 4..FDS_BIOS_SIZE - 2].copy_from_slice(&[0x24, 0xEE]);
+        system.set_fds_bios(Some(bios));
+        system.load_rom(&rom).expect("a BIOS to boot with");
+        assert_eq!(system.cpu.pc(), 0xEE24);
+
+        assert_eq!((system.disk_sides(), system.disk_side()), (2, Some(0)));
+        system.flip_disk_side();
+        assert_eq!(system.disk_side(), Some(1));
+        system.flip_disk_side();
+        assert_eq!(system.disk_side(), Some(0));
+        system.insert_disk(None);
+        assert_eq!(system.disk_side(), None);
+    }
+
+    /// An NSF's init routine gets the track, its play routine is called once a frame at the
+    /// file's rate, and changing track starts again from a cleared machine.
+    #[test]
+    fn an_nsf_is_initialised_for_its_track_and_played_at_its_rate() {
+        // init: STA $0300 / RTS, play: INC $0301 / RTS.
+        let nsf = Nsf {
+            load_address: 0x8000,
+            init_address: 0x8000,
+            play_address: 0x8004,
+            data: vec![0x8D, 0x00, 0x03, 0x60, 0xEE, 0x01, 0x03, 0x60],
+            region: Some(crate::region::Region::Ntsc),
+            songs: 3,
+            ..Nsf::default()
+        };
+        let mut system = NesSystem::new();
+        let run_frames = |system: &mut NesSystem, frames: u64| {
+            let start = system.cpu.cycles();
+            while system.cpu.cycles() - start < frames * 29781 {
+                system.step().unwrap();
+            }
+        };
+
+        system.load_nsf(&nsf, 2).unwrap();
+        run_frames(&mut system, 10);
+        assert_eq!(system.cpu.read_byte(0x0300).unwrap(), 2);
+        let plays = system.cpu.read_byte(0x0301).unwrap();
+        assert!((9..=10).contains(&plays), "{plays} play calls in ten frames");
+
+        system.load_nsf(&nsf, 1).unwrap();
+        run_frames(&mut system, 1);
+        assert_eq!(system.cpu.read_byte(0x0300).unwrap(), 1);
+        assert!(system.cpu.read_byte(0x0301).unwrap() <= 1, "RAM was cleared for the new track");
+    }
 }
 
 /// Where a sprite DMA puts an interrupt that arrives during it.
@@ -0,0 +1,168 @@
+//! Battery-backed save RAM, kept in a `.sav` file beside the ROM.
+//!
+//! A cartridge with a battery keeps `$6000..=$7FFF` powered while the console is off, and that is
+//! where a game keeps its saved progress. Until this existed the only way that RAM survived a
+//! restart was a save state, which is not what anyone means by "my save".
+
+use std::path::PathBuf;
+
+use rn_core::{cartridge::load_rom, memory::Addressable, system::NesSystem};
+
+/// A battery-backed NROM cartridge whose program increments `$6000` once and spins.
+///
+/// The store is `INC $6000`, not a plain write, so a restored save is visible in what the program
+/// does with it: a fresh RAM ends up holding 1, and one that came back from a `.sav` holding 5
+/// ends up holding 6.
+fn battery_rom(name: &str) -> PathBuf {
+    let mut prg = vec![0xEAu8; 16 * 1024];
+    prg[..6].copy_from_slice(&[
+        0xEE, 0x00, 0x60, // INC $6000
+        0x4C, 0x03, 0x80, // JMP $8003 (spin)
+    ]);
+    let vector = prg.len() - 4;
+    prg[vector..vector + 2].copy_from_slice(&0x8000u16.to_le_bytes());
+
+    let mut image = Vec::new();
+    image.extend_from_slice(b"NES\x1A");
+    image.extend_from_slice(&[1, 1, 0x02, 0x00]); // 16 KB PRG, 8 KB CHR, battery
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&prg);
+    image.extend_from_slice(&vec![0u8; 8 * 1024]);
+
+    let path = std::env::temp_dir().join(format!("{}_{name}.nes", std::process::id()));
+    std::fs::write(&path, image).expect("writing the test ROM");
+    let _ = std::fs::remove_file(path.with_extension("sav"));
+    path
+}
+
+fn boot(path: &std::path::Path, read_only: bool) -> NesSystem {
+    let rom = load_rom(path).expect("loading the ROM");
+    let mut system = NesSystem::new();
+    system.set_battery_read_only(read_only);
+    system.load_rom(&rom).expect("loading into the system");
+    for _ in 0..8 {
+        system.step().expect("stepping");
+    }
+    system
+}
+
+fn marker(system: &NesSystem) -> u8 {
+    system.cpu().read_byte(0x6000).expect("reading save RAM")
+}
+
+#[test]
+fn a_battery_save_survives_a_restart() {
+    let path = battery_rom("battery_restart");
+
+    let mut first = boot(&path, false);
+    assert_eq!(marker(&first), 1, "a first play starts from empty RAM");
+    first.flush_battery().expect("flushing");
+    assert_eq!(first.battery_path(), Some(path.with_extension("sav")));
+    drop(first);
+
+    let saved = std::fs::read(path.with_extension("sav")).expect("the save should exist");
+    assert_eq!(saved.len(), 8 * 1024, "iNES's battery keeps the whole 8 KB");
+    assert_eq!(saved[0], 1);
+
+    let second = boot(&path, false);
+    assert_eq!(marker(&second), 2, "the program should have found its save and carried on from it");
+}
+
+/// Switching off flushes, with no explicit call — the way a window being closed usually goes.
+#[test]
+fn dropping_the_system_flushes_the_save() {
+    let path = battery_rom("battery_drop");
+
+    drop(boot(&path, false));
+
+    let saved = std::fs::read(path.with_extension("sav")).expect("dropping should have saved");
+    assert_eq!(saved[0], 1);
+}
+
+/// Loading another cartridge unplugs the first one, and its save is written as it goes.
+#[test]
+fn loading_another_rom_flushes_the_previous_save() {
+    let first = battery_rom("battery_unload_first");
+    let second = battery_rom("battery_unload_second");
+
+    let mut system = boot(&first, false);
+    let rom = load_rom(&second).expect("loading the second ROM");
+    system.load_rom(&rom).expect("swapping cartridges");
+
+    let saved = std::fs::read(first.with_extension("sav")).expect("unloading should have saved");
+    assert_eq!(saved[0], 1);
+}
+
+/// A save that cannot be written does not stop the next cartridge going in.
+///
+/// The `.sav` is made a directory, which no save can be renamed over — the same failure a
+/// read-only save folder gives, without depending on who the tests run as.
+#[test]
+fn a_save_that_cannot_be_written_does_not_block_the_next_load() {
+    let first = battery_rom("battery_unwritable_first");
+    let second = battery_rom("battery_unwritable_second");
+
+    let mut system = boot(&first, false);
+    std::fs::create_dir(first.with_extension("sav")).expect("blocking the save");
+    assert!(system.flush_battery().is_err(), "the save itself still reports the failure");
+
+    let rom = load_rom(&second).expect("loading the second ROM");
+    let loaded = system.load_rom(&rom);
+    drop(system);
+    let _ = std::fs::remove_dir(first.with_extension("sav"));
+    let _ = std::fs::remove_file(first.with_extension("sav.tmp"));
+
+    loaded.expect("the second cartridge should load regardless");
+}
+
+/// A read-only run starts from the save and leaves it exactly as it found it, so a test run
+/// cannot change what the next one sees.
+#[test]
+fn a_read_only_run_reads_the_save_but_never_writes_it() {
+    let path = battery_rom("battery_read_only");
+    let mut save = vec![0u8; 8 * 1024];
+    save[0] = 5;
+    std::fs::write(path.with_extension("sav"), &save).expect("writing a save");
+
+    let mut system = boot(&path, true);
+    assert_eq!(marker(&system), 6, "the save is still restored");
+    system.flush_battery().expect("flushing");
+    drop(system);
+
+    let after = std::fs::read(path.with_extension("sav")).expect("the save should still exist");
+    assert_eq!(after, save, "read-only must leave the file untouched");
+}
+
+/// A cartridge without a battery has no save, and makes no file.
+#[test]
+fn no_battery_means_no_save_file() {
+    let path = battery_rom("battery_none");
+    let mut image = std::fs::read(&path).expect("reading the ROM back");
+    image[6] = 0x00; // clear the battery flag
+    std::fs::write(&path, image).expect("rewriting the ROM");
+
+    let mut system = boot(&path, false);
+    system.flush_battery().expect("flushing");
+    assert_eq!(system.battery_path(), None);
+    drop(system);
+
+    assert!(!path.with_extension("sav").exists(), "no battery, no .sav");
+}
+
+/// Running long enough writes the save without being asked, for the crash nobody flushed before.
+#[test]
+fn a_running_game_flushes_periodically() {
+    let path = battery_rom("battery_periodic");
+
+    let mut system = boot(&path, false);
+    // A little over five seconds of emulated time, at three cycles a spin.
+    for _ in 0..(5 * 1_789_773 / 3 + 1000) {
+        system.step().expect("stepping");
+    }
+
+    // Read while the system is still alive, so it is the periodic flush that wrote the file and
+    // not the drop.
+    let saved = std::fs::read(path.with_extension("sav")).expect("a periodic flush should have saved");
+    assert_eq!(saved[0], 1);
+    drop(system);
+}
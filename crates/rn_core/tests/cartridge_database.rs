@@ -0,0 +1,42 @@
+//! The built-in cartridge database, against the dump that made it necessary.
+//!
+//! `super-mario-3-eu.nes` is a PAL cartridge whose iNES header says NTSC, and a header's TV bit is
+//! not acted on at load. So the only thing that can boot it PAL is the database recognising its
+//! PRG and CHR data — which is what this checks, through `load_rom` rather than a lookup.
+
+use rn_core::{
+    cartridge::{crc32, load_rom},
+    region::Region,
+    system::NesSystem,
+};
+
+#[test]
+fn the_built_in_database_boots_the_european_super_mario_bros_3_as_pal() {
+    let path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../../nes-roms/super-mario-3-eu.nes");
+    // Commercial ROMs cannot live in this repository, so this skips without one, as
+    // `status_bar_split` does, and `RN_REQUIRE_ROMS` turns the skip into a failure.
+    if !path.exists() {
+        assert!(
+            std::env::var_os("RN_REQUIRE_ROMS").is_none(),
+            "RN_REQUIRE_ROMS is set but there is no ROM at {}",
+            path.display()
+        );
+        eprintln!("SKIP: no ROM at {} (set RN_REQUIRE_ROMS to make this a failure)", path.display());
+        return;
+    }
+
+    let image = std::fs::read(&path).expect("reading the ROM");
+    assert_ne!(image[7] & 0x0C, 0x08, "an iNES header, which has only the one TV bit");
+    assert_eq!(image[9] & 0x01, 0x00, "and that bit says NTSC");
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    let crc = crc32(&[&rom.prg_rom, &rom.chr_rom]);
+    let correction = rom.correction.as_ref().unwrap_or_else(|| {
+        panic!("the database does not know this dump; it needs `{crc:08X} region=pal \"Super Mario Bros. 3 (Europe)\"`")
+    });
+    assert_eq!(correction.changed.region, Some(Region::Pal), "{correction}");
+
+    let mut system = NesSystem::new();
+    system.load_rom(&rom).expect("loading into the system");
+    assert_eq!(system.region(), Region::Pal, "the correction, not the header, decides the machine");
+}
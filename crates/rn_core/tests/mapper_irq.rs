@@ -138,3 +138,58 @@ fn a_counter_watching_the_address_needs_the_sprite_patterns_to_be_in_the_upper_h
         "with nothing fetched above $0FFF bit 12 never rises, so the counter must not run"
     );
 }
+
+/// A minimal MMC5 ROM that spins from the bank the chip powers on with at `$E000`.
+fn spinning_mmc5_rom() -> std::path::PathBuf {
+    const BANKS: usize = 2;
+    let mut prg = vec![0u8; BANKS * 16 * 1024];
+
+    let last = prg.len() - 8 * 1024;
+    prg[last..last + 3].copy_from_slice(&[0x4C, 0x00, 0xE0]);
+    let vector = prg.len() - 4;
+    prg[vector..vector + 2].copy_from_slice(&0xE000u16.to_le_bytes());
+
+    let mut image = Vec::new();
+    image.extend_from_slice(b"NES\x1A");
+    image.push(BANKS as u8);
+    image.push(1);
+    image.extend_from_slice(&[0x50, 0x00]); // mapper 5
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&prg);
+    image.extend_from_slice(&vec![0u8; 8 * 1024]);
+
+    let path = std::env::temp_dir().join(format!(
+        "rn_mmc5_irq_{}_{}.nes",
+        std::process::id(),
+        NEXT_ROM.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    std::fs::write(&path, &image).expect("writing the ROM");
+    path
+}
+
+/// MMC5 counts lines from the PPU's nametable reads, not from bit 12, so where the pattern tables
+/// sit makes no difference to it — but rendering being on does, since off there are no reads.
+#[test]
+fn the_mmc5_counter_follows_the_ppus_reads() {
+    for rendering in [false, true] {
+        let path = spinning_mmc5_rom();
+        let rom = load_rom(&path).expect("loading");
+        let mut system = NesSystem::new();
+        system.load_rom(&rom).expect("loading into system");
+
+        system.cpu().write_byte(0x4017, 0x40).ok(); // inhibit the APU frame IRQ
+        system.cpu().write_byte(0x5203, 100).ok(); // fire on line 100
+        system.cpu().write_byte(0x5204, 0x80).ok(); // enable
+        system.cpu().write_byte(0x2000, 0x00).ok();
+        system.cpu().write_byte(0x2001, if rendering { 0x08 } else { 0x00 }).ok();
+        run_frames(&mut system, 2);
+
+        assert_eq!(
+            system.cpu().irq_line(),
+            rendering,
+            "with rendering {} the scanline IRQ should {}",
+            if rendering { "on" } else { "off" },
+            if rendering { "fire" } else { "stay quiet" }
+        );
+    }
+}
@@ -8,7 +8,21 @@
 //! cannot be committed to this repository, and a test that silently skips when a file is missing
 //! is worse than one that builds its own input.
 
-use rn_core::{cartridge::load_rom, memory::Addressable, system::NesSystem};
+use rn_core::{
+    cartridge::{
+        load_chr_rom,
+        load_rom,
+        load_rom_with_patch,
+        ConsoleType,
+        ExpansionDevice,
+        HeaderFormat,
+        RomLoadError,
+        Timing,
+    },
+    memory::Addressable,
+    region::Region,
+    system::NesSystem,
+};
 
 /// Build a minimal iNES image: a header, `prg` padded to whole 16 KB banks, and 8 KB of CHR.
 ///
@@ -305,3 +319,270 @@ fn a_cartridge_runs_through_a_brk_instead_of_halting_on_it() {
         "the BRK should have entered its handler and the machine should have kept running"
     );
 }
+
+/// Build an NES 2.0 image around `header` (bytes 4 to 15), with PRG and CHR of the sizes given.
+///
+/// The sizes are passed separately rather than read back out of the header, so a test of the
+/// exponent notation checks the parser against an independent figure instead of against itself.
+/// The reset vector points at `$8000`, where the PRG is a `JMP` to itself.
+fn synthesise_nes2(header: [u8; 12], prg_len: usize, chr_len: usize) -> Vec<u8> {
+    let mut image = Vec::new();
+    image.extend_from_slice(b"NES\x1A");
+    image.extend_from_slice(&header);
+
+    let mut prg = vec![0u8; prg_len];
+    prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]); // JMP $8000
+    let vector = prg_len - 4;
+    prg[vector..vector + 2].copy_from_slice(&0x8000u16.to_le_bytes());
+
+    image.extend_from_slice(&prg);
+    image.extend_from_slice(&vec![0u8; chr_len]);
+    image
+}
+
+/// Every field an NES 2.0 header adds, each set to something an iNES reader would get wrong.
+#[test]
+fn an_nes2_header_is_read_field_by_field() {
+    let header = [
+        0x02,        // 4: PRG, 2 x 16 KB
+        0x01,        // 5: CHR, 1 x 8 KB
+        0x43,        // 6: mapper low nibble 4, battery, vertical
+        0x18 | 0x01, // 7: mapper middle nibble 1, NES 2.0 marker, Vs. System
+        0x52,        // 8: submapper 5, mapper high nibble 2 -> mapper $214
+        0x00,        // 9: no high size bits
+        0x97,        // 10: PRG-NVRAM 64 << 9, PRG-RAM 64 << 7
+        0xA0,        // 11: CHR-NVRAM 64 << 10, no CHR-RAM
+        0x03,        // 12: Dendy
+        0x21,        // 13: Vs. hardware 2, Vs. PPU 1
+        0x00,        // 14: no miscellaneous ROMs
+        0x08,        // 15: Zapper
+    ];
+    let image = synthesise_nes2(header, 32 * 1024, 8 * 1024);
+    let path = write_temp_rom("rn_nes2_fields.nes", &image);
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    let parsed = &rom.header;
+
+    assert_eq!(parsed.format, HeaderFormat::Nes2);
+    assert_eq!(parsed.mapper, 0x214, "all twelve bits of the mapper number");
+    assert_eq!(parsed.submapper, 5);
+    assert_eq!(parsed.prg_rom_size, 32 * 1024);
+    assert_eq!(parsed.chr_rom_size, 8 * 1024);
+    assert!(parsed.mirroring, "vertical");
+    assert!(parsed.battery);
+    assert!(!parsed.trainer);
+    assert!(!parsed.four_screen);
+    assert_eq!(parsed.prg_ram_size, 8 * 1024);
+    assert_eq!(parsed.prg_nvram_size, 32 * 1024);
+    assert_eq!(parsed.chr_ram_size, 0);
+    assert_eq!(parsed.chr_nvram_size, 64 * 1024);
+    assert_eq!(parsed.timing, Timing::Dendy);
+    assert_eq!(parsed.region, Region::Dendy);
+    assert_eq!(parsed.console_type, ConsoleType::VsSystem { ppu: 1, hardware: 2 });
+    assert_eq!(parsed.expansion_device, ExpansionDevice::Zapper);
+}
+
+/// The remaining values of the fields above that have more than one interesting answer.
+#[test]
+fn nes2_timing_console_and_expansion_values() {
+    let parse = |byte7: u8, byte12: u8, byte13: u8, byte15: u8| {
+        let header = [0x02, 0x01, 0x00, 0x08 | byte7, 0, 0, 0, 0, byte12, byte13, 0, byte15];
+        let path = write_temp_rom("rn_nes2_values.nes", &synthesise_nes2(header, 32 * 1024, 8 * 1024));
+        load_rom(&path).expect("loading the ROM").header
+    };
+
+    let ntsc = parse(0x00, 0x00, 0x00, 0x01);
+    assert_eq!((ntsc.timing, ntsc.region), (Timing::Ntsc, Region::Ntsc));
+    assert_eq!(ntsc.console_type, ConsoleType::Nes);
+    assert_eq!(ntsc.expansion_device, ExpansionDevice::StandardControllers);
+
+    let pal = parse(0x02, 0x01, 0x00, 0x02);
+    assert_eq!((pal.timing, pal.region), (Timing::Pal, Region::Pal));
+    assert_eq!(pal.console_type, ConsoleType::Playchoice10);
+    assert_eq!(pal.expansion_device, ExpansionDevice::FourScore);
+
+    // A multi-region cartridge runs on NTSC timing unless told otherwise.
+    let multi = parse(0x03, 0x02, 0x05, 0x3F);
+    assert_eq!((multi.timing, multi.region), (Timing::MultiRegion, Region::Ntsc));
+    assert_eq!(multi.console_type, ConsoleType::Extended(5));
+    assert_eq!(multi.expansion_device, ExpansionDevice::Other(0x3F));
+}
+
+/// Exponent-multiplier ROM sizes: a high nibble of `$F` makes the low byte `2^E * (2M + 1)`.
+///
+/// 24 KB of PRG is `E = 13, M = 1`; 24 KB of CHR is the same, in the other pair. Neither is a
+/// whole number of the banks the plain notation counts in, which is the reason the form exists.
+#[test]
+fn nes2_exponent_multiplier_rom_sizes() {
+    let header = [13 << 2 | 1, 13 << 2 | 1, 0x00, 0x08, 0x00, 0xFF, 0, 0, 0, 0, 0, 0];
+    let image = synthesise_nes2(header, 24 * 1024, 24 * 1024);
+    let path = write_temp_rom("rn_nes2_exponent.nes", &image);
+
+    let rom = load_rom(&path).expect("loading the ROM");
+
+    assert_eq!(rom.header.prg_rom_size, 24 * 1024);
+    assert_eq!(rom.header.chr_rom_size, 24 * 1024);
+    assert_eq!(rom.prg_rom.len(), 24 * 1024);
+    assert_eq!(rom.chr_rom.len(), 24 * 1024);
+}
+
+/// The high nibbles of byte 9 extend the plain sizes past iNES's 255 banks.
+#[test]
+fn nes2_size_high_nibbles_extend_the_bank_counts() {
+    // PRG $100 x 16 KB = 4 MB would be a large fixture, so only CHR uses its nibble: $101 x 8 KB.
+    let header = [0x02, 0x01, 0x00, 0x08, 0x00, 0x10, 0, 0, 0, 0, 0, 0];
+    let image = synthesise_nes2(header, 32 * 1024, 0x101 * 8 * 1024);
+    let path = write_temp_rom("rn_nes2_high_nibble.nes", &image);
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    assert_eq!(rom.header.chr_rom_size, 0x101 * 8 * 1024);
+}
+
+/// A header whose sizes outrun the file is refused with a reason, not by trying to allocate them.
+///
+/// Exponent notation can claim 2^62 bytes in one byte, and a corrupt header should say it is
+/// corrupt rather than take the process down asking for that much memory.
+#[test]
+fn an_nes2_header_larger_than_its_file_is_rejected() {
+    let header = [62 << 2, 0x00, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0];
+    let mut image = synthesise_nes2([0x02, 0x00, 0x00, 0x08, 0, 0, 0, 0, 0, 0, 0, 0], 32 * 1024, 0);
+    image[4..16].copy_from_slice(&header);
+    let path = write_temp_rom("rn_nes2_too_large.nes", &image);
+
+    let error = load_rom(&path).expect_err("a 4 EB PRG ROM cannot be in a 32 KB file");
+    assert!(error.to_string().contains("more PRG and CHR"), "unexpected error: {error}");
+
+    let error = load_chr_rom(&path).expect_err("nor can reading only its CHR allocate it");
+    assert!(error.to_string().contains("more PRG and CHR"), "unexpected error: {error}");
+}
+
+/// A file cut short is refused when only its CHR is wanted, rather than read as far as it goes.
+#[test]
+fn a_truncated_file_is_rejected_when_reading_its_chr() {
+    let mut image = synthesise_rom(&[0xEA], 0x8000, 1);
+    image.truncate(image.len() - 1024);
+    let path = write_temp_rom("rn_truncated_chr.nes", &image);
+
+    let error = load_chr_rom(&path).expect_err("the last kilobyte of CHR is missing");
+    assert!(error.to_string().contains("more PRG and CHR"), "unexpected error: {error}");
+}
+
+/// An iNES header leaves the NES 2.0 fields at what the common boards had.
+#[test]
+fn an_ines_header_implies_the_usual_ram_and_timing() {
+    let image = synthesise_rom(&[0xEA], 0x8000, 1);
+    let path = write_temp_rom("rn_ines_defaults.nes", &image);
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    let header = &rom.header;
+
+    assert_eq!(header.format, HeaderFormat::INes);
+    assert_eq!(header.submapper, 0);
+    assert_eq!(header.prg_ram_total(), 8 * 1024, "8 KB of work RAM, which iNES cannot rule out");
+    assert_eq!(header.chr_ram_total(), 0, "this image has CHR ROM");
+    assert_eq!(header.timing, Timing::Ntsc);
+    assert_eq!(header.console_type, ConsoleType::Nes);
+    assert_eq!(header.expansion_device, ExpansionDevice::Unspecified);
+}
+
+/// A mapper number past 255 can only come from an NES 2.0 header, and is reported by number.
+#[test]
+fn a_twelve_bit_mapper_number_reaches_the_error_message() {
+    let header = [0x02, 0x01, 0x00, 0x08, 0x01, 0, 0, 0, 0, 0, 0, 0]; // mapper $100
+    let path = write_temp_rom("rn_nes2_mapper_256.nes", &synthesise_nes2(header, 32 * 1024, 8 * 1024));
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    let error = NesSystem::new().load_rom(&rom).expect_err("mapper 256 is not implemented");
+    assert!(error.to_string().contains("Mapper 256"), "unexpected error: {error}");
+}
+
+/// The system takes an NES 2.0 header's timing at its word, and boots PAL or Dendy timing from it.
+#[test]
+fn an_nes2_header_sets_the_region() {
+    for (timing, region) in [(0x01, Region::Pal), (0x03, Region::Dendy)] {
+        let header = [0x02, 0x01, 0x00, 0x08, 0x00, 0, 0, 0, timing, 0, 0, 0];
+        let path = write_temp_rom("rn_nes2_region.nes", &synthesise_nes2(header, 32 * 1024, 8 * 1024));
+
+        let rom = load_rom(&path).expect("loading the ROM");
+        let mut system = NesSystem::new();
+        system.load_rom(&rom).expect("loading into the system");
+
+        assert_eq!(system.region(), region, "timing byte {timing}");
+    }
+}
+
+/// An iNES header's TV bit is not trusted on load: the machine stays where it was put.
+#[test]
+fn an_ines_header_does_not_set_the_region() {
+    let mut image = synthesise_rom(&[0xEA], 0x8000, 1);
+    image[9] = 0x01; // claims PAL
+    let path = write_temp_rom("rn_ines_pal_bit.nes", &image);
+
+    let rom = load_rom(&path).expect("loading the ROM");
+    assert_eq!(rom.header.region, Region::Pal, "the claim is still read");
+
+    let mut system = NesSystem::new();
+    system.load_rom(&rom).expect("loading into the system");
+    assert_eq!(system.region(), Region::Ntsc, "but not acted on");
+}
+
+/// An IPS patch writing each `(offset, data)` record into the file.
+fn ips_patch(records: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    for (offset, data) in records {
+        patch.extend_from_slice(&offset.to_be_bytes()[1..]);
+        patch.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        patch.extend_from_slice(data);
+    }
+    patch.extend_from_slice(b"EOF");
+    patch
+}
+
+/// A patch beside the ROM under its name is applied on load, and before the header is read: this
+/// one rewrites the mapper number, which only matters if the header is parsed afterwards.
+#[test]
+fn a_patch_beside_the_rom_is_applied_before_the_header_is_read() {
+    let image = synthesise_rom(&[0xEA], 0x8000, 1);
+    let path = write_temp_rom("rn_patched.nes", &image);
+    let patch = path.with_extension("ips");
+    // Byte 6 carries the mapper's low nibble; the program starts at offset 16.
+    std::fs::write(&patch, ips_patch(&[(6, &[0x20]), (16, &[0xA9, 0x42])])).unwrap();
+
+    let rom = load_rom(&path).expect("loading the patched ROM");
+    assert_eq!(rom.header.mapper, 2, "the header is read from the patched image");
+    assert_eq!(&rom.prg_rom[..2], &[0xA9, 0x42]);
+
+    let unpatched = load_rom_with_patch(&path, None).expect("loading without the patch");
+    assert_eq!(unpatched.header.mapper, 0);
+    assert_eq!(unpatched.prg_rom[0], 0xEA);
+    std::fs::remove_file(&patch).unwrap();
+}
+
+#[test]
+fn a_gzipped_rom_loads_and_takes_its_patch_from_beside_the_archive() {
+    use std::io::Write;
+
+    let image = synthesise_rom(&[0xEA], 0x8000, 1);
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&image).unwrap();
+    let path = write_temp_rom("rn_gzipped.nes.gz", &encoder.finish().unwrap());
+
+    let rom = load_rom_with_patch(&path, None).expect("loading the gzipped ROM");
+    assert_eq!(rom.prg_rom, image[16..16 + 16 * 1024]);
+    assert_eq!(rom.path.as_deref(), Some(path.as_path()), "saves go beside the archive");
+
+    let patch = path.with_extension("ips");
+    std::fs::write(&patch, ips_patch(&[(16, &[0xA9, 0x42])])).unwrap();
+    let rom = load_rom(&path).expect("loading the gzipped ROM with its patch");
+    assert_eq!(&rom.prg_rom[..2], &[0xA9, 0x42], "the patch applies to what the archive holds");
+    std::fs::remove_file(&patch).unwrap();
+}
+
+#[test]
+fn a_damaged_zip_is_reported_as_an_archive_error() {
+    let path = write_temp_rom("rn_not_an_archive.zip", b"PK\x03\x04 and nothing else");
+    match load_rom(&path) {
+        Err(RomLoadError::Archive(reason)) => assert!(reason.contains("damaged"), "{reason}"),
+        other => panic!("expected the archive to be refused, got {other:?}"),
+    }
+}
@@ -142,6 +142,27 @@ impl AudioWidget {
                     log::warn!("Failed to update APU channel enables: {error}");
                 }
             }
+
+            // The cartridge's own channels, when it has a sound chip. Each has its enable bit in
+            // the chip's registers rather than in $4015, so they are switched one at a time.
+            for (index, channel) in apu.expansion_channels().into_iter().enumerate() {
+                let mut enabled = channel.enabled;
+                if ui.checkbox(&mut enabled, channel.label).changed() {
+                    apu.set_expansion_channel_enabled(index, enabled);
+                }
+            }
+
+            // How loud the chip is against the console. Kept per chip, since boards with the same
+            // chip were mixed alike and boards with different ones were not.
+            if let Some(chip) = apu.expansion_chip() {
+                let mut level = apu.expansion_level(chip);
+                if ui
+                    .add(Slider::new(&mut level, 0.0..=4.0).text(format!("{} level", chip.label())))
+                    .changed()
+                {
+                    apu.set_expansion_level(chip, level);
+                }
+            }
         });
     }
 }
@@ -0,0 +1,107 @@
+#![allow(dead_code)]
+
+use egui::{Color32, RichText, TextEdit, Ui};
+use rn_core::system::NesSystem;
+
+/// The loaded ROM's cheat codes: switch each on or off, add new ones and remove old ones.
+///
+/// Every change is written to the list beside the ROM straight away, so there is no save button
+/// to forget and nothing lost when the emulator is closed.
+#[derive(Default)]
+pub struct CheatsWidget {
+    code: String,
+    description: String,
+    /// Why the last code could not be added, or the list not saved.
+    error: Option<String>,
+}
+
+impl CheatsWidget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, system: &mut NesSystem) {
+        match system.cheat_file() {
+            Some(path) => ui.small(format!("Kept in {}", path.display())),
+            None => ui.small("Not loaded from a file, so these codes will not be kept."),
+        };
+        ui.separator();
+
+        let mut changed = false;
+        let mut removed = None;
+        {
+            let mut cheats = system.cheats_mut();
+            if cheats.is_empty() {
+                ui.label("No codes yet. Game Genie codes are six or eight letters; RAM codes are AAAA:VV.");
+            }
+            egui::Grid::new("cheat_list")
+                .striped(true)
+                .num_columns(4)
+                .show(ui, |ui| {
+                    for index in 0..cheats.cheats().len() {
+                        let cheat = &cheats.cheats()[index];
+                        let mut enabled = cheat.enabled;
+                        let effect = cheat.effect.to_string();
+                        let description = cheat.description.clone();
+                        if ui
+                            .checkbox(&mut enabled, RichText::new(&cheat.code).monospace())
+                            .changed()
+                        {
+                            cheats.set_enabled(index, enabled);
+                            changed = true;
+                        }
+                        ui.monospace(effect);
+                        ui.label(description);
+                        if ui.small_button("🗑").on_hover_text("Remove this code").clicked() {
+                            removed = Some(index);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(index) = removed {
+                cheats.remove(index);
+                changed = true;
+            }
+        }
+
+        ui.separator();
+        let mut add = false;
+        ui.horizontal(|ui| {
+            let code = ui.add(
+                TextEdit::singleline(&mut self.code)
+                    .hint_text("Code")
+                    .desired_width(90.0),
+            );
+            let description = ui.add(TextEdit::singleline(&mut self.description).hint_text("Description"));
+            let entered = (code.lost_focus() || description.lost_focus())
+                && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            add = ui.button("Add").clicked() || entered;
+        });
+        if add && !self.code.trim().is_empty() {
+            match system.cheats_mut().add(&self.code, &self.description) {
+                Ok(_) => {
+                    self.code.clear();
+                    self.description.clear();
+                    self.error = None;
+                    changed = true;
+                },
+                Err(error) => self.error = Some(error.to_string()),
+            }
+        }
+
+        if changed {
+            if let Err(error) = system.save_cheats() {
+                log::error!("{error}");
+                self.error = Some(error.to_string());
+            }
+        }
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+    }
+
+    /// Forget a half-typed code and any error, once another ROM is loaded.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,129 @@
+#![allow(dead_code)]
+
+use egui::{Color32, Grid, RichText, Ui};
+use rn_core::{
+    cartridge::{mapper_name, BankMemory, BankWindow},
+    system::NesSystem,
+};
+
+/// The cartridge's mapper as it stands: where each part of CPU and PPU space points, its
+/// registers, and its IRQ counter. Redrawn every frame, so it follows the game as it switches.
+#[derive(Default)]
+pub struct MapperWidget {}
+
+impl MapperWidget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, system: &NesSystem) {
+        let cartridge = system.cartridge();
+        let header = cartridge.header();
+        let mapper = cartridge.mapper();
+
+        ui.heading(format!(
+            "Mapper {} ({}), submapper {}",
+            header.mapper,
+            mapper_name(header.mapper).unwrap_or("unknown"),
+            header.submapper
+        ));
+        ui.small(format!(
+            "{} KB PRG ROM, {} KB CHR {}",
+            header.prg_rom_size / 1024,
+            if header.chr_rom_size == 0 {
+                header.chr_ram_total() / 1024
+            } else {
+                header.chr_rom_size / 1024
+            },
+            if header.chr_rom_size == 0 { "RAM" } else { "ROM" }
+        ));
+        ui.separator();
+
+        ui.collapsing("PRG banks", |ui| Self::windows(ui, "mapper_prg_windows", &cartridge.prg_windows()));
+        ui.collapsing("CHR banks", |ui| Self::windows(ui, "mapper_chr_windows", &mapper.chr_windows()));
+
+        ui.collapsing("Registers", |ui| {
+            let registers = mapper.registers();
+            if registers.is_empty() {
+                ui.label("This board has no registers to show.");
+                return;
+            }
+            Grid::new("mapper_registers")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    for register in registers {
+                        ui.label(format!("{}:", register.name));
+                        if register.value > 0xFF {
+                            ui.monospace(format!("${:04X}", register.value));
+                        } else {
+                            ui.monospace(format!("${:02X}", register.value));
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+
+        ui.collapsing("IRQ", |ui| {
+            let Some(irq) = mapper.irq_counter() else {
+                ui.label("This board has no IRQ counter.");
+                return;
+            };
+            Grid::new("mapper_irq")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Counter:");
+                    ui.monospace(format!("{} (${:X})", irq.counter, irq.counter));
+                    ui.end_row();
+
+                    ui.label("Latch:");
+                    ui.monospace(format!("{} (${:X})", irq.latch, irq.latch));
+                    ui.end_row();
+
+                    ui.label("Enabled:");
+                    ui.label(format!("{}", irq.enabled));
+                    ui.end_row();
+
+                    ui.label("Pending:");
+                    if irq.pending {
+                        ui.colored_label(Color32::RED, "IRQ asserted");
+                    } else {
+                        ui.label("false");
+                    }
+                    ui.end_row();
+                });
+        });
+    }
+
+    /// One row per window: the addresses, the memory behind them, the bank and its offset.
+    fn windows(ui: &mut Ui, id: &str, windows: &[BankWindow]) {
+        if windows.is_empty() {
+            ui.label("This board does not say where its banks point.");
+            return;
+        }
+        Grid::new(id)
+            .num_columns(4)
+            .spacing([24.0, 4.0])
+            .striped(true)
+            .show(ui, |ui| {
+                for label in ["Addresses", "Memory", "Bank", "Offset"] {
+                    ui.label(RichText::new(label).strong());
+                }
+                ui.end_row();
+
+                for window in windows {
+                    ui.monospace(format!("${:04X}-${:04X}", window.start, window.end()));
+                    ui.label(match window.memory {
+                        BankMemory::Rom => "ROM",
+                        BankMemory::Ram => "RAM",
+                    });
+                    ui.monospace(format!("{} ({} KB)", window.bank(), window.size / 1024));
+                    ui.monospace(format!("${:06X}", window.offset));
+                    ui.end_row();
+                }
+            });
+    }
+}
@@ -3,6 +3,7 @@
 // Export all widget modules from here
 mod asm_widget;
 mod audio_widget;
+mod cheats_widget;
 mod controller_widget;
 mod cpu_widget;
 mod disasm_widget;
@@ -10,8 +11,10 @@ mod dma_widget;
 mod hex_edit_text;
 mod key_conversion;
 mod keyboard_mappings_widget;
+mod mapper_widget;
 mod memory_viz;
 mod memory_widget;
+mod nsf_player_widget;
 mod pattern_table_widget;
 mod pixel_display;
 mod pixel_provider;
@@ -21,6 +24,7 @@ mod waveform_widget;
 // Re-export types
 pub use asm_widget::AsmWidget;
 pub use audio_widget::{AudioStats, AudioWidget};
+pub use cheats_widget::CheatsWidget;
 pub use controller_widget::ControllerWidget;
 pub use cpu_widget::CpuWidget;
 pub use disasm_widget::DisasmWidget;
@@ -29,8 +33,10 @@ pub use hex_edit_text::{HexEditText, ValueType};
 // Input conversion from egui keys to rn_input keys
 pub use key_conversion::convert_egui_key;
 pub use keyboard_mappings_widget::KeyboardMappingsWidget;
+pub use mapper_widget::MapperWidget;
 pub use memory_viz::MemoryVisualizer;
 pub use memory_widget::MemoryWidget;
+pub use nsf_player_widget::NsfPlayerWidget;
 pub use pattern_table_widget::PatternTableWidget;
 pub use pixel_display::PixelDisplay;
 pub use pixel_provider::{MemoryPixelAdapter, NametableMapAdapter, PixelDataProvider, PpuPixelAdapter};
@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use egui::{ComboBox, DragValue, Ui};
+use rn_core::{apu::Channel, cartridge::Nsf, errors::NesError, system::NesSystem};
+
+/// How long a track plays before the next one starts, when the file does not say. Music in games
+/// loops for ever, so something has to end it; two and a half minutes hears most of them through
+/// at least once.
+const DEFAULT_TRACK_SECONDS: f32 = 150.0;
+
+/// The controls for a loaded NSF: which track, for how long, and which channels are heard.
+///
+/// Time is measured in emulated cycles rather than on the wall clock, so a paused or slowed
+/// machine does not cut a track short.
+#[derive(Default)]
+pub struct NsfPlayerWidget {
+    nsf: Option<Nsf>,
+    track: u8,
+    /// Seconds each track plays for, from the file where it says and the default where not.
+    time_limits: Vec<f32>,
+    /// Go on to the next track when this one's time is up, rather than stopping there.
+    auto_advance: bool,
+    /// The CPU's cycle count when the track started.
+    started_at: u64,
+}
+
+impl NsfPlayerWidget {
+    pub fn new() -> Self {
+        Self {
+            auto_advance: true,
+            ..Self::default()
+        }
+    }
+
+    /// Take over `nsf` and start its first track.
+    pub fn load(&mut self, nsf: Nsf, system: &mut NesSystem) -> Result<(), NesError> {
+        self.time_limits = (0..nsf.songs)
+            .map(|track| {
+                nsf.track_length(track)
+                    .map_or(DEFAULT_TRACK_SECONDS, |length| length.as_secs_f32())
+            })
+            .collect();
+        let first = nsf.first_song;
+        self.nsf = Some(nsf);
+        self.play(first, system, false)
+    }
+
+    /// Forget the file, once something else has been loaded in its place.
+    pub fn eject(&mut self) {
+        self.nsf = None;
+    }
+
+    /// Start `track`, keeping the sound chips muted that were, for a change of track within the
+    /// file. The console's own mutes are the APU's and outlast any cartridge.
+    fn play(&mut self, track: u8, system: &mut NesSystem, keep_mutes: bool) -> Result<(), NesError> {
+        let Some(nsf) = &self.nsf else {
+            return Ok(());
+        };
+        let heard: Vec<bool> = system
+            .apu()
+            .expansion_channels()
+            .iter()
+            .map(|chip| chip.enabled)
+            .collect();
+        system.load_nsf(nsf, track)?;
+        if keep_mutes {
+            for (index, heard) in heard.into_iter().enumerate() {
+                system.apu().set_expansion_channel_enabled(index, heard);
+            }
+        }
+        self.track = track;
+        self.started_at = system.cpu().cycles();
+        Ok(())
+    }
+
+    /// Seconds of emulated time the current track has been playing.
+    fn elapsed(&self, system: &NesSystem) -> f32 {
+        let cycles = system.cpu().cycles().saturating_sub(self.started_at);
+        (cycles as f64 / system.region().cpu_clock_rate()) as f32
+    }
+
+    /// Start the next track if this one has run its time. Called every frame, whether or not the
+    /// tab is showing.
+    pub fn advance_if_due(&mut self, system: &mut NesSystem) {
+        let Some(nsf) = &self.nsf else {
+            return;
+        };
+        let limit = self
+            .time_limits
+            .get(self.track as usize)
+            .copied()
+            .unwrap_or(DEFAULT_TRACK_SECONDS);
+        if !self.auto_advance || self.elapsed(system) < limit {
+            return;
+        }
+        let next = (self.track + 1) % nsf.songs.max(1);
+        if let Err(error) = self.play(next, system, true) {
+            log::warn!("Could not start track {}: {error}", next + 1);
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, system: &mut NesSystem) {
+        let Some(nsf) = &self.nsf else {
+            ui.label("No NSF loaded. Open an .nsf or .nsfe file to play it here.");
+            return;
+        };
+        let songs = nsf.songs;
+        let label = |track: u8| match nsf.track_label(track) {
+            Some(label) => format!("{}. {label}", track + 1),
+            None => format!("Track {}", track + 1),
+        };
+
+        ui.heading(if nsf.title.is_empty() {
+            "Untitled"
+        } else {
+            nsf.title.as_str()
+        });
+        ui.label(&nsf.artist);
+        ui.small(&nsf.copyright);
+        if !nsf.chips.is_empty() {
+            let chips: Vec<&str> = nsf.chips.iter().map(|chip| chip.label()).collect();
+            ui.label(format!("Expansion audio: {}", chips.join(", ")));
+        }
+        ui.separator();
+
+        let mut chosen = None;
+        ui.horizontal(|ui| {
+            if ui.button("⏮").clicked() {
+                chosen = Some((self.track + songs - 1) % songs);
+            }
+            ComboBox::from_id_salt("nsf_track")
+                .selected_text(label(self.track))
+                .show_ui(ui, |ui| {
+                    for track in 0..songs {
+                        if ui.selectable_label(track == self.track, label(track)).clicked() {
+                            chosen = Some(track);
+                        }
+                    }
+                });
+            if ui.button("⏭").clicked() {
+                chosen = Some((self.track + 1) % songs);
+            }
+            if ui.button("Restart").clicked() {
+                chosen = Some(self.track);
+            }
+        });
+
+        let elapsed = self.elapsed(system);
+        if let Some(limit) = self.time_limits.get_mut(self.track as usize) {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}:{:02} of", elapsed as u32 / 60, elapsed as u32 % 60));
+                ui.add(DragValue::new(limit).range(1.0..=3600.0).speed(1.0).suffix(" s"));
+            });
+            ui.add(egui::ProgressBar::new((elapsed / *limit).min(1.0)));
+        }
+        ui.checkbox(&mut self.auto_advance, "Go on to the next track when time is up");
+
+        // Mutes rather than the enable bits, which the music's driver rewrites on every note.
+        ui.separator();
+        ui.label("Channels");
+        let apu = system.apu();
+        ui.horizontal_wrapped(|ui| {
+            for channel in Channel::ALL {
+                let mut heard = !apu.channel_muted(channel);
+                if ui.checkbox(&mut heard, channel.label()).changed() {
+                    apu.set_channel_muted(channel, !heard);
+                }
+            }
+            for (index, chip) in apu.expansion_channels().into_iter().enumerate() {
+                let mut heard = chip.enabled;
+                if ui.checkbox(&mut heard, chip.label).changed() {
+                    apu.set_expansion_channel_enabled(index, heard);
+                }
+            }
+        });
+
+        if let Some(track) = chosen {
+            if let Err(error) = self.play(track, system, true) {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+            }
+        }
+    }
+}
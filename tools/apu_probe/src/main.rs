@@ -11,6 +11,7 @@
 //! apu_probe run pulse
 //! apu_probe run triangle --seconds 3 --out /tmp/tri.wav
 //! apu_probe run --asm asm/simple_tone_test.asm
+//! apu_probe run --nsf music.nsfe --track 3 --out /tmp/track3.wav
 //! apu_probe check
 //! ```
 
@@ -25,7 +26,13 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
-use rn_core::{apu::CPU_CLOCK_RATE, audio::SampleProducer, cpu::Assembler, system::NesSystem};
+use rn_core::{
+    apu::{ExpansionChannel, CPU_CLOCK_RATE},
+    audio::SampleProducer,
+    cartridge::{load_nsf, mapper_name, INesHeader, Rom},
+    cpu::Assembler,
+    system::NesSystem,
+};
 
 const LOAD_ADDRESS: u16 = 0x8000;
 
@@ -54,9 +61,18 @@ enum Command {
         #[arg(long, value_name = "FILE")]
         rom: Option<PathBuf>,
 
-        /// Seconds of emulated time to run
-        #[arg(long, default_value_t = 2.0)]
-        seconds: f64,
+        /// Play a track of an NSF or NSFe music file instead of a preset
+        #[arg(long, value_name = "FILE")]
+        nsf: Option<PathBuf>,
+
+        /// The track to play from --nsf, counting from one. Defaults to the file's own first track.
+        #[arg(long, value_name = "N", requires = "nsf")]
+        track: Option<u8>,
+
+        /// Seconds of emulated time to run. Defaults to 2, or for --nsf to the track's length as
+        /// an NSFe file gives it, or 150.
+        #[arg(long)]
+        seconds: Option<f64>,
 
         /// Output sample rate in Hz
         #[arg(long, default_value_t = 48_000.0)]
@@ -99,8 +115,12 @@ impl SampleProducer<f32> for Capture {
     }
 }
 
-/// Assemble `source`, run it for `seconds` of emulated time, return every sample it played.
-fn capture(source: &str, seconds: f64, sample_rate: f64) -> Result<Vec<f32>> {
+/// Assemble `source`, run it for `seconds` of emulated time, return every sample it played and
+/// the cartridge's channels as they stood at the end.
+///
+/// With a `mapper`, the program runs from a cartridge on that board rather than from memory, so it
+/// can reach the board's sound chip.
+fn capture(source: &str, seconds: f64, sample_rate: f64, mapper: Option<u16>) -> Result<Captured> {
     let mut assembler = Assembler::new(LOAD_ADDRESS).with_nes_segments();
     let segments = assembler
         .assemble_program(source)
@@ -114,10 +134,12 @@ fn capture(source: &str, seconds: f64, sample_rate: f64) -> Result<Vec<f32>> {
     let mut system = NesSystem::new();
     let (sender, receiver) = channel();
     system.connect_audio_output(Box::new(Capture(sender)), sample_rate);
-    system
-        .load_program(code, LOAD_ADDRESS)
-        .map_err(|e| anyhow::anyhow!("{e}"))
-        .context("loading the program")?;
+    match mapper {
+        None => system.load_program(code, LOAD_ADDRESS),
+        Some(mapper) => system.load_rom(&cartridge(code, mapper)),
+    }
+    .map_err(|e| anyhow::anyhow!("{e}"))
+    .context("loading the program")?;
 
     let target = (CPU_CLOCK_RATE * seconds) as u64;
     let mut cycles = 0u64;
@@ -136,19 +158,42 @@ fn capture(source: &str, seconds: f64, sample_rate: f64) -> Result<Vec<f32>> {
         }
     }
 
-    Ok(receiver.try_iter().collect())
+    Ok((receiver.try_iter().collect(), system.apu().expansion_channels()))
+}
+
+/// What a run played, and the cartridge's sound channels as it left them.
+type Captured = (Vec<f32>, Vec<ExpansionChannel>);
+
+/// A 32 KB cartridge on `mapper` with `code` at `$8000` and every vector pointing at it.
+///
+/// Every mapper here powers up with the start of the image at `$8000` and its end at `$E000`, so
+/// the program and the vectors are both where the CPU will look without any bank switching.
+fn cartridge(code: &[u8], mapper: u16) -> Rom {
+    let mut prg = vec![0xEA; 32 * 1024];
+    prg[..code.len()].copy_from_slice(code);
+    for vector in (0x7FFA..0x8000).step_by(2) {
+        prg[vector..vector + 2].copy_from_slice(&LOAD_ADDRESS.to_le_bytes());
+    }
+
+    Rom {
+        header: INesHeader::for_mapper(mapper),
+        prg_rom: prg,
+        chr_rom: vec![0; 8 * 1024],
+        path: None,
+        correction: None,
+    }
 }
 
 /// Run an iNES ROM and capture what the APU produced.
 ///
 /// The same measurement as for assembly, but entered through the reset vector — which is how a
 /// real game starts, and the only way to answer "why is this game silent?".
-fn capture_rom(path: &std::path::Path, seconds: f64, sample_rate: f64) -> Result<Vec<f32>> {
+fn capture_rom(path: &std::path::Path, seconds: f64, sample_rate: f64) -> Result<Captured> {
     let rom = rn_core::cartridge::load_rom(path)
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", path.display()))?;
 
-    if rom.header.mapper != 0 {
+    if mapper_name(rom.header.mapper).is_none() {
         eprintln!(
             "warning: mapper {} is not implemented; this ROM will not run correctly",
             rom.header.mapper
@@ -175,7 +220,64 @@ fn capture_rom(path: &std::path::Path, seconds: f64, sample_rate: f64) -> Result
         }
     }
 
-    Ok(receiver.try_iter().collect())
+    Ok((receiver.try_iter().collect(), system.apu().expansion_channels()))
+}
+
+/// Seconds of a music file's track to play when the file does not say how long it is: long
+/// enough for most to loop at least once.
+const DEFAULT_TRACK_SECONDS: f64 = 150.0;
+
+/// Play `track` (counting from one) of an NSF or NSFe file, and capture what the APU produced.
+///
+/// Returns the seconds played too, which are the track's own length when neither `seconds` nor
+/// anything else was asked for.
+fn capture_nsf(
+    path: &std::path::Path,
+    track: Option<u8>,
+    seconds: Option<f64>,
+    sample_rate: f64,
+) -> Result<(Captured, f64)> {
+    let nsf = load_nsf(path)
+        .map_err(|e| anyhow::anyhow!("{e}"))
+        .with_context(|| format!("loading {}", path.display()))?;
+    let track = match track {
+        Some(0) => bail!("tracks count from 1"),
+        Some(track) if track > nsf.songs => bail!("{} has {} tracks", path.display(), nsf.songs),
+        Some(track) => track - 1,
+        None => nsf.first_song,
+    };
+    let seconds = seconds
+        .or(nsf.track_length(track).map(|length| length.as_secs_f64()))
+        .unwrap_or(DEFAULT_TRACK_SECONDS);
+
+    let label = nsf.track_label(track).map(|label| format!(" \"{label}\"")).unwrap_or_default();
+    println!("\"{}\" by {}, track {} of {}{label}", nsf.title, nsf.artist, track + 1, nsf.songs);
+    if !nsf.chips.is_empty() {
+        println!("Expansion audio: {:?}", nsf.chips);
+    }
+
+    let mut system = NesSystem::new();
+    let (sender, receiver) = channel();
+    system.connect_audio_output(Box::new(Capture(sender)), sample_rate);
+    system
+        .load_nsf(&nsf, track)
+        .map_err(|e| anyhow::anyhow!("{e}"))
+        .context("building the player")?;
+
+    // A PAL file's seconds are PAL cycles.
+    let target = (system.region().cpu_clock_rate() * seconds) as u64;
+    let mut cycles = 0u64;
+    while cycles < target {
+        match system.step() {
+            Ok(step_cycles) => cycles += step_cycles.max(1) as u64,
+            Err(error) => {
+                eprintln!("warning: stopped at PC ${:04X}: {error}", system.cpu().pc());
+                break;
+            },
+        }
+    }
+
+    Ok(((receiver.try_iter().collect(), system.apu().expansion_channels()), seconds))
 }
 
 fn main() -> Result<()> {
@@ -197,29 +299,39 @@ fn main() -> Result<()> {
             preset,
             asm,
             rom,
+            nsf,
+            track,
             seconds,
             rate,
             out,
             dump,
             segments,
         } => {
-            let samples;
+            let (samples, channels);
             let label;
             let mut expected_hz = None;
+            let requested_seconds = seconds;
+            let mut seconds = requested_seconds.unwrap_or(2.0);
 
-            if let Some(path) = rom {
-                if preset.is_some() || asm.is_some() {
-                    bail!("give only one of a preset name, --asm or --rom");
-                }
+            let sources = [preset.is_some(), asm.is_some(), rom.is_some(), nsf.is_some()];
+            if sources.iter().filter(|&&given| given).count() > 1 {
+                bail!("give only one of a preset name, --asm, --rom or --nsf");
+            }
+            if let Some(path) = nsf {
+                label = format!("{}", path.display());
+                println!("Playing {label} at {rate:.0} Hz\n");
+                ((samples, channels), seconds) = capture_nsf(&path, track, requested_seconds, rate)?;
+                println!();
+            } else if let Some(path) = rom {
                 label = format!("{}", path.display());
                 println!("Running {label} for {seconds}s at {rate:.0} Hz\n");
-                samples = capture_rom(&path, seconds, rate)?;
+                (samples, channels) = capture_rom(&path, seconds, rate)?;
             } else {
-                let (resolved_label, source, hz) = resolve(preset, asm)?;
+                let (resolved_label, source, hz, mapper) = resolve(preset, asm)?;
                 label = resolved_label;
                 expected_hz = hz;
                 println!("Running {label} for {seconds}s at {rate:.0} Hz\n");
-                samples = capture(&source, seconds, rate)?;
+                (samples, channels) = capture(&source, seconds, rate, mapper)?;
             }
 
             let result = analysis::analyse(&samples, rate, seconds);
@@ -228,6 +340,15 @@ fn main() -> Result<()> {
                 result.report_expected_pitch(hz);
             }
 
+            // The console's channels are all in the figures above; a cartridge's sound chip is in
+            // them too, but says which of its channels were playing only when asked.
+            if !channels.is_empty() {
+                println!("\nCartridge channels");
+                for channel in &channels {
+                    println!("  {:<16} {}", channel.label, if channel.enabled { "enabled" } else { "off" });
+                }
+            }
+
             if let Some(count) = segments {
                 println!("\nOver time ({count} windows)");
                 println!("  {:>8}  {:>10}  {:>7}", "start", "pitch", "peak");
@@ -253,7 +374,7 @@ fn main() -> Result<()> {
             let mut failures = 0;
 
             for preset in programs::all() {
-                let samples = capture(preset.source, seconds, rate)?;
+                let (samples, _) = capture(preset.source, seconds, rate, preset.mapper)?;
                 let result = analysis::analyse(&samples, rate, seconds);
                 let mut problems = Vec::new();
 
@@ -309,7 +430,7 @@ fn main() -> Result<()> {
 }
 
 /// Work out what to run: a named preset, or a source file.
-fn resolve(preset: Option<String>, asm: Option<PathBuf>) -> Result<(String, String, Option<f64>)> {
+fn resolve(preset: Option<String>, asm: Option<PathBuf>) -> Result<(String, String, Option<f64>, Option<u16>)> {
     match (preset, asm) {
         (Some(_), Some(_)) => bail!("give either a preset name or --asm, not both"),
 
@@ -328,13 +449,14 @@ fn resolve(preset: Option<String>, asm: Option<PathBuf>) -> Result<(String, Stri
                 format!("preset '{}'", preset.name),
                 preset.source.to_string(),
                 preset.expected_hz,
+                preset.mapper,
             ))
         },
 
         (None, Some(path)) => {
             let source = std::fs::read_to_string(&path)
                 .with_context(|| format!("reading {}", path.display()))?;
-            Ok((format!("{}", path.display()), source, None))
+            Ok((format!("{}", path.display()), source, None, None))
         },
 
         (None, None) => bail!("give a preset name or --asm (see `apu_probe list`)"),
@@ -16,6 +16,9 @@ pub struct Preset {
     pub source: &'static str,
     /// The pitch this program should produce, if it is a tone.
     pub expected_hz: Option<f64>,
+    /// Run from a cartridge on this mapper, for a program that plays the cartridge's own sound
+    /// chip. `None` runs it straight from memory, which is all the console's channels need.
+    pub mapper: Option<u16>,
 }
 
 pub fn all() -> Vec<Preset> {
@@ -25,36 +28,77 @@ pub fn all() -> Vec<Preset> {
             description: "Pulse 1, 25% duty, constant volume 15, period 254",
             source: PULSE,
             expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / (16.0 * (PERIOD as f64 + 1.0))),
+            mapper: None,
         },
         Preset {
             name: "pulse-both",
             description: "Both pulse channels, slightly detuned, to exercise the mixer",
             source: PULSE_BOTH,
             expected_hz: None,
+            mapper: None,
         },
         Preset {
             name: "triangle",
             description: "Triangle channel, period 254 (an octave below the pulse preset)",
             source: TRIANGLE,
             expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / (32.0 * (PERIOD as f64 + 1.0))),
+            mapper: None,
         },
         Preset {
             name: "noise",
             description: "Noise channel, period index 4, constant volume 15",
             source: NOISE,
             expected_hz: None,
+            mapper: None,
         },
         Preset {
             name: "sweep",
             description: "Pulse 1 with the sweep unit enabled, to hear the pitch slide",
             source: SWEEP,
             expected_hz: None,
+            mapper: None,
+        },
+        Preset {
+            name: "vrc6-pulse",
+            description: "VRC6 pulse 1 on a mapper 24 cartridge, 50% duty, volume 15, period 254",
+            source: VRC6_PULSE,
+            expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / (16.0 * (PERIOD as f64 + 1.0))),
+            mapper: Some(24),
+        },
+        Preset {
+            name: "vrc6-saw",
+            description: "VRC6 sawtooth on a mapper 24 cartridge, rate 42, period 254",
+            source: VRC6_SAW,
+            expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / (14.0 * (PERIOD as f64 + 1.0))),
+            mapper: Some(24),
+        },
+        Preset {
+            name: "vrc7-sine",
+            description: "VRC7 channel 1 on a mapper 85 cartridge, custom sine instrument, F-number 289, block 4",
+            source: VRC7_SINE,
+            expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / 36.0 * 289.0 * 16.0 / (1 << 19) as f64),
+            mapper: Some(85),
+        },
+        Preset {
+            name: "n163-square",
+            description: "Namco 163 channel 8 alone on a mapper 19 cartridge, 16-sample square wave, frequency $2000",
+            source: N163_SQUARE,
+            expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE * 8192.0 / (15.0 * 16.0 * 65536.0)),
+            mapper: Some(19),
+        },
+        Preset {
+            name: "5b-square",
+            description: "Sunsoft 5B channel A on a mapper 69 cartridge, square only, volume 15, period 254",
+            source: SUNSOFT_5B_SQUARE,
+            expected_hz: Some(rn_core::apu::CPU_CLOCK_RATE / (32.0 * PERIOD as f64)),
+            mapper: Some(69),
         },
         Preset {
             name: "silence",
             description: "All channels disabled — output must be exactly zero",
             source: SILENCE,
             expected_hz: None,
+            mapper: None,
         },
     ]
 }
@@ -160,3 +204,157 @@ RESET:
 Loop:
   JMP Loop
 "#;
+
+/// VRC6's pulses have no length counter or `$4015` bit: the enable is bit 7 of the period's high
+/// byte, so three writes start one.
+const VRC6_PULSE: &str = r#"
+.segment "STARTUP"
+RESET:
+  LDA #%01111111  ; duty 7 of 16 (50%), volume 15
+  STA $9000
+  LDA #$FE        ; period low (254)
+  STA $9001
+  LDA #%10000000  ; enable, period high 0
+  STA $9002
+Loop:
+  JMP Loop
+"#;
+
+const VRC6_SAW: &str = r#"
+.segment "STARTUP"
+RESET:
+  LDA #$2A        ; rate 42, the largest that does not wrap the accumulator
+  STA $B000
+  LDA #$FE
+  STA $B001
+  LDA #%10000000
+  STA $B002
+Loop:
+  JMP Loop
+"#;
+
+/// VRC7's chip is reached a register at a time: the number to `$9010`, then the value to `$9030`.
+///
+/// The custom instrument is set up as a bare sine — the modulator silenced, the carrier held at
+/// full level — so the pitch is the F-number's alone. A built-in instrument's harmonics would make
+/// the zero-crossing count wander.
+const VRC7_SINE: &str = r#"
+.segment "STARTUP"
+RESET:
+  LDA #$00
+  STA $9010
+  LDA #$01        ; modulator: multiplier 1
+  STA $9030
+  LDA #$01
+  STA $9010
+  LDA #$21        ; carrier: sustained, multiplier 1
+  STA $9030
+  LDA #$02
+  STA $9010
+  LDA #$3F        ; modulator level 63, the quietest
+  STA $9030
+  LDA #$03
+  STA $9010
+  LDA #$00        ; no feedback, full sines
+  STA $9030
+  LDA #$04
+  STA $9010
+  LDA #$FF        ; modulator: instant attack, fast decay
+  STA $9030
+  LDA #$05
+  STA $9010
+  LDA #$F0        ; carrier: instant attack, no decay
+  STA $9030
+  LDA #$06
+  STA $9010
+  LDA #$0F        ; modulator: fast release
+  STA $9030
+  LDA #$07
+  STA $9010
+  LDA #$0F        ; carrier: fast release
+  STA $9030
+  LDA #$30        ; channel 1: instrument, volume
+  STA $9010
+  LDA #$00        ; the custom instrument, loudest
+  STA $9030
+  LDA #$10        ; channel 1: F-number low
+  STA $9010
+  LDA #$21        ; 289 & $FF
+  STA $9030
+  LDA #$20        ; channel 1: key, block, F-number bit 8
+  STA $9010
+  LDA #%00011001  ; key on, block 4, bit 8 set
+  STA $9030
+Loop:
+  JMP Loop
+"#;
+
+/// Namco 163's channels are bytes in its RAM, written through `$F800` (the address) and `$4800`
+/// (the data). Channel 8 is the one that plays when only one does.
+const N163_SQUARE: &str = r#"
+.segment "STARTUP"
+RESET:
+  LDA #$80        ; RAM address 0, incrementing
+  STA $F800
+  LDA #$FF        ; the wave: eight samples at 15, eight at 0
+  STA $4800
+  LDA #$FF
+  STA $4800
+  LDA #$FF
+  STA $4800
+  LDA #$FF
+  STA $4800
+  LDA #$00
+  STA $4800
+  LDA #$00
+  STA $4800
+  LDA #$00
+  STA $4800
+  LDA #$00
+  STA $4800
+  LDA #$F8        ; channel 8's registers, incrementing
+  STA $F800
+  LDA #$00        ; frequency low
+  STA $4800
+  LDA #$00        ; phase low
+  STA $4800
+  LDA #$20        ; frequency middle
+  STA $4800
+  LDA #$00        ; phase middle
+  STA $4800
+  LDA #$F0        ; length 16, frequency high 0
+  STA $4800
+  LDA #$00        ; phase high
+  STA $4800
+  LDA #$00        ; the wave starts at sample 0
+  STA $4800
+  LDA #$0F        ; one channel, volume 15
+  STA $4800
+Loop:
+  JMP Loop
+"#;
+
+/// The 5B is a YM2149: register number to `$C000`, value to `$E000`. Register 7 starts with every
+/// channel's square and noise off, so it has to be written as well as the period and volume.
+const SUNSOFT_5B_SQUARE: &str = r#"
+.segment "STARTUP"
+RESET:
+  LDA #$00        ; channel A period, low
+  STA $C000
+  LDA #$FE        ; 254
+  STA $E000
+  LDA #$01        ; channel A period, high
+  STA $C000
+  LDA #$00
+  STA $E000
+  LDA #$07        ; mixer
+  STA $C000
+  LDA #%00111110  ; channel A's square on; everything else off
+  STA $E000
+  LDA #$08        ; channel A volume
+  STA $C000
+  LDA #$0F        ; 15, fixed
+  STA $E000
+Loop:
+  JMP Loop
+"#;
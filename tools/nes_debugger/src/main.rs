@@ -13,7 +13,10 @@ use egui_dock::{DockArea, DockState, NodeIndex, Style, TabViewer};
 extern crate log;
 use rn_audio::{AudioControls, ChannelBuilder, CpalAudioBuilder, CpalAudioConsumer, Multiplexer};
 use rn_core::{
-    cartridge::load_rom,
+    cartridge::{
+        is_archive, load_fds_bios, load_rom_from_archive, load_rom_with_patch, patch_beside, unpack_archive, Nsf,
+        RomLoadError, NSFE_MAGIC, NSF_MAGIC, UNIF_MAGIC,
+    },
     cpu::CpuWrapper,
     errors::NesError,
     memory::Addressable,
@@ -26,14 +29,17 @@ use rn_ui::widgets::{
     AsmWidget,
     AudioStats,
     AudioWidget,
+    CheatsWidget,
     ControllerWidget,
     CpuWidget,
     DisasmWidget,
     DmaControllerWidget,
     KeyboardMappingsWidget,
+    MapperWidget,
     MemoryPixelAdapter,
     MemoryWidget,
     NametableMapAdapter,
+    NsfPlayerWidget,
     PatternTableWidget,
     PixelDisplay,
     PpuPixelAdapter,
@@ -91,7 +97,8 @@ const MAX_CATCH_UP_FRAMES: u32 = 2;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// File to load on startup: either an iNES ROM (.nes) or 6502 assembly.
+    /// File to load on startup: an iNES ROM (.nes), a UNIF ROM (.unf), an FDS disk image (.fds), an
+    /// NSF or NSFe music file (.nsf, .nsfe) or 6502 assembly.
     ///
     /// Detected by content, not extension.
     #[arg(value_name = "FILE")]
@@ -100,6 +107,19 @@ struct Args {
     /// Start running as soon as the file is loaded, instead of waiting for Run to be pressed.
     #[arg(long, short = 'p')]
     play: bool,
+
+    /// Restore battery saves (the `.sav` beside a ROM) but never write them back.
+    #[arg(long)]
+    read_only_saves: bool,
+
+    /// The Famicom Disk System BIOS (`disksys.rom`, 8 KB), needed to run .fds disk images.
+    #[arg(long, value_name = "FILE")]
+    fds_bios: Option<PathBuf>,
+
+    /// An IPS, UPS or BPS patch to apply to the ROM. Without it, one beside the ROM under the
+    /// same name is applied if there is one.
+    #[arg(long, value_name = "FILE")]
+    patch: Option<PathBuf>,
 }
 
 /// Adapter to use CPU's memory with the memory editor
@@ -146,12 +166,15 @@ enum DockTab {
     AssembledCode,
     Assembly,
     Audio,
+    Cheats,
     Controller,
     Cpu,
     Disassembly,
     Display,
     Dma,
+    Mapper,
     Memory,
+    NsfPlayer,
     PatternTable,
     Ppu,
     WaveformVisualizer,
@@ -163,12 +186,15 @@ impl DockTab {
             DockTab::AssembledCode => "Assembled Code",
             DockTab::Assembly => "Assembly",
             DockTab::Audio => "Audio Controls",
+            DockTab::Cheats => "Cheats",
             DockTab::Controller => "Controller State",
             DockTab::Cpu => "CPU State",
             DockTab::Disassembly => "Disassembly",
             DockTab::Display => "Display",
             DockTab::Dma => "DMA State",
+            DockTab::Mapper => "Mapper",
             DockTab::Memory => "Memory",
+            DockTab::NsfPlayer => "NSF Player",
             DockTab::PatternTable => "Pattern Tables",
             DockTab::Ppu => "PPU State",
             DockTab::WaveformVisualizer => "Audio Waveform",
@@ -203,6 +229,9 @@ struct NesDebugger {
     pixel_display: PixelDisplay,
     ppu_widget: PpuWidget,
     audio_widget: AudioWidget,
+    nsf_player: NsfPlayerWidget,
+    cheats_widget: CheatsWidget,
+    mapper_widget: MapperWidget,
     waveform_visualizer: WaveformWidget,
     audio_output: CpalAudioConsumer,
     /// Buffer fill level and underrun/drop counts for the running audio stream.
@@ -230,6 +259,17 @@ struct NesDebugger {
     fullscreen: bool,
     /// Result of the most recent frame dump, shown beside the button.
     last_dump: Option<String>,
+    /// What the cartridge database corrected in the loaded ROM's header, shown in the toolbar so
+    /// a game running as PAL despite its header does not look like a bug.
+    header_correction: Option<String>,
+    /// The ROM that is loaded, so that choosing a patch can load it again with the patch applied.
+    rom_path: Option<PathBuf>,
+    /// The patch chosen for the loaded ROM, in place of any found beside it.
+    patch: Option<PathBuf>,
+    /// Which ROM to take from a zip archive holding several, once one has been chosen.
+    archive_entry: Option<String>,
+    /// A zip archive holding several ROMs and their names, while the user is asked which to load.
+    archive_choice: Option<(PathBuf, Vec<String>)>,
     repaints_per_frame: u32,
     /// Repaints since the last emulated frame, for the locked cadence.
     repaints_since_frame: u32,
@@ -269,6 +309,9 @@ struct NesTabViewer<'a> {
     memory_widget: &'a mut MemoryWidget,
     pattern_table_widget: &'a mut PatternTableWidget,
     audio_widget: &'a mut AudioWidget,
+    nsf_player: &'a mut NsfPlayerWidget,
+    cheats_widget: &'a mut CheatsWidget,
+    mapper_widget: &'a mut MapperWidget,
     audio_stats: AudioStats,
     /// The active controller mapping, so the Controller tab can show what is bound.
     controller_profile: &'a ControllerProfile,
@@ -360,14 +403,9 @@ impl<'a> TabViewer for NesTabViewer<'a> {
                     .id_salt("pattern_table_scroll")
                     .show(ui, |ui| {
                         let system_borrow = self.system.borrow();
-                        // Get cartridge reference from the system and convert to the expected format
-                        if let Some(cart_rc) = system_borrow.ppu().cartridge() {
-                            // Pass a reference to the cloned Rc
-                            let _ = self.pattern_table_widget.ui(ui, Some(&cart_rc));
-                        } else {
-                            // No cartridge
-                            let _ = self.pattern_table_widget.ui(ui, None);
-                        }
+                        // The cartridge in the slot, so a loaded ROM shows its CHR banks as the
+                        // PPU sees them and an assembled program shows its own tiles.
+                        let _ = self.pattern_table_widget.ui(ui, Some(&system_borrow.cartridge()));
                     });
             },
             DockTab::Cpu => {
@@ -537,6 +575,9 @@ impl<'a> TabViewer for NesTabViewer<'a> {
                             rn_core::ppu::Mirroring::SingleScreenUpper => {
                                 "Single-screen (upper): all four show the same table"
                             },
+                            rn_core::ppu::Mirroring::FourScreen => {
+                                "Four-screen: all four are separate memory"
+                            },
                         });
                         ui.add_space(4.0);
 
@@ -561,6 +602,18 @@ impl<'a> TabViewer for NesTabViewer<'a> {
                 // Use the audio widget
                 self.audio_widget.ui(ui, system.apu(), self.audio_stats);
             },
+            DockTab::NsfPlayer => {
+                let mut system = self.system.borrow_mut();
+                self.nsf_player.ui(ui, &mut system);
+            },
+            DockTab::Cheats => {
+                let mut system = self.system.borrow_mut();
+                self.cheats_widget.ui(ui, &mut system);
+            },
+            DockTab::Mapper => {
+                let system = self.system.borrow();
+                self.mapper_widget.ui(ui, &system);
+            },
             DockTab::WaveformVisualizer => {
                 // Waveform Visualizer Tab content
                 self.waveform_visualizer.ui(ui);
@@ -578,6 +631,13 @@ impl NesDebugger {
     fn new(_cc: &eframe::CreationContext<'_>, args: Args) -> Result<Self> {
         // Create the NES system
         let system = Rc::new(RefCell::new(NesSystem::new()));
+        system.borrow_mut().set_battery_read_only(args.read_only_saves);
+        if let Some(path) = &args.fds_bios {
+            let bios = load_fds_bios(path)
+                .map_err(|e| anyhow::anyhow!("{e}"))
+                .with_context(|| format!("loading the FDS BIOS {}", path.display()))?;
+            system.borrow_mut().set_fds_bios(Some(bios));
+        }
 
         let (audio_producer, audio_consumer) = CpalAudioBuilder::build_default()?;
 
@@ -627,6 +687,9 @@ impl NesDebugger {
             DockTab::Memory,
             DockTab::PatternTable,
             DockTab::Audio,
+            DockTab::NsfPlayer,
+            DockTab::Cheats,
+            DockTab::Mapper,
         ]);
 
         // Create layout with Assembly/Memory/PatternTable in center, and CPU/PPU on the left
@@ -666,9 +729,13 @@ impl NesDebugger {
 
         // Create an instance with all components
         Ok(Self {
+            patch: args.patch.clone(),
             args,
             asm_widget: AsmWidget::new(),
             audio_widget,
+            nsf_player: NsfPlayerWidget::new(),
+            cheats_widget: CheatsWidget::new(),
+            mapper_widget: MapperWidget::new(),
             cpu_widget: CpuWidget::new(),
             ppu_widget: PpuWidget::new(),
             dma_widget: DmaControllerWidget::new(),
@@ -689,6 +756,10 @@ impl NesDebugger {
             save_state_path: None,
             fullscreen: false,
             last_dump: None,
+            header_correction: None,
+            rom_path: None,
+            archive_entry: None,
+            archive_choice: None,
             repaints_per_frame: 0,
             repaints_since_frame: 0,
             fps_window_start: std::time::Instant::now(),
@@ -759,27 +830,83 @@ impl NesDebugger {
         });
     }
 
-    /// Load a `.nes` ROM or 6502 assembly, from the command line or the File menu.
+    /// Load a `.nes` or `.unf` ROM, an `.fds` disk, an `.nsf` or `.nsfe` tune or 6502 assembly, from
+    /// the command line or the File menu.
     ///
     /// Detected by content rather than by extension: an iNES image starts with the four bytes
-    /// `NES\x1A`. Reading a ROM as text fails with "stream did not contain valid UTF-8", which
-    /// says nothing useful about what the user actually passed.
+    /// `NES\x1A`, a UNIF image with `UNIF`, a disk image with `FDS\x1A` or with its first side's
+    /// disk header, and music with `NESM\x1A` or `NSFE`. Reading a ROM as text fails with "stream did
+    /// not contain valid UTF-8", which says nothing useful about what the user actually passed.
     fn load_file(&mut self, path: &Path) -> Result<()> {
-        let bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut bytes = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+
+        // A zip or gzip archive is looked inside, and everything below sees what it holds. With
+        // several ROMs in it, nothing is loaded until the user has picked one.
+        if is_archive(&bytes) {
+            bytes = match unpack_archive(&bytes, self.archive_entry.as_deref()) {
+                Ok(unpacked) => unpacked,
+                Err(RomLoadError::ArchiveHasSeveral(names)) => {
+                    self.archive_choice = Some((path.to_path_buf(), names));
+                    anyhow::bail!("{} holds several ROMs; choose one to load", path.display());
+                },
+                Err(error) => anyhow::bail!("opening {}: {error}", path.display()),
+            };
+        }
 
         // One snapshot slot per file, beside it, so loading a different game cannot restore the
         // wrong machine into it.
         self.save_state_path = Some(path.with_extension("state.json"));
-
-        if bytes.starts_with(b"NES\x1A") {
-            info!("Loading iNES ROM: {}", path.display());
-            let rom = load_rom(path).map_err(|e| anyhow::anyhow!("{e}"))?;
+        self.header_correction = None;
+        self.cheats_widget.clear();
+
+        // Music has no picture, so it opens on the player instead.
+        if bytes.starts_with(&NSF_MAGIC) || bytes.starts_with(&NSFE_MAGIC) {
+            info!("Loading NSF: {}", path.display());
+            let nsf = Nsf::parse(&bytes).map_err(|e| anyhow::anyhow!("{e}"))?;
+            self.nsf_player
+                .load(nsf, &mut self.system.borrow_mut())
+                .map_err(|e| anyhow::anyhow!("{e}"))?;
+            self.rom_path = None;
+            if let Some(tab) = self.dock_state.find_tab(&DockTab::NsfPlayer) {
+                self.dock_state.set_active_tab(tab);
+            }
+            return Ok(());
+        }
+        self.nsf_player.eject();
+
+        let disk = bytes.starts_with(b"FDS\x1A") || bytes.starts_with(b"\x01*NINTENDO-HVC*");
+        let unif = bytes.starts_with(&UNIF_MAGIC);
+        if bytes.starts_with(b"NES\x1A") || disk || unif {
+            let kind = match (disk, unif) {
+                (true, _) => "FDS disk",
+                (_, true) => "UNIF ROM",
+                _ => "iNES ROM",
+            };
+            info!("Loading {kind}: {}", path.display());
+            let patch = self.patch.clone().or_else(|| patch_beside(path));
+            if let Some(patch) = &patch {
+                info!("Applying {}", patch.display());
+            }
+            let rom = match &self.archive_entry {
+                Some(entry) => load_rom_from_archive(path, entry, patch.as_deref()),
+                None => load_rom_with_patch(path, patch.as_deref()),
+            }
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+            self.rom_path = Some(path.to_path_buf());
+            if let Some(correction) = &rom.correction {
+                info!("Header corrected from the cartridge database: {correction}");
+                self.header_correction = Some(correction.to_string());
+            }
 
             self.system
                 .borrow_mut()
                 .load_rom(&rom)
                 .map_err(|e| anyhow::anyhow!("{e}"))?;
 
+            if let Some(save) = self.system.borrow().battery_path() {
+                info!("Battery saves go to {}", save.display());
+            }
+
             // No mapper warning here: `load_rom` refuses a ROM whose mapper is not implemented,
             // with a message naming what is supported. Warning separately meant maintaining a
             // second, independent idea of what works — which is exactly how it came to claim that
@@ -794,6 +921,7 @@ impl NesDebugger {
             .with_context(|| format!("{} is neither an iNES ROM nor valid UTF-8 assembly", path.display()))?;
 
         info!("Loading assembly: {}", path.display());
+        self.rom_path = None;
         self.asm_widget = AsmWidget::with_code(&source);
 
         let mut system = self.system.borrow_mut();
@@ -805,6 +933,47 @@ impl NesDebugger {
         Ok(())
     }
 
+    /// Ask which ROM to load from an archive holding several, and load it once one is clicked.
+    fn archive_choice_ui(&mut self, ctx: &egui::Context) {
+        let Some((path, names)) = &self.archive_choice else {
+            return;
+        };
+
+        let mut chosen = None;
+        let mut cancelled = false;
+        egui::Window::new("Choose a ROM")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("{} holds several ROMs:", path.display()));
+                egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    for name in names {
+                        if ui.button(name).clicked() {
+                            chosen = Some(name.clone());
+                        }
+                    }
+                });
+                ui.separator();
+                cancelled = ui.button("Cancel").clicked();
+            });
+
+        if cancelled {
+            self.archive_choice = None;
+        }
+        if let Some(entry) = chosen {
+            let (path, _) = self.archive_choice.take().expect("the choice being shown");
+            self.archive_entry = Some(entry.clone());
+            match self.load_file(&path) {
+                Ok(()) => self.last_dump = Some(format!("loaded {entry} from {}", path.display())),
+                Err(error) => {
+                    error!("loading {entry} from {}: {error:#}", path.display());
+                    self.last_dump = Some(format!("failed to load: {error}"));
+                },
+            }
+        }
+    }
+
     /// Start or stop the audio stream to match whether the emulator is running.
     ///
     /// Idempotent, so it is safe to call every frame: it only acts on a transition.
@@ -934,6 +1103,15 @@ impl NesDebugger {
 }
 
 impl App for NesDebugger {
+    /// Write the battery save before the window goes, where a failure can still be reported.
+    ///
+    /// Dropping the system would flush it too, but only into a log nobody is watching by then.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Err(error) = self.system.borrow_mut().flush_battery() {
+            error!("{error}");
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
         // Shortcuts are read here, before the controller block below empties the event queue.
         //
@@ -1053,6 +1231,7 @@ impl App for NesDebugger {
                         break;
                     }
                 }
+                self.nsf_player.advance_if_due(&mut system);
                 self.frames_in_window += frames;
             }
 
@@ -1075,10 +1254,17 @@ impl App for NesDebugger {
                         // whichever way it arrives — including the content sniffing that tells a
                         // ROM from assembly.
                         if let Some(path) = rfd::FileDialog::new()
-                            .add_filter("NES ROM or 6502 assembly", &["nes", "asm", "s", "txt"])
+                            .add_filter(
+                                "NES ROM, FDS disk, NSF music or 6502 assembly",
+                                &["nes", "unf", "unif", "fds", "nsf", "nsfe", "zip", "gz", "asm", "s", "txt"],
+                            )
                             .add_filter("All files", &["*"])
                             .pick_file()
                         {
+                            // A patch chosen for the last ROM is no patch for this one, and an
+                            // entry chosen from the last archive names nothing in this one.
+                            self.patch = None;
+                            self.archive_entry = None;
                             match self.load_file(&path) {
                                 Ok(()) => self.last_dump = Some(format!("loaded {}", path.display())),
                                 Err(error) => {
@@ -1089,6 +1275,29 @@ impl App for NesDebugger {
                         }
                     }
 
+                    if ui
+                        .add_enabled(self.rom_path.is_some(), egui::Button::new("Apply Patch..."))
+                        .on_hover_text("Load the ROM again with an IPS, UPS or BPS patch applied")
+                        .clicked()
+                    {
+                        ui.close_menu();
+                        let picked = rfd::FileDialog::new()
+                            .add_filter("ROM patch", &["ips", "ups", "bps"])
+                            .add_filter("All files", &["*"])
+                            .pick_file();
+                        if let (Some(patch), Some(rom)) = (picked, self.rom_path.clone()) {
+                            let previous = self.patch.replace(patch.clone());
+                            match self.load_file(&rom) {
+                                Ok(()) => self.last_dump = Some(format!("patched with {}", patch.display())),
+                                Err(error) => {
+                                    error!("patching {}: {error:#}", rom.display());
+                                    self.last_dump = Some(format!("failed to patch: {error}"));
+                                    self.patch = previous;
+                                },
+                            }
+                        }
+                    }
+
                     if ui.button("Save As...").clicked() {
                         // File save code would go here - can be added later
                         info!("File Save As clicked - functionality not yet implemented");
@@ -1121,6 +1330,27 @@ impl App for NesDebugger {
 
                     ui.separator();
 
+                    // Only a disk image has sides; the drive takes about a second to swap them,
+                    // which the game sees as the disk going out and the next coming in.
+                    if self.system.borrow().disk_sides() > 0 {
+                        let label = match self.system.borrow().disk_side() {
+                            Some(side) => {
+                                format!("Flip Disk Side (in: disk {} side {})", side / 2 + 1, ["A", "B"][side % 2])
+                            },
+                            None => "Insert Disk".to_string(),
+                        };
+                        if ui.button(label).clicked() {
+                            self.system.borrow_mut().flip_disk_side();
+                            info!("Disk side now {:?}", self.system.borrow().disk_side());
+                            ui.close_menu();
+                        }
+                        if ui.button("Eject Disk").clicked() {
+                            self.system.borrow_mut().insert_disk(None);
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                    }
+
                     // Controller profile submenu
                     ui.menu_button("Controller Profile", |ui| {
                         // Default profile
@@ -1215,6 +1445,10 @@ impl App for NesDebugger {
                 if let Some(message) = &self.last_dump {
                     ui.label(message);
                 }
+                if let Some(correction) = &self.header_correction {
+                    ui.colored_label(egui::Color32::YELLOW, "⚠ header corrected")
+                        .on_hover_text(correction);
+                }
 
                 ui.add_space(4.0);
                 ui.separator();
@@ -1392,6 +1626,9 @@ impl App for NesDebugger {
                 memory_widget: &mut self.memory_widget,
                 pattern_table_widget: &mut self.pattern_table_widget,
                 audio_widget: &mut self.audio_widget,
+                nsf_player: &mut self.nsf_player,
+                cheats_widget: &mut self.cheats_widget,
+                mapper_widget: &mut self.mapper_widget,
                 audio_stats,
                 controller_profile: self.key_mapping_manager.controller1_profile(),
                 waveform_visualizer: &mut self.waveform_visualizer,
@@ -1407,6 +1644,8 @@ impl App for NesDebugger {
             // Render keyboard mappings widget (if visible)
             self.keyboard_mappings_widget.ui(ctx, &self.key_mapping_manager);
         });
+
+        self.archive_choice_ui(ctx);
     }
 }
 
@@ -82,7 +82,7 @@ pub fn run(rom_path: &Path, max_instructions: usize) -> Result<Outcome> {
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", rom_path.display()))?;
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
     system
         .load_rom(&rom)
         .map_err(|e| anyhow::anyhow!("{e}"))
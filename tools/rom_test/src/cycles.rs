@@ -22,7 +22,7 @@ use std::collections::BTreeMap;
 use std::path::Path;
 
 use anyhow::{Context, Result};
-use rn_core::{cartridge::load_rom, memory::Addressable, system::NesSystem};
+use rn_core::{cartridge::load_rom, memory::Addressable};
 
 /// nestest's automated entry point, which needs no PPU.
 const AUTOMATED_ENTRY: u16 = 0xC000;
@@ -37,7 +37,7 @@ pub fn report(rom_path: &Path, instructions: usize) -> Result<()> {
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", rom_path.display()))?;
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
     system
         .load_rom(&rom)
         .map_err(|e| anyhow::anyhow!("{e}"))
@@ -34,6 +34,8 @@ pub struct Capture {
     pub stopped: Option<String>,
     /// What the PPU did while producing the capture.
     pub diagnostics: rn_core::ppu::FrameDiagnostics,
+    /// What the cartridge database corrected in the header, if it recognised the ROM.
+    pub correction: Option<rn_core::cartridge::AppliedCorrection>,
 }
 
 /// Run `rom` for `frames` video frames and capture the final framebuffer.
@@ -131,11 +133,11 @@ pub fn capture(rom_path: &Path, options: Options<'_>) -> Result<Capture> {
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", rom_path.display()))?;
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
 
     // The header first, then the override. Byte 9's TV-system bit is right for most cartridges and
-    // wrong for a good many European ones — `super-mario-3-eu.nes` claims NTSC and is PAL — which
-    // is why reference emulators keep a database of cartridge hashes and why this takes a flag.
+    // wrong for a good many European ones — `super-mario-3-eu.nes` claims NTSC and is PAL. The
+    // cartridge database has already put right the ones it knows, and the flag covers the rest.
     let region = if force_pal { rn_core::region::Region::Pal } else { rom.header.region };
     system.set_region(region);
 
@@ -212,6 +214,7 @@ pub fn capture(rom_path: &Path, options: Options<'_>) -> Result<Capture> {
         coverage,
         stopped,
         diagnostics,
+        correction: rom.correction,
     })
 }
 
@@ -262,7 +265,7 @@ pub fn to_ascii(pixels: &[u8], columns: usize, rows: usize) -> String {
                 }
             }
 
-            let brightness = if count == 0 { 0 } else { (sum / count) as usize };
+            let brightness = sum.checked_div(count).unwrap_or(0) as usize;
             out.push(RAMP[brightness * (RAMP.len() - 1) / 255] as char);
         }
         out.push('\n');
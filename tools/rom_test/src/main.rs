@@ -27,6 +27,9 @@ use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use rn_core::cartridge::{
+    crc32, load_rom, lookup_database, mapper_name, sha1_digest, Mmc3Variant, VrcVariant,
+};
 
 /// Instruction budget before a ROM is declared hung.
 ///
@@ -38,6 +41,62 @@ const DEFAULT_BUDGET: usize = 30_000_000;
 struct Args {
     #[command(subcommand)]
     command: Command,
+
+    /// Restore battery saves (the `.sav` beside a ROM) but never write them, so one run cannot
+    /// change what the next starts from
+    #[arg(long, global = true)]
+    read_only_saves: bool,
+
+    /// Build Konami VRC2/VRC4 cartridges (mappers 21, 22, 23, 25) as this board — `vrc2a` to
+    /// `vrc2c`, `vrc4a` to `vrc4f` — for iNES files whose mapper number leaves the wiring unclear
+    #[arg(long, global = true)]
+    vrc: Option<VrcVariant>,
+
+    /// Build MMC3 cartridges (mappers 4, 118, 119) as this chip or board — `mmc3`, `mmc3a`, `mmc6`,
+    /// `txsrom` or `tqrom` — for iNES files that cannot say which
+    #[arg(long, global = true)]
+    mmc3: Option<Mmc3Variant>,
+
+    /// Leave out bus conflicts on the boards that have them, for homebrew only ever tested on
+    /// emulators that never had them
+    #[arg(long, global = true)]
+    no_bus_conflicts: bool,
+
+    /// The Famicom Disk System BIOS (`disksys.rom`, 8 KB) to boot `.fds` disk images through
+    #[arg(long, global = true)]
+    fds_bios: Option<PathBuf>,
+}
+
+/// Whether this run writes battery saves. Set once from the command line, before any ROM loads.
+static READ_ONLY_SAVES: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// The VRC board the run forces, if any. Set once from the command line, like `READ_ONLY_SAVES`.
+static VRC_VARIANT: std::sync::OnceLock<VrcVariant> = std::sync::OnceLock::new();
+
+/// The MMC3 variant the run forces, if any. Set once from the command line, like `VRC_VARIANT`.
+static MMC3_VARIANT: std::sync::OnceLock<Mmc3Variant> = std::sync::OnceLock::new();
+
+/// Whether this run leaves out bus conflicts. Set once from the command line, like `VRC_VARIANT`.
+static NO_BUS_CONFLICTS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// The FDS BIOS the run boots disks with, if one was given. Read once from the command line, like
+/// `VRC_VARIANT`.
+static FDS_BIOS: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+/// A machine to run a ROM on, with the run's battery-save policy applied.
+///
+/// Every command builds its system here rather than with `NesSystem::new`, so a flag given once on
+/// the command line reaches all of them.
+pub fn new_system() -> rn_core::system::NesSystem {
+    let mut system = rn_core::system::NesSystem::new();
+    system.set_battery_read_only(READ_ONLY_SAVES.load(std::sync::atomic::Ordering::Relaxed));
+    system.set_vrc_variant(VRC_VARIANT.get().copied());
+    system.set_mmc3_variant(MMC3_VARIANT.get().copied());
+    if NO_BUS_CONFLICTS.load(std::sync::atomic::Ordering::Relaxed) {
+        system.set_bus_conflicts(Some(false));
+    }
+    system.set_fds_bios(FDS_BIOS.get().cloned());
+    system
 }
 
 #[derive(Subcommand, Debug)]
@@ -135,6 +194,10 @@ enum Command {
         /// Press Start into a Super Mario Bros 3 level first, matching `nesref --into-level`
         #[arg(long)]
         into_level: bool,
+
+        /// Print each address as bank:address, with the PRG ROM bank the mapper has there
+        #[arg(long)]
+        banks: bool,
     },
 
     /// Print the text a ROM has drawn on screen, for ROMs that report no other way
@@ -151,6 +214,12 @@ enum Command {
         raw: bool,
     },
 
+    /// Print what a ROM's header says, its hashes, and what the cartridge database made of it
+    Header {
+        /// Path to the .nes or .unf file
+        rom: PathBuf,
+    },
+
     /// Check rendered frames against the hashes committed beside this tool
     Baselines {
         /// Directory holding the games the baselines name
@@ -187,6 +256,20 @@ enum Command {
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    READ_ONLY_SAVES.store(args.read_only_saves, std::sync::atomic::Ordering::Relaxed);
+    NO_BUS_CONFLICTS.store(args.no_bus_conflicts, std::sync::atomic::Ordering::Relaxed);
+    if let Some(variant) = args.vrc {
+        let _ = VRC_VARIANT.set(variant);
+    }
+    if let Some(variant) = args.mmc3 {
+        let _ = MMC3_VARIANT.set(variant);
+    }
+    if let Some(path) = &args.fds_bios {
+        let bios = rn_core::cartridge::load_fds_bios(path)
+            .map_err(|e| anyhow::anyhow!("{e}"))
+            .with_context(|| format!("loading the FDS BIOS {}", path.display()))?;
+        let _ = FDS_BIOS.set(bios);
+    }
 
     match args.command {
         Command::Nestest { rom, log, limit } => run_nestest(&rom, &log, limit),
@@ -217,8 +300,10 @@ fn main() -> Result<()> {
             state,
             skip_frames,
             into_level,
-        } => trace::report(&rom, instructions, state.as_deref(), skip_frames, into_level),
+            banks,
+        } => trace::report(&rom, instructions, state.as_deref(), skip_frames, into_level, banks),
         Command::Screen { rom, frames, raw } => screen::report(&rom, frames, raw),
+        Command::Header { rom } => print_header(&rom),
         Command::Baselines { roms, update, file } => {
             let path = file.unwrap_or_else(baseline::default_path);
             if missing(&path, "the baselines file") {
@@ -329,6 +414,74 @@ fn run_one(rom: &Path, budget: usize) -> Result<()> {
     }
 }
 
+/// Print a ROM's header as loaded, with the hashes that key the cartridge database.
+///
+/// The last line is the database line that describes the ROM as loaded, to start from when adding
+/// one whose header is wrong.
+fn print_header(rom_path: &Path) -> Result<()> {
+    if missing(rom_path, "ROM") {
+        return Ok(());
+    }
+    let rom = load_rom(rom_path)
+        .map_err(|e| anyhow::anyhow!("{e}"))
+        .with_context(|| format!("loading {}", rom_path.display()))?;
+    let header = &rom.header;
+    let (prg, chr) = (&rom.prg_rom[..], &rom.chr_rom[..]);
+    let crc = crc32(&[prg, chr]);
+    let sha1: String = sha1_digest(&[prg, chr]).iter().map(|byte| format!("{byte:02x}")).collect();
+
+    println!("{}\n", rom_path.display());
+    println!("  format        {:?}", header.format);
+    println!(
+        "  mapper        {}.{} ({})",
+        header.mapper,
+        header.submapper,
+        mapper_name(header.mapper).unwrap_or("not implemented")
+    );
+    println!("  PRG ROM       {} KB", prg.len() / 1024);
+    println!("  CHR ROM       {} KB", chr.len() / 1024);
+    println!("  mirroring     {:?}", header.nametable_mirroring());
+    println!("  region        {:?} ({:?} timing)", header.region, header.timing);
+    println!(
+        "  PRG RAM       {} KB, {} KB battery-backed{}",
+        header.prg_ram_size / 1024,
+        header.prg_nvram_size / 1024,
+        if header.battery { ", battery" } else { "" }
+    );
+    println!(
+        "  CHR RAM       {} KB, {} KB battery-backed",
+        header.chr_ram_size / 1024,
+        header.chr_nvram_size / 1024
+    );
+    println!("  CRC32         {crc:08X}");
+    println!("  SHA-1         {sha1}");
+    match (&rom.correction, lookup_database(prg, chr)) {
+        (Some(correction), _) => println!("  database      CORRECTED  {correction}"),
+        (None, Some(entry)) => println!("  database      {} (the header already agrees)", entry.name),
+        (None, None) => println!("  database      not listed"),
+    }
+
+    let region = format!("{:?}", header.region).to_lowercase();
+    let mirroring = match header.nametable_mirroring() {
+        rn_core::cartridge::Mirroring::FourScreen => "four",
+        rn_core::cartridge::Mirroring::Vertical => "vertical",
+        _ => "horizontal",
+    };
+    println!(
+        "\n{crc:08X} sha1={sha1} region={region} mapper={} submapper={} mirroring={mirroring} battery={} \
+         prg_ram={} prg_nvram={} chr_ram={} chr_nvram={} \"{}\"",
+        header.mapper,
+        header.submapper,
+        if header.battery { "yes" } else { "no" },
+        header.prg_ram_size,
+        header.prg_nvram_size,
+        header.chr_ram_size,
+        header.chr_nvram_size,
+        rom_path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    Ok(())
+}
+
 fn run_frame(
     rom: &Path,
     out: Option<&Path>,
@@ -346,6 +499,9 @@ fn run_frame(
     println!("Running {} for {frames} frames\n", rom.display());
     let capture = frame::capture(rom, options)?;
 
+    if let Some(correction) = &capture.correction {
+        println!("  CORRECTED  {correction}");
+    }
     if let Some(reason) = &capture.stopped {
         println!("  WARNING  {reason}");
     }
@@ -535,7 +691,10 @@ fn collect_roms(directory: &Path, into: &mut Vec<PathBuf>) -> Result<()> {
         let path = entry?.path();
         if path.is_dir() {
             collect_roms(&path, into)?;
-        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("nes")) {
+        } else if path
+            .extension()
+            .is_some_and(|e| ["nes", "unf", "unif", "zip", "gz"].iter().any(|ext| e.eq_ignore_ascii_case(ext)))
+        {
             into.push(path);
         }
     }
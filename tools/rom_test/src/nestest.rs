@@ -19,7 +19,7 @@
 use std::{fmt, path::Path};
 
 use anyhow::{bail, Context, Result};
-use rn_core::{cartridge::load_rom, cpu::CpuRegisters, system::NesSystem};
+use rn_core::{cartridge::load_rom, cpu::CpuRegisters};
 
 /// nestest's automated entry point, bypassing the menu that needs a PPU.
 const AUTOMATED_ENTRY: u16 = 0xC000;
@@ -129,7 +129,7 @@ pub fn run(rom_path: &Path, log_path: &Path, max_instructions: usize) -> Result<
         bail!("no parseable lines in the log — is this really nestest.log?");
     }
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
     system
         .load_rom(&rom)
         .map_err(|e| anyhow::anyhow!("{e}"))
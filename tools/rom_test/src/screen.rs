@@ -71,7 +71,7 @@ pub fn report(rom_path: &Path, frames: usize, raw: bool) -> Result<()> {
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", rom_path.display()))?;
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
     system
         .load_rom(&rom)
         .map_err(|e| anyhow::anyhow!("{e}"))
@@ -12,11 +12,16 @@
 //!
 //! The first differing line is the bug, and everything before it is agreement rather than a
 //! guess.
+//!
+//! With `--banks` each address is printed as `bank:address`, the bank being whichever PRG ROM
+//! bank the mapper has at that address, so the same routine in two different banks does not read
+//! as the same code. That breaks the columns' agreement with other emulators, and is for reading
+//! rather than diffing.
 
 use std::path::Path;
 
 use anyhow::{Context, Result};
-use rn_core::{cartridge::load_rom, system::NesSystem};
+use rn_core::cartridge::{load_rom, BankMemory, Cartridge};
 
 /// Emit one line per instruction: where it was, the registers, and the cost so far.
 ///
@@ -28,12 +33,13 @@ pub fn report(
     state: Option<&Path>,
     skip_frames: usize,
     into_level: bool,
+    banks: bool,
 ) -> Result<()> {
     let rom = load_rom(rom_path)
         .map_err(|e| anyhow::anyhow!("{e}"))
         .with_context(|| format!("loading {}", rom_path.display()))?;
 
-    let mut system = NesSystem::new();
+    let mut system = crate::new_system();
     system
         .load_rom(&rom)
         .map_err(|e| anyhow::anyhow!("{e}"))
@@ -68,10 +74,15 @@ pub fn report(
     for _ in 0..instructions {
         let registers = system.cpu().registers();
         let (scanline, dot) = system.ppu().scanline_cycle();
+        let address = if banks {
+            bank_address(&system.cartridge(), registers.pc)
+        } else {
+            format!("${:04X}", registers.pc)
+        };
 
         println!(
-            "${:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
-            registers.pc,
+            "{} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:3},{:3} CYC:{}",
+            address,
             registers.a,
             registers.x,
             registers.y,
@@ -89,3 +100,33 @@ pub fn report(
 
     Ok(())
 }
+
+/// `address` as `BB:AAAA`, with the PRG ROM bank behind it, or `--:AAAA` where no ROM bank is:
+/// RAM, the registers, or a mapper that does not say.
+fn bank_address(cartridge: &Cartridge, address: u16) -> String {
+    match cartridge.prg_window_at(address) {
+        Some(window) if window.memory == BankMemory::Rom => format!("{:02X}:{address:04X}", window.bank()),
+        _ => format!("--:{address:04X}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rn_core::cartridge::{create_mapper, INesHeader};
+
+    use super::*;
+
+    #[test]
+    fn addresses_carry_the_rom_bank_switched_in_there() {
+        let header = INesHeader::for_mapper(2);
+        // All ones, so that the board's bus conflicts leave the bank number written intact.
+        let mapper = create_mapper(&header, vec![0xFF; 0x10000], Vec::new()).unwrap();
+        let mut cartridge = Cartridge::with_mapper(header, mapper);
+        cartridge.write_cpu(0x8000, 2);
+
+        assert_eq!(bank_address(&cartridge, 0x8123), "02:8123");
+        assert_eq!(bank_address(&cartridge, 0xFFFC), "03:FFFC", "the last bank is fixed");
+        assert_eq!(bank_address(&cartridge, 0x6000), "--:6000", "work RAM has no ROM bank");
+        assert_eq!(bank_address(&cartridge, 0x0200), "--:0200");
+    }
+}